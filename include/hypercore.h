@@ -0,0 +1,72 @@
+/* C API of the hypercore crate, built with the `ffi` feature, e.g.
+ *
+ *   cargo rustc --release --features ffi --crate-type cdylib
+ *
+ * Operations on a handle are queued to its thread: they return at once, and their callback is
+ * called from that thread when they complete, in the order they were queued. See the
+ * documentation of `hypercore::ffi` for details. */
+#ifndef HYPERCORE_H
+#define HYPERCORE_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define HYPERCORE_OK 0
+#define HYPERCORE_NOT_FOUND 1
+#define HYPERCORE_ERR_ARGUMENT -1
+#define HYPERCORE_ERR_CORE -2
+#define HYPERCORE_ERR_NOT_WRITABLE -3
+#define HYPERCORE_ERR_INVALID_SIGNATURE -4
+#define HYPERCORE_ERR_CORRUPT -5
+#define HYPERCORE_ERR_EMPTY_STORAGE -6
+#define HYPERCORE_ERR_KEY_MISMATCH -7
+#define HYPERCORE_ERR_QUOTA_EXCEEDED -8
+#define HYPERCORE_ERR_CLOSED -9
+#define HYPERCORE_ERR_TIMEOUT -10
+#define HYPERCORE_ERR_STORAGE_FULL -11
+#define HYPERCORE_ERR_IO -12
+#define HYPERCORE_ERR_EQUIVOCATION -13
+#define HYPERCORE_ERR_POISONED -14
+#define HYPERCORE_ERR_LOCKED -15
+
+typedef struct HypercoreHandle HypercoreHandle;
+
+typedef struct HypercoreInfo {
+  uint64_t length;
+  uint64_t byte_length;
+  uint64_t contiguous_length;
+  uint64_t fork;
+  bool writeable;
+} HypercoreInfo;
+
+typedef void (*HypercoreAppendCallback)(void *user_data, int32_t status, uint64_t length,
+                                        uint64_t byte_length);
+/* `data` is only valid during the call */
+typedef void (*HypercoreGetCallback)(void *user_data, int32_t status, const uint8_t *data,
+                                     size_t data_len);
+typedef void (*HypercoreInfoCallback)(void *user_data, HypercoreInfo info);
+
+/* Create a hypercore on disk in the directory `path`, or in memory if `path` is NULL */
+int32_t hypercore_create(const char *path, HypercoreHandle **out);
+/* Open the hypercore stored in the directory `path` */
+int32_t hypercore_open(const char *path, HypercoreHandle **out);
+/* `data` is copied before the call returns */
+int32_t hypercore_append(const HypercoreHandle *handle, const uint8_t *data, size_t data_len,
+                         HypercoreAppendCallback callback, void *user_data);
+int32_t hypercore_get(const HypercoreHandle *handle, uint64_t index,
+                      HypercoreGetCallback callback, void *user_data);
+int32_t hypercore_info(const HypercoreHandle *handle, HypercoreInfoCallback callback,
+                       void *user_data);
+/* Wait for the queued operations, then close the hypercore and free the handle */
+void hypercore_close(HypercoreHandle *handle);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* HYPERCORE_H */
@@ -0,0 +1,462 @@
+//! Archive format of [`crate::Hypercore::export`] and [`crate::Hypercore::import`].
+//!
+//! An archive starts with [`MAGIC`], followed by records each framed with a varint length. The
+//! first record is the header: the format version, the key and manifest of the core, the signed
+//! [`Checkpoint`] of its tree and the ranges of blocks that are present. Each present block then
+//! follows as a proof, with the tree nodes from the block up to a root of the checkpoint, so it
+//! can be verified on its own. An end record closes the archive, which tells a complete archive
+//! from a cut one.
+//!
+//! Incremental backups of [`crate::Hypercore::export_since`] have the same layout, with a backup
+//! header instead: the key, the length the backup starts from, the upgrade proof from that length
+//! to the signed length of the core, and the ranges of blocks added since.
+use std::ops::Range;
+
+use compact_encoding::{
+    CompactEncoding, EncodingError, EncodingErrorKind, decode_usize, encode_usize_var,
+    encoded_size_usize, map_decode, map_first, sum_encoded_size, take_array, write_slice,
+};
+use ed25519_dalek::VerifyingKey;
+use futures::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use hypercore_schema::{Node, Proof};
+
+use crate::{Checkpoint, HypercoreError, crypto::Manifest, encoding::EncodableProof};
+
+/// First bytes of an archive
+pub(crate) const MAGIC: [u8; 8] = *b"hcarchiv";
+/// Version of the archive format
+pub(crate) const VERSION: u64 = 1;
+/// Largest record accepted when reading, to not allocate whatever a corrupt length says
+const MAX_RECORD_LENGTH: usize = 64 * 1024 * 1024;
+
+/// First record of an archive
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct ArchiveHeader {
+    pub(crate) key: VerifyingKey,
+    pub(crate) manifest: Manifest,
+    /// None for an empty core
+    pub(crate) checkpoint: Option<Checkpoint>,
+    /// Ranges of the blocks in the archive, in order
+    pub(crate) blocks: Vec<Range<u64>>,
+}
+
+/// First record of an incremental backup
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct BackupHeader {
+    pub(crate) key: VerifyingKey,
+    /// Length of the core at the previous backup
+    pub(crate) since: u64,
+    /// Proof upgrading the core from `since`, None if nothing was appended
+    pub(crate) upgrade: Option<Proof>,
+    /// Ranges of the blocks in the backup, in order
+    pub(crate) blocks: Vec<Range<u64>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Record {
+    Header(ArchiveHeader),
+    Block(Proof),
+    End,
+    Backup(BackupHeader),
+}
+
+fn checkpoint_encoded_size(checkpoint: &Option<Checkpoint>) -> Result<usize, EncodingError> {
+    Ok(1 + match checkpoint {
+        Some(checkpoint) => sum_encoded_size!(
+            checkpoint.fork,
+            checkpoint.length,
+            checkpoint.roots,
+            checkpoint.signature
+        ),
+        None => 0,
+    })
+}
+
+fn encode_checkpoint<'a>(
+    checkpoint: &Option<Checkpoint>,
+    buffer: &'a mut [u8],
+) -> Result<&'a mut [u8], EncodingError> {
+    match checkpoint {
+        Some(checkpoint) => {
+            let rest = write_slice(&[1], buffer)?;
+            let rest = checkpoint.fork.encode(rest)?;
+            let rest = checkpoint.length.encode(rest)?;
+            let rest = checkpoint.roots.encode(rest)?;
+            checkpoint.signature.encode(rest)
+        }
+        None => write_slice(&[0], buffer),
+    }
+}
+
+fn decode_checkpoint(buffer: &[u8]) -> Result<(Option<Checkpoint>, &[u8]), EncodingError> {
+    let ([present], rest) = take_array::<1>(buffer)?;
+    if present == 0 {
+        return Ok((None, rest));
+    }
+    let ((fork, length, roots, signature), rest) =
+        map_decode!(rest, [u64, u64, Vec<Node>, Vec<u8>]);
+    Ok((
+        Some(Checkpoint {
+            fork,
+            length,
+            roots,
+            signature,
+        }),
+        rest,
+    ))
+}
+
+fn ranges_encoded_size(ranges: &[Range<u64>]) -> Result<usize, EncodingError> {
+    let mut out = encoded_size_usize(ranges.len());
+    for range in ranges {
+        out += sum_encoded_size!(range.start, range.end - range.start);
+    }
+    Ok(out)
+}
+
+fn encode_ranges<'a>(
+    ranges: &[Range<u64>],
+    buffer: &'a mut [u8],
+) -> Result<&'a mut [u8], EncodingError> {
+    let mut rest = encode_usize_var(&ranges.len(), buffer)?;
+    for range in ranges {
+        rest = range.start.encode(rest)?;
+        rest = (range.end - range.start).encode(rest)?;
+    }
+    Ok(rest)
+}
+
+fn decode_ranges(buffer: &[u8]) -> Result<(Vec<Range<u64>>, &[u8]), EncodingError> {
+    let (count, mut rest) = decode_usize(buffer)?;
+    let mut ranges = Vec::with_capacity(count.min(rest.len() / 2));
+    for _ in 0..count {
+        let ((start, length), next) = map_decode!(rest, [u64, u64]);
+        let end = start.checked_add(length).ok_or_else(|| {
+            EncodingError::new(EncodingErrorKind::InvalidData, "Block range overflows")
+        })?;
+        ranges.push(start..end);
+        rest = next;
+    }
+    Ok((ranges, rest))
+}
+
+fn decode_version(buffer: &[u8]) -> Result<&[u8], EncodingError> {
+    let (version, rest) = u64::decode(buffer)?;
+    if version != VERSION {
+        return Err(EncodingError::new(
+            EncodingErrorKind::InvalidData,
+            &format!("Unsupported archive version: {version}"),
+        ));
+    }
+    Ok(rest)
+}
+
+fn decode_key(buffer: &[u8]) -> Result<(VerifyingKey, &[u8]), EncodingError> {
+    let (key, rest) = take_array::<32>(buffer)?;
+    let key = VerifyingKey::from_bytes(&key).map_err(|err| {
+        EncodingError::new(
+            EncodingErrorKind::InvalidData,
+            &format!("Invalid key in archive: {err}"),
+        )
+    })?;
+    Ok((key, rest))
+}
+
+impl CompactEncoding for ArchiveHeader {
+    fn encoded_size(&self) -> Result<usize, EncodingError> {
+        Ok(VERSION.encoded_size()?
+            + 32
+            + self.manifest.encoded_size()?
+            + checkpoint_encoded_size(&self.checkpoint)?
+            + ranges_encoded_size(&self.blocks)?)
+    }
+
+    fn encode<'a>(&self, buffer: &'a mut [u8]) -> Result<&'a mut [u8], EncodingError> {
+        let rest = VERSION.encode(buffer)?;
+        let rest = write_slice(self.key.as_bytes(), rest)?;
+        let rest = self.manifest.encode(rest)?;
+        let rest = encode_checkpoint(&self.checkpoint, rest)?;
+        encode_ranges(&self.blocks, rest)
+    }
+
+    fn decode(buffer: &[u8]) -> Result<(Self, &[u8]), EncodingError> {
+        let rest = decode_version(buffer)?;
+        let (key, rest) = decode_key(rest)?;
+        let (manifest, rest) = Manifest::decode(rest)?;
+        let (checkpoint, rest) = decode_checkpoint(rest)?;
+        let (blocks, rest) = decode_ranges(rest)?;
+        Ok((
+            ArchiveHeader {
+                key,
+                manifest,
+                checkpoint,
+                blocks,
+            },
+            rest,
+        ))
+    }
+}
+
+impl CompactEncoding for BackupHeader {
+    fn encoded_size(&self) -> Result<usize, EncodingError> {
+        Ok(VERSION.encoded_size()?
+            + 32
+            + self.since.encoded_size()?
+            + 1
+            + match &self.upgrade {
+                Some(upgrade) => EncodableProof(upgrade).encoded_size()?,
+                None => 0,
+            }
+            + ranges_encoded_size(&self.blocks)?)
+    }
+
+    fn encode<'a>(&self, buffer: &'a mut [u8]) -> Result<&'a mut [u8], EncodingError> {
+        let rest = VERSION.encode(buffer)?;
+        let rest = write_slice(self.key.as_bytes(), rest)?;
+        let rest = self.since.encode(rest)?;
+        let rest = match &self.upgrade {
+            Some(upgrade) => EncodableProof(upgrade).encode(write_slice(&[1], rest)?)?,
+            None => write_slice(&[0], rest)?,
+        };
+        encode_ranges(&self.blocks, rest)
+    }
+
+    fn decode(buffer: &[u8]) -> Result<(Self, &[u8]), EncodingError> {
+        let rest = decode_version(buffer)?;
+        let (key, rest) = decode_key(rest)?;
+        let (since, rest) = u64::decode(rest)?;
+        let ([has_upgrade], rest) = take_array::<1>(rest)?;
+        let (upgrade, rest) = if has_upgrade != 0 {
+            map_first!(EncodableProof::decode(rest)?, Some)
+        } else {
+            (None, rest)
+        };
+        let (blocks, rest) = decode_ranges(rest)?;
+        Ok((
+            BackupHeader {
+                key,
+                since,
+                upgrade,
+                blocks,
+            },
+            rest,
+        ))
+    }
+}
+
+impl CompactEncoding for Record {
+    fn encoded_size(&self) -> Result<usize, EncodingError> {
+        Ok(1 + match self {
+            Record::Header(header) => header.encoded_size()?,
+            Record::Block(proof) => EncodableProof(proof).encoded_size()?,
+            Record::End => 0,
+            Record::Backup(header) => header.encoded_size()?,
+        })
+    }
+
+    fn encode<'a>(&self, buffer: &'a mut [u8]) -> Result<&'a mut [u8], EncodingError> {
+        match self {
+            Record::Header(header) => header.encode(write_slice(&[0], buffer)?),
+            Record::Block(proof) => EncodableProof(proof).encode(write_slice(&[1], buffer)?),
+            Record::End => write_slice(&[2], buffer),
+            Record::Backup(header) => header.encode(write_slice(&[3], buffer)?),
+        }
+    }
+
+    fn decode(buffer: &[u8]) -> Result<(Self, &[u8]), EncodingError> {
+        let ([type_id], rest) = take_array::<1>(buffer)?;
+        Ok(match type_id {
+            0 => map_first!(ArchiveHeader::decode(rest)?, Record::Header),
+            1 => map_first!(EncodableProof::decode(rest)?, Record::Block),
+            2 => (Record::End, rest),
+            3 => map_first!(BackupHeader::decode(rest)?, Record::Backup),
+            _ => {
+                return Err(EncodingError::new(
+                    EncodingErrorKind::InvalidData,
+                    &format!("Unknown archive record type: {type_id}"),
+                ));
+            }
+        })
+    }
+}
+
+/// Error for an archive that can't be imported
+pub(crate) fn invalid_archive(context: impl std::fmt::Display) -> HypercoreError {
+    HypercoreError::InvalidOperation {
+        context: format!("Invalid archive: {context}"),
+    }
+}
+
+/// Write the magic bytes starting an archive
+pub(crate) async fn write_magic<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+) -> Result<(), HypercoreError> {
+    writer.write_all(&MAGIC).await?;
+    Ok(())
+}
+
+/// Read and check the magic bytes starting an archive
+pub(crate) async fn read_magic<R: AsyncRead + Unpin>(reader: &mut R) -> Result<(), HypercoreError> {
+    let mut magic = [0; MAGIC.len()];
+    reader.read_exact(&mut magic).await?;
+    if magic != MAGIC {
+        return Err(invalid_archive("not a hypercore archive"));
+    }
+    Ok(())
+}
+
+/// Write a record, prefixed with its length
+pub(crate) async fn write_record<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    record: &Record,
+) -> Result<(), HypercoreError> {
+    let length = record.encoded_size()?;
+    let mut frame = vec![0; encoded_size_usize(length) + length];
+    let rest = encode_usize_var(&length, &mut frame)?;
+    record.encode(rest)?;
+    writer.write_all(&frame).await?;
+    Ok(())
+}
+
+/// Read the next record
+pub(crate) async fn read_record<R: AsyncRead + Unpin>(
+    reader: &mut R,
+) -> Result<Record, HypercoreError> {
+    let mut header = [0; 9];
+    reader.read_exact(&mut header[..1]).await?;
+    let header_length = match header[0] {
+        0xfd => 3,
+        0xfe => 5,
+        0xff => 9,
+        _ => 1,
+    };
+    reader.read_exact(&mut header[1..header_length]).await?;
+    let (length, _) = decode_usize(&header[..header_length])?;
+    if length > MAX_RECORD_LENGTH {
+        return Err(invalid_archive(format!(
+            "record of {length} bytes is too long"
+        )));
+    }
+    let mut buffer = vec![0; length];
+    reader.read_exact(&mut buffer).await?;
+    let (record, rest) = Record::decode(&buffer)?;
+    if !rest.is_empty() {
+        return Err(invalid_archive("record is shorter than its frame"));
+    }
+    Ok(record)
+}
+
+/// Read the start of an archive, expecting a header
+pub(crate) async fn read_header<R: AsyncRead + Unpin>(
+    reader: &mut R,
+) -> Result<ArchiveHeader, HypercoreError> {
+    read_magic(reader).await?;
+    match read_record(reader).await? {
+        Record::Header(header) => Ok(header),
+        _ => Err(invalid_archive("missing header")),
+    }
+}
+
+/// Read the start of an incremental backup, expecting a backup header
+pub(crate) async fn read_backup_header<R: AsyncRead + Unpin>(
+    reader: &mut R,
+) -> Result<BackupHeader, HypercoreError> {
+    read_magic(reader).await?;
+    match read_record(reader).await? {
+        Record::Backup(header) => Ok(header),
+        _ => Err(invalid_archive("missing backup header")),
+    }
+}
+
+/// Check that a block proof read from an archive is the next block announced in the header
+pub(crate) fn check_block(proof: &Proof, expected: Option<u64>) -> Result<u64, HypercoreError> {
+    let index = proof
+        .block
+        .as_ref()
+        .map(|block| block.index)
+        .ok_or_else(|| invalid_archive("block record without a block"))?;
+    if proof.hash.is_some() || proof.seek.is_some() || proof.upgrade.is_some() {
+        return Err(invalid_archive(format!(
+            "block record {index} has more than a block"
+        )));
+    }
+    if Some(index) != expected {
+        return Err(invalid_archive(format!(
+            "block {index} is not the next block of the header"
+        )));
+    }
+    Ok(index)
+}
+
+/// Number of tree nodes between the leaf of the block at `index` and the root containing it, in
+/// a tree of `length` blocks. A core knowing only the roots misses this many nodes.
+pub(crate) fn nodes_below_root(index: u64, length: u64) -> u64 {
+    let head = 2 * length;
+    let mut iter = flat_tree::Iterator::new(2 * index);
+    let mut nodes = 0;
+    loop {
+        iter.parent();
+        if iter.contains(head) {
+            return nodes;
+        }
+        nodes += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::generate_signing_key;
+    use futures::io::Cursor;
+
+    #[async_std::test]
+    async fn archive_records_round_trip() -> Result<(), HypercoreError> {
+        let key = generate_signing_key().verifying_key();
+        let records = vec![
+            Record::Header(ArchiveHeader {
+                key,
+                manifest: Manifest::new(&key),
+                checkpoint: Some(Checkpoint {
+                    fork: 1,
+                    length: 3,
+                    roots: vec![Node::new(1, vec![7; 32], 10), Node::new(4, vec![8; 32], 5)],
+                    signature: vec![9; 64],
+                }),
+                blocks: vec![0..1, 2..3],
+            }),
+            Record::Block(Proof {
+                fork: 1,
+                block: Some(hypercore_schema::DataBlock {
+                    index: 2,
+                    value: vec![1; 5],
+                    nodes: vec![],
+                }),
+                hash: None,
+                seek: None,
+                upgrade: None,
+            }),
+            Record::End,
+        ];
+        let mut writer = Cursor::new(vec![]);
+        write_magic(&mut writer).await?;
+        for record in &records {
+            write_record(&mut writer, record).await?;
+        }
+        let mut reader = Cursor::new(writer.into_inner());
+        read_magic(&mut reader).await?;
+        for record in &records {
+            assert_eq!(&read_record(&mut reader).await?, record);
+        }
+        assert!(read_record(&mut reader).await.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn archive_nodes_below_root() {
+        // Roots of a tree of 3 blocks are 1 and 4
+        assert_eq!(nodes_below_root(0, 3), 1);
+        assert_eq!(nodes_below_root(1, 3), 1);
+        assert_eq!(nodes_below_root(2, 3), 0);
+        assert_eq!(nodes_below_root(5, 8), 3);
+    }
+}
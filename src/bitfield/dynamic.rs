@@ -4,7 +4,7 @@ use crate::{
     common::{BitfieldUpdate, StoreInfo, StoreInfoInstruction, StoreInfoType},
 };
 use futures::future::Either;
-use std::{cell::RefCell, convert::TryInto};
+use std::convert::TryInto;
 
 const DYNAMIC_BITFIELD_PAGE_SIZE: usize = 32768;
 
@@ -14,7 +14,7 @@ const DYNAMIC_BITFIELD_PAGE_SIZE: usize = 32768;
 /// for reference.
 #[derive(Debug)]
 pub(crate) struct DynamicBitfield {
-    pages: intmap::IntMap<RefCell<FixedBitfield>>,
+    pages: intmap::IntMap<FixedBitfield>,
     biggest_page_index: u64,
     unflushed: Vec<u64>,
 }
@@ -38,14 +38,11 @@ impl DynamicBitfield {
                 let resumed = data.len() >= 4;
                 let mut biggest_page_index = 0;
                 if resumed {
-                    let mut pages: intmap::IntMap<RefCell<FixedBitfield>> = intmap::IntMap::new();
+                    let mut pages: intmap::IntMap<FixedBitfield> = intmap::IntMap::new();
                     let mut data_index = 0;
                     while data_index < data.len() {
                         let parent_index: u64 = (data_index / FIXED_BITFIELD_LENGTH) as u64;
-                        pages.insert(
-                            parent_index,
-                            RefCell::new(FixedBitfield::from_data(data_index, &data)),
-                        );
+                        pages.insert(parent_index, FixedBitfield::from_data(data_index, &data));
                         if parent_index > biggest_page_index {
                             biggest_page_index = parent_index;
                         }
@@ -67,23 +64,30 @@ impl DynamicBitfield {
         }
     }
 
-    /// Flushes pending changes, returns info slices to write to storage.
-    pub(crate) fn flush(&mut self) -> Box<[StoreInfo]> {
+    /// Flushes pending changes, returns info slices to write to storage. The pages stay pending
+    /// until marked [`DynamicBitfield::flushed`].
+    pub(crate) fn flush(&self) -> Box<[StoreInfo]> {
         let mut infos_to_flush: Vec<StoreInfo> = Vec::with_capacity(self.unflushed.len());
         for unflushed_id in &self.unflushed {
-            let mut p = self.pages.get_mut(*unflushed_id).unwrap().borrow_mut();
+            let p = self.pages.get(*unflushed_id).unwrap();
             let data = p.to_bytes();
             infos_to_flush.push(StoreInfo::new_content(
                 Store::Bitfield,
                 *unflushed_id * data.len() as u64,
                 &data,
             ));
-            p.dirty = false;
         }
-        self.unflushed = vec![];
         infos_to_flush.into_boxed_slice()
     }
 
+    /// Mark the pages returned by [`DynamicBitfield::flush`] as written
+    pub(crate) fn flushed(&mut self) {
+        for unflushed_id in &self.unflushed {
+            self.pages.get_mut(*unflushed_id).unwrap().dirty = false;
+        }
+        self.unflushed = vec![];
+    }
+
     pub(crate) fn get(&self, index: u64) -> bool {
         let j = index & (DYNAMIC_BITFIELD_PAGE_SIZE as u64 - 1);
         let i = (index - j) / DYNAMIC_BITFIELD_PAGE_SIZE as u64;
@@ -91,7 +95,7 @@ impl DynamicBitfield {
         if !self.pages.contains_key(i) {
             false
         } else {
-            let p = self.pages.get(i).unwrap().borrow();
+            let p = self.pages.get(i).unwrap();
             p.get(j.try_into().expect("Index should have fit into u32"))
         }
     }
@@ -103,7 +107,7 @@ impl DynamicBitfield {
 
         if !self.pages.contains_key(i) {
             if value {
-                self.pages.insert(i, RefCell::new(FixedBitfield::new()));
+                self.pages.insert(i, FixedBitfield::new());
                 if i > self.biggest_page_index {
                     self.biggest_page_index = i;
                 }
@@ -113,7 +117,7 @@ impl DynamicBitfield {
             }
         }
 
-        let mut p = self.pages.get_mut(i).unwrap().borrow_mut();
+        let p = self.pages.get_mut(i).unwrap();
         let changed: bool = p.set(j.try_into().expect("Index should have fit into u32"), value);
 
         if changed && !p.dirty {
@@ -138,12 +142,12 @@ impl DynamicBitfield {
 
         while length > 0 {
             if !self.pages.contains_key(i) {
-                self.pages.insert(i, RefCell::new(FixedBitfield::new()));
+                self.pages.insert(i, FixedBitfield::new());
                 if i > self.biggest_page_index {
                     self.biggest_page_index = i;
                 }
             }
-            let mut p = self.pages.get_mut(i).unwrap().borrow_mut();
+            let p = self.pages.get_mut(i).unwrap();
 
             let end = std::cmp::min(j + length, DYNAMIC_BITFIELD_PAGE_SIZE as u64);
 
@@ -177,7 +181,7 @@ impl DynamicBitfield {
 
             // To keep the common case fast, first try the same page as the position
             if let Some(p) = self.pages.get(first_page)
-                && let Some(index) = p.borrow().index_of(value, first_index as u32)
+                && let Some(index) = p.index_of(value, first_index as u32)
             {
                 return Some(first_page * DYNAMIC_BITFIELD_PAGE_SIZE as u64 + index as u64);
             };
@@ -188,7 +192,7 @@ impl DynamicBitfield {
             keys.sort();
             for key in keys {
                 if let Some(p) = self.pages.get(*key)
-                    && let Some(index) = p.borrow().index_of(value, 0)
+                    && let Some(index) = p.index_of(value, 0)
                 {
                     return Some(key * DYNAMIC_BITFIELD_PAGE_SIZE as u64 + index as u64);
                 };
@@ -200,7 +204,7 @@ impl DynamicBitfield {
             let mut j = first_index as u32;
             while i == first_page || i <= self.biggest_page_index {
                 if let Some(p) = self.pages.get(i) {
-                    if let Some(index) = p.borrow().index_of(value, j) {
+                    if let Some(index) = p.index_of(value, j) {
                         return Some(i * DYNAMIC_BITFIELD_PAGE_SIZE as u64 + index as u64);
                     };
                 } else {
@@ -224,7 +228,7 @@ impl DynamicBitfield {
 
             // To keep the common case fast, first try the same page as the position
             if let Some(p) = self.pages.get(last_page)
-                && let Some(index) = p.borrow().last_index_of(value, last_index as u32)
+                && let Some(index) = p.last_index_of(value, last_index as u32)
             {
                 return Some(last_page * DYNAMIC_BITFIELD_PAGE_SIZE as u64 + index as u64);
             };
@@ -237,9 +241,8 @@ impl DynamicBitfield {
 
             for key in keys {
                 if let Some(p) = self.pages.get(*key)
-                    && let Some(index) = p
-                        .borrow()
-                        .last_index_of(value, FIXED_BITFIELD_BITS_LENGTH as u32 - 1)
+                    && let Some(index) =
+                        p.last_index_of(value, FIXED_BITFIELD_BITS_LENGTH as u32 - 1)
                 {
                     return Some(key * DYNAMIC_BITFIELD_PAGE_SIZE as u64 + index as u64);
                 };
@@ -251,7 +254,7 @@ impl DynamicBitfield {
             let mut j = last_index as u32;
             while i == last_page || i == 0 {
                 if let Some(p) = self.pages.get(i) {
-                    if let Some(index) = p.borrow().last_index_of(value, j) {
+                    if let Some(index) = p.last_index_of(value, j) {
                         return Some(i * DYNAMIC_BITFIELD_PAGE_SIZE as u64 + index as u64);
                     };
                 } else {
@@ -0,0 +1,120 @@
+//! Synchronous API for code that doesn't run in an async executor.
+use std::future::Future;
+
+use crate::{AppendOutcome, Hypercore, HypercoreError, Info};
+
+/// Runs futures to completion on the current thread
+#[derive(Debug)]
+struct Executor {
+    #[cfg(feature = "tokio")]
+    runtime: tokio::runtime::Runtime,
+}
+
+impl Executor {
+    fn new() -> Result<Self, HypercoreError> {
+        Ok(Self {
+            #[cfg(feature = "tokio")]
+            runtime: tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()?,
+        })
+    }
+
+    fn block_on<F: Future>(&self, future: F) -> F::Output {
+        #[cfg(feature = "tokio")]
+        {
+            self.runtime.block_on(future)
+        }
+        #[cfg(not(feature = "tokio"))]
+        {
+            futures::executor::block_on(future)
+        }
+    }
+}
+
+/// Wrapper of a [`Hypercore`] with synchronous methods, which block the current thread until the
+/// operation is done. With the `tokio` feature it owns a single-threaded tokio runtime that drives
+/// the operations, otherwise they are run with [`futures::executor::block_on`].
+///
+/// The methods panic if called from within an async runtime, use [`Hypercore`] directly there.
+#[derive(Debug)]
+pub struct BlockingHypercore {
+    core: Hypercore,
+    executor: Executor,
+}
+
+impl BlockingHypercore {
+    /// Create a blocking hypercore from the future that builds it, e.g.
+    /// `async { HypercoreBuilder::new(Storage::new_memory().await?).build().await }`. The future is
+    /// run by the blocking hypercore, so that disk storage can be opened in its runtime.
+    pub fn new<F: Future<Output = Result<Hypercore, HypercoreError>>>(
+        build: F,
+    ) -> Result<Self, HypercoreError> {
+        let executor = Executor::new()?;
+        let core = executor.block_on(build)?;
+        Ok(Self { core, executor })
+    }
+
+    /// Get core info, see [`Hypercore::info`]
+    pub fn info(&self) -> Info {
+        self.core.info()
+    }
+
+    /// Read value at given index, if any, see [`Hypercore::get`]
+    pub fn get(&self, index: u64) -> Result<Option<Vec<u8>>, HypercoreError> {
+        self.executor.block_on(self.core.get(index))
+    }
+
+    /// Append data, see [`Hypercore::append`]
+    pub fn append(&mut self, data: &[u8]) -> Result<AppendOutcome, HypercoreError> {
+        self.executor.block_on(self.core.append(data))
+    }
+
+    /// Append a batch of data, see [`Hypercore::append_batch`]
+    pub fn append_batch<A: AsRef<[u8]>, B: AsRef<[A]>>(
+        &mut self,
+        batch: B,
+    ) -> Result<AppendOutcome, HypercoreError> {
+        self.executor.block_on(self.core.append_batch(batch))
+    }
+
+    /// Get the wrapped [`Hypercore`]
+    pub fn into_inner(self) -> Hypercore {
+        self.core
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{HypercoreBuilder, Storage};
+
+    #[test]
+    fn blocking_hypercore() -> Result<(), HypercoreError> {
+        let dir = tempfile::Builder::new()
+            .prefix("blocking_hypercore")
+            .tempdir()
+            .unwrap();
+        let path = dir.path().to_owned();
+        let mut core = BlockingHypercore::new(async {
+            HypercoreBuilder::new(Storage::new_disk(&path, false).await?)
+                .build()
+                .await
+        })?;
+        core.append(b"hello")?;
+        assert_eq!(core.append_batch([b"small", b"world"])?.length, 3);
+        assert_eq!(core.get(2)?, Some(b"world".to_vec()));
+        assert_eq!(core.get(3)?, None);
+        assert_eq!(core.info().byte_length, 15);
+        drop(core);
+
+        let core = BlockingHypercore::new(async {
+            HypercoreBuilder::new(Storage::new_disk(&path, false).await?)
+                .open(true)
+                .build()
+                .await
+        })?;
+        assert_eq!(core.get(0)?, Some(b"hello".to_vec()));
+        Ok(())
+    }
+}
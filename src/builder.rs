@@ -1,11 +1,18 @@
+use ed25519_dalek::VerifyingKey;
+use rand::{CryptoRng, RngCore, SeedableRng, rngs::StdRng};
 use std::fmt::Debug;
+use std::sync::Arc;
 #[cfg(feature = "cache")]
 use std::time::Duration;
+#[cfg(feature = "tracing")]
 use tracing::instrument;
 
 #[cfg(feature = "cache")]
 use crate::common::cache::CacheOptions;
-use crate::{Hypercore, HypercoreError, PartialKeypair, Storage, core::HypercoreOptions};
+use crate::{
+    BuildOutcome, Checkpoint, Hypercore, HypercoreError, Manifest, PartialKeypair,
+    PersistencePolicy, Quota, Retention, Storage, StorageTraits, core::HypercoreOptions,
+};
 
 /// Build CacheOptions.
 #[cfg(feature = "cache")]
@@ -51,18 +58,25 @@ impl CacheOptionsBuilder {
 }
 
 /// Build a Hypercore instance with options.
+///
+/// Built from a [`Storage<T>`] with concrete stores, it builds a [`Hypercore<T>`] calling them
+/// without dynamic dispatch.
 #[derive(Debug)]
-pub struct HypercoreBuilder {
-    storage: Storage,
+pub struct HypercoreBuilder<T: ?Sized = dyn StorageTraits + Send> {
+    storage: Storage<T>,
     options: HypercoreOptions,
+    #[cfg(all(feature = "shared-core", any(feature = "tokio", feature = "async-std")))]
+    scrub: Option<crate::replication::ScrubOptions>,
 }
 
-impl HypercoreBuilder {
+impl<T: StorageTraits + Send + ?Sized> HypercoreBuilder<T> {
     /// Create a hypercore builder with a given storage
-    pub fn new(storage: Storage) -> Self {
+    pub fn new(storage: Storage<T>) -> Self {
         Self {
             storage,
             options: HypercoreOptions::new(),
+            #[cfg(all(feature = "shared-core", any(feature = "tokio", feature = "async-std")))]
+            scrub: None,
         }
     }
 
@@ -72,6 +86,49 @@ impl HypercoreBuilder {
         self
     }
 
+    /// Set the public key of a read-only hypercore, to be filled via replication. Opening existing
+    /// storage fails if it contains a hypercore with a different public key.
+    pub fn verifying_key(mut self, public: VerifyingKey) -> Self {
+        self.options.key_pair = Some(PartialKeypair {
+            public,
+            secret: None,
+        });
+        self
+    }
+
+    /// Set the manifest of a new hypercore instead of the default single signer manifest of the
+    /// key pair. Opening existing storage fails if the stored manifest is different.
+    pub fn manifest(mut self, manifest: Manifest) -> Self {
+        self.options.manifest = Some(manifest);
+        self
+    }
+
+    /// Set overwrite. When true, any hypercore already in the storage is removed and a new one
+    /// is created. Without it, building with a key pair or verifying key that doesn't match the
+    /// stored hypercore fails with [`HypercoreError::KeyMismatch`].
+    pub fn overwrite(mut self, overwrite: bool) -> Self {
+        self.options.overwrite = overwrite;
+        self
+    }
+
+    /// Start a new hypercore from a trusted [`Checkpoint`] instead of from an empty tree, so that
+    /// blocks can be replicated without verifying the history from block zero. The signature of
+    /// the checkpoint is verified against the public key.
+    pub fn checkpoint(mut self, checkpoint: Checkpoint) -> Self {
+        self.options.checkpoint = Some(checkpoint);
+        self
+    }
+
+    /// Set light mode. A light hypercore stores its tree, bitfield and oplog but never block data:
+    /// appended blocks and blocks of verified proofs are hashed into the tree and discarded, so
+    /// they are never marked as stored and [`Hypercore::get`] doesn't return them. For mirrors and
+    /// index nodes that follow and attest to a hypercore without storing its content. Light mode
+    /// isn't stored, blocks appended or verified after opening without it are stored as usual.
+    pub fn light(mut self, light: bool) -> Self {
+        self.options.light = light;
+        self
+    }
+
     /// Set open.
     pub fn open(mut self, open: bool) -> Self {
         self.options.open = open;
@@ -85,9 +142,131 @@ impl HypercoreBuilder {
         self
     }
 
+    /// Set block cache options. When set, [`Hypercore::get`] reads data blocks from a cache bounded
+    /// by the byte size of the blocks before touching storage.
+    #[cfg(feature = "cache")]
+    pub fn block_cache_options(mut self, builder: CacheOptionsBuilder) -> Self {
+        self.options.block_cache_options = Some(builder.build());
+        self
+    }
+
+    /// Set proof cache options. When set, proofs created with
+    /// [`Hypercore::create_valueless_proof`] and [`Hypercore::create_proof`] are cached by fork
+    /// and request until the core is appended to or truncated.
+    #[cfg(feature = "cache")]
+    pub fn proof_cache_options(mut self, builder: CacheOptionsBuilder) -> Self {
+        self.options.proof_cache_options = Some(builder.build());
+        self
+    }
+
+    /// Set the number of events kept for each subscriber of [`Hypercore::event_subscribe`],
+    /// defaults to 32. When a subscriber lags behind, its oldest events are dropped and counted
+    /// in [`Hypercore::events_dropped`].
+    #[cfg(feature = "replication")]
+    pub fn event_queue_capacity(mut self, capacity: usize) -> Self {
+        self.options.event_queue_capacity = capacity;
+        self
+    }
+
+    /// Set a policy deciding which blocks of proofs given to [`Hypercore::verify_and_apply_proof`]
+    /// are written to the data store, e.g. [`crate::PersistRanges`] for a mirror of a subset of a
+    /// large hypercore. The other blocks are verified into the tree and discarded, so waiting for
+    /// them to be stored with [`crate::replication::SharedCore`] never returns. Appended blocks
+    /// are always stored.
+    pub fn persistence_policy(mut self, policy: Arc<dyn PersistencePolicy>) -> Self {
+        self.options.persistence_policy = Some(policy);
+        self
+    }
+
+    /// Set a rolling retention: after each append or applied proof, the blocks older than the last
+    /// given number of blocks or bytes are cleared like with [`Hypercore::clear`]. The tree is kept, so the
+    /// hypercore stays verifiable and can still create proofs without the cleared blocks.
+    pub fn retention(mut self, retention: Retention) -> Self {
+        self.options.retention = Some(retention);
+        self
+    }
+
+    /// Set a size quota: [`Hypercore::append`] and upgrades given to
+    /// [`Hypercore::verify_and_apply_proof`] fail with [`crate::HypercoreError::QuotaExceeded`]
+    /// when the hypercore would grow past it. With the `replication` feature, a `QuotaWarning`
+    /// event is emitted when the hypercore reaches [`Quota::warning_percent`] of it.
+    pub fn quota(mut self, quota: Quota) -> Self {
+        self.options.quota = Some(quota);
+        self
+    }
+
+    /// Store the bytes of blocks with the same content only once, e.g. for snapshots of a dataset
+    /// appended repeatedly. Blocks are matched by their hash in the tree, and read from the first
+    /// stored block with the same content. Once built with it, a hypercore stays deduplicated
+    /// when opened again. Opening reads the tree node of every stored block to index them.
+    pub fn deduplicate(mut self, deduplicate: bool) -> Self {
+        self.options.deduplicate = deduplicate;
+        self
+    }
+
+    /// Keep the byte ranges of the blocks in memory, 8 bytes per block, so that
+    /// [`Hypercore::seek`] and reading blocks don't read tree nodes from storage. Opening reads
+    /// the leaves of the tree to index them. Blocks received from peers are indexed once the
+    /// leaves of all the blocks before them are known, until then they are looked up in the tree.
+    pub fn byte_index(mut self, byte_index: bool) -> Self {
+        self.options.byte_index = byte_index;
+        self
+    }
+
+    /// Set the random number generator used to generate the key pair when none is given, instead
+    /// of the one of the operating system.
+    pub fn rng(mut self, rng: impl RngCore + CryptoRng + Send + Sync + 'static) -> Self {
+        self.options.rng = Some(Box::new(rng));
+        self
+    }
+
+    /// Generate the key pair when none is given from a random number generator seeded with
+    /// `seed`, so the same seed gives the same key pair. Only meant for tests.
+    pub fn rng_seed(self, seed: [u8; 32]) -> Self {
+        self.rng(StdRng::from_seed(seed))
+    }
+
+    /// Set the number of proofs given to [`Hypercore::verify_and_apply_proof`] that are buffered
+    /// until the upgrade they need is applied, defaults to 32. When full, the oldest proof is
+    /// dropped. Zero disables the buffering.
+    pub fn pending_proofs_capacity(mut self, capacity: usize) -> Self {
+        self.options.pending_proofs_capacity = capacity;
+        self
+    }
+
     /// Build a new Hypercore.
-    #[instrument(err, skip_all)]
-    pub async fn build(self) -> Result<Hypercore, HypercoreError> {
+    #[cfg_attr(feature = "tracing", instrument(err, skip_all))]
+    pub async fn build(self) -> Result<Hypercore<T>, HypercoreError> {
         Hypercore::new(self.storage, self.options).await
     }
+
+    /// Build a new Hypercore, returning also whether it was created or opened.
+    #[cfg_attr(feature = "tracing", instrument(err, skip_all))]
+    pub async fn build_with_outcome(self) -> Result<(Hypercore<T>, BuildOutcome), HypercoreError> {
+        Box::pin(Hypercore::new_with_outcome(self.storage, self.options)).await
+    }
+}
+
+#[cfg(all(feature = "shared-core", any(feature = "tokio", feature = "async-std")))]
+impl HypercoreBuilder {
+    /// Scrub the hypercore built with [`HypercoreBuilder::build_shared`] in a background task,
+    /// which slowly walks the stored blocks with [`Hypercore::scrub`] so a long-running seeder
+    /// stops advertising blocks that got corrupted on disk. The task ends when the core is
+    /// dropped.
+    pub fn scrub(mut self, options: crate::replication::ScrubOptions) -> Self {
+        self.scrub = Some(options);
+        self
+    }
+
+    /// Build a new Hypercore wrapped in a [`crate::replication::SharedCore`], starting the
+    /// background scrubbing of [`HypercoreBuilder::scrub`] if set.
+    #[cfg_attr(feature = "tracing", instrument(err, skip_all))]
+    pub async fn build_shared(self) -> Result<crate::replication::SharedCore, HypercoreError> {
+        let core =
+            crate::replication::SharedCore::from(Hypercore::new(self.storage, self.options).await?);
+        if let Some(options) = self.scrub {
+            core.spawn_scrubber(options);
+        }
+        Ok(core)
+    }
 }
@@ -0,0 +1,183 @@
+//! Content-defined chunking with [FastCDC](https://www.usenix.org/conference/atc16/technical-sessions/presentation/xia),
+//! used by [`crate::Hypercore::append_reader`] to append a stream as blocks.
+//!
+//! Boundaries between chunks depend on the content around them, not on their offset. An edit of
+//! a file only changes the chunks around the edit, so versions of similar files share most of
+//! their blocks, which makes them cheap to deduplicate when syncing.
+
+use crate::HypercoreError;
+
+/// Table of the gear hash, 256 pseudorandom values generated with splitmix64
+const GEAR: [u64; 256] = gear_table();
+
+const fn gear_table() -> [u64; 256] {
+    let mut table = [0; 256];
+    let mut state: u64 = 0x9e37_79b9_7f4a_7c15;
+    let mut i = 0;
+    while i < table.len() {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+}
+
+/// Mask of the given number of high bits. The gear hash shifts left, so its high bits depend on
+/// the most bytes.
+const fn mask(bits: u32) -> u64 {
+    if bits == 0 {
+        0
+    } else {
+        u64::MAX << (64 - bits)
+    }
+}
+
+/// FastCDC chunker, with normalized chunking: boundaries are harder to find before the average
+/// size and easier after it, which keeps most chunks close to the average.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FastCdc {
+    min_size: usize,
+    avg_size: usize,
+    max_size: usize,
+    mask_small: u64,
+    mask_large: u64,
+}
+
+impl FastCdc {
+    /// Chunker of chunks of at least `min_size` bytes and at most `max_size` bytes, around
+    /// `avg_size` bytes on average.
+    pub fn new(min_size: usize, avg_size: usize, max_size: usize) -> Result<Self, HypercoreError> {
+        if min_size == 0 || min_size > avg_size || avg_size > max_size {
+            return Err(HypercoreError::BadArgument {
+                context: format!(
+                    "Chunk sizes must satisfy 0 < min <= avg <= max, got {min_size}, {avg_size} and {max_size}"
+                ),
+            });
+        }
+        let bits = avg_size.ilog2();
+        Ok(Self {
+            min_size,
+            avg_size,
+            max_size,
+            mask_small: mask((bits + 1).min(64)),
+            mask_large: mask(bits.saturating_sub(1)),
+        })
+    }
+
+    /// Minimum size of a chunk, unless it ends the data
+    pub fn min_size(&self) -> usize {
+        self.min_size
+    }
+
+    /// Average size of a chunk
+    pub fn avg_size(&self) -> usize {
+        self.avg_size
+    }
+
+    /// Maximum size of a chunk
+    pub fn max_size(&self) -> usize {
+        self.max_size
+    }
+
+    /// Length of the first chunk of `data`. When `data` is shorter than [`FastCdc::max_size`]
+    /// and no boundary is found, the whole `data` is a chunk, so pass at least
+    /// [`FastCdc::max_size`] bytes unless `data` ends the stream.
+    pub fn cut(&self, data: &[u8]) -> usize {
+        if data.len() <= self.min_size {
+            return data.len();
+        }
+        let end = data.len().min(self.max_size);
+        let normal = self.avg_size.min(end);
+        let mut hash: u64 = 0;
+        for (i, byte) in data.iter().enumerate().take(end).skip(self.min_size) {
+            hash = (hash << 1).wrapping_add(GEAR[*byte as usize]);
+            let mask = if i < normal {
+                self.mask_small
+            } else {
+                self.mask_large
+            };
+            if hash & mask == 0 {
+                return i + 1;
+            }
+        }
+        end
+    }
+
+    /// Split `data`, a whole stream, into chunks
+    pub fn chunks<'a>(&'a self, mut data: &'a [u8]) -> impl Iterator<Item = &'a [u8]> + 'a {
+        std::iter::from_fn(move || {
+            if data.is_empty() {
+                return None;
+            }
+            let (chunk, rest) = data.split_at(self.cut(data));
+            data = rest;
+            Some(chunk)
+        })
+    }
+}
+
+impl Default for FastCdc {
+    /// Chunks of 16 KiB to 256 KiB, 64 KiB on average
+    fn default() -> Self {
+        Self::new(16 * 1024, 64 * 1024, 256 * 1024).expect("Valid default chunk sizes")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{Rng, SeedableRng, rngs::StdRng};
+
+    fn data(length: usize) -> Vec<u8> {
+        let mut data = vec![0; length];
+        StdRng::seed_from_u64(7).fill(&mut data[..]);
+        data
+    }
+
+    #[test]
+    fn chunks_within_bounds() -> Result<(), HypercoreError> {
+        let chunker = FastCdc::new(64, 256, 1024)?;
+        let data = data(64 * 1024);
+        let chunks: Vec<&[u8]> = chunker.chunks(&data).collect();
+        assert_eq!(chunks.concat(), data);
+        let (last, chunks) = chunks.split_last().unwrap();
+        assert!(!last.is_empty() && last.len() <= 1024);
+        assert!(
+            chunks
+                .iter()
+                .all(|chunk| (64..=1024).contains(&chunk.len()))
+        );
+        // Normalized chunking keeps the average close to the target
+        let average = data.len() / (chunks.len() + 1);
+        assert!((128..=512).contains(&average), "average {average}");
+
+        assert!(FastCdc::new(0, 256, 1024).is_err());
+        assert!(FastCdc::new(512, 256, 1024).is_err());
+        assert!(FastCdc::new(64, 2048, 1024).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn chunks_are_content_defined() -> Result<(), HypercoreError> {
+        let chunker = FastCdc::new(64, 256, 1024)?;
+        let data = data(64 * 1024);
+        let mut edited = b"inserted".to_vec();
+        edited.extend_from_slice(&data);
+
+        let original: Vec<&[u8]> = chunker.chunks(&data).collect();
+        let shared = chunker
+            .chunks(&edited)
+            .filter(|chunk| original.contains(chunk))
+            .count();
+        // Only the chunks around the insertion differ
+        assert!(
+            shared + 2 >= original.len(),
+            "{shared} of {}",
+            original.len()
+        );
+        Ok(())
+    }
+}
@@ -1,10 +1,17 @@
-use moka::sync::Cache;
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) use moka::sync::Cache;
 use std::time::Duration;
 
-use hypercore_schema::Node;
+use hypercore_schema::{Node, RequestBlock, RequestSeek, RequestUpgrade};
+
+use crate::common::ValuelessProof;
+#[cfg(target_arch = "wasm32")]
+pub(crate) use crate::common::lru::LruCache as Cache;
 
 // Default to 1 year of cache
+#[cfg(not(target_arch = "wasm32"))]
 const DEFAULT_CACHE_TTL_SEC: u64 = 31556952;
+#[cfg(not(target_arch = "wasm32"))]
 const DEFAULT_CACHE_TTI_SEC: u64 = 31556952;
 // Default to 100kb of node cache
 const DEFAULT_CACHE_MAX_SIZE: u64 = 100000;
@@ -13,6 +20,11 @@ const NODE_WEIGHT: u32 =
     3 * 8 + 32 + 4 +
     // Then 8 for key and guesstimate 8 bytes of overhead.
     8 + 8;
+// Guesstimated byte size of a proof without its nodes: the key, the optional parts
+// and the upgrade signature.
+const PROOF_WEIGHT: u32 = 128;
+// Key and guesstimated overhead of a cached block on top of its value.
+const BLOCK_OVERHEAD_WEIGHT: u32 = 8 + 24 + 8;
 
 #[derive(Debug, Clone)]
 pub(crate) struct CacheOptions {
@@ -31,7 +43,38 @@ impl CacheOptions {
     }
 
     pub(crate) fn to_node_cache(&self, initial_nodes: Vec<Node>) -> Cache<u64, Node> {
-        let cache = if self.time_to_live.is_some() || self.time_to_idle.is_some() {
+        let cache = self.to_cache(|_, _| NODE_WEIGHT);
+        for node in initial_nodes {
+            cache.insert(node.index, node);
+        }
+        cache
+    }
+
+    pub(crate) fn to_proof_cache(&self) -> Cache<ProofCacheKey, ValuelessProof> {
+        self.to_cache(|_, proof: &ValuelessProof| {
+            let nodes = proof.block.as_ref().map_or(0, |block| block.nodes.len())
+                + proof.hash.as_ref().map_or(0, |hash| hash.nodes.len())
+                + proof.seek.as_ref().map_or(0, |seek| seek.nodes.len())
+                + proof.upgrade.as_ref().map_or(0, |upgrade| {
+                    upgrade.nodes.len() + upgrade.additional_nodes.len()
+                });
+            PROOF_WEIGHT.saturating_add(NODE_WEIGHT.saturating_mul(nodes as u32))
+        })
+    }
+
+    pub(crate) fn to_block_cache(&self) -> Cache<u64, Vec<u8>> {
+        self.to_cache(|_, value: &Vec<u8>| {
+            BLOCK_OVERHEAD_WEIGHT.saturating_add(u32::try_from(value.len()).unwrap_or(u32::MAX))
+        })
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn to_cache<K, V>(&self, weigher: impl Fn(&K, &V) -> u32 + Send + Sync + 'static) -> Cache<K, V>
+    where
+        K: std::hash::Hash + Eq + Send + Sync + 'static,
+        V: Clone + Send + Sync + 'static,
+    {
+        if self.time_to_live.is_some() || self.time_to_idle.is_some() {
             Cache::builder()
                 .time_to_live(
                     self.time_to_live
@@ -42,17 +85,51 @@ impl CacheOptions {
                         .unwrap_or_else(|| Duration::from_secs(DEFAULT_CACHE_TTI_SEC)),
                 )
                 .max_capacity(self.max_capacity.unwrap_or(DEFAULT_CACHE_MAX_SIZE))
-                .weigher(|_, _| NODE_WEIGHT)
+                .weigher(weigher)
                 .build()
         } else {
             Cache::builder()
                 .max_capacity(self.max_capacity.unwrap_or(DEFAULT_CACHE_MAX_SIZE))
-                .weigher(|_, _| NODE_WEIGHT)
+                .weigher(weigher)
                 .build()
-        };
-        for node in initial_nodes {
-            cache.insert(node.index, node);
         }
-        cache
+    }
+
+    /// Without a clock on wasm32, entries don't expire and are only evicted by capacity
+    #[cfg(target_arch = "wasm32")]
+    fn to_cache<K, V>(&self, weigher: impl Fn(&K, &V) -> u32 + Send + Sync + 'static) -> Cache<K, V>
+    where
+        K: std::hash::Hash + Eq,
+        V: Clone,
+    {
+        Cache::new(self.max_capacity.unwrap_or(DEFAULT_CACHE_MAX_SIZE), weigher)
+    }
+}
+
+/// Key of a cached proof: the fork the proof was created in and the request it answers.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct ProofCacheKey {
+    fork: u64,
+    block: Option<(u64, u64)>,
+    hash: Option<(u64, u64)>,
+    seek: Option<u64>,
+    upgrade: Option<(u64, u64)>,
+}
+
+impl ProofCacheKey {
+    pub(crate) fn new(
+        fork: u64,
+        block: Option<&RequestBlock>,
+        hash: Option<&RequestBlock>,
+        seek: Option<&RequestSeek>,
+        upgrade: Option<&RequestUpgrade>,
+    ) -> Self {
+        Self {
+            fork,
+            block: block.map(|block| (block.index, block.nodes)),
+            hash: hash.map(|hash| (hash.index, hash.nodes)),
+            seek: seek.map(|seek| seek.bytes),
+            upgrade: upgrade.map(|upgrade| (upgrade.start, upgrade.length)),
+        }
     }
 }
@@ -1,7 +1,7 @@
 use compact_encoding::EncodingError;
 use thiserror::Error;
 
-use crate::Store;
+use crate::{Quota, Store};
 
 /// Common error type for the hypercore interface
 #[derive(Error, Debug)]
@@ -34,20 +34,84 @@ pub enum HypercoreError {
         store: Store,
     },
     /// Corrupt storage
-    #[error("Corrupt storage: {store}.{}",
+    #[error("Corrupt storage: {store}{}.{}",
+          .offset.map_or_else(String::new, |offset| format!(" at byte {offset}")),
           .context.as_ref().map_or_else(String::new, |ctx| format!(" Context: {ctx}.")))]
     CorruptStorage {
         /// Store that was corrupt
         store: Store,
+        /// Byte offset of the corruption in the store, if known
+        offset: Option<u64>,
         /// Context for the error
         context: Option<String>,
     },
+    /// Given key does not match the key of the stored hypercore
+    #[error("Key mismatch. {context}")]
+    KeyMismatch {
+        /// Context for the error
+        context: String,
+    },
+    /// Appending or verifying would grow the hypercore past its quota
+    #[error("Quota exceeded: length {length} and byte length {byte_length} exceed {quota:?}.")]
+    QuotaExceeded {
+        /// Length the hypercore would have had
+        length: u64,
+        /// Byte length the hypercore would have had
+        byte_length: u64,
+        /// Quota of the hypercore
+        quota: Quota,
+    },
+    /// The writer signed two conflicting trees on the same fork, see
+    /// [`crate::Hypercore::equivocation`]
+    #[error("Writer equivocated at fork {fork} and length {length}.")]
+    Equivocation {
+        /// Fork of the conflicting trees
+        fork: u64,
+        /// Length of the local tree
+        length: u64,
+    },
+    /// A write to storage failed before, so the stored state may differ from the in-memory
+    /// one, see [`crate::Hypercore::recover`]
+    #[error("Hypercore poisoned by a failed write, recover it to write again")]
+    Poisoned,
+    /// The storage is locked by another process, see [`crate::Storage::new_disk_locked`]
+    #[error("Storage locked. {context}")]
+    Locked {
+        /// Context for the error
+        context: String,
+    },
     /// Invalid operation
     #[error("Invalid operation. {context}")]
     InvalidOperation {
         /// Context for the error
         context: String,
     },
+    /// The hypercore was closed before the operation could run
+    #[error("Hypercore closed. {context}")]
+    Closed {
+        /// Context for the error
+        context: String,
+    },
+    /// The storage timed out
+    #[error("Timed out.{}",
+          .context.as_ref().map_or_else(String::new, |ctx| format!(" {ctx}.")))]
+    Timeout {
+        /// Context for the error
+        context: Option<String>,
+        /// Original source error
+        #[source]
+        source: std::io::Error,
+    },
+    /// The storage has no space left
+    #[error("Storage full.{}",
+          .context.as_ref().map_or_else(String::new, |ctx| format!(" {ctx}.")))]
+    StorageFull {
+        /// Context for the error
+        context: Option<String>,
+        /// Original source error
+        #[source]
+        source: std::io::Error,
+    },
     /// Unexpected IO error occured
     #[error("Unrecoverable input/output error occured.{}",
           .context.as_ref().map_or_else(String::new, |ctx| format!(" {ctx}.")))]
@@ -60,12 +124,150 @@ pub enum HypercoreError {
     },
 }
 
+/// Kind of a [`HypercoreError`], to branch on without matching the fields of the variants
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// [`HypercoreError::BadArgument`]
+    BadArgument,
+    /// [`HypercoreError::NotWritable`]
+    NotWritable,
+    /// [`HypercoreError::InvalidSignature`]
+    InvalidSignature,
+    /// [`HypercoreError::InvalidChecksum`] and [`HypercoreError::CorruptStorage`]
+    Corrupt,
+    /// [`HypercoreError::EmptyStorage`]
+    EmptyStorage,
+    /// [`HypercoreError::KeyMismatch`]
+    KeyMismatch,
+    /// [`HypercoreError::QuotaExceeded`]
+    QuotaExceeded,
+    /// [`HypercoreError::Equivocation`]
+    Equivocation,
+    /// [`HypercoreError::Poisoned`]
+    Poisoned,
+    /// [`HypercoreError::Locked`]
+    Locked,
+    /// [`HypercoreError::InvalidOperation`]
+    InvalidOperation,
+    /// [`HypercoreError::Closed`]
+    Closed,
+    /// [`HypercoreError::Timeout`]
+    Timeout,
+    /// [`HypercoreError::StorageFull`]
+    StorageFull,
+    /// [`HypercoreError::IO`]
+    Io,
+}
+
+impl ErrorKind {
+    /// Stable numeric code of the kind, e.g. for FFI, which is also the status of the C API of
+    /// the `ffi` feature. Codes are negative and never change, new kinds get new codes.
+    pub fn code(self) -> i32 {
+        match self {
+            Self::BadArgument => -1,
+            Self::InvalidOperation => -2,
+            Self::NotWritable => -3,
+            Self::InvalidSignature => -4,
+            Self::Corrupt => -5,
+            Self::EmptyStorage => -6,
+            Self::KeyMismatch => -7,
+            Self::QuotaExceeded => -8,
+            Self::Closed => -9,
+            Self::Timeout => -10,
+            Self::StorageFull => -11,
+            Self::Io => -12,
+            Self::Equivocation => -13,
+            Self::Poisoned => -14,
+            Self::Locked => -15,
+        }
+    }
+}
+
+impl HypercoreError {
+    /// Kind of the error
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Self::BadArgument { .. } => ErrorKind::BadArgument,
+            Self::NotWritable => ErrorKind::NotWritable,
+            Self::InvalidSignature { .. } => ErrorKind::InvalidSignature,
+            Self::InvalidChecksum { .. } | Self::CorruptStorage { .. } => ErrorKind::Corrupt,
+            Self::EmptyStorage { .. } => ErrorKind::EmptyStorage,
+            Self::KeyMismatch { .. } => ErrorKind::KeyMismatch,
+            Self::QuotaExceeded { .. } => ErrorKind::QuotaExceeded,
+            Self::Equivocation { .. } => ErrorKind::Equivocation,
+            Self::Poisoned => ErrorKind::Poisoned,
+            Self::Locked { .. } => ErrorKind::Locked,
+            Self::InvalidOperation { .. } => ErrorKind::InvalidOperation,
+            Self::Closed { .. } => ErrorKind::Closed,
+            Self::Timeout { .. } => ErrorKind::Timeout,
+            Self::StorageFull { .. } => ErrorKind::StorageFull,
+            Self::IO { .. } => ErrorKind::Io,
+        }
+    }
+
+    /// Whether the same operation may succeed when retried later, i.e. the error is a timeout or
+    /// an interrupted IO. Other errors need a change first, e.g. freeing space for
+    /// [`HypercoreError::StorageFull`].
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Self::Timeout { .. } => true,
+            Self::IO { source, .. } => matches!(
+                source.kind(),
+                std::io::ErrorKind::Interrupted | std::io::ErrorKind::WouldBlock
+            ),
+            _ => false,
+        }
+    }
+
+    /// Error of an IO error, with the specific variants of timeouts and full storage
+    pub(crate) fn from_io(context: Option<String>, source: std::io::Error) -> Self {
+        match source.kind() {
+            std::io::ErrorKind::TimedOut => Self::Timeout { context, source },
+            std::io::ErrorKind::StorageFull => Self::StorageFull { context, source },
+            _ => Self::IO { context, source },
+        }
+    }
+}
+
 impl From<std::io::Error> for HypercoreError {
+    /// Unwraps the hypercore error of an IO error converted from one
     fn from(err: std::io::Error) -> Self {
-        Self::IO {
-            context: None,
-            source: err,
+        if err
+            .get_ref()
+            .is_some_and(|inner| inner.is::<HypercoreError>())
+        {
+            let inner = err.into_inner().expect("Has an inner error");
+            return *inner.downcast().expect("Is a hypercore error");
         }
+        Self::from_io(None, err)
+    }
+}
+
+impl From<HypercoreError> for std::io::Error {
+    /// IO error of the closest [`std::io::ErrorKind`] wrapping the hypercore error, which
+    /// [`std::io::Error::get_ref`] or converting back to a [`HypercoreError`] return
+    fn from(err: HypercoreError) -> Self {
+        use std::io::ErrorKind as IoKind;
+        let kind = match &err {
+            HypercoreError::BadArgument { .. } | HypercoreError::KeyMismatch { .. } => {
+                IoKind::InvalidInput
+            }
+            HypercoreError::NotWritable => IoKind::PermissionDenied,
+            HypercoreError::InvalidSignature { .. }
+            | HypercoreError::InvalidChecksum { .. }
+            | HypercoreError::CorruptStorage { .. }
+            | HypercoreError::Equivocation { .. } => IoKind::InvalidData,
+            HypercoreError::EmptyStorage { .. } => IoKind::NotFound,
+            HypercoreError::QuotaExceeded { .. } => IoKind::QuotaExceeded,
+            HypercoreError::InvalidOperation { .. } | HypercoreError::Poisoned => IoKind::Other,
+            HypercoreError::Closed { .. } => IoKind::BrokenPipe,
+            HypercoreError::Locked { .. } => IoKind::ResourceBusy,
+            HypercoreError::Timeout { .. } => IoKind::TimedOut,
+            HypercoreError::StorageFull { .. } => IoKind::StorageFull,
+            HypercoreError::IO { source, .. } => source.kind(),
+        };
+        std::io::Error::new(kind, err)
     }
 }
 
@@ -76,3 +278,50 @@ impl From<EncodingError> for HypercoreError {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn error_kind_and_retryability() {
+        let timeout = HypercoreError::from(std::io::Error::from(std::io::ErrorKind::TimedOut));
+        assert_eq!(timeout.kind(), ErrorKind::Timeout);
+        assert!(timeout.is_retryable());
+
+        let full = HypercoreError::from(std::io::Error::from(std::io::ErrorKind::StorageFull));
+        assert_eq!(full.kind(), ErrorKind::StorageFull);
+        assert!(!full.is_retryable());
+
+        let interrupted =
+            HypercoreError::from(std::io::Error::from(std::io::ErrorKind::Interrupted));
+        assert_eq!(interrupted.kind(), ErrorKind::Io);
+        assert!(interrupted.is_retryable());
+
+        let corrupt = HypercoreError::CorruptStorage {
+            store: Store::Tree,
+            offset: Some(4096),
+            context: None,
+        };
+        assert_eq!(corrupt.kind(), ErrorKind::Corrupt);
+        assert!(!corrupt.is_retryable());
+        assert_eq!(corrupt.to_string(), "Corrupt storage: tree at byte 4096.");
+        assert_eq!(HypercoreError::NotWritable.kind(), ErrorKind::NotWritable);
+    }
+
+    #[test]
+    fn error_to_and_from_io_error() {
+        let io = std::io::Error::from(HypercoreError::NotWritable);
+        assert_eq!(io.kind(), std::io::ErrorKind::PermissionDenied);
+        assert!(matches!(
+            HypercoreError::from(io),
+            HypercoreError::NotWritable
+        ));
+
+        let io = std::io::Error::from(HypercoreError::from(std::io::Error::from(
+            std::io::ErrorKind::ConnectionReset,
+        )));
+        assert_eq!(io.kind(), std::io::ErrorKind::ConnectionReset);
+        assert_eq!(HypercoreError::from(io).kind(), ErrorKind::Io);
+    }
+}
@@ -0,0 +1,198 @@
+//! Least recently used cache bounded by the weight of its entries, used by the `cache` feature on
+//! wasm32 in place of moka, which needs threads and a clock there.
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+type Weigher<K, V> = dyn Fn(&K, &V) -> u32 + Send + Sync;
+
+#[derive(Debug)]
+struct Entry<V> {
+    value: V,
+    weight: u64,
+    /// Tick of the last use of the entry, its key in [`State::recency`]
+    used: u64,
+}
+
+#[derive(Debug)]
+struct State<K, V> {
+    entries: HashMap<Arc<K>, Entry<V>>,
+    /// Keys by the tick of their last use, the least recently used first
+    recency: BTreeMap<u64, Arc<K>>,
+    tick: u64,
+    weight: u64,
+}
+
+/// Cache evicting the least recently used entries once the weight of its entries exceeds its
+/// capacity. Clones share the same entries, like clones of a moka cache.
+pub(crate) struct LruCache<K, V> {
+    state: Arc<Mutex<State<K, V>>>,
+    max_capacity: u64,
+    weigher: Arc<Weigher<K, V>>,
+}
+
+impl<K, V> Clone for LruCache<K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            state: self.state.clone(),
+            max_capacity: self.max_capacity,
+            weigher: self.weigher.clone(),
+        }
+    }
+}
+
+impl<K, V> std::fmt::Debug for LruCache<K, V> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let state = self.state();
+        f.debug_struct("LruCache")
+            .field("entries", &state.entries.len())
+            .field("weight", &state.weight)
+            .field("max_capacity", &self.max_capacity)
+            .finish()
+    }
+}
+
+impl<K, V> LruCache<K, V> {
+    pub(crate) fn new(
+        max_capacity: u64,
+        weigher: impl Fn(&K, &V) -> u32 + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(State {
+                entries: HashMap::new(),
+                recency: BTreeMap::new(),
+                tick: 0,
+                weight: 0,
+            })),
+            max_capacity,
+            weigher: Arc::new(weigher),
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, State<K, V>> {
+        self.state.lock().expect("LruCache state poisoned")
+    }
+
+    pub(crate) fn invalidate_all(&self) {
+        let mut state = self.state();
+        state.entries.clear();
+        state.recency.clear();
+        state.weight = 0;
+    }
+}
+
+impl<K: Hash + Eq, V: Clone> LruCache<K, V> {
+    /// Get the value of the key, making it the most recently used
+    pub(crate) fn get(&self, key: &K) -> Option<V> {
+        let mut guard = self.state();
+        let state = &mut *guard;
+        let tick = state.next_tick();
+        let entry = state.entries.get_mut(key)?;
+        let used = std::mem::replace(&mut entry.used, tick);
+        let value = entry.value.clone();
+        if let Some(key) = state.recency.remove(&used) {
+            state.recency.insert(tick, key);
+        }
+        Some(value)
+    }
+
+    pub(crate) fn contains_key(&self, key: &K) -> bool {
+        self.state().entries.contains_key(key)
+    }
+
+    /// Insert the value as the most recently used, then evict the least recently used entries
+    /// until the cache is within its capacity. A value heavier than the capacity isn't kept.
+    pub(crate) fn insert(&self, key: K, value: V) {
+        let weight = u64::from((self.weigher)(&key, &value));
+        let mut state = self.state();
+        state.remove(&key);
+        if weight > self.max_capacity {
+            return;
+        }
+        let tick = state.next_tick();
+        let key = Arc::new(key);
+        state.recency.insert(tick, key.clone());
+        state.entries.insert(
+            key,
+            Entry {
+                value,
+                weight,
+                used: tick,
+            },
+        );
+        state.weight += weight;
+        while state.weight > self.max_capacity {
+            let Some((_, key)) = state.recency.pop_first() else {
+                break;
+            };
+            if let Some(entry) = state.entries.remove(&key) {
+                state.weight -= entry.weight;
+            }
+        }
+    }
+
+    pub(crate) fn invalidate(&self, key: &K) {
+        self.state().remove(key);
+    }
+
+    /// Snapshot of the entries, in no particular order
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (Arc<K>, V)> {
+        let state = self.state();
+        let entries: Vec<(Arc<K>, V)> = state
+            .entries
+            .iter()
+            .map(|(key, entry)| (key.clone(), entry.value.clone()))
+            .collect();
+        entries.into_iter()
+    }
+}
+
+impl<K: Hash + Eq, V> State<K, V> {
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+
+    fn remove(&mut self, key: &K) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.recency.remove(&entry.used);
+            self.weight -= entry.weight;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lru_cache_evicts_least_recently_used() {
+        let cache: LruCache<u64, Vec<u8>> =
+            LruCache::new(10, |_, value: &Vec<u8>| value.len() as u32);
+        cache.insert(0, vec![0; 4]);
+        cache.insert(1, vec![1; 4]);
+        // Using 0 makes 1 the least recently used
+        assert_eq!(cache.get(&0), Some(vec![0; 4]));
+        cache.insert(2, vec![2; 4]);
+        assert!(cache.contains_key(&0) && !cache.contains_key(&1) && cache.contains_key(&2));
+
+        // Replacing a value updates the weight
+        cache.insert(0, vec![0; 6]);
+        assert!(cache.contains_key(&0) && cache.contains_key(&2));
+        cache.insert(3, vec![3; 1]);
+        assert!(!cache.contains_key(&2));
+
+        // Too heavy values aren't kept
+        cache.insert(4, vec![4; 11]);
+        assert!(!cache.contains_key(&4));
+
+        let shared = cache.clone();
+        shared.invalidate(&0);
+        let mut keys: Vec<u64> = cache.iter().map(|(key, _)| *key).collect();
+        keys.sort();
+        assert_eq!(keys, vec![3]);
+        shared.invalidate_all();
+        assert_eq!(cache.iter().count(), 0);
+        assert_eq!(cache.get(&3), None);
+    }
+}
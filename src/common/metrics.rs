@@ -0,0 +1,87 @@
+//! Counters recorded with the [metrics](https://docs.rs/metrics) facade when the `metrics` feature
+//! is on, and no-ops otherwise. The metrics of all hypercores of a process are added together.
+#![cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+
+use crate::Store;
+
+#[cfg(feature = "metrics")]
+fn store_label(store: &Store) -> &'static str {
+    match store {
+        Store::Tree => "tree",
+        Store::Data => "data",
+        Store::Bitfield => "bitfield",
+        Store::Oplog => "oplog",
+    }
+}
+
+/// Record a read of `bytes` bytes from a store
+pub(crate) fn storage_read(store: &Store, bytes: u64) {
+    #[cfg(feature = "metrics")]
+    {
+        let store = store_label(store);
+        ::metrics::counter!("hypercore_storage_reads_total", "store" => store).increment(1);
+        ::metrics::counter!("hypercore_storage_read_bytes_total", "store" => store)
+            .increment(bytes);
+    }
+}
+
+/// Record a write of `bytes` bytes to a store
+pub(crate) fn storage_write(store: &Store, bytes: u64) {
+    #[cfg(feature = "metrics")]
+    {
+        let store = store_label(store);
+        ::metrics::counter!("hypercore_storage_writes_total", "store" => store).increment(1);
+        ::metrics::counter!("hypercore_storage_written_bytes_total", "store" => store)
+            .increment(bytes);
+    }
+}
+
+/// Record a lookup in the given cache, returning the looked up value
+#[cfg(feature = "cache")]
+pub(crate) fn cache_lookup<T>(cache: &'static str, value: Option<T>) -> Option<T> {
+    #[cfg(feature = "metrics")]
+    if value.is_some() {
+        ::metrics::counter!("hypercore_cache_hits_total", "cache" => cache).increment(1);
+    } else {
+        ::metrics::counter!("hypercore_cache_misses_total", "cache" => cache).increment(1);
+    }
+    value
+}
+
+/// Record blocks appended to a hypercore
+pub(crate) fn appended(blocks: u64, bytes: u64) {
+    #[cfg(feature = "metrics")]
+    {
+        ::metrics::counter!("hypercore_appended_blocks_total").increment(blocks);
+        ::metrics::counter!("hypercore_appended_bytes_total").increment(bytes);
+    }
+}
+
+/// Record a proof created for a peer
+pub(crate) fn proof_created() {
+    #[cfg(feature = "metrics")]
+    ::metrics::counter!("hypercore_proofs_created_total").increment(1);
+}
+
+/// Record the verification of a proof from a peer, with the byte length of its block if any
+pub(crate) fn proof_verified(verified: bool, block_bytes: Option<u64>) {
+    #[cfg(feature = "metrics")]
+    {
+        if !verified {
+            ::metrics::counter!("hypercore_proof_verification_failures_total").increment(1);
+            return;
+        }
+        ::metrics::counter!("hypercore_proofs_verified_total").increment(1);
+        if let Some(bytes) = block_bytes {
+            ::metrics::counter!("hypercore_received_blocks_total").increment(1);
+            ::metrics::counter!("hypercore_received_bytes_total").increment(bytes);
+        }
+    }
+}
+
+/// Record the number of events queued for the subscribers of a hypercore
+#[cfg(feature = "replication")]
+pub(crate) fn event_queue_depth(depth: usize) {
+    #[cfg(feature = "metrics")]
+    ::metrics::histogram!("hypercore_event_queue_depth").record(depth as f64);
+}
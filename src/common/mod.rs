@@ -1,16 +1,34 @@
 #[cfg(feature = "cache")]
 pub(crate) mod cache;
 mod error;
+#[cfg(all(feature = "cache", any(target_arch = "wasm32", test)))]
+pub(crate) mod lru;
+pub(crate) mod metrics;
 mod node;
 mod peer;
+mod persistence;
+mod pool;
+mod rate_limiter;
 mod store;
+#[cfg(any(feature = "tokio", feature = "async-std"))]
+pub(crate) mod time;
 
-pub use self::error::HypercoreError;
+pub use self::error::{ErrorKind, HypercoreError};
 pub(crate) use self::node::NodeByteRange;
-pub(crate) use self::peer::ValuelessProof;
+pub use self::peer::ValuelessProof;
+pub use self::persistence::{PersistIf, PersistRanges, PersistencePolicy};
+pub(crate) use self::pool::BufferPool;
+pub use self::rate_limiter::RateLimiter;
+#[cfg(any(feature = "tokio", feature = "async-std"))]
+pub use self::rate_limiter::TokenBucket;
 pub use self::store::Store;
 pub(crate) use self::store::{StoreInfo, StoreInfoInstruction, StoreInfoType};
 
+/// Lowercase hex encoding of the given bytes
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub(crate) struct BitfieldUpdate {
     pub(crate) drop: bool,
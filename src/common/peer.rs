@@ -4,19 +4,65 @@
 
 use hypercore_schema::{DataBlock, DataHash, DataSeek, DataUpgrade, Proof};
 
+use crate::HypercoreError;
+
+/// Proof without the value of the requested data block. Allows forwarding proofs separately from
+/// the block data, e.g. by relays that don't store the values themselves. Create with
+/// [`crate::Hypercore::create_valueless_proof`] and turn into a verifiable [`Proof`] with
+/// [`ValuelessProof::attach_value`].
 #[derive(Debug, Clone, PartialEq)]
-/// Valueless proof generated from corresponding requests
-pub(crate) struct ValuelessProof {
-    pub(crate) fork: u64,
+pub struct ValuelessProof {
+    /// Fork of the core the proof was created from
+    pub fork: u64,
     /// Data block. NB: The ValuelessProof struct uses the Hash type because
     /// the stored binary value is processed externally to the proof.
-    pub(crate) block: Option<DataHash>,
-    pub(crate) hash: Option<DataHash>,
-    pub(crate) seek: Option<DataSeek>,
-    pub(crate) upgrade: Option<DataUpgrade>,
+    pub block: Option<DataHash>,
+    /// Data hash
+    pub hash: Option<DataHash>,
+    /// Data seek
+    pub seek: Option<DataSeek>,
+    /// Data upgrade
+    pub upgrade: Option<DataUpgrade>,
 }
 
 impl ValuelessProof {
+    /// Attach the value of the requested block, returning a full [`Proof`]. The value must be given
+    /// exactly when the proof contains a block. The value is verified only when the resulting proof
+    /// is verified.
+    pub fn attach_value(self, block_value: Option<Vec<u8>>) -> Result<Proof, HypercoreError> {
+        match (&self.block, &block_value) {
+            (Some(block), None) => Err(HypercoreError::BadArgument {
+                context: format!("Proof for block {} needs a value", block.index),
+            }),
+            (None, Some(_)) => Err(HypercoreError::BadArgument {
+                context: "Proof does not contain a block, value can not be attached".to_string(),
+            }),
+            _ => Ok(self.into_proof(block_value)),
+        }
+    }
+
+    /// Split a [`Proof`] into a valueless proof and the value of its data block, if any.
+    pub fn from_proof(proof: Proof) -> (Self, Option<Vec<u8>>) {
+        let (block, value) = match proof.block {
+            Some(DataBlock {
+                index,
+                value,
+                nodes,
+            }) => (Some(DataHash { index, nodes }), Some(value)),
+            None => (None, None),
+        };
+        (
+            Self {
+                fork: proof.fork,
+                block,
+                hash: proof.hash,
+                seek: proof.seek,
+                upgrade: proof.upgrade,
+            },
+            value,
+        )
+    }
+
     pub(crate) fn into_proof(mut self, block_value: Option<Vec<u8>>) -> Proof {
         let block = self.block.take().map(|block| DataBlock {
             index: block.index,
@@ -0,0 +1,35 @@
+use std::fmt::{self, Debug};
+use std::ops::Range;
+
+/// Decides which blocks of verified proofs a hypercore writes to its data store, see
+/// [`crate::HypercoreBuilder::persistence_policy`]. Blocks that aren't persisted are still
+/// verified into the tree, but aren't marked as stored.
+pub trait PersistencePolicy: Debug + Send + Sync {
+    /// True if the verified block at `index` should be written to the data store
+    fn persist(&self, index: u64, value: &[u8]) -> bool;
+}
+
+/// [`PersistencePolicy`] persisting the blocks whose index is in one of the ranges
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PersistRanges(pub Vec<Range<u64>>);
+
+impl PersistencePolicy for PersistRanges {
+    fn persist(&self, index: u64, _value: &[u8]) -> bool {
+        self.0.iter().any(|range| range.contains(&index))
+    }
+}
+
+/// [`PersistencePolicy`] persisting the blocks for which a function returns true
+pub struct PersistIf<F>(pub F);
+
+impl<F> Debug for PersistIf<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("PersistIf").finish_non_exhaustive()
+    }
+}
+
+impl<F: Fn(u64, &[u8]) -> bool + Send + Sync> PersistencePolicy for PersistIf<F> {
+    fn persist(&self, index: u64, value: &[u8]) -> bool {
+        (self.0)(index, value)
+    }
+}
@@ -0,0 +1,62 @@
+/// Number of buffers kept by a [`BufferPool`]
+const MAX_POOLED_BUFFERS: usize = 4;
+/// Largest capacity of a buffer kept by a [`BufferPool`], larger ones are freed
+const MAX_POOLED_CAPACITY: usize = 4 * 1024 * 1024;
+
+/// Pool of scratch buffers, reused across operations so that appends and message encoding
+/// don't allocate a fresh buffer each time under high throughput. It keeps a few buffers of up
+/// to a few megabytes, so a single large batch doesn't stay allocated.
+#[derive(Debug, Default)]
+pub(crate) struct BufferPool {
+    buffers: Vec<Vec<u8>>,
+}
+
+impl BufferPool {
+    /// Empty buffer with room for at least `capacity` bytes, reused from the pool if possible.
+    pub(crate) fn take(&mut self, capacity: usize) -> Vec<u8> {
+        let mut buffer = self.buffers.pop().unwrap_or_default();
+        buffer.reserve(capacity);
+        buffer
+    }
+
+    /// Zeroed buffer of `length` bytes, reused from the pool if possible.
+    pub(crate) fn take_zeroed(&mut self, length: usize) -> Vec<u8> {
+        let mut buffer = self.take(length);
+        buffer.resize(length, 0);
+        buffer
+    }
+
+    /// Give back a buffer taken with [`BufferPool::take`].
+    pub(crate) fn put(&mut self, mut buffer: Vec<u8>) {
+        if self.buffers.len() < MAX_POOLED_BUFFERS && buffer.capacity() <= MAX_POOLED_CAPACITY {
+            buffer.clear();
+            self.buffers.push(buffer);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buffer_pool_reuses_buffers() {
+        let mut pool = BufferPool::default();
+        let mut buffer = pool.take(100);
+        buffer.extend_from_slice(b"hello");
+        let pointer = buffer.as_ptr();
+        pool.put(buffer);
+
+        let buffer = pool.take_zeroed(50);
+        assert_eq!(buffer.as_ptr(), pointer);
+        assert_eq!(buffer, vec![0; 50]);
+        pool.put(buffer);
+
+        pool.put(Vec::with_capacity(MAX_POOLED_CAPACITY + 1));
+        assert_eq!(pool.buffers.len(), 1);
+        for _ in 0..MAX_POOLED_BUFFERS + 1 {
+            pool.put(vec![]);
+        }
+        assert_eq!(pool.buffers.len(), MAX_POOLED_BUFFERS);
+    }
+}
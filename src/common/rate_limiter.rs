@@ -0,0 +1,121 @@
+use futures::future::BoxFuture;
+use std::fmt::Debug;
+#[cfg(all(
+    any(feature = "tokio", feature = "async-std"),
+    not(target_arch = "wasm32")
+))]
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Limits the rate of block data sent to or received from a remote peer, see
+/// `Peer::with_upload_rate_limiter` and `Peer::with_download_rate_limiter` of the `replication`
+/// module.
+pub trait RateLimiter: Debug + Send + Sync {
+    /// Wait until `bytes` of block data may be transferred
+    fn acquire(&self, bytes: u64) -> BoxFuture<'_, ()>;
+}
+
+/// Token bucket [`RateLimiter`], allowing bursts of up to `burst` bytes and on average
+/// `bytes_per_second` bytes. A transfer bigger than the available tokens waits until the bucket
+/// has refilled enough to cover it.
+///
+/// On wasm32, where there is no clock or timer of the runtime, it doesn't limit anything.
+#[cfg(any(feature = "tokio", feature = "async-std"))]
+#[derive(Debug)]
+pub struct TokenBucket {
+    #[cfg(not(target_arch = "wasm32"))]
+    bytes_per_second: f64,
+    #[cfg(not(target_arch = "wasm32"))]
+    burst: f64,
+    #[cfg(not(target_arch = "wasm32"))]
+    state: Mutex<TokenBucketState>,
+}
+
+#[cfg(all(
+    any(feature = "tokio", feature = "async-std"),
+    not(target_arch = "wasm32")
+))]
+#[derive(Debug)]
+struct TokenBucketState {
+    tokens: f64,
+    updated: Instant,
+}
+
+#[cfg(any(feature = "tokio", feature = "async-std"))]
+impl TokenBucket {
+    /// Create a full token bucket.
+    ///
+    /// # Panics
+    ///
+    /// If `bytes_per_second` is zero.
+    pub fn new(bytes_per_second: u64, burst: u64) -> Self {
+        assert!(bytes_per_second > 0, "bytes_per_second must be positive");
+        #[cfg(target_arch = "wasm32")]
+        let _ = burst;
+        Self {
+            #[cfg(not(target_arch = "wasm32"))]
+            bytes_per_second: bytes_per_second as f64,
+            #[cfg(not(target_arch = "wasm32"))]
+            burst: burst as f64,
+            #[cfg(not(target_arch = "wasm32"))]
+            state: Mutex::new(TokenBucketState {
+                tokens: burst as f64,
+                updated: Instant::now(),
+            }),
+        }
+    }
+
+    /// Take `bytes` tokens, returning how long to wait for the bucket to cover them
+    #[cfg(not(target_arch = "wasm32"))]
+    fn take(&self, bytes: u64) -> Duration {
+        let mut state = self.state.lock().expect("TokenBucket state poisoned");
+        let now = Instant::now();
+        let refill = now.duration_since(state.updated).as_secs_f64() * self.bytes_per_second;
+        state.tokens = (state.tokens + refill).min(self.burst) - bytes as f64;
+        state.updated = now;
+        if state.tokens < 0.0 {
+            Duration::from_secs_f64(-state.tokens / self.bytes_per_second)
+        } else {
+            Duration::ZERO
+        }
+    }
+}
+
+#[cfg(any(feature = "tokio", feature = "async-std"))]
+impl RateLimiter for TokenBucket {
+    #[cfg(target_arch = "wasm32")]
+    fn acquire(&self, _bytes: u64) -> BoxFuture<'_, ()> {
+        Box::pin(async {})
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn acquire(&self, bytes: u64) -> BoxFuture<'_, ()> {
+        let wait = self.take(bytes);
+        Box::pin(async move {
+            if !wait.is_zero() {
+                crate::common::time::sleep(wait).await;
+            }
+        })
+    }
+}
+
+#[cfg(all(
+    test,
+    any(feature = "tokio", feature = "async-std"),
+    not(target_arch = "wasm32")
+))]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn token_bucket() {
+        let bucket = TokenBucket::new(1000, 100);
+        let start = Instant::now();
+        bucket.acquire(100).await;
+        assert!(start.elapsed() < Duration::from_millis(50));
+        bucket.acquire(100).await;
+        assert!(start.elapsed() >= Duration::from_millis(90));
+    }
+}
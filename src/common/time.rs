@@ -0,0 +1,19 @@
+//! Timers and tasks of the async runtime selected with the `tokio` or `async-std` feature.
+use std::time::Duration;
+
+/// Wait for `duration` to pass
+pub(crate) async fn sleep(duration: Duration) {
+    #[cfg(feature = "tokio")]
+    tokio::time::sleep(duration).await;
+    #[cfg(all(feature = "async-std", not(feature = "tokio")))]
+    async_std::task::sleep(duration).await;
+}
+
+/// Run `future` in the background
+#[cfg(feature = "shared-core")]
+pub(crate) fn spawn(future: impl std::future::Future<Output = ()> + Send + 'static) {
+    #[cfg(feature = "tokio")]
+    tokio::spawn(future);
+    #[cfg(all(feature = "async-std", not(feature = "tokio")))]
+    async_std::task::spawn(future);
+}
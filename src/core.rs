@@ -1,30 +1,62 @@
 //! Hypercore's main abstraction. Exposes an append-only, secure log structure.
-use ed25519_dalek::Signature;
+use ed25519_dalek::{Signature, VerifyingKey};
+use futures::TryStreamExt;
 use futures::future::Either;
+use futures::io::AsyncWriteExt;
+use std::collections::{HashSet, VecDeque};
 use std::convert::TryFrom;
 use std::fmt::Debug;
+use std::ops::Range;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+#[cfg(feature = "tracing")]
 use tracing::instrument;
 
 #[cfg(feature = "cache")]
-use crate::common::cache::CacheOptions;
+use crate::common::cache::{Cache, CacheOptions, ProofCacheKey};
 use crate::{
+    archive::{self, ArchiveHeader, BackupHeader, Record},
     bitfield::Bitfield,
-    common::{BitfieldUpdate, HypercoreError, NodeByteRange, StoreInfo, ValuelessProof},
-    crypto::{PartialKeypair, generate_signing_key},
-    data::BlockStore,
-    oplog::{Header, MAX_OPLOG_ENTRIES_BYTE_SIZE, Oplog},
-    storage::Storage,
-    tree::{MerkleTree, MerkleTreeChangeset},
+    common::{
+        BitfieldUpdate, HypercoreError, NodeByteRange, PersistencePolicy, Store, StoreInfo,
+        StoreInfoInstruction, ValuelessProof, metrics, to_hex,
+    },
+    crypto::{
+        KeyRng, Manifest, PartialKeypair, generate_signing_key, generate_signing_key_with,
+        signable_tree, verify,
+    },
+    data::{BlockStore, DedupIndex},
+    dump::{HeaderDump, NodeDump, OplogEntryDump, StateDump},
+    oplog::{Header, MAX_OPLOG_ENTRIES_BYTE_SIZE, Oplog, OplogCommit, OplogWrite},
+    storage::{Storage, StorageTraits},
+    tree::{ByteIndex, MerkleTree, MerkleTreeChangeset, NODE_SIZE, seek_position},
 };
 
-use hypercore_schema::{Proof, RequestBlock, RequestSeek, RequestUpgrade};
+use hypercore_schema::{DataUpgrade, Hash, Node, Proof, RequestBlock, RequestSeek, RequestUpgrade};
 
 #[derive(Debug)]
 pub(crate) struct HypercoreOptions {
     pub(crate) key_pair: Option<PartialKeypair>,
     pub(crate) open: bool,
+    pub(crate) overwrite: bool,
+    pub(crate) manifest: Option<Manifest>,
+    pub(crate) checkpoint: Option<Checkpoint>,
+    pub(crate) light: bool,
     #[cfg(feature = "cache")]
     pub(crate) node_cache_options: Option<CacheOptions>,
+    #[cfg(feature = "cache")]
+    pub(crate) block_cache_options: Option<CacheOptions>,
+    #[cfg(feature = "cache")]
+    pub(crate) proof_cache_options: Option<CacheOptions>,
+    #[cfg(feature = "replication")]
+    pub(crate) event_queue_capacity: usize,
+    pub(crate) persistence_policy: Option<Arc<dyn PersistencePolicy>>,
+    pub(crate) retention: Option<Retention>,
+    pub(crate) quota: Option<Quota>,
+    pub(crate) deduplicate: bool,
+    pub(crate) byte_index: bool,
+    pub(crate) pending_proofs_capacity: usize,
+    pub(crate) rng: Option<Box<dyn KeyRng>>,
 }
 
 impl HypercoreOptions {
@@ -32,38 +64,361 @@ impl HypercoreOptions {
         Self {
             key_pair: None,
             open: false,
+            overwrite: false,
+            manifest: None,
+            checkpoint: None,
+            light: false,
             #[cfg(feature = "cache")]
             node_cache_options: None,
+            #[cfg(feature = "cache")]
+            block_cache_options: None,
+            #[cfg(feature = "cache")]
+            proof_cache_options: None,
+            #[cfg(feature = "replication")]
+            event_queue_capacity: crate::replication::events::DEFAULT_EVENT_QUEUE_CAPACITY,
+            persistence_policy: None,
+            retention: None,
+            quota: None,
+            deduplicate: false,
+            byte_index: false,
+            pending_proofs_capacity: DEFAULT_PENDING_PROOFS_CAPACITY,
+            rng: None,
         }
     }
 }
 
+/// Default number of proofs buffered until the upgrade they need is applied
+const DEFAULT_PENDING_PROOFS_CAPACITY: usize = 32;
+
+/// Number of the last oplog entries in [`Hypercore::dump_state`]
+const DUMP_OPLOG_ENTRIES: usize = 16;
+
+/// Number of bytes read at once by [`Hypercore::get_stream`]
+const STREAM_CHUNK_SIZE: u64 = 64 * 1024;
+
+/// Memory budget of the proofs created ahead by [`Hypercore::export`] and
+/// [`Hypercore::export_since`]
+const EXPORT_MEMORY_BUDGET: u64 = 4 * 1024 * 1024;
+
+/// Value of the header user data marking a hypercore whose blocks are deduplicated
+const DEDUPLICATE_USER_DATA: &str = "hypercore-rs:deduplicate";
+
 /// Hypercore is an append-only log structure.
+///
+/// `T` is the type of the stores of its [`Storage`], boxed [`StorageTraits`] objects by default.
+///
+/// Its async methods are cancel safe: when the future of an operation is dropped before it
+/// completes, the hypercore is left as if the operation either didn't happen or completed. Like
+/// after a crash, an append or proof dropped after its oplog entry was written is applied.
 #[derive(Debug)]
-pub struct Hypercore {
+pub struct Hypercore<T: ?Sized = dyn StorageTraits + Send> {
     pub(crate) key_pair: PartialKeypair,
-    pub(crate) storage: Storage,
+    /// Hex discovery key, recorded in the tracing spans of the hypercore
+    #[cfg(feature = "tracing")]
+    discovery_key: String,
+    pub(crate) storage: Storage<T>,
     pub(crate) oplog: Oplog,
     pub(crate) tree: MerkleTree,
     pub(crate) block_store: BlockStore,
     pub(crate) bitfield: Bitfield,
+    /// Options of the node cache of the tree, kept to reopen it in [`Hypercore::recover`]
+    #[cfg(feature = "cache")]
+    node_cache_options: Option<CacheOptions>,
     skip_flush_count: u8, // autoFlush in Javascript
     header: Header,
+    light: bool,
+    #[cfg(feature = "cache")]
+    block_cache: Option<Cache<u64, Vec<u8>>>,
+    #[cfg(feature = "cache")]
+    proof_cache: Option<Cache<ProofCacheKey, ValuelessProof>>,
+    stats: StatsCounters,
+    persistence_policy: Option<Arc<dyn PersistencePolicy>>,
+    retention: Option<Retention>,
+    quota: Option<Quota>,
+    /// Stored blocks by hash, when deduplicating
+    dedup: Option<DedupIndex>,
+    /// Byte ranges of the first blocks, when indexing them
+    byte_index: Option<ByteIndex>,
+    /// Equivocation of the writer detected since opening
+    equivocation: Option<Equivocation>,
+    /// Proofs received before the upgrade they need, oldest first
+    pending_proofs: VecDeque<Proof>,
+    pending_proofs_capacity: usize,
+    /// Writes of the proofs applied so far by [`Hypercore::verify_and_apply_proofs`], flushed
+    /// once all of them are applied
+    deferred_infos: Option<Vec<StoreInfo>>,
     #[cfg(feature = "replication")]
     events: crate::replication::events::Events,
+    #[cfg(feature = "replication")]
+    wants: Vec<(Range<u64>, crate::replication::Priority)>,
+    /// Range wanted by the latest [`Hypercore::prefetch`]
+    #[cfg(feature = "replication")]
+    prefetched: Option<Range<u64>>,
 }
 
 /// Response from append, matches that of the Javascript result
 #[derive(Debug, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde")
+)]
 pub struct AppendOutcome {
     /// Length of the hypercore after append
     pub length: u64,
     /// Byte length of the hypercore after append
     pub byte_length: u64,
+    /// Index range of the appended blocks
+    pub blocks: Range<u64>,
+    /// Byte offset of each appended block, where it's stored in the data store unless the
+    /// hypercore is light or deduplicated
+    pub byte_offsets: Vec<u64>,
+    /// Fork of the hypercore
+    pub fork: u64,
+    /// Signature of the tree after append, empty if the hypercore has no blocks
+    pub signature: Vec<u8>,
+}
+
+/// Outcome of [`Hypercore::apply_backup`] and [`Hypercore::restore`]
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct RestoreOutcome {
+    /// Number of blocks added to the hypercore
+    pub added: u64,
+    /// Number of blocks of the archive the hypercore already had
+    pub skipped: u64,
+}
+
+/// Rolling retention of a hypercore, see [`crate::HypercoreBuilder::retention`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Retention {
+    /// Keep the last given number of blocks
+    Blocks(u64),
+    /// Keep the most recent blocks whose byte length adds up to at most the given budget
+    Bytes(u64),
+}
+
+/// Size quota of a hypercore, see [`crate::HypercoreBuilder::quota`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde")
+)]
+pub struct Quota {
+    /// Maximum length of the hypercore, in blocks
+    pub max_length: Option<u64>,
+    /// Maximum byte length of the hypercore
+    pub max_byte_length: Option<u64>,
+    /// Percentage of a maximum from which a `QuotaWarning` event is emitted, 90 by default
+    pub warning_percent: u8,
+}
+
+impl Quota {
+    /// Quota of at most the given number of blocks and bytes
+    pub fn new(max_length: Option<u64>, max_byte_length: Option<u64>) -> Self {
+        Self {
+            max_length,
+            max_byte_length,
+            warning_percent: 90,
+        }
+    }
+
+    /// Set the percentage of a maximum from which a warning is emitted
+    pub fn warning_percent(mut self, warning_percent: u8) -> Self {
+        self.warning_percent = warning_percent;
+        self
+    }
+
+    /// True if the given length and byte length fit in the quota
+    pub fn allows(&self, length: u64, byte_length: u64) -> bool {
+        self.max_length.is_none_or(|max| length <= max)
+            && self.max_byte_length.is_none_or(|max| byte_length <= max)
+    }
+
+    /// True if the given length or byte length reach the warning percentage of their maximum
+    pub fn warns(&self, length: u64, byte_length: u64) -> bool {
+        let reaches = |value: u64, max: u64| {
+            u128::from(value) * 100 >= u128::from(max) * u128::from(self.warning_percent)
+        };
+        self.max_length.is_some_and(|max| reaches(length, max))
+            || self
+                .max_byte_length
+                .is_some_and(|max| reaches(byte_length, max))
+    }
+}
+
+/// Outcome of building a hypercore
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BuildOutcome {
+    /// True if a new hypercore was created, false if an existing one was opened
+    pub created: bool,
+    /// True if entries that were not yet flushed to the tree and bitfield were replayed from
+    /// the oplog, e.g. after the process exited before a flush
+    pub recovered: bool,
+}
+
+/// State of a hypercore read from its storage, see [`Hypercore::open_state`]
+struct OpenedState {
+    oplog: Oplog,
+    header: Header,
+    tree: MerkleTree,
+    bitfield: Bitfield,
+    outcome: BuildOutcome,
+}
+
+/// Trusted state of a hypercore's tree at a given length: the roots of the tree and the signature
+/// over them. Create with [`Hypercore::checkpoint`] and use with
+/// [`crate::HypercoreBuilder::checkpoint`] to start a new core from it.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde")
+)]
+pub struct Checkpoint {
+    /// Fork of the hypercore
+    pub fork: u64,
+    /// Length of the hypercore
+    pub length: u64,
+    /// Root nodes of the tree at `length`
+    #[cfg_attr(feature = "serde", serde(with = "crate::encoding::serde::nodes"))]
+    pub roots: Vec<Node>,
+    /// Signature of the tree at `length`
+    pub signature: Vec<u8>,
+}
+
+impl Checkpoint {
+    /// Verify the signature of the roots with the given public key, returning the hash of the tree
+    fn verify(&self, public_key: &VerifyingKey) -> Result<Box<[u8]>, HypercoreError> {
+        let hash: Box<[u8]> = Hash::tree(&self.roots).as_bytes().into();
+        let signature = Signature::try_from(&*self.signature).map_err(|_| {
+            HypercoreError::InvalidSignature {
+                context: "Could not parse checkpoint signature".to_string(),
+            }
+        })?;
+        verify(
+            public_key,
+            &signable_tree(&hash, self.length, self.fork),
+            Some(&signature),
+        )?;
+        Ok(hash)
+    }
+}
+
+/// Proof that the writer of a hypercore equivocated: it signed two conflicting trees on the same
+/// fork, of the same length, or of different lengths where the longer one doesn't extend the
+/// shorter one. Emitted as an event with the `replication` feature when a peer sends a proof
+/// conflicting with the local tree, see [`Hypercore::equivocation`]. Anyone with the public key of
+/// the hypercore can check it with [`Equivocation::verify`].
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde")
+)]
+pub struct Equivocation {
+    /// Signed head of the local tree
+    pub local: Checkpoint,
+    /// Conflicting signed head received from a peer
+    pub remote: Checkpoint,
+    /// For heads of different lengths, the nodes of the longer tree at the roots of the shorter
+    /// one, with their siblings up to the roots of the longer tree
+    #[cfg_attr(feature = "serde", serde(with = "crate::encoding::serde::nodes"))]
+    pub nodes: Vec<Node>,
+}
+
+impl Equivocation {
+    /// Verify that both heads are signed with the given public key, for the same fork but
+    /// conflicting trees
+    pub fn verify(&self, public_key: &VerifyingKey) -> Result<(), HypercoreError> {
+        if self.local.fork != self.remote.fork {
+            return Err(HypercoreError::BadArgument {
+                context: "Equivocating heads must have the same fork".to_string(),
+            });
+        }
+        let (shorter, longer) = if self.local.length <= self.remote.length {
+            (&self.local, &self.remote)
+        } else {
+            (&self.remote, &self.local)
+        };
+        let shorter_hash = shorter.verify(public_key)?;
+        let longer_hash = longer.verify(public_key)?;
+        let conflicting = if shorter.length == longer.length {
+            shorter_hash != longer_hash
+        } else {
+            let roots = roots_within(shorter.length, &longer.roots, &self.nodes)?;
+            *Hash::tree(&roots).as_bytes() != *shorter_hash
+        };
+        if !conflicting {
+            return Err(HypercoreError::BadArgument {
+                context: "Equivocating heads must sign conflicting trees".to_string(),
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Indexes of the nodes needed to hash the roots of the tree at `length` up to the roots of a tree
+/// at `longer_length`: the roots of `length` and their siblings up to the longer roots
+fn root_path_indexes(length: u64, longer_length: u64) -> Vec<u64> {
+    let mut longer_roots = vec![];
+    flat_tree::full_roots(2 * longer_length, &mut longer_roots);
+    let mut indexes = vec![];
+    flat_tree::full_roots(2 * length, &mut indexes);
+    for root in indexes.clone() {
+        let mut index = root;
+        while !longer_roots.contains(&index) {
+            indexes.push(flat_tree::sibling(index));
+            index = flat_tree::parent(index);
+        }
+    }
+    indexes
+}
+
+/// The nodes at the roots of the tree at `length`, taken from `nodes` of a longer tree and
+/// checked to hash up to its `roots`
+fn roots_within(length: u64, roots: &[Node], nodes: &[Node]) -> Result<Vec<Node>, HypercoreError> {
+    let node = |index: u64| {
+        nodes
+            .iter()
+            .find(|node| node.index == index)
+            .ok_or_else(|| HypercoreError::BadArgument {
+                context: format!("Missing node {index} of the longer tree"),
+            })
+    };
+    let mut indexes = vec![];
+    flat_tree::full_roots(2 * length, &mut indexes);
+    let mut within = vec![];
+    for index in indexes {
+        let mut current = node(index)?.clone();
+        within.push(current.clone());
+        loop {
+            if let Some(root) = roots.iter().find(|root| root.index == current.index) {
+                if root.hash != current.hash || root.length != current.length {
+                    return Err(HypercoreError::InvalidChecksum {
+                        context: format!("Nodes don't hash up to root {}", root.index),
+                    });
+                }
+                break;
+            }
+            let sibling = node(flat_tree::sibling(current.index))?;
+            current = Node::new(
+                flat_tree::parent(current.index),
+                Hash::parent(&current, sibling).as_bytes().to_vec(),
+                current.length + sibling.length,
+            );
+        }
+    }
+    Ok(within)
 }
 
 /// Info about the hypercore
 #[derive(Debug, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde")
+)]
 pub struct Info {
     /// Length of the hypercore
     pub length: u64,
@@ -78,12 +433,121 @@ pub struct Info {
     pub writeable: bool,
 }
 
-impl Hypercore {
+/// Detailed state of a hypercore, see [`Hypercore::detailed_info`]
+#[derive(Debug, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde")
+)]
+pub struct DetailedInfo {
+    /// Info about the hypercore, with its contiguous length
+    pub info: Info,
+    /// Number of blocks stored locally
+    pub downloaded_blocks: u64,
+    /// Number of node slots in the tree store, including blank ones of sparse trees. Nodes not
+    /// flushed yet are not counted.
+    pub tree_nodes: u64,
+    /// Number of entries in the oplog, which are not flushed to the tree and bitfield yet
+    pub oplog_entries: u64,
+    /// Bytes used by each store
+    pub store_bytes: StoreBytes,
+}
+
+/// Bytes used by each store of a hypercore, i.e. the length of their storage
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde")
+)]
+pub struct StoreBytes {
+    /// Bytes of the tree store
+    pub tree: u64,
+    /// Bytes of the data store
+    pub data: u64,
+    /// Bytes of the bitfield store
+    pub bitfield: u64,
+    /// Bytes of the oplog store
+    pub oplog: u64,
+}
+
+/// Statistics of a hypercore since it was opened, see [`Hypercore::stats`]
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde")
+)]
+pub struct Stats {
+    /// Number of blocks appended
+    pub blocks_appended: u64,
+    /// Number of bytes appended
+    pub bytes_appended: u64,
+    /// Number of proofs created for peers
+    pub proofs_created: u64,
+    /// Number of proofs from peers that were verified and applied
+    pub proofs_verified: u64,
+    /// Number of blocks received in verified proofs
+    pub blocks_received: u64,
+    /// Number of bytes received in verified proofs
+    pub bytes_received: u64,
+    /// Number of proofs from peers that failed verification
+    pub verification_failures: u64,
+}
+
+#[derive(Debug, Default)]
+struct StatsCounters {
+    blocks_appended: AtomicU64,
+    bytes_appended: AtomicU64,
+    proofs_created: AtomicU64,
+    proofs_verified: AtomicU64,
+    blocks_received: AtomicU64,
+    bytes_received: AtomicU64,
+    verification_failures: AtomicU64,
+}
+
+impl StatsCounters {
+    fn add(counter: &AtomicU64, value: u64) {
+        counter.fetch_add(value, Ordering::Relaxed);
+    }
+
+    fn to_stats(&self) -> Stats {
+        Stats {
+            blocks_appended: self.blocks_appended.load(Ordering::Relaxed),
+            bytes_appended: self.bytes_appended.load(Ordering::Relaxed),
+            proofs_created: self.proofs_created.load(Ordering::Relaxed),
+            proofs_verified: self.proofs_verified.load(Ordering::Relaxed),
+            blocks_received: self.blocks_received.load(Ordering::Relaxed),
+            bytes_received: self.bytes_received.load(Ordering::Relaxed),
+            verification_failures: self.verification_failures.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl<T: StorageTraits + Send + ?Sized> Hypercore<T> {
     /// Creates/opens new hypercore using given storage and options
     pub(crate) async fn new(
-        mut storage: Storage,
+        storage: Storage<T>,
+        options: HypercoreOptions,
+    ) -> Result<Self, HypercoreError> {
+        // Opening reads and replays the whole oplog, box its large future off the stack
+        Ok(Box::pin(Self::new_with_outcome(storage, options)).await?.0)
+    }
+
+    /// Creates/opens new hypercore using given storage and options, returning also whether the
+    /// hypercore was created or opened
+    pub(crate) async fn new_with_outcome(
+        mut storage: Storage<T>,
         mut options: HypercoreOptions,
-    ) -> Result<Hypercore, HypercoreError> {
+    ) -> Result<(Self, BuildOutcome), HypercoreError> {
+        #[cfg(feature = "replication")]
+        if options.event_queue_capacity == 0 {
+            return Err(HypercoreError::BadArgument {
+                context: "Event queue capacity must be greater than zero".to_string(),
+            });
+        }
+        let requested_key_pair = options.key_pair.clone();
         let key_pair: Option<PartialKeypair> = if options.open {
             if options.key_pair.is_some() {
                 return Err(HypercoreError::BadArgument {
@@ -91,10 +555,19 @@ impl Hypercore {
                         .to_string(),
                 });
             }
+            if options.overwrite {
+                return Err(HypercoreError::BadArgument {
+                    context: "Overwrite can not be used when building an openable hypercore"
+                        .to_string(),
+                });
+            }
             None
         } else {
             Some(options.key_pair.take().unwrap_or_else(|| {
-                let signing_key = generate_signing_key();
+                let signing_key = match options.rng.as_mut() {
+                    Some(rng) => generate_signing_key_with(rng.as_mut()),
+                    None => generate_signing_key(),
+                };
                 PartialKeypair {
                     public: signing_key.verifying_key(),
                     secret: Some(signing_key),
@@ -102,12 +575,116 @@ impl Hypercore {
             }))
         };
 
+        if let (Some(manifest), Some(key_pair)) = (&options.manifest, &key_pair) {
+            manifest.validate(&key_pair.public)?;
+        }
+
+        if options.overwrite {
+            storage.truncate_all().await?;
+        }
+
+        let OpenedState {
+            oplog,
+            header,
+            tree,
+            bitfield,
+            outcome,
+        } = Self::open_state(
+            &mut storage,
+            &key_pair,
+            &options.manifest,
+            #[cfg(feature = "cache")]
+            &options.node_cache_options,
+        )
+        .await?;
+        let block_store = BlockStore::default();
+        let key_pair = match requested_key_pair {
+            Some(requested) if requested.public != header.key_pair.public => {
+                return Err(HypercoreError::KeyMismatch {
+                    context: "Given public key does not match the public key of the stored \
+                        hypercore, use overwrite to replace it"
+                        .to_string(),
+                });
+            }
+            // Only the verifying key was given, keep the core read-only
+            Some(requested) if requested.secret.is_none() => requested,
+            _ => header.key_pair.clone(),
+        };
+        if let Some(manifest) = &options.manifest
+            && *manifest != header.manifest
+        {
+            return Err(HypercoreError::BadArgument {
+                context: "Given manifest does not match the manifest of the stored hypercore"
+                    .to_string(),
+            });
+        }
+
+        let mut hypercore = Hypercore {
+            #[cfg(feature = "tracing")]
+            discovery_key: to_hex(&crate::crypto::discovery_key(&key_pair.public)),
+            key_pair,
+            storage,
+            oplog,
+            tree,
+            block_store,
+            bitfield,
+            #[cfg(feature = "cache")]
+            node_cache_options: options.node_cache_options.take(),
+            header,
+            skip_flush_count: 0,
+            light: options.light,
+            #[cfg(feature = "cache")]
+            block_cache: options
+                .block_cache_options
+                .as_ref()
+                .map(|options| options.to_block_cache()),
+            #[cfg(feature = "cache")]
+            proof_cache: options
+                .proof_cache_options
+                .as_ref()
+                .map(|options| options.to_proof_cache()),
+            stats: StatsCounters::default(),
+            persistence_policy: options.persistence_policy.take(),
+            retention: options.retention,
+            quota: options.quota,
+            dedup: None,
+            byte_index: None,
+            equivocation: None,
+            pending_proofs: VecDeque::new(),
+            pending_proofs_capacity: options.pending_proofs_capacity,
+            deferred_infos: None,
+            #[cfg(feature = "replication")]
+            events: crate::replication::events::Events::new(options.event_queue_capacity),
+            #[cfg(feature = "replication")]
+            wants: vec![],
+            #[cfg(feature = "replication")]
+            prefetched: None,
+        };
+        hypercore.open_dedup_index(options.deduplicate).await?;
+        if let Some(checkpoint) = options.checkpoint {
+            hypercore.restore_checkpoint(checkpoint).await?;
+        }
+        if options.byte_index {
+            hypercore.open_byte_index().await?;
+        }
+        Ok((hypercore, outcome))
+    }
+
+    /// Read the oplog, tree and bitfield from storage, creating the oplog if the storage is empty
+    /// and a key pair is given, and replay the oplog entries not yet flushed to the tree and
+    /// bitfield
+    async fn open_state(
+        storage: &mut Storage<T>,
+        key_pair: &Option<PartialKeypair>,
+        manifest: &Option<Manifest>,
+        #[cfg(feature = "cache")] node_cache_options: &Option<CacheOptions>,
+    ) -> Result<OpenedState, HypercoreError> {
         // Open/create oplog
-        let mut oplog_open_outcome = match Oplog::open(&key_pair, None)? {
+        let mut oplog_open_outcome = match Oplog::open(key_pair, manifest, None)? {
             Either::Right(value) => value,
             Either::Left(instruction) => {
                 let info = storage.read_info(instruction).await?;
-                match Oplog::open(&key_pair, Some(info))? {
+                match Oplog::open(key_pair, manifest, Some(info))? {
                     Either::Right(value) => value,
                     Either::Left(_) => {
                         return Err(HypercoreError::InvalidOperation {
@@ -126,7 +703,7 @@ impl Hypercore {
             &oplog_open_outcome.header.tree,
             None,
             #[cfg(feature = "cache")]
-            &options.node_cache_options,
+            node_cache_options,
         )? {
             Either::Right(value) => value,
             Either::Left(instructions) => {
@@ -135,7 +712,7 @@ impl Hypercore {
                     &oplog_open_outcome.header.tree,
                     Some(&infos),
                     #[cfg(feature = "cache")]
-                    &options.node_cache_options,
+                    node_cache_options,
                 )? {
                     Either::Right(value) => value,
                     Either::Left(_) => {
@@ -147,9 +724,6 @@ impl Hypercore {
             }
         };
 
-        // Create block store instance
-        let block_store = BlockStore::default();
-
         // Open bitfield
         let mut bitfield = match Bitfield::open(None) {
             Either::Right(value) => value,
@@ -172,6 +746,14 @@ impl Hypercore {
             }
         };
 
+        let outcome = BuildOutcome {
+            created: oplog_open_outcome.created,
+            recovered: oplog_open_outcome
+                .entries
+                .as_ref()
+                .is_some_and(|entries| !entries.is_empty()),
+        };
+
         // Process entries stored only to the oplog and not yet flushed into bitfield or tree
         if let Some(entries) = oplog_open_outcome.entries {
             for entry in entries.iter() {
@@ -237,24 +819,40 @@ impl Hypercore {
             }
         }
 
-        let oplog = oplog_open_outcome.oplog;
-        let header = oplog_open_outcome.header;
-        let key_pair = header.key_pair.clone();
-
-        Ok(Hypercore {
-            key_pair,
-            storage,
-            oplog,
+        Ok(OpenedState {
+            oplog: oplog_open_outcome.oplog,
+            header: oplog_open_outcome.header,
             tree,
-            block_store,
             bitfield,
-            header,
-            skip_flush_count: 0,
-            #[cfg(feature = "replication")]
-            events: crate::replication::events::Events::new(),
+            outcome,
         })
     }
 
+    /// Current trusted state of the tree that another core can start from. None if the hypercore is
+    /// empty.
+    pub fn checkpoint(&self) -> Option<Checkpoint> {
+        let signature = self.tree.signature?;
+        Some(Checkpoint {
+            fork: self.tree.fork,
+            length: self.tree.length,
+            roots: self.tree.roots.clone(),
+            signature: signature.to_bytes().to_vec(),
+        })
+    }
+
+    /// Equivocation of the writer detected by [`Hypercore::verify_and_apply_proof`] since opening,
+    /// when a peer sent a proof of a tree signed for the fork of the local tree but conflicting
+    /// with it. Upgrades on the fork of the equivocation are then refused with
+    /// [`HypercoreError::Equivocation`].
+    pub fn equivocation(&self) -> Option<&Equivocation> {
+        self.equivocation.as_ref()
+    }
+
+    /// Manifest of the hypercore
+    pub fn manifest(&self) -> &Manifest {
+        &self.header.manifest
+    }
+
     /// Gets basic info about the Hypercore
     pub fn info(&self) -> Info {
         Info {
@@ -266,14 +864,175 @@ impl Hypercore {
         }
     }
 
+    /// Get detailed info about the hypercore, to tell how synced it is: the number of stored
+    /// blocks, tree nodes and oplog entries, and the bytes used by each store, which are read
+    /// from storage.
+    #[cfg_attr(
+        feature = "tracing",
+        instrument(err, skip_all, fields(discovery_key = %self.discovery_key))
+    )]
+    pub async fn detailed_info(&self) -> Result<DetailedInfo, HypercoreError> {
+        let infos = self
+            .storage
+            .read_infos_to_vec(&[
+                StoreInfoInstruction::new_size(Store::Tree, 0),
+                StoreInfoInstruction::new_size(Store::Data, 0),
+                StoreInfoInstruction::new_size(Store::Bitfield, 0),
+                StoreInfoInstruction::new_size(Store::Oplog, 0),
+            ])
+            .await?;
+        let [tree, data, bitfield, oplog] =
+            [0, 1, 2, 3].map(|i| infos[i].length.expect("Size info has a length"));
+        Ok(DetailedInfo {
+            info: self.info(),
+            downloaded_blocks: self
+                .present_ranges(0..self.tree.length)
+                .iter()
+                .map(|range| range.end - range.start)
+                .sum(),
+            tree_nodes: tree / NODE_SIZE,
+            oplog_entries: self.oplog.entries_length,
+            store_bytes: StoreBytes {
+                tree,
+                data,
+                bitfield,
+                oplog,
+            },
+        })
+    }
+
+    /// Dump a summary of the internal state of the hypercore: the tree and its roots, the oplog
+    /// header, the ranges of stored blocks and the last oplog entries, which are read from
+    /// storage. Meant for bug reports about mismatched state, see [`StateDump`].
+    #[cfg_attr(
+        feature = "tracing",
+        instrument(err, skip_all, fields(discovery_key = %self.discovery_key))
+    )]
+    pub async fn dump_state(&self) -> Result<StateDump, HypercoreError> {
+        let oplog = self
+            .storage
+            .read_info(StoreInfoInstruction::new_all_content(Store::Oplog))
+            .await?;
+        let (entries, _) = Oplog::entries(
+            oplog.data.as_deref().unwrap_or_default(),
+            self.oplog.get_current_header_bit(),
+        )?;
+        let header = &self.header;
+        Ok(StateDump {
+            key: to_hex(self.key_pair.public.as_bytes()),
+            writeable: self.key_pair.secret.is_some(),
+            fork: self.tree.fork,
+            length: self.tree.length,
+            byte_length: self.tree.byte_length,
+            roots: self.tree.roots.iter().map(NodeDump::from).collect(),
+            header: HeaderDump {
+                fork: header.tree.fork,
+                length: header.tree.length,
+                root_hash: to_hex(&header.tree.root_hash),
+                signature: to_hex(&header.tree.signature),
+                contiguous_length: header.hints.contiguous_length,
+                user_data: header.user_data.clone(),
+            },
+            stored: self.present_ranges(0..self.tree.length),
+            oplog_length: entries.len() as u64,
+            oplog_entries: entries
+                .iter()
+                .skip(entries.len().saturating_sub(DUMP_OPLOG_ENTRIES))
+                .map(OplogEntryDump::from)
+                .collect(),
+        })
+    }
+
+    /// Get statistics of the hypercore since it was opened
+    pub fn stats(&self) -> Stats {
+        self.stats.to_stats()
+    }
+
+    /// Whether a write to storage failed, after which writes fail with
+    /// [`HypercoreError::Poisoned`] until the hypercore is recovered with [`Hypercore::recover`].
+    /// Reads still work, from the state in memory.
+    pub fn is_poisoned(&self) -> bool {
+        self.storage.is_poisoned()
+    }
+
+    /// Renew the lease of the lock of the storage, if it has one, see
+    /// [`Storage::new_disk_locked`]. Writes renew it too, so only a hypercore that doesn't write
+    /// for a third of the lease needs this, e.g. from a timer. Fails with
+    /// [`HypercoreError::Locked`] if another process took the lock over after the lease expired.
+    pub fn heartbeat(&mut self) -> Result<(), HypercoreError> {
+        self.storage.renew_lock()
+    }
+
+    /// Read the state of the hypercore from its storage again and allow writing again, e.g. after
+    /// a failed write poisoned it, instead of reopening it. As when reopening, the oplog entries
+    /// not yet flushed to the tree and bitfield are replayed, but the options, caches and event
+    /// subscribers are kept. Returns whether entries were replayed.
+    #[cfg_attr(
+        feature = "tracing",
+        instrument(err, skip(self), fields(discovery_key = %self.discovery_key))
+    )]
+    pub async fn recover(&mut self) -> Result<bool, HypercoreError> {
+        let OpenedState {
+            oplog,
+            header,
+            tree,
+            bitfield,
+            outcome,
+        } = Self::open_state(
+            &mut self.storage,
+            &None,
+            &None,
+            #[cfg(feature = "cache")]
+            &self.node_cache_options,
+        )
+        .await?;
+        if header.key_pair.public != self.key_pair.public {
+            return Err(HypercoreError::KeyMismatch {
+                context: "The stored hypercore has another public key".to_string(),
+            });
+        }
+        self.oplog = oplog;
+        self.header = header;
+        self.tree = tree;
+        self.bitfield = bitfield;
+        self.skip_flush_count = 0;
+        self.deferred_infos = None;
+        self.storage.clear_poisoned();
+        #[cfg(feature = "replication")]
+        self.events.discard();
+        self.invalidate_block_cache(0..u64::MAX);
+        self.invalidate_proof_cache();
+        if self.dedup.take().is_some() {
+            self.open_dedup_index(true).await?;
+        }
+        if self.byte_index.is_some() {
+            self.open_byte_index().await?;
+        }
+        Ok(outcome.recovered)
+    }
+
     /// Appends a data slice to the hypercore.
-    #[instrument(err, skip_all, fields(data_len = data.len()))]
+    #[cfg_attr(
+        feature = "tracing",
+        instrument(
+            err,
+            skip_all,
+            fields(discovery_key = %self.discovery_key, data_len = data.len())
+        )
+    )]
     pub async fn append(&mut self, data: &[u8]) -> Result<AppendOutcome, HypercoreError> {
         self.append_batch(&[data]).await
     }
 
     /// Appends a given batch of data slices to the hypercore.
-    #[instrument(err, skip_all, fields(batch_len = batch.as_ref().len()))]
+    #[cfg_attr(
+        feature = "tracing",
+        instrument(
+            err,
+            skip_all,
+            fields(discovery_key = %self.discovery_key, batch_len = batch.as_ref().len())
+        )
+    )]
     pub async fn append_batch<A: AsRef<[u8]>, B: AsRef<[A]>>(
         &mut self,
         batch: B,
@@ -283,59 +1042,112 @@ impl Hypercore {
             None => return Err(HypercoreError::NotWritable),
         };
 
+        let start = self.tree.length;
+        let mut byte_offsets = Vec::with_capacity(batch.as_ref().len());
         if !batch.as_ref().is_empty() {
             // Create a changeset for the tree
             let mut changeset = self.tree.changeset();
             let mut batch_length: usize = 0;
             for data in batch.as_ref().iter() {
+                byte_offsets.push(self.tree.byte_length + batch_length as u64);
                 batch_length += changeset.append(data.as_ref());
             }
+            self.check_quota(&changeset)?;
             changeset.hash_and_sign(secret_key);
 
-            // Write the received data to the block store
-            let info =
-                self.block_store
-                    .append_batch(batch.as_ref(), batch_length, self.tree.byte_length);
-            self.storage.flush_info(info).await?;
-
-            // Append the changeset to the Oplog
+            // Write the received data to the block store, unless light
             let bitfield_update = BitfieldUpdate {
                 drop: false,
                 start: changeset.ancestors,
                 length: changeset.batch_length,
             };
+            let stored_update = (!self.light).then(|| bitfield_update.clone());
+            let mut deduplicated = vec![];
+            if stored_update.is_some() {
+                if let Some(dedup) = self.dedup.as_ref() {
+                    // Write only the blocks whose content isn't stored yet
+                    let mut offset = self.tree.byte_length;
+                    let mut infos = vec![];
+                    let mut written = HashSet::new();
+                    for (index, data) in (changeset.ancestors..).zip(batch.as_ref()) {
+                        let data = data.as_ref();
+                        let hash = Hash::data(data).as_bytes().to_vec();
+                        if dedup.source(&hash).is_none() && written.insert(hash.clone()) {
+                            infos.push(self.block_store.put(data, offset));
+                        }
+                        deduplicated.push((index, hash));
+                        offset += data.len() as u64;
+                    }
+                    self.storage.flush_infos(&infos).await?;
+                } else {
+                    // Borrowed as byte slices, as the blocks need not be `Sync`
+                    let slices: Vec<&[u8]> = batch.as_ref().iter().map(AsRef::as_ref).collect();
+                    self.storage
+                        .write_slices(Store::Data, self.tree.byte_length, &slices)
+                        .await?;
+                }
+            }
+
+            // Append the changeset to the Oplog
             let outcome = self.oplog.append_changeset(
                 &changeset,
-                Some(bitfield_update.clone()),
+                stored_update.clone(),
                 false,
                 &self.header,
             )?;
-            self.storage.flush_infos(&outcome.infos_to_flush).await?;
+            // Writing the entry commits the append, the rest doesn't await until the core is updated
+            let commit = outcome.write.flush(&mut self.storage).await?;
             self.header = outcome.header;
+            self.oplog.commit(commit);
+            if let Some(dedup) = self.dedup.as_mut() {
+                for (index, hash) in deduplicated {
+                    dedup.insert(&hash, index);
+                }
+            }
 
-            // Write to bitfield
-            self.bitfield.update(&bitfield_update);
+            if let Some(stored_update) = &stored_update {
+                // Write to bitfield
+                self.bitfield.update(stored_update);
 
-            // Contiguous length is known only now
-            update_contiguous_length(&mut self.header, &self.bitfield, &bitfield_update);
+                // Contiguous length is known only now
+                update_contiguous_length(&mut self.header, &self.bitfield, stored_update);
+            }
 
             // Commit changeset to in-memory tree
+            #[cfg(feature = "replication")]
+            let old_tree = (self.tree.length, self.tree.byte_length);
+            if let Some(byte_index) = self
+                .byte_index
+                .as_mut()
+                .filter(|byte_index| byte_index.len() == self.tree.length)
+            {
+                for data in batch.as_ref() {
+                    byte_index.push(data.as_ref().len() as u64);
+                }
+            }
             self.tree.commit(changeset)?;
+            self.invalidate_proof_cache();
+            StatsCounters::add(&self.stats.blocks_appended, bitfield_update.length);
+            StatsCounters::add(&self.stats.bytes_appended, batch_length as u64);
+            metrics::appended(bitfield_update.length, batch_length as u64);
 
             // Now ready to flush
             if self.should_flush_bitfield_and_tree_and_oplog() {
                 self.flush_bitfield_and_tree_and_oplog(false).await?;
             }
+            self.apply_retention().await?;
 
             #[cfg(feature = "replication")]
             {
-                use tracing::trace;
-
-                trace!(bitfield_update = ?bitfield_update, "Hppercore.append_batch emit DataUpgrade & Have");
+                #[cfg(feature = "tracing")]
+                tracing::trace!(bitfield_update = ?bitfield_update, "Hppercore.append_batch emit DataUpgrade & Have");
                 let _ = self.events.send(crate::replication::events::DataUpgrade {});
-                let _ = self
-                    .events
-                    .send(crate::replication::events::Have::from(&bitfield_update));
+                self.emit_quota_warning(old_tree);
+                let _ = self.events.send(crate::replication::events::Have::new(
+                    &bitfield_update,
+                    batch_length as u64,
+                    stored_update.is_some(),
+                ));
             }
         }
 
@@ -343,70 +1155,322 @@ impl Hypercore {
         Ok(AppendOutcome {
             length: self.tree.length,
             byte_length: self.tree.byte_length,
+            blocks: start..self.tree.length,
+            byte_offsets,
+            fork: self.tree.fork,
+            signature: self
+                .tree
+                .signature
+                .map(|signature| signature.to_bytes().to_vec())
+                .unwrap_or_default(),
         })
     }
 
-    #[cfg(feature = "replication")]
-    /// Subscribe to core events relevant to replication
-    pub fn event_subscribe(&self) -> async_broadcast::Receiver<crate::replication::events::Event> {
-        self.events.channel.new_receiver()
+    /// Appends a payload split into blocks of at most `chunk_size` bytes, returning the index
+    /// range of the blocks. Read it back with [`Hypercore::get_chunked`].
+    #[cfg_attr(
+        feature = "tracing",
+        instrument(err, skip(self, data), fields(data_len = data.len()))
+    )]
+    pub async fn append_chunked(
+        &mut self,
+        data: &[u8],
+        chunk_size: usize,
+    ) -> Result<Range<u64>, HypercoreError> {
+        if chunk_size == 0 {
+            return Err(HypercoreError::BadArgument {
+                context: "Chunk size must be greater than zero".to_string(),
+            });
+        }
+        let start = self.tree.length;
+        let chunks: Vec<&[u8]> = data.chunks(chunk_size).collect();
+        let outcome = self.append_batch(chunks).await?;
+        Ok(start..outcome.length)
     }
 
-    /// Check if core has the block at the given `index` locally
-    #[instrument(ret, skip(self))]
-    pub fn has(&self, index: u64) -> bool {
-        self.bitfield.get(index)
+    /// Reassembles the payload of the blocks in the given index range, e.g. as returned by
+    /// [`Hypercore::append_chunked`]. Returns `None` if a block is missing.
+    #[cfg_attr(feature = "tracing", instrument(err, skip(self)))]
+    pub async fn get_chunked(&self, range: Range<u64>) -> Result<Option<Vec<u8>>, HypercoreError> {
+        let mut data = vec![];
+        for index in range {
+            match self.get(index).await? {
+                Some(block) => data.extend_from_slice(&block),
+                None => return Ok(None),
+            }
+        }
+        Ok(Some(data))
     }
 
-    /// Read value at given index, if any.
-    #[instrument(err, skip(self))]
-    pub async fn get(&mut self, index: u64) -> Result<Option<Vec<u8>>, HypercoreError> {
-        if !self.bitfield.get(index) {
-            #[cfg(feature = "replication")]
-            // if not in this core, emit Event::Get(index)
-            {
-                use tracing::trace;
+    /// Appends the content of `reader` split into blocks with content-defined boundaries, see
+    /// [`crate::chunking`], returning the index range of the blocks.
+    #[cfg(feature = "chunking")]
+    #[cfg_attr(feature = "tracing", instrument(err, skip_all))]
+    pub async fn append_reader<R: futures::io::AsyncRead + Unpin>(
+        &mut self,
+        mut reader: R,
+        chunker: &crate::chunking::FastCdc,
+    ) -> Result<Range<u64>, HypercoreError> {
+        use futures::io::AsyncReadExt;
 
-                trace!(index = index, "Hppercore emit 'get' event");
-                self.events.send_on_get(index);
+        let start = self.tree.length;
+        let max_size = chunker.max_size();
+        let mut buffer = Vec::with_capacity(max_size);
+        let mut eof = false;
+        while !eof || !buffer.is_empty() {
+            // Read until a whole chunk fits in the buffer, so the chunker sees enough content to
+            // find its boundary
+            while !eof && buffer.len() < max_size {
+                let filled = buffer.len();
+                buffer.resize(max_size, 0);
+                let read = reader.read(&mut buffer[filled..]).await?;
+                buffer.truncate(filled + read);
+                eof = read == 0;
             }
-            return Ok(None);
+            let length = chunker.cut(&buffer);
+            self.append(&buffer[..length]).await?;
+            buffer.drain(..length);
         }
+        Ok(start..self.tree.length)
+    }
 
-        let byte_range = self.byte_range(index, None).await?;
+    #[cfg(feature = "shared-core")]
+    /// Emit a [`crate::replication::events::Get`] event for a missing block and return a receiver
+    /// that is notified when the block arrives.
+    pub(crate) fn wait_for_block(
+        &self,
+        index: u64,
+        priority: crate::replication::Priority,
+    ) -> async_broadcast::Receiver<()> {
+        self.events.send_on_get(index, priority)
+    }
 
-        // TODO: Generalize Either response stack
-        let data = match self.block_store.read(&byte_range, None) {
-            Either::Right(value) => value,
-            Either::Left(instruction) => {
-                let info = self.storage.read_info(instruction).await?;
-                match self.block_store.read(&byte_range, Some(info)) {
-                    Either::Right(value) => value,
-                    Either::Left(_) => {
-                        return Err(HypercoreError::InvalidOperation {
-                            context: "Could not read block storage range".to_string(),
-                        });
-                    }
-                }
-            }
-        };
+    #[cfg(feature = "shared-core")]
+    /// First block in `range` that isn't stored locally
+    pub(crate) fn first_missing(&self, range: Range<u64>) -> Option<u64> {
+        if range.is_empty() {
+            return None;
+        }
+        self.bitfield
+            .index_of(false, range.start)
+            .filter(|index| *index < range.end)
+    }
 
-        Ok(Some(data.to_vec()))
+    #[cfg(feature = "replication")]
+    /// Subscribe to core events relevant to replication
+    pub fn event_subscribe(&self) -> crate::replication::events::EventReceiver {
+        self.events.channel.new_receiver()
     }
 
-    /// Clear data for entries between start and end (exclusive) indexes.
-    #[instrument(err, skip(self))]
-    pub async fn clear(&mut self, start: u64, end: u64) -> Result<(), HypercoreError> {
+    #[cfg(feature = "replication")]
+    /// Announce that a remote peer has the blocks in the range `start..start + length`, emitting a
+    /// [`crate::replication::events::PeerHave`] event. Called by replicators.
+    pub fn peer_have(&self, start: u64, length: u64) {
+        let _ = self
+            .events
+            .send(crate::replication::events::PeerHave { start, length });
+    }
+
+    #[cfg(feature = "replication")]
+    /// Register interest in the blocks in `range`, emitting a
+    /// [`crate::replication::events::Want`] event. Replicators download wanted blocks without
+    /// waiting for them to be read, like `core.download({ start, end })` in Javascript, the
+    /// ranges of higher `priority` first. Ranges are independent of each other, and each is
+    /// withdrawn with [`Hypercore::unwant`].
+    pub fn want(&mut self, range: Range<u64>, priority: crate::replication::Priority) {
+        let _ = self.events.send(crate::replication::events::Want {
+            start: range.start,
+            length: range.end.saturating_sub(range.start),
+            priority,
+        });
+        self.wants.push((range, priority));
+    }
+
+    #[cfg(feature = "replication")]
+    /// Withdraw interest registered with [`Hypercore::want`] for exactly `range`, emitting a
+    /// [`crate::replication::events::Unwant`] event. Returns false if the range wasn't wanted.
+    pub fn unwant(&mut self, range: Range<u64>) -> bool {
+        let Some(position) = self.wants.iter().position(|(want, _)| *want == range) else {
+            return false;
+        };
+        self.wants.remove(position);
+        let _ = self.events.send(crate::replication::events::Unwant {
+            start: range.start,
+            length: range.end.saturating_sub(range.start),
+        });
+        true
+    }
+
+    #[cfg(feature = "replication")]
+    /// Ranges registered with [`Hypercore::want`] and their priority, in registration order
+    pub fn wants(&self) -> &[(Range<u64>, crate::replication::Priority)] {
+        &self.wants
+    }
+
+    #[cfg(feature = "replication")]
+    /// Number of events dropped from the event queue because a subscriber lagged behind, see
+    /// [`crate::HypercoreBuilder::event_queue_capacity`]
+    pub fn events_dropped(&self) -> u64 {
+        self.events.dropped()
+    }
+
+    #[cfg(feature = "replication")]
+    /// Subscribe to core events relevant to replication as a [`futures::Stream`]
+    pub fn event_stream(&self) -> crate::replication::EventStream {
+        self.event_subscribe().into()
+    }
+
+    /// True if the hypercore was built with [`crate::HypercoreBuilder::light`], and only stores
+    /// its tree
+    pub fn is_light(&self) -> bool {
+        self.light
+    }
+
+    /// Check if core has the block at the given `index` locally
+    #[cfg_attr(feature = "tracing", instrument(ret, skip(self)))]
+    pub fn has(&self, index: u64) -> bool {
+        self.bitfield.get(index)
+    }
+
+    /// Read value at given index, if any.
+    #[cfg_attr(
+        feature = "tracing",
+        instrument(err, skip(self), fields(discovery_key = %self.discovery_key))
+    )]
+    pub async fn get(&self, index: u64) -> Result<Option<Vec<u8>>, HypercoreError> {
+        if !self.bitfield.get(index) {
+            #[cfg(feature = "replication")]
+            // if not in this core, emit Event::Get(index), unless light and never stored
+            if !self.light {
+                #[cfg(feature = "tracing")]
+                tracing::trace!(index = index, "Hppercore emit 'get' event");
+                self.events
+                    .send_on_get(index, crate::replication::Priority::Normal);
+            }
+            return Ok(None);
+        }
+
+        #[cfg(feature = "cache")]
+        if let Some(value) = self
+            .block_cache
+            .as_ref()
+            .and_then(|cache| metrics::cache_lookup("block", cache.get(&index)))
+        {
+            return Ok(Some(value));
+        }
+
+        let byte_range = self.stored_byte_range(index).await?;
+        let data = self.read_block(&byte_range).await?.to_vec();
+
+        #[cfg(feature = "cache")]
+        if let Some(cache) = self.block_cache.as_ref() {
+            cache.insert(index, data.clone());
+        }
+        Ok(Some(data))
+    }
+
+    /// Read `length` bytes at `offset` within the block at given index, if any, without loading
+    /// the rest of the block from storage. Like [`Hypercore::get`], the bytes are not verified
+    /// against the tree, use [`Hypercore::verify_range`] for that. Fails if the range exceeds the
+    /// block.
+    #[cfg_attr(feature = "tracing", instrument(err, skip(self)))]
+    pub async fn get_partial(
+        &self,
+        index: u64,
+        offset: u64,
+        length: u64,
+    ) -> Result<Option<Vec<u8>>, HypercoreError> {
+        if !self.bitfield.get(index) {
+            // Emits the get event of the missing block
+            return self.get(index).await.map(|_| None);
+        }
+        let byte_range = self.stored_byte_range(index).await?;
+        if offset
+            .checked_add(length)
+            .is_none_or(|end| end > byte_range.length)
+        {
+            return Err(HypercoreError::BadArgument {
+                context: format!(
+                    "Range of {length} bytes at offset {offset} exceeds block {index} of {} bytes",
+                    byte_range.length
+                ),
+            });
+        }
+
+        #[cfg(feature = "cache")]
+        if let Some(value) = self
+            .block_cache
+            .as_ref()
+            .and_then(|cache| metrics::cache_lookup("block", cache.get(&index)))
+        {
+            return Ok(Some(
+                value[offset as usize..(offset + length) as usize].to_vec(),
+            ));
+        }
+
+        let partial_range = NodeByteRange {
+            index: byte_range.index + offset,
+            length,
+        };
+        Ok(Some(self.read_block(&partial_range).await?.to_vec()))
+    }
+
+    /// Stream the bytes of the block at given index, if any, reading them from storage in chunks
+    /// of at most 64 KiB so serving a very large block doesn't load all of it into memory. Like
+    /// [`Hypercore::get`], the bytes are not verified against the tree.
+    #[cfg_attr(feature = "tracing", instrument(err, skip(self)))]
+    pub async fn get_stream(
+        &self,
+        index: u64,
+    ) -> Result<Option<impl futures::io::AsyncRead + Unpin + '_>, HypercoreError> {
+        if !self.bitfield.get(index) {
+            // Emits the get event of the missing block
+            return self.get(index).await.map(|_| None);
+        }
+        let length = self.stored_byte_range(index).await?.length;
+        let chunks = futures::stream::unfold(0, move |offset| async move {
+            if offset >= length {
+                return None;
+            }
+            let chunk_length = (length - offset).min(STREAM_CHUNK_SIZE);
+            let chunk = match self.get_partial(index, offset, chunk_length).await {
+                Ok(Some(chunk)) => Ok(chunk),
+                Ok(None) => Err(std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    format!("Block {index} was cleared while streaming it"),
+                )),
+                Err(err) => Err(std::io::Error::other(err)),
+            };
+            Some((chunk, offset + chunk_length))
+        });
+        Ok(Some(Box::pin(chunks).into_async_read()))
+    }
+
+    /// Clear data for entries between start and end (exclusive) indexes.
+    #[cfg_attr(feature = "tracing", instrument(err, skip(self)))]
+    pub async fn clear(&mut self, start: u64, end: u64) -> Result<(), HypercoreError> {
         if start >= end {
             // NB: This is what javascript does, so we mimic that here
             return Ok(());
         }
+        let cleared = self.move_deduplicated_blocks(start, end).await?;
+
         // Write to oplog
-        let infos_to_flush = self.oplog.clear(start, end)?;
-        self.storage.flush_infos(&infos_to_flush).await?;
+        let commit = self
+            .oplog
+            .clear(start, end)?
+            .flush(&mut self.storage)
+            .await?;
+        self.oplog.commit(commit);
 
         // Set bitfield
+        if let Some(dedup) = self.dedup.as_mut() {
+            for (block, hash) in cleared {
+                dedup.remove(&hash, block);
+            }
+        }
         self.bitfield.set_range(start, end - start, false);
+        self.invalidate_block_cache(start..end);
 
         // Set contiguous length
         if start < self.header.hints.contiguous_length {
@@ -443,10 +1507,15 @@ impl Hypercore {
             }
         };
 
-        // Find byte range for last value
-        let last_byte_range = self.byte_range(end - 1, Some(&infos)).await?;
+        // Find byte offset of the next stored value, the blocks after the last one may be missing
+        // from the tree of a sparse hypercore
+        let clear_end = if end < self.tree.length {
+            self.byte_range(end, Some(&infos)).await?.index
+        } else {
+            self.tree.byte_length
+        };
 
-        let clear_length = (last_byte_range.index + last_byte_range.length) - clear_offset;
+        let clear_length = clear_end - clear_offset;
 
         // Clear blocks
         let info_to_flush = self.block_store.clear(clear_offset, clear_length);
@@ -465,10 +1534,110 @@ impl Hypercore {
         &self.key_pair
     }
 
+    /// Create a proof for given request without the value of the requested block. Use
+    /// [`ValuelessProof::attach_value`] to turn it into a [`Proof`] that can be verified.
+    #[cfg_attr(
+        feature = "tracing",
+        instrument(
+            err,
+            skip_all,
+            fields(
+                discovery_key = %self.discovery_key,
+                block = block.as_ref().map(|block| block.index),
+                hash = hash.as_ref().map(|hash| hash.index),
+                seek = seek.as_ref().map(|seek| seek.bytes),
+                upgrade = upgrade.as_ref().map(|upgrade| upgrade.start + upgrade.length),
+            )
+        )
+    )]
+    pub async fn create_valueless_proof(
+        &self,
+        block: Option<RequestBlock>,
+        hash: Option<RequestBlock>,
+        seek: Option<RequestSeek>,
+        upgrade: Option<RequestUpgrade>,
+    ) -> Result<ValuelessProof, HypercoreError> {
+        #[cfg(feature = "cache")]
+        let cache_key = self.proof_cache.as_ref().map(|_| {
+            ProofCacheKey::new(
+                self.tree.fork,
+                block.as_ref(),
+                hash.as_ref(),
+                seek.as_ref(),
+                upgrade.as_ref(),
+            )
+        });
+        #[cfg(feature = "cache")]
+        if let Some(proof) = self
+            .proof_cache
+            .as_ref()
+            .zip(cache_key.as_ref())
+            .and_then(|(cache, key)| metrics::cache_lookup("proof", cache.get(key)))
+        {
+            StatsCounters::add(&self.stats.proofs_created, 1);
+            metrics::proof_created();
+            return Ok(proof);
+        }
+
+        let proof = self
+            .create_valueless_proof_from_tree(block, hash, seek, upgrade)
+            .await?;
+        StatsCounters::add(&self.stats.proofs_created, 1);
+        metrics::proof_created();
+
+        #[cfg(feature = "cache")]
+        if let (Some(cache), Some(key)) = (self.proof_cache.as_ref(), cache_key) {
+            cache.insert(key, proof.clone());
+        }
+        Ok(proof)
+    }
+
+    async fn create_valueless_proof_from_tree(
+        &self,
+        block: Option<RequestBlock>,
+        hash: Option<RequestBlock>,
+        seek: Option<RequestSeek>,
+        upgrade: Option<RequestUpgrade>,
+    ) -> Result<ValuelessProof, HypercoreError> {
+        match self.tree.create_valueless_proof(
+            block.as_ref(),
+            hash.as_ref(),
+            seek.as_ref(),
+            upgrade.as_ref(),
+            None,
+        )? {
+            Either::Right(value) => Ok(value),
+            Either::Left(instructions) => {
+                let mut instructions = instructions;
+                let mut infos: Vec<StoreInfo> = vec![];
+                loop {
+                    infos.extend(self.storage.read_infos_to_vec(&instructions).await?);
+                    match self.tree.create_valueless_proof(
+                        block.as_ref(),
+                        hash.as_ref(),
+                        seek.as_ref(),
+                        upgrade.as_ref(),
+                        Some(&infos),
+                    )? {
+                        Either::Right(value) => {
+                            return Ok(value);
+                        }
+                        Either::Left(new_instructions) => {
+                            instructions = new_instructions;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
     /// Create a proof for given request
-    #[instrument(err, skip_all)]
+    #[cfg_attr(
+        feature = "tracing",
+        instrument(err, skip_all, fields(discovery_key = %self.discovery_key))
+    )]
     pub async fn create_proof(
-        &mut self,
+        &self,
         block: Option<RequestBlock>,
         hash: Option<RequestBlock>,
         seek: Option<RequestSeek>,
@@ -479,34 +1648,405 @@ impl Hypercore {
             .await?;
         let value: Option<Vec<u8>> = if let Some(block) = valueless_proof.block.as_ref() {
             let value = self.get(block.index).await?;
-            if value.is_none() {
+            let Some(value) = value else {
                 // The data value requested in the proof can not be read, we return None here
                 // and let the party requesting figure out what to do.
                 return Ok(None);
-            }
-            value
+            };
+            Some(value)
         } else {
             None
         };
         Ok(Some(valueless_proof.into_proof(value)))
     }
 
+    /// Find the block containing the byte at `byte_offset`. Returns the index of the block and the
+    /// offset of the byte within that block. Walks down the tree from a root, unless the block is
+    /// in the index enabled with [`crate::HypercoreBuilder::byte_index`].
+    #[cfg_attr(feature = "tracing", instrument(err, skip(self)))]
+    pub async fn seek(&self, byte_offset: u64) -> Result<(u64, u64), HypercoreError> {
+        if byte_offset >= self.tree.byte_length {
+            return Err(HypercoreError::BadArgument {
+                context: format!(
+                    "Byte offset {byte_offset} is not before byte length {}",
+                    self.tree.byte_length
+                ),
+            });
+        }
+        if let Some(position) = self
+            .byte_index
+            .as_ref()
+            .and_then(|byte_index| byte_index.seek(byte_offset))
+        {
+            return Ok(position);
+        }
+
+        let mut bytes = byte_offset;
+        let mut index = self.tree.roots[0].index;
+        for root in &self.tree.roots {
+            if bytes < root.length {
+                index = root.index;
+                break;
+            }
+            bytes -= root.length;
+        }
+        while let Some((left, right)) = flat_tree::children(index) {
+            let Some(node) = self.tree_node(left).await? else {
+                return Err(HypercoreError::InvalidOperation {
+                    context: format!("Could not read node {left} from tree"),
+                });
+            };
+            if bytes < node.length {
+                index = left;
+            } else {
+                bytes -= node.length;
+                index = right;
+            }
+        }
+        Ok((index / 2, bytes))
+    }
+
+    /// Stream proofs of the locally stored blocks in `range`, in index order, e.g. for a server
+    /// seeding a huge core. Each proof has the nodes from its block up to a root of the tree at
+    /// the current length, so it verifies once the roots are known, e.g. from an upgrade proof of
+    /// [`Hypercore::create_proof`]. The proofs are created concurrently in windows of blocks whose
+    /// bytes add up to at most `memory_budget`, or of a single block larger than that, so the
+    /// stream never holds more than one window in memory.
+    pub fn create_proof_stream(
+        &self,
+        range: Range<u64>,
+        memory_budget: u64,
+    ) -> impl futures::Stream<Item = Result<Proof, HypercoreError>> + Unpin + '_ {
+        let end = range.end.min(self.tree.length);
+        let state = (range.start, VecDeque::new());
+        Box::pin(futures::stream::try_unfold(
+            state,
+            move |(mut next, mut window): (u64, VecDeque<Proof>)| async move {
+                if window.is_empty() {
+                    let mut blocks = vec![];
+                    let mut bytes = 0;
+                    while let Some(index) = self
+                        .bitfield
+                        .index_of(true, next)
+                        .filter(|index| *index < end)
+                    {
+                        let length = self.byte_range(index, None).await?.length;
+                        if !blocks.is_empty() && bytes + length > memory_budget {
+                            break;
+                        }
+                        bytes += length;
+                        blocks.push(index);
+                        next = index + 1;
+                    }
+                    let proofs = blocks
+                        .into_iter()
+                        .map(|index| self.create_block_proof(index));
+                    window = futures::future::try_join_all(proofs).await?.into();
+                }
+                Ok(window.pop_front().map(|proof| (proof, (next, window))))
+            },
+        ))
+    }
+
+    /// Proof of a stored block with the nodes up to a root of the tree
+    async fn create_block_proof(&self, index: u64) -> Result<Proof, HypercoreError> {
+        let block = RequestBlock {
+            index,
+            nodes: archive::nodes_below_root(index, self.tree.length),
+        };
+        self.create_proof(Some(block), None, None, None)
+            .await?
+            .ok_or_else(|| HypercoreError::InvalidOperation {
+                context: format!("Could not create a proof of block {index}"),
+            })
+    }
+
+    /// Create a proof for the block containing the byte at `byte_offset`. The proof includes an
+    /// upgrade to the current length so that it can be verified without any local state, see
+    /// [`Hypercore::verify_seek_proof`].
+    #[cfg_attr(feature = "tracing", instrument(err, skip(self)))]
+    pub async fn create_seek_proof(&self, byte_offset: u64) -> Result<Proof, HypercoreError> {
+        if byte_offset >= self.tree.byte_length {
+            return Err(HypercoreError::BadArgument {
+                context: format!(
+                    "Byte offset {byte_offset} is out of bounds for byte length {}",
+                    self.tree.byte_length
+                ),
+            });
+        }
+        let valueless_proof = self
+            .create_valueless_proof(
+                None,
+                None,
+                Some(RequestSeek { bytes: byte_offset }),
+                Some(RequestUpgrade {
+                    start: 0,
+                    length: self.tree.length,
+                }),
+            )
+            .await?;
+        Ok(valueless_proof.into_proof(None))
+    }
+
+    /// Verify a seek proof received from a peer without applying it. Returns the index of the
+    /// block that contains the sought byte and the offset of the byte within that block.
+    #[cfg_attr(feature = "tracing", instrument(err, skip_all))]
+    pub async fn verify_seek_proof(&self, proof: &Proof) -> Result<(u64, u64), HypercoreError> {
+        let Some(seek) = proof.seek.as_ref() else {
+            return Err(HypercoreError::BadArgument {
+                context: "Proof does not contain a seek".to_string(),
+            });
+        };
+        if proof.upgrade.is_none() && proof.fork != self.tree.fork {
+            return Err(HypercoreError::InvalidOperation {
+                context: format!(
+                    "Seek proof fork {} does not match fork {}",
+                    proof.fork, self.tree.fork
+                ),
+            });
+        }
+        let changeset = self.verify_proof(proof).await?;
+        seek_position(seek, &changeset.roots)
+    }
+
     /// Verify and apply proof received from peer, returns true if changed, false if not
-    /// possible to apply.
-    #[instrument(skip_all)]
+    /// possible to apply. Proofs that need an upgrade that isn't applied yet, e.g. when they
+    /// arrive out of order from several peers, are buffered and applied once it is, see
+    /// [`crate::HypercoreBuilder::pending_proofs_capacity`].
+    #[cfg_attr(
+        feature = "tracing",
+        instrument(
+            err,
+            skip_all,
+            fields(
+                discovery_key = %self.discovery_key,
+                fork = proof.fork,
+                block = proof.block.as_ref().map(|block| block.index),
+                upgrade = proof.upgrade.as_ref().map(|upgrade| upgrade.start + upgrade.length),
+            )
+        )
+    )]
     pub async fn verify_and_apply_proof(&mut self, proof: &Proof) -> Result<bool, HypercoreError> {
+        self.check_no_dropped_batch()?;
+        if self.awaits_upgrade(proof) {
+            self.buffer_pending_proof(proof);
+            return Ok(false);
+        }
+        let applied = self.apply_proof(proof).await?;
+        if applied && proof.upgrade.is_some() {
+            self.apply_pending_proofs().await;
+        }
+        if applied {
+            self.apply_retention().await?;
+        }
+        Ok(applied)
+    }
+
+    /// Verify and apply a burst of proofs received from peers, e.g. while catching up, with a
+    /// single flush of their writes. The proofs are applied in the order given, except that a
+    /// proof needing an upgrade waits for the proof of the batch with that upgrade. Returns
+    /// whether each proof was applied, as [`Hypercore::verify_and_apply_proof`] does, and buffers
+    /// the proofs whose upgrade is still missing in the same way.
+    ///
+    /// If any proof fails to verify, the whole batch is rejected: nothing is written, and the
+    /// hypercore is read from its storage again as by [`Hypercore::recover`]. A crash while the
+    /// batch is flushed keeps the proofs whose oplog entries were written, as if they were applied
+    /// one by one. Unlike other methods, dropping the future before it completes poisons the
+    /// hypercore until it is recovered, which reverts the proofs not written yet.
+    #[cfg_attr(
+        feature = "tracing",
+        instrument(
+            err,
+            skip_all,
+            fields(discovery_key = %self.discovery_key, proofs = proofs.len())
+        )
+    )]
+    pub async fn verify_and_apply_proofs(
+        &mut self,
+        proofs: &[Proof],
+    ) -> Result<Vec<bool>, HypercoreError> {
+        self.check_no_dropped_batch()?;
+        if self.storage.is_poisoned() {
+            return Err(HypercoreError::Poisoned);
+        }
+        let mut applied = vec![false; proofs.len()];
+        let mut remaining: Vec<usize> = (0..proofs.len()).collect();
+        let mut upgraded = false;
+
+        // Writes fail until the batch is flushed, in case the future is dropped meanwhile
+        self.storage.poison();
+        self.deferred_infos = Some(vec![]);
+        #[cfg(feature = "replication")]
+        self.events.hold();
+        let mut result = Ok(());
+        while let Some(position) = remaining
+            .iter()
+            .position(|index| !self.awaits_upgrade(&proofs[*index]))
+        {
+            let index = remaining.remove(position);
+            match self.apply_proof(&proofs[index]).await {
+                Ok(proof_applied) => {
+                    applied[index] = proof_applied;
+                    upgraded |= proof_applied && proofs[index].upgrade.is_some();
+                }
+                Err(err) => {
+                    result = Err(err);
+                    break;
+                }
+            }
+        }
+        let infos = self.deferred_infos.take().unwrap_or_default();
+        if let Err(err) = result {
+            // Nothing was written
+            self.storage.clear_poisoned();
+            self.recover().await?;
+            return Err(err);
+        }
+
+        // Writing the oplog entries commits the proofs
+        let flushed = self.storage.flush_poisoned_infos(&infos).await;
+        #[cfg(feature = "replication")]
+        if flushed.is_ok() {
+            self.events.release();
+        } else {
+            self.events.discard();
+        }
+        flushed?;
+        if self.should_flush_bitfield_and_tree_and_oplog() {
+            self.flush_bitfield_and_tree_and_oplog(false).await?;
+        }
+        for index in remaining {
+            self.buffer_pending_proof(&proofs[index]);
+        }
+        if upgraded {
+            self.apply_pending_proofs().await;
+        }
+        if applied.contains(&true) {
+            self.apply_retention().await?;
+        }
+        Ok(applied)
+    }
+
+    /// Fail if the future of [`Hypercore::verify_and_apply_proofs`] was dropped before it
+    /// completed, leaving its proofs applied in memory only
+    fn check_no_dropped_batch(&self) -> Result<(), HypercoreError> {
+        if self.deferred_infos.is_some() {
+            return Err(HypercoreError::Poisoned);
+        }
+        Ok(())
+    }
+
+    /// Buffer a proof until the upgrade it needs is applied, dropping the oldest one if the
+    /// buffer is full
+    fn buffer_pending_proof(&mut self, proof: &Proof) {
+        if self.pending_proofs_capacity > 0 {
+            if self.pending_proofs.len() == self.pending_proofs_capacity {
+                self.pending_proofs.pop_front();
+            }
+            self.pending_proofs.push_back(proof.clone());
+        }
+    }
+
+    /// Number of proofs buffered by [`Hypercore::verify_and_apply_proof`] until the upgrade they
+    /// need is applied
+    pub fn pending_proofs(&self) -> usize {
+        self.pending_proofs.len()
+    }
+
+    /// Whether the proof needs an upgrade of the tree that isn't applied yet
+    fn awaits_upgrade(&self, proof: &Proof) -> bool {
+        if proof.fork != self.tree.fork {
+            return false;
+        }
+        if let Some(upgrade) = &proof.upgrade {
+            return upgrade.start > self.tree.length;
+        }
+        if let Some(block) = &proof.block {
+            return block.index >= self.tree.length;
+        }
+        if let Some(hash) = &proof.hash {
+            return flat_tree::right_span(hash.index) >= 2 * self.tree.length;
+        }
+        proof
+            .seek
+            .as_ref()
+            .is_some_and(|seek| seek.bytes >= self.tree.byte_length)
+    }
+
+    /// Apply the buffered proofs whose upgrade got applied, until none is left. Proofs of an older
+    /// fork are dropped. Failing proofs are logged and reported with a
+    /// [`crate::replication::events::ProofRejected`] event.
+    async fn apply_pending_proofs(&mut self) {
+        let fork = self.tree.fork;
+        self.pending_proofs.retain(|proof| proof.fork >= fork);
+        while let Some(position) = self
+            .pending_proofs
+            .iter()
+            .position(|proof| !self.awaits_upgrade(proof))
+        {
+            let proof = self
+                .pending_proofs
+                .remove(position)
+                .expect("Position is in the buffer");
+            if let Err(_err) = Box::pin(self.apply_proof(&proof)).await {
+                #[cfg(feature = "tracing")]
+                tracing::warn!(
+                    err = %_err,
+                    fork = proof.fork,
+                    block = proof.block.as_ref().map(|block| block.index),
+                    "Buffered proof rejected"
+                );
+                #[cfg(feature = "replication")]
+                let _ = self.events.send(crate::replication::events::ProofRejected {
+                    fork: proof.fork,
+                    block: proof.block.as_ref().map(|block| block.index),
+                    error: _err.to_string(),
+                });
+            }
+        }
+    }
+
+    /// Verify and apply a proof that doesn't need a missing upgrade
+    async fn apply_proof(&mut self, proof: &Proof) -> Result<bool, HypercoreError> {
         if proof.fork != self.tree.fork {
+            // Notify replicator of a newer fork, which needs a reorg
+            #[cfg(feature = "replication")]
+            if let Some(upgrade) = &proof.upgrade
+                && proof.fork > self.tree.fork
+            {
+                let _ = self.events.send(crate::replication::events::ForkDetected {
+                    remote_fork: proof.fork,
+                    remote_length: upgrade.start + upgrade.length,
+                });
+            }
             return Ok(false);
         }
-        let changeset = self.verify_proof(proof).await?;
+        if let Err(err) = self.check_equivocation(proof).await {
+            StatsCounters::add(&self.stats.verification_failures, 1);
+            metrics::proof_verified(false, None);
+            return Err(err);
+        }
+        let changeset = match self.verify_proof(proof).await {
+            Ok(changeset) => changeset,
+            Err(err) => {
+                StatsCounters::add(&self.stats.verification_failures, 1);
+                metrics::proof_verified(false, None);
+                return Err(err);
+            }
+        };
         if !self.tree.commitable(&changeset) {
             return Ok(false);
         }
+        if changeset.upgraded {
+            self.check_quota(&changeset)?;
+        }
 
         // In javascript there's _verifyExclusive and _verifyShared based on changeset.upgraded, but
         // here we do only one. _verifyShared groups together many subsequent changesets into a single
         // oplog push, and then flushes in the end only for the whole group.
-        let bitfield_update: Option<BitfieldUpdate> = if let Some(block) = &proof.block.as_ref() {
+        let bitfield_update: Option<BitfieldUpdate> = if let Some(block) = &proof.block.as_ref()
+            && self.persists(block.index, &block.value)
+        {
             let byte_offset =
                 match self
                     .tree
@@ -533,9 +2073,15 @@ impl Hypercore {
                     }
                 };
 
-            // Write the value to the block store
-            let info_to_flush = self.block_store.put(&block.value, byte_offset);
-            self.storage.flush_info(info_to_flush).await?;
+            // Write the value to the block store, unless deduplicated
+            if self.dedup.as_ref().is_none_or(|dedup| {
+                dedup
+                    .source(Hash::data(&block.value).as_bytes())
+                    .is_none_or(|source| block.index <= source)
+            }) {
+                let info_to_flush = self.block_store.put(&block.value, byte_offset);
+                self.flush_proof_infos(Box::new([info_to_flush])).await?;
+            }
 
             // Return a bitfield update for the given value
             Some(BitfieldUpdate {
@@ -544,7 +2090,8 @@ impl Hypercore {
                 length: 1,
             })
         } else {
-            // Only from DataBlock can there be changes to the bitfield
+            // Only from DataBlock can there be changes to the bitfield, and only if the block is
+            // persisted
             None
         };
 
@@ -555,8 +2102,15 @@ impl Hypercore {
             false,
             &self.header,
         )?;
-        self.storage.flush_infos(&outcome.infos_to_flush).await?;
+        // Writing the entry commits the proof, the rest doesn't await until the core is updated
+        let commit = self.flush_proof_write(outcome.write).await?;
         self.header = outcome.header;
+        self.oplog.commit(commit);
+        if let (Some(dedup), Some(block), Some(_)) =
+            (self.dedup.as_mut(), &proof.block, &bitfield_update)
+        {
+            dedup.insert(Hash::data(&block.value).as_bytes(), block.index);
+        }
 
         if let Some(bitfield_update) = &bitfield_update {
             // Write to bitfield
@@ -567,52 +2121,222 @@ impl Hypercore {
         }
 
         // Commit changeset to in-memory tree
+        let truncated = (changeset.upgraded
+            && changeset.ancestors < changeset.original_tree_length)
+            .then_some((changeset.original_tree_length, changeset.ancestors));
+        #[cfg(feature = "replication")]
+        let old_tree = (self.tree.length, self.tree.byte_length);
         self.tree.commit(changeset)?;
+        if let Some((_, truncated_from)) = truncated {
+            self.invalidate_block_cache(truncated_from..u64::MAX);
+            if let Some(dedup) = self.dedup.as_mut() {
+                dedup.truncate(truncated_from);
+            }
+            if let Some(byte_index) = self.byte_index.as_mut() {
+                byte_index.truncate(truncated_from);
+            }
+        }
+        if let (Some(byte_index), Some(block)) = (self.byte_index.as_ref(), &proof.block)
+            && block.index == byte_index.len()
+        {
+            self.extend_byte_index().await?;
+        }
+        if proof.upgrade.is_some() {
+            self.invalidate_proof_cache();
+        }
 
-        // Now ready to flush
-        if self.should_flush_bitfield_and_tree_and_oplog() {
+        // Now ready to flush, unless a batch of proofs flushes at its end
+        if self.deferred_infos.is_none() && self.should_flush_bitfield_and_tree_and_oplog() {
             self.flush_bitfield_and_tree_and_oplog(false).await?;
         }
 
+        StatsCounters::add(&self.stats.proofs_verified, 1);
+        if let Some(block) = &proof.block {
+            StatsCounters::add(&self.stats.blocks_received, 1);
+            StatsCounters::add(&self.stats.bytes_received, block.value.len() as u64);
+        }
+        metrics::proof_verified(
+            true,
+            proof.block.as_ref().map(|block| block.value.len() as u64),
+        );
+
         #[cfg(feature = "replication")]
         {
+            if let Some((old_length, new_length)) = truncated {
+                // Notify replicator if the upgrade truncated the core
+                let _ = self.events.send(crate::replication::events::Truncate {
+                    old_length,
+                    new_length,
+                    fork: self.tree.fork,
+                });
+            }
             if proof.upgrade.is_some() {
                 // Notify replicator if we receieved an upgrade
                 let _ = self.events.send(crate::replication::events::DataUpgrade {});
+                self.emit_quota_warning(old_tree);
             }
 
-            // Notify replicator if we receieved a bitfield update
-            if let Some(ref bitfield) = bitfield_update {
-                let _ = self
-                    .events
-                    .send(crate::replication::events::Have::from(bitfield));
+            // Notify replicator if we receieved a block, which is only in the tree unless there
+            // was a bitfield update
+            if let Some(block) = &proof.block {
+                let update = BitfieldUpdate {
+                    drop: false,
+                    start: block.index,
+                    length: 1,
+                };
+                let _ = self.events.send(crate::replication::events::Have::new(
+                    &update,
+                    block.value.len() as u64,
+                    bitfield_update.is_some(),
+                ));
             }
         }
         Ok(true)
     }
 
-    /// Used to fill the nodes field of a `RequestBlock` during
-    /// synchronization.
-    #[instrument(err, skip(self))]
-    pub async fn missing_nodes(&mut self, index: u64) -> Result<u64, HypercoreError> {
-        self.missing_nodes_from_merkle_tree_index(index * 2).await
+    /// Flush the writes of a proof, or defer them to the end of the batch of proofs it's part of
+    async fn flush_proof_infos(&mut self, infos: Box<[StoreInfo]>) -> Result<(), HypercoreError> {
+        match self.deferred_infos.as_mut() {
+            Some(deferred) => {
+                deferred.extend(infos);
+                Ok(())
+            }
+            None => self.storage.flush_infos(&infos).await,
+        }
     }
 
-    /// Get missing nodes using a merkle tree index. Advanced variant of missing_nodex
-    /// that allow for special cases of searching directly from the merkle tree.
-    #[instrument(err, skip(self))]
-    pub async fn missing_nodes_from_merkle_tree_index(
+    /// Like [`Hypercore::flush_proof_infos`], for the oplog write that commits the proof
+    async fn flush_proof_write(
         &mut self,
-        merkle_tree_index: u64,
-    ) -> Result<u64, HypercoreError> {
-        match self.tree.missing_nodes(merkle_tree_index, None)? {
+        write: OplogWrite,
+    ) -> Result<OplogCommit, HypercoreError> {
+        match self.deferred_infos.as_mut() {
+            Some(deferred) => Ok(write.defer(deferred)),
+            None => write.flush(&mut self.storage).await,
+        }
+    }
+
+    /// Move the core onto the newer fork of the given proof, e.g. after a `ForkDetected` event of
+    /// the `replication` feature. The proof must contain only an upgrade from the start of the
+    /// core, as created by the remote with [`Hypercore::create_proof`] given a `RequestUpgrade`
+    /// with `start` zero. The blocks under the leading roots of the remote tree that the core
+    /// already has are kept, the others are dropped and can be downloaded again from the new fork.
+    /// Returns the number of blocks kept.
+    #[cfg_attr(
+        feature = "tracing",
+        instrument(err, skip_all, fields(discovery_key = %self.discovery_key, fork = proof.fork) )
+    )]
+    pub async fn reorg(&mut self, proof: &Proof) -> Result<u64, HypercoreError> {
+        if proof.fork <= self.tree.fork {
+            return Err(HypercoreError::BadArgument {
+                context: format!(
+                    "Reorg proof fork {} is not newer than fork {}",
+                    proof.fork, self.tree.fork
+                ),
+            });
+        }
+        let changeset = match self.tree.verify_reorg(proof, &self.key_pair.public, None)? {
+            Either::Right(value) => value,
+            Either::Left(instructions) => {
+                let infos = self.storage.read_infos_to_vec(&instructions).await?;
+                match self
+                    .tree
+                    .verify_reorg(proof, &self.key_pair.public, Some(&infos))?
+                {
+                    Either::Right(value) => value,
+                    Either::Left(_) => {
+                        return Err(HypercoreError::InvalidOperation {
+                            context: "Could not verify reorg proof from tree".to_string(),
+                        });
+                    }
+                }
+            }
+        };
+        self.check_quota(&changeset)?;
+
+        // Drop the blocks that aren't shared with the new fork
+        let old_length = self.tree.length;
+        let ancestors = changeset.ancestors;
+        let bitfield_update = (ancestors < old_length).then(|| BitfieldUpdate {
+            drop: true,
+            start: ancestors,
+            length: old_length - ancestors,
+        });
+        #[cfg(feature = "replication")]
+        let dropped_bytes = match bitfield_update {
+            Some(_) => self.tree.byte_length - self.byte_range(ancestors, None).await?.index,
+            None => 0,
+        };
+        let outcome = self.oplog.append_changeset(
+            &changeset,
+            bitfield_update.clone(),
+            false,
+            &self.header,
+        )?;
+        let commit = outcome.write.flush(&mut self.storage).await?;
+        self.header = outcome.header;
+        self.oplog.commit(commit);
+        if let Some(bitfield_update) = &bitfield_update {
+            self.bitfield.update(bitfield_update);
+            update_contiguous_length(&mut self.header, &self.bitfield, bitfield_update);
+        }
+
+        #[cfg(feature = "replication")]
+        let old_tree = (self.tree.length, self.tree.byte_length);
+        self.tree.commit(changeset)?;
+        self.invalidate_block_cache(ancestors..u64::MAX);
+        self.invalidate_proof_cache();
+        if let Some(dedup) = self.dedup.as_mut() {
+            dedup.truncate(ancestors);
+        }
+        if let Some(byte_index) = self.byte_index.as_mut() {
+            byte_index.truncate(ancestors);
+        }
+
+        // Flush right away, until then the tree treats the new nodes past the ancestors as
+        // truncated
+        self.flush_bitfield_and_tree_and_oplog(false).await?;
+
+        #[cfg(feature = "replication")]
+        {
+            let _ = self.events.send(crate::replication::events::Truncate {
+                old_length,
+                new_length: ancestors,
+                fork: self.tree.fork,
+            });
+            let _ = self.events.send(crate::replication::events::DataUpgrade {});
+            self.emit_quota_warning(old_tree);
+            if let Some(ref bitfield) = bitfield_update {
+                let _ = self.events.send(crate::replication::events::Have::new(
+                    bitfield,
+                    dropped_bytes,
+                    false,
+                ));
+            }
+        }
+        self.apply_pending_proofs().await;
+        Ok(ancestors)
+    }
+
+    /// Used to fill the nodes field of a `RequestBlock` during
+    /// synchronization.
+    #[cfg_attr(feature = "tracing", instrument(err, skip(self)))]
+    pub async fn missing_nodes(&self, index: u64) -> Result<u64, HypercoreError> {
+        self.missing_nodes_from_merkle_tree_index(index * 2).await
+    }
+
+    /// Batch variant of [`Hypercore::missing_nodes`]. Returns the number of missing nodes for every
+    /// index in the given range, in order.
+    #[cfg_attr(feature = "tracing", instrument(err, skip(self)))]
+    pub async fn missing_nodes_range(&self, range: Range<u64>) -> Result<Vec<u64>, HypercoreError> {
+        match self.tree.missing_nodes_range(range.clone(), None)? {
             Either::Right(value) => Ok(value),
             Either::Left(instructions) => {
                 let mut instructions = instructions;
                 let mut infos: Vec<StoreInfo> = vec![];
                 loop {
                     infos.extend(self.storage.read_infos_to_vec(&instructions).await?);
-                    match self.tree.missing_nodes(merkle_tree_index, Some(&infos))? {
+                    match self.tree.missing_nodes_range(range.clone(), Some(&infos))? {
                         Either::Right(value) => {
                             return Ok(value);
                         }
@@ -625,36 +2349,21 @@ impl Hypercore {
         }
     }
 
-    /// Makes the hypercore read-only by deleting the secret key. Returns true if the
-    /// hypercore was changed, false if the hypercore was already read-only. This is useful
-    /// in scenarios where a hypercore should be made immutable after initial values have
-    /// been stored.
-    #[instrument(err, skip_all)]
-    pub async fn make_read_only(&mut self) -> Result<bool, HypercoreError> {
-        if self.key_pair.secret.is_some() {
-            self.key_pair.secret = None;
-            self.header.key_pair.secret = None;
-            // Need to flush clearing traces to make sure both oplog slots are cleared
-            self.flush_bitfield_and_tree_and_oplog(true).await?;
-            Ok(true)
-        } else {
-            Ok(false)
-        }
-    }
-
-    async fn byte_range(
-        &mut self,
-        index: u64,
-        initial_infos: Option<&[StoreInfo]>,
-    ) -> Result<NodeByteRange, HypercoreError> {
-        match self.tree.byte_range(index, initial_infos)? {
+    /// Get missing nodes using a merkle tree index. Advanced variant of missing_nodex
+    /// that allow for special cases of searching directly from the merkle tree.
+    #[cfg_attr(feature = "tracing", instrument(err, skip(self)))]
+    pub async fn missing_nodes_from_merkle_tree_index(
+        &self,
+        merkle_tree_index: u64,
+    ) -> Result<u64, HypercoreError> {
+        match self.tree.missing_nodes(merkle_tree_index, None)? {
             Either::Right(value) => Ok(value),
             Either::Left(instructions) => {
                 let mut instructions = instructions;
                 let mut infos: Vec<StoreInfo> = vec![];
                 loop {
                     infos.extend(self.storage.read_infos_to_vec(&instructions).await?);
-                    match self.tree.byte_range(index, Some(&infos))? {
+                    match self.tree.missing_nodes(merkle_tree_index, Some(&infos))? {
                         Either::Right(value) => {
                             return Ok(value);
                         }
@@ -667,33 +2376,87 @@ impl Hypercore {
         }
     }
 
-    async fn create_valueless_proof(
-        &mut self,
-        block: Option<RequestBlock>,
-        hash: Option<RequestBlock>,
-        seek: Option<RequestSeek>,
-        upgrade: Option<RequestUpgrade>,
-    ) -> Result<ValuelessProof, HypercoreError> {
-        match self.tree.create_valueless_proof(
-            block.as_ref(),
-            hash.as_ref(),
-            seek.as_ref(),
-            upgrade.as_ref(),
-            None,
-        )? {
+    /// Verify the locally stored blocks between start and end (exclusive) indexes against the
+    /// merkle tree. Every present block is re-hashed and its ancestors checked up to a root of the
+    /// tree. Returns the index of each present block along with whether it verified, blocks that
+    /// are not stored locally are skipped.
+    #[cfg_attr(feature = "tracing", instrument(err, skip(self)))]
+    pub async fn verify_range(
+        &self,
+        start: u64,
+        end: u64,
+    ) -> Result<Vec<(u64, bool)>, HypercoreError> {
+        let end = std::cmp::min(end, self.tree.length);
+        let mut verified_ancestors: HashSet<u64> = HashSet::new();
+        let mut results: Vec<(u64, bool)> = Vec::new();
+        for index in start..end {
+            if !self.bitfield.get(index) {
+                continue;
+            }
+            let byte_range = self.stored_byte_range(index).await?;
+            let value = self.read_block(&byte_range).await?;
+            let valid = self
+                .verify_block(index, &value, &mut verified_ancestors)
+                .await?;
+            results.push((index, valid));
+        }
+        Ok(results)
+    }
+
+    /// Re-verify the locally stored blocks between start and end (exclusive) indexes like
+    /// [`Hypercore::verify_range`], and clear the corrupt ones so they are no longer advertised and
+    /// can be downloaded again. Emits a [`crate::replication::events::CorruptBlock`] and a dropping
+    /// [`crate::replication::events::Have`] event for each. Returns the indexes of the corrupt
+    /// blocks.
+    #[cfg_attr(feature = "tracing", instrument(err, skip(self)))]
+    pub async fn scrub(&mut self, range: Range<u64>) -> Result<Vec<u64>, HypercoreError> {
+        let mut corrupt = vec![];
+        for (index, valid) in self.verify_range(range.start, range.end).await? {
+            if valid {
+                continue;
+            }
+            #[cfg(feature = "replication")]
+            let byte_length = self.byte_range(index, None).await?.length;
+            self.clear(index, index + 1).await?;
+            corrupt.push(index);
+            #[cfg(feature = "replication")]
+            {
+                let _ = self
+                    .events
+                    .send(crate::replication::events::CorruptBlock { index });
+                let _ = self.events.send(crate::replication::events::Have {
+                    start: index,
+                    length: 1,
+                    byte_length,
+                    stored: false,
+                    drop: true,
+                });
+            }
+        }
+        Ok(corrupt)
+    }
+
+    /// Check the tree, bitfield and oplog stores against the checksums kept by a storage built
+    /// with [`Storage::with_checksums`], failing with [`HypercoreError::CorruptStorage`] naming
+    /// the store and bytes of the first mismatch. Does nothing for storages without checksums.
+    #[cfg_attr(feature = "tracing", instrument(err, skip(self)))]
+    pub async fn verify_checksums(&self) -> Result<(), HypercoreError> {
+        self.storage.verify_checksums().await
+    }
+
+    /// Get the stored nodes of the merkle tree within the given range of merkle tree indexes, e.g.
+    /// for debugging or auditing. Leaves are at even indexes, block `i` being at `2 * i`. Nodes that
+    /// are missing locally or are outside of the current tree are skipped.
+    #[cfg_attr(feature = "tracing", instrument(err, skip(self)))]
+    pub async fn tree_nodes(&self, range: Range<u64>) -> Result<Vec<Node>, HypercoreError> {
+        match self.tree.get_nodes(range.clone(), None)? {
             Either::Right(value) => Ok(value),
             Either::Left(instructions) => {
                 let mut instructions = instructions;
                 let mut infos: Vec<StoreInfo> = vec![];
                 loop {
                     infos.extend(self.storage.read_infos_to_vec(&instructions).await?);
-                    match self.tree.create_valueless_proof(
-                        block.as_ref(),
-                        hash.as_ref(),
-                        seek.as_ref(),
-                        upgrade.as_ref(),
-                        Some(&infos),
-                    )? {
+                    match self.tree.get_nodes(range.clone(), Some(&infos))? {
                         Either::Right(value) => {
                             return Ok(value);
                         }
@@ -706,103 +2469,2634 @@ impl Hypercore {
         }
     }
 
-    /// Verify a proof received from a peer. Returns a changeset that should be
-    /// applied.
-    async fn verify_proof(&mut self, proof: &Proof) -> Result<MerkleTreeChangeset, HypercoreError> {
-        match self.tree.verify_proof(proof, &self.key_pair.public, None)? {
-            Either::Right(value) => Ok(value),
-            Either::Left(instructions) => {
-                let infos = self.storage.read_infos_to_vec(&instructions).await?;
-                match self
-                    .tree
-                    .verify_proof(proof, &self.key_pair.public, Some(&infos))?
-                {
-                    Either::Right(value) => Ok(value),
-                    Either::Left(_) => Err(HypercoreError::InvalidOperation {
-                        context: "Could not verify proof from tree".to_string(),
-                    }),
+    /// Get the stored node of the merkle tree at the given tree index, like
+    /// [`Hypercore::tree_nodes`] for a single node, e.g. to traverse the tree and mirror it. Returns
+    /// `None` when the node is missing locally or is outside of the current tree.
+    #[cfg_attr(feature = "tracing", instrument(err, skip(self)))]
+    pub async fn node(&self, index: u64) -> Result<Option<Node>, HypercoreError> {
+        if index >= 2 * self.tree.length {
+            return Ok(None);
+        }
+        self.tree_node(index).await
+    }
+
+    /// Load the tree nodes of the blocks in the given range into the node cache ahead of reads,
+    /// and with `blocks` also the locally stored blocks into the block cache. Caches that are not
+    /// enabled in the builder are skipped.
+    #[cfg(feature = "cache")]
+    #[cfg_attr(feature = "tracing", instrument(err, skip(self)))]
+    pub async fn preload(&self, range: Range<u64>, blocks: bool) -> Result<(), HypercoreError> {
+        let end = range.end.min(self.tree.length);
+        if range.start >= end {
+            return Ok(());
+        }
+        if self.tree.has_node_cache() {
+            self.tree_nodes(2 * range.start..2 * end).await?;
+        }
+        if blocks && self.block_cache.is_some() {
+            for index in range.start..end {
+                if self.bitfield.get(index) {
+                    self.get(index).await?;
                 }
             }
         }
+        Ok(())
     }
 
-    fn should_flush_bitfield_and_tree_and_oplog(&mut self) -> bool {
-        if self.skip_flush_count == 0
-            || self.oplog.entries_byte_length >= MAX_OPLOG_ENTRIES_BYTE_SIZE
+    /// Hint that the blocks in `range` are about to be read, e.g. by a sequential reader playing
+    /// a video or tailing a log. Purely advisory: the stored blocks are loaded into the caches
+    /// enabled in the builder, see [`Hypercore::preload`], and the missing ones are wanted at
+    /// [`crate::replication::Priority::Background`] so replicators fetch them ahead of the reads.
+    /// Only the latest hint is kept, the blocks wanted by the previous one are withdrawn.
+    #[cfg_attr(
+        not(any(feature = "cache", feature = "replication")),
+        allow(unused_variables)
+    )]
+    // Wanting the missing blocks needs `&mut self`, which is public API whether or not the
+    // `replication` feature is enabled
+    #[cfg_attr(not(feature = "replication"), allow(clippy::needless_pass_by_ref_mut))]
+    #[cfg_attr(feature = "tracing", instrument(err, skip(self)))]
+    pub async fn prefetch(&mut self, range: Range<u64>) -> Result<(), HypercoreError> {
+        #[cfg(feature = "cache")]
+        self.preload(range.clone(), true).await?;
+
+        #[cfg(feature = "replication")]
         {
-            self.skip_flush_count = 3;
-            true
-        } else {
-            self.skip_flush_count -= 1;
-            false
+            // From the first to the last missing block, light hypercores never store any
+            let missing = self
+                .bitfield
+                .index_of(false, range.start)
+                .filter(|start| *start < range.end && !self.light)
+                .map(|start| {
+                    let end = if range.end > self.tree.length {
+                        range.end
+                    } else {
+                        self.bitfield
+                            .last_index_of(false, range.end - 1)
+                            .map_or(range.end, |index| index + 1)
+                    };
+                    start..end
+                });
+            if self.prefetched != missing {
+                if let Some(prefetched) = self.prefetched.take() {
+                    self.unwant(prefetched);
+                }
+                if let Some(missing) = missing.clone() {
+                    self.want(missing, crate::replication::Priority::Background);
+                }
+                self.prefetched = missing;
+            }
         }
+        Ok(())
     }
 
-    async fn flush_bitfield_and_tree_and_oplog(
-        &mut self,
-        clear_traces: bool,
-    ) -> Result<(), HypercoreError> {
-        let infos = self.bitfield.flush();
-        self.storage.flush_infos(&infos).await?;
-        let infos = self.tree.flush();
-        self.storage.flush_infos(&infos).await?;
-        let infos = self.oplog.flush(&self.header, clear_traces)?;
-        self.storage.flush_infos(&infos).await?;
+    /// Flush the pending changes of the bitfield, tree and oplog to storage. Appends and applied
+    /// proofs are flushed on their own every few writes, this flushes the ones in between, e.g.
+    /// before handing the storage to another process. Emits [`crate::replication::events::Flush`].
+    #[cfg_attr(feature = "tracing", instrument(err, skip_all))]
+    pub async fn flush(&mut self) -> Result<(), HypercoreError> {
+        self.flush_bitfield_and_tree_and_oplog(false).await?;
+        #[cfg(feature = "replication")]
+        {
+            let _ = self.events.send(crate::replication::events::Flush {
+                length: self.tree.length,
+            });
+        }
         Ok(())
     }
-}
 
-fn update_contiguous_length(
-    header: &mut Header,
-    bitfield: &Bitfield,
-    bitfield_update: &BitfieldUpdate,
-) {
-    let end = bitfield_update.start + bitfield_update.length;
-    let mut c = header.hints.contiguous_length;
-    if bitfield_update.drop {
-        if c <= end && c > bitfield_update.start {
-            c = bitfield_update.start;
+    /// Makes the hypercore read-only by deleting the secret key. Returns true if the
+    /// hypercore was changed, false if the hypercore was already read-only. This is useful
+    /// in scenarios where a hypercore should be made immutable after initial values have
+    /// been stored.
+    #[cfg_attr(feature = "tracing", instrument(err, skip_all))]
+    pub async fn make_read_only(&mut self) -> Result<bool, HypercoreError> {
+        if self.key_pair.secret.is_some() {
+            self.key_pair.secret = None;
+            self.header.key_pair.secret = None;
+            // Need to flush clearing traces to make sure both oplog slots are cleared
+            self.flush_bitfield_and_tree_and_oplog(true).await?;
+            Ok(true)
+        } else {
+            Ok(false)
         }
-    } else if c <= end && c >= bitfield_update.start {
-        c = end;
-        while bitfield.get(c) {
-            c += 1;
+    }
+
+    /// Copy the hypercore into another, empty, storage and return the copy, e.g. to move a core
+    /// from memory to disk. The copy starts from the signed tree of this core, and every locally
+    /// stored block is then transferred as a proof, so hashes are verified as they are copied.
+    /// Blocks missing locally are missing in the copy too. The copy has the same key pair and
+    /// manifest, and is built with default options.
+    #[cfg_attr(feature = "tracing", instrument(err, skip_all))]
+    pub async fn copy_to<U: StorageTraits + Send + ?Sized>(
+        &self,
+        storage: Storage<U>,
+    ) -> Result<Hypercore<U>, HypercoreError> {
+        let mut builder = crate::HypercoreBuilder::new(storage)
+            .key_pair(self.key_pair.clone())
+            .manifest(self.header.manifest.clone());
+        if let Some(checkpoint) = self.checkpoint() {
+            builder = builder.checkpoint(checkpoint);
+        }
+        // Boxed, as building is a large future to keep on the stack of the caller
+        let mut copy = Box::pin(builder.build()).await?;
+        let mut index = self.bitfield.index_of(true, 0);
+        while let Some(block) = index.filter(|index| *index < self.tree.length) {
+            let nodes = copy.missing_nodes(block).await?;
+            let proof = self
+                .create_proof(
+                    Some(RequestBlock {
+                        index: block,
+                        nodes,
+                    }),
+                    None,
+                    None,
+                    None,
+                )
+                .await?;
+            let copied = match proof {
+                Some(proof) => copy.verify_and_apply_proof(&proof).await?,
+                None => false,
+            };
+            if !copied {
+                return Err(HypercoreError::InvalidOperation {
+                    context: format!("Could not copy block {block}"),
+                });
+            }
+            index = self.bitfield.index_of(true, block + 1);
         }
+        copy.flush_bitfield_and_tree_and_oplog(false).await?;
+        Ok(copy)
     }
 
-    if c != header.hints.contiguous_length {
-        header.hints.contiguous_length = c;
+    /// Write the whole hypercore into `writer` as a single archive, to move it as one file or
+    /// blob: the key, manifest and signed tree, and every locally stored block with the tree
+    /// nodes proving it. The secret key is never exported. Read it back with
+    /// [`Hypercore::import`].
+    #[cfg_attr(feature = "tracing", instrument(err, skip_all))]
+    pub async fn export<W: futures::io::AsyncWrite + Unpin>(
+        &self,
+        mut writer: W,
+    ) -> Result<(), HypercoreError> {
+        let header = ArchiveHeader {
+            key: self.key_pair.public,
+            manifest: self.header.manifest.clone(),
+            checkpoint: self.checkpoint(),
+            blocks: self.present_ranges(0..self.tree.length),
+        };
+        archive::write_magic(&mut writer).await?;
+        archive::write_record(&mut writer, &Record::Header(header.clone())).await?;
+        self.export_blocks(&mut writer, header.blocks).await
     }
-}
 
-#[cfg(test)]
-pub(crate) mod tests {
-    use super::*;
+    /// Create a hypercore in the empty `storage` from an archive written by
+    /// [`Hypercore::export`]. The signature of the tree is verified against the public key of the
+    /// archive, and each block against the tree, so a tampered archive is rejected. The imported
+    /// core has no secret key. On error the storage may hold a partial import.
+    #[cfg_attr(feature = "tracing", instrument(err, skip_all))]
+    pub async fn import<R: futures::io::AsyncRead + Unpin>(
+        storage: Storage<T>,
+        mut reader: R,
+    ) -> Result<Self, HypercoreError> {
+        let header = archive::read_header(&mut reader).await?;
+        let mut builder = crate::HypercoreBuilder::new(storage)
+            .verifying_key(header.key)
+            .manifest(header.manifest);
+        match header.checkpoint {
+            Some(checkpoint) => builder = builder.checkpoint(checkpoint),
+            None if !header.blocks.is_empty() => {
+                return Err(archive::invalid_archive("blocks without a signed tree"));
+            }
+            None => {}
+        }
+        // Boxed, as building is a large future to keep on the stack of the caller
+        let mut core = Box::pin(builder.build()).await?;
+        core.import_blocks(&mut reader, header.blocks).await?;
+        Ok(core)
+    }
 
-    #[async_std::test]
-    async fn core_create_proof_block_only() -> Result<(), HypercoreError> {
-        let mut hypercore = create_hypercore_with_data(10).await?;
+    /// Write an incremental backup of what was added since the core had `length` blocks into
+    /// `writer`: the tree nodes and signature upgrading a core of `length` blocks to the current
+    /// length, and the locally stored blocks from `length`. Applied with
+    /// [`Hypercore::apply_backup`] to a copy that was backed up up to `length`.
+    #[cfg_attr(feature = "tracing", instrument(err, skip(self, writer)))]
+    pub async fn export_since<W: futures::io::AsyncWrite + Unpin>(
+        &self,
+        length: u64,
+        mut writer: W,
+    ) -> Result<(), HypercoreError> {
+        if length > self.tree.length {
+            return Err(HypercoreError::BadArgument {
+                context: format!(
+                    "Backup from length {length} past the length of the core {}",
+                    self.tree.length
+                ),
+            });
+        }
+        let upgrade = if length < self.tree.length {
+            let upgrade = RequestUpgrade {
+                start: length,
+                length: self.tree.length - length,
+            };
+            self.create_proof(None, None, None, Some(upgrade)).await?
+        } else {
+            None
+        };
+        let header = BackupHeader {
+            key: self.key_pair.public,
+            since: length,
+            upgrade,
+            blocks: self.present_ranges(length..self.tree.length),
+        };
+        archive::write_magic(&mut writer).await?;
+        archive::write_record(&mut writer, &Record::Backup(header.clone())).await?;
+        self.export_blocks(&mut writer, header.blocks).await
+    }
 
-        let proof = hypercore
-            .create_proof(Some(RequestBlock { index: 4, nodes: 2 }), None, None, None)
-            .await?
-            .unwrap();
-        let block = proof.block.unwrap();
-        assert_eq!(proof.upgrade, None);
-        assert_eq!(proof.seek, None);
-        assert_eq!(block.index, 4);
-        assert_eq!(block.nodes.len(), 2);
-        assert_eq!(block.nodes[0].index, 10);
-        assert_eq!(block.nodes[1].index, 13);
+    /// Apply an incremental backup written by [`Hypercore::export_since`]. The core must have
+    /// the key of the backup and the length the backup starts from, e.g. by applying the
+    /// backups in the order they were taken. The whole backup is verified against the signed
+    /// head of the core before anything is applied, so a tampered or cut backup leaves the core
+    /// as it was.
+    #[cfg_attr(feature = "tracing", instrument(err, skip_all))]
+    pub async fn apply_backup<R: futures::io::AsyncRead + Unpin>(
+        &mut self,
+        mut reader: R,
+    ) -> Result<RestoreOutcome, HypercoreError> {
+        let header = archive::read_backup_header(&mut reader).await?;
+        self.check_archive_key(&header.key)?;
+        if header.since != self.tree.length {
+            return Err(HypercoreError::BadArgument {
+                context: format!(
+                    "The backup starts at length {} but the core has length {}",
+                    header.since, self.tree.length
+                ),
+            });
+        }
+        let mut staged = self.staging_core(self.checkpoint()).await?;
+        if let Some(upgrade) = &header.upgrade {
+            let upgraded = upgrade.block.is_none()
+                && upgrade
+                    .upgrade
+                    .as_ref()
+                    .is_some_and(|upgrade| upgrade.start == header.since)
+                && staged.verify_and_apply_proof(upgrade).await?;
+            if !upgraded {
+                return Err(archive::invalid_archive("could not verify the upgrade"));
+            }
+        } else if !header.blocks.is_empty() {
+            return Err(archive::invalid_archive("blocks without an upgrade"));
+        }
+        staged.import_blocks(&mut reader, header.blocks).await?;
+        self.apply_staged(&staged).await
+    }
+
+    /// Restore the blocks of an archive written by [`Hypercore::export`] into this core, which
+    /// must have the key of the archive and be either empty or at the signed head of the
+    /// archive, e.g. to recover blocks that were cleared or lost. The whole archive is verified
+    /// against its signed head before anything is applied, so a tampered or cut archive leaves
+    /// the core as it was. Blocks the core already has are skipped.
+    #[cfg_attr(feature = "tracing", instrument(err, skip_all))]
+    pub async fn restore<R: futures::io::AsyncRead + Unpin>(
+        &mut self,
+        mut reader: R,
+    ) -> Result<RestoreOutcome, HypercoreError> {
+        let header = archive::read_header(&mut reader).await?;
+        self.check_archive_key(&header.key)?;
+        if header.manifest != self.header.manifest {
+            return Err(HypercoreError::KeyMismatch {
+                context: "The archive has another manifest than the hypercore".to_string(),
+            });
+        }
+        let local = self.checkpoint();
+        if self.tree.length != 0 && header.checkpoint != local {
+            return Err(HypercoreError::BadArgument {
+                context: format!(
+                    "The archive is at length {} but the core is at another head of length {}",
+                    header
+                        .checkpoint
+                        .as_ref()
+                        .map_or(0, |checkpoint| checkpoint.length),
+                    self.tree.length
+                ),
+            });
+        }
+        if header.checkpoint.is_none() && !header.blocks.is_empty() {
+            return Err(archive::invalid_archive("blocks without a signed tree"));
+        }
+        let mut staged = self.staging_core(header.checkpoint).await?;
+        staged.import_blocks(&mut reader, header.blocks).await?;
+        self.apply_staged(&staged).await
+    }
+
+    fn check_archive_key(&self, key: &VerifyingKey) -> Result<(), HypercoreError> {
+        if *key != self.key_pair.public {
+            return Err(HypercoreError::KeyMismatch {
+                context: "The archive is of another hypercore".to_string(),
+            });
+        }
         Ok(())
     }
 
-    #[async_std::test]
+    /// In-memory core with the key of this core, starting from `checkpoint`, to verify an archive
+    /// in before applying it
+    async fn staging_core(
+        &self,
+        checkpoint: Option<Checkpoint>,
+    ) -> Result<Hypercore, HypercoreError> {
+        let mut builder = crate::HypercoreBuilder::new(Storage::new_memory().await?)
+            .verifying_key(self.key_pair.public)
+            .manifest(self.header.manifest.clone());
+        if let Some(checkpoint) = checkpoint {
+            builder = builder.checkpoint(checkpoint);
+        }
+        Box::pin(builder.build()).await
+    }
+
+    /// Apply the verified state of `staged`, which starts from the head of this core or from a
+    /// head this core can be upgraded to: the upgrade to its length and the blocks this core
+    /// doesn't have
+    async fn apply_staged(&mut self, staged: &Hypercore) -> Result<RestoreOutcome, HypercoreError> {
+        let mut outcome = RestoreOutcome::default();
+        if staged.tree.length > self.tree.length {
+            let upgrade = RequestUpgrade {
+                start: self.tree.length,
+                length: staged.tree.length - self.tree.length,
+            };
+            let proof = staged.create_proof(None, None, None, Some(upgrade)).await?;
+            let upgraded = match proof {
+                Some(proof) => self.verify_and_apply_proof(&proof).await?,
+                None => false,
+            };
+            if !upgraded {
+                return Err(HypercoreError::InvalidOperation {
+                    context: format!("Could not upgrade to length {}", staged.tree.length),
+                });
+            }
+        }
+        for block in staged
+            .present_ranges(0..staged.tree.length)
+            .into_iter()
+            .flatten()
+        {
+            if self.has(block) {
+                outcome.skipped += 1;
+                continue;
+            }
+            let nodes = self.missing_nodes(block).await?;
+            let proof = staged
+                .create_proof(
+                    Some(RequestBlock {
+                        index: block,
+                        nodes,
+                    }),
+                    None,
+                    None,
+                    None,
+                )
+                .await?;
+            let applied = match proof {
+                Some(proof) => self.verify_and_apply_proof(&proof).await?,
+                None => false,
+            };
+            if !applied {
+                return Err(HypercoreError::InvalidOperation {
+                    context: format!("Could not restore block {block}"),
+                });
+            }
+            outcome.added += 1;
+        }
+        self.flush_bitfield_and_tree_and_oplog(false).await?;
+        Ok(outcome)
+    }
+
+    /// Write a block record for each block in `blocks`, then the end record
+    async fn export_blocks<W: futures::io::AsyncWrite + Unpin>(
+        &self,
+        writer: &mut W,
+        blocks: Vec<Range<u64>>,
+    ) -> Result<(), HypercoreError> {
+        for range in blocks {
+            let mut proofs = self.create_proof_stream(range, EXPORT_MEMORY_BUDGET);
+            while let Some(proof) = proofs.try_next().await? {
+                archive::write_record(writer, &Record::Block(proof)).await?;
+            }
+        }
+        archive::write_record(writer, &Record::End).await?;
+        writer.flush().await?;
+        Ok(())
+    }
+
+    /// Verify and apply the block records up to the end record, which must be the `blocks`
+    /// announced in the header
+    async fn import_blocks<R: futures::io::AsyncRead + Unpin>(
+        &mut self,
+        reader: &mut R,
+        blocks: Vec<Range<u64>>,
+    ) -> Result<(), HypercoreError> {
+        let mut blocks = blocks.into_iter().flatten();
+        loop {
+            match archive::read_record(reader).await? {
+                Record::Block(proof) => {
+                    let index = archive::check_block(&proof, blocks.next())?;
+                    if !self.verify_and_apply_proof(&proof).await? {
+                        return Err(archive::invalid_archive(format!(
+                            "could not verify block {index}"
+                        )));
+                    }
+                }
+                Record::End => break,
+                Record::Header(_) | Record::Backup(_) => {
+                    return Err(archive::invalid_archive("second header"));
+                }
+            }
+        }
+        if let Some(index) = blocks.next() {
+            return Err(archive::invalid_archive(format!(
+                "block {index} is missing"
+            )));
+        }
+        self.flush_bitfield_and_tree_and_oplog(false).await
+    }
+
+    /// Ranges of locally stored blocks within `range`
+    fn present_ranges(&self, range: Range<u64>) -> Vec<Range<u64>> {
+        let mut ranges = vec![];
+        let mut start = self.bitfield.index_of(true, range.start);
+        while let Some(first) = start.filter(|index| *index < range.end) {
+            let end = self
+                .bitfield
+                .index_of(false, first)
+                .map_or(range.end, |end| end.min(range.end));
+            ranges.push(first..end);
+            start = self.bitfield.index_of(true, end);
+        }
+        ranges
+    }
+
+    /// Errors if the proof upgrades the fork of a detected equivocation, or if its upgrade from the
+    /// start conflicts with the local tree, recording the equivocation. The trees are compared at
+    /// the shorter of both lengths. When the remote tree is longer, that needs its nodes at the
+    /// local roots, e.g. from a block of the proof, otherwise the proof is left to the usual
+    /// verification.
+    async fn check_equivocation(&mut self, proof: &Proof) -> Result<(), HypercoreError> {
+        let Some(upgrade) = &proof.upgrade else {
+            return Ok(());
+        };
+        if let Some(equivocation) = &self.equivocation
+            && equivocation.local.fork == proof.fork
+        {
+            return Err(HypercoreError::Equivocation {
+                fork: equivocation.local.fork,
+                length: equivocation.local.length,
+            });
+        }
+        // Only a tree from the start can be compared on its own
+        if upgrade.start != 0 {
+            return Ok(());
+        }
+        let Some(local) = self.checkpoint() else {
+            return Ok(());
+        };
+        // An invalid proof fails the usual verification
+        let Ok(remote) = self.tree.verify_remote_tree(proof, &self.key_pair.public) else {
+            return Ok(());
+        };
+        let mut nodes = vec![];
+        if remote.length > local.length {
+            for index in root_path_indexes(local.length, remote.length) {
+                match remote.nodes.iter().find(|node| node.index == index) {
+                    Some(node) => nodes.push(node.clone()),
+                    None => return Ok(()),
+                }
+            }
+        } else if remote.length < local.length {
+            for index in root_path_indexes(remote.length, local.length) {
+                match self.tree_node(index).await? {
+                    Some(node) => nodes.push(node),
+                    None => return Ok(()),
+                }
+            }
+        }
+        let equivocation = Equivocation {
+            local,
+            remote: Checkpoint {
+                fork: remote.fork,
+                length: remote.length,
+                roots: remote.roots,
+                signature: remote
+                    .signature
+                    .expect("Verified changeset is signed")
+                    .to_bytes()
+                    .to_vec(),
+            },
+            nodes,
+        };
+        if equivocation.verify(&self.key_pair.public).is_err() {
+            return Ok(());
+        }
+        #[cfg(feature = "replication")]
+        let _ = self.events.send(equivocation.clone());
+        let err = HypercoreError::Equivocation {
+            fork: equivocation.local.fork,
+            length: equivocation.local.length,
+        };
+        self.equivocation = Some(equivocation);
+        Err(err)
+    }
+
+    #[cfg_attr(not(feature = "cache"), allow(unused_variables))]
+    /// Errors if committing the changeset would exceed the quota
+    fn check_quota(&self, changeset: &MerkleTreeChangeset) -> Result<(), HypercoreError> {
+        match self.quota {
+            Some(quota) if !quota.allows(changeset.length, changeset.byte_length) => {
+                Err(HypercoreError::QuotaExceeded {
+                    length: changeset.length,
+                    byte_length: changeset.byte_length,
+                    quota,
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Emit a quota warning if the tree crossed the warning percentage of the quota since it had
+    /// the given length and byte length
+    #[cfg(feature = "replication")]
+    fn emit_quota_warning(&self, (old_length, old_byte_length): (u64, u64)) {
+        if let Some(quota) = self.quota
+            && quota.warns(self.tree.length, self.tree.byte_length)
+            && !quota.warns(old_length, old_byte_length)
+        {
+            let _ = self.events.send(crate::replication::events::QuotaWarning {
+                length: self.tree.length,
+                byte_length: self.tree.byte_length,
+                quota,
+            });
+        }
+    }
+
+    /// Clear the blocks older than the retention, keeping the tree. Clearing starts from the
+    /// first stored block, so the blocks cleared before, e.g. before the core was reopened, aren't
+    /// cleared again.
+    async fn apply_retention(&mut self) -> Result<(), HypercoreError> {
+        let Some(retention) = self.retention else {
+            return Ok(());
+        };
+        let retain_from = match retention {
+            Retention::Blocks(blocks) => self.tree.length.saturating_sub(blocks),
+            Retention::Bytes(bytes) if bytes >= self.tree.byte_length => 0,
+            Retention::Bytes(0) => self.tree.length,
+            // The first block starting at or after the cutoff
+            Retention::Bytes(bytes) => match self.seek(self.tree.byte_length - bytes).await? {
+                (index, 0) => index,
+                (index, _) => index + 1,
+            },
+        };
+        if let Some(first_stored) = self
+            .bitfield
+            .index_of(true, 0)
+            .filter(|index| *index < retain_from)
+        {
+            self.clear(first_stored, retain_from).await?;
+        }
+        Ok(())
+    }
+
+    /// True if the verified block at `index` is written to the data store
+    fn persists(&self, index: u64, value: &[u8]) -> bool {
+        !self.light
+            && self
+                .persistence_policy
+                .as_ref()
+                .is_none_or(|policy| policy.persist(index, value))
+    }
+
+    #[cfg_attr(not(feature = "cache"), allow(unused_variables))]
+    fn invalidate_block_cache(&self, range: Range<u64>) {
+        #[cfg(feature = "cache")]
+        if let Some(cache) = self.block_cache.as_ref() {
+            for (index, _) in cache.iter() {
+                if range.contains(&index) {
+                    cache.invalidate(&*index);
+                }
+            }
+        }
+    }
+
+    fn invalidate_proof_cache(&self) {
+        #[cfg(feature = "cache")]
+        if let Some(cache) = self.proof_cache.as_ref() {
+            cache.invalidate_all();
+        }
+    }
+
+    async fn read_block(&self, byte_range: &NodeByteRange) -> Result<Box<[u8]>, HypercoreError> {
+        // TODO: Generalize Either response stack
+        match self.block_store.read(byte_range, None) {
+            Either::Right(value) => Ok(value),
+            Either::Left(instruction) => {
+                let info = self.storage.read_info(instruction).await?;
+                match self.block_store.read(byte_range, Some(info)) {
+                    Either::Right(value) => Ok(value),
+                    Either::Left(_) => Err(HypercoreError::InvalidOperation {
+                        context: "Could not read block storage range".to_string(),
+                    }),
+                }
+            }
+        }
+    }
+
+    /// Byte range in the block store holding the bytes of a stored block, which is that of
+    /// another block with the same content when deduplicating
+    async fn stored_byte_range(&self, index: u64) -> Result<NodeByteRange, HypercoreError> {
+        let Some(dedup) = self.dedup.as_ref() else {
+            return self.byte_range(index, None).await;
+        };
+        let source = match self.tree_node(2 * index).await? {
+            Some(node) => dedup.source(&node.hash).unwrap_or(index),
+            None => index,
+        };
+        self.byte_range(source, None).await
+    }
+
+    /// Enable deduplication if requested or if the hypercore was built with it before, and index
+    /// the stored blocks by hash
+    async fn open_dedup_index(&mut self, deduplicate: bool) -> Result<(), HypercoreError> {
+        let marked = self
+            .header
+            .user_data
+            .iter()
+            .any(|value| value == DEDUPLICATE_USER_DATA);
+        if !deduplicate && !marked {
+            return Ok(());
+        }
+        let mut dedup = DedupIndex::default();
+        let mut index = self.bitfield.index_of(true, 0);
+        while let Some(block) = index.filter(|block| *block < self.tree.length) {
+            if let Some(node) = self.tree_node(2 * block).await? {
+                dedup.insert(&node.hash, block);
+            }
+            index = self.bitfield.index_of(true, block + 1);
+        }
+        self.dedup = Some(dedup);
+        if !marked {
+            self.header
+                .user_data
+                .push(DEDUPLICATE_USER_DATA.to_string());
+            self.flush_bitfield_and_tree_and_oplog(false).await?;
+        }
+        Ok(())
+    }
+
+    /// Copy the bytes of the blocks between start and end (exclusive) that other blocks reference
+    /// to the first of them that remains stored. Returns the blocks to remove from the
+    /// deduplication index once cleared.
+    async fn move_deduplicated_blocks(
+        &mut self,
+        start: u64,
+        end: u64,
+    ) -> Result<Vec<(u64, Vec<u8>)>, HypercoreError> {
+        if self.dedup.is_none() {
+            return Ok(vec![]);
+        }
+        let mut cleared = vec![];
+        let mut index = self.bitfield.index_of(true, start);
+        while let Some(block) = index.filter(|block| *block < end.min(self.tree.length)) {
+            if let Some(node) = self.tree_node(2 * block).await? {
+                cleared.push((block, node.hash));
+            }
+            index = self.bitfield.index_of(true, block + 1);
+        }
+        for (block, hash) in &cleared {
+            let dedup = self.dedup.as_ref().expect("Deduplicating");
+            if dedup.source(hash) == Some(*block)
+                && let Some(next) = dedup.next(hash, end)
+            {
+                let value = self
+                    .read_block(&self.byte_range(*block, None).await?)
+                    .await?;
+                let byte_range = self.byte_range(next, None).await?;
+                let info = self.block_store.put(&value, byte_range.index);
+                self.storage.flush_info(info).await?;
+            }
+        }
+        Ok(cleared)
+    }
+
+    /// Index the byte ranges of the first blocks whose leaves are stored, reading them in batches
+    async fn open_byte_index(&mut self) -> Result<(), HypercoreError> {
+        const BATCH: u64 = 1024;
+        let mut byte_index = ByteIndex::default();
+        'batches: while byte_index.len() < self.tree.length {
+            let start = byte_index.len();
+            let end = (start + BATCH).min(self.tree.length);
+            let nodes = self.tree_nodes(2 * start..2 * end).await?;
+            for node in nodes.iter().filter(|node| node.index % 2 == 0) {
+                if node.index != 2 * byte_index.len() {
+                    break 'batches;
+                }
+                byte_index.push(node.length);
+            }
+            if byte_index.len() < end {
+                break;
+            }
+        }
+        self.byte_index = Some(byte_index);
+        Ok(())
+    }
+
+    /// Index the blocks following the indexed ones for as long as their leaves are stored
+    async fn extend_byte_index(&mut self) -> Result<(), HypercoreError> {
+        while let Some(next) = self
+            .byte_index
+            .as_ref()
+            .map(ByteIndex::len)
+            .filter(|next| *next < self.tree.length)
+        {
+            let Some(node) = self.tree_node(2 * next).await? else {
+                break;
+            };
+            self.byte_index
+                .as_mut()
+                .expect("Indexing")
+                .push(node.length);
+        }
+        Ok(())
+    }
+
+    async fn tree_node(&self, index: u64) -> Result<Option<Node>, HypercoreError> {
+        match self.tree.get_node(index, None)? {
+            Either::Right(value) => Ok(value),
+            Either::Left(instructions) => {
+                let infos = self.storage.read_infos_to_vec(&instructions).await?;
+                match self.tree.get_node(index, Some(&infos))? {
+                    Either::Right(value) => Ok(value),
+                    Either::Left(_) => Err(HypercoreError::InvalidOperation {
+                        context: format!("Could not read node {index} from tree"),
+                    }),
+                }
+            }
+        }
+    }
+
+    /// Checks the hash of the given block value and the hashes of its ancestors up to a root.
+    /// Ancestors that verified are added to `verified_ancestors` so that they need to be checked
+    /// only once.
+    async fn verify_block(
+        &self,
+        index: u64,
+        value: &[u8],
+        verified_ancestors: &mut HashSet<u64>,
+    ) -> Result<bool, HypercoreError> {
+        let leaf_index = index * 2;
+        let Some(mut node) = self.tree_node(leaf_index).await? else {
+            return Ok(false);
+        };
+        if node.length != value.len() as u64 || node.hash != Hash::data(value).as_bytes() {
+            return Ok(false);
+        }
+
+        let mut iter = flat_tree::Iterator::new(leaf_index);
+        let mut chain: Vec<u64> = Vec::new();
+        let valid = loop {
+            if verified_ancestors.contains(&node.index) {
+                break true;
+            }
+            if let Some(root) = self.tree.roots.iter().find(|root| root.index == node.index) {
+                break root.hash == node.hash;
+            }
+            chain.push(node.index);
+            let sibling = self.tree_node(iter.sibling()).await?;
+            let parent = self.tree_node(iter.parent()).await?;
+            let (Some(sibling), Some(parent)) = (sibling, parent) else {
+                break false;
+            };
+            if parent.length != node.length + sibling.length
+                || parent.hash != Hash::parent(&node, &sibling).as_bytes()
+            {
+                break false;
+            }
+            node = parent;
+        };
+        if valid {
+            verified_ancestors.extend(chain.into_iter().filter(|i| *i != leaf_index));
+        }
+        Ok(valid)
+    }
+
+    async fn byte_range(
+        &self,
+        index: u64,
+        initial_infos: Option<&[StoreInfo]>,
+    ) -> Result<NodeByteRange, HypercoreError> {
+        if let Some(value) = self
+            .byte_index
+            .as_ref()
+            .and_then(|byte_index| byte_index.byte_range(index))
+        {
+            return Ok(value);
+        }
+        match self.tree.byte_range(index, initial_infos)? {
+            Either::Right(value) => Ok(value),
+            Either::Left(instructions) => {
+                let mut instructions = instructions;
+                let mut infos: Vec<StoreInfo> = vec![];
+                loop {
+                    infos.extend(self.storage.read_infos_to_vec(&instructions).await?);
+                    match self.tree.byte_range(index, Some(&infos))? {
+                        Either::Right(value) => {
+                            return Ok(value);
+                        }
+                        Either::Left(new_instructions) => {
+                            instructions = new_instructions;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Verify a proof received from a peer. Returns a changeset that should be
+    /// applied.
+    async fn verify_proof(&self, proof: &Proof) -> Result<MerkleTreeChangeset, HypercoreError> {
+        match self.tree.verify_proof(proof, &self.key_pair.public, None)? {
+            Either::Right(value) => Ok(value),
+            Either::Left(instructions) => {
+                let infos = self.storage.read_infos_to_vec(&instructions).await?;
+                match self
+                    .tree
+                    .verify_proof(proof, &self.key_pair.public, Some(&infos))?
+                {
+                    Either::Right(value) => Ok(value),
+                    Either::Left(_) => Err(HypercoreError::InvalidOperation {
+                        context: "Could not verify proof from tree".to_string(),
+                    }),
+                }
+            }
+        }
+    }
+
+    /// Initialize an empty tree from a checkpoint, verifying its signature. The checkpoint is an
+    /// upgrade from zero to its length containing only the roots.
+    async fn restore_checkpoint(&mut self, checkpoint: Checkpoint) -> Result<(), HypercoreError> {
+        if self.tree.length != 0 {
+            return Err(HypercoreError::BadArgument {
+                context: "Checkpoint can only be restored into an empty hypercore".to_string(),
+            });
+        }
+        let proof = Proof {
+            fork: checkpoint.fork,
+            block: None,
+            hash: None,
+            seek: None,
+            upgrade: Some(DataUpgrade {
+                start: 0,
+                length: checkpoint.length,
+                nodes: checkpoint.roots,
+                additional_nodes: vec![],
+                signature: checkpoint.signature,
+            }),
+        };
+        let changeset = self.verify_proof(&proof).await?;
+        let outcome = self
+            .oplog
+            .append_changeset(&changeset, None, false, &self.header)?;
+        let commit = outcome.write.flush(&mut self.storage).await?;
+        self.header = outcome.header;
+        self.oplog.commit(commit);
+        self.tree.commit(changeset)?;
+        self.flush_bitfield_and_tree_and_oplog(false).await
+    }
+
+    fn should_flush_bitfield_and_tree_and_oplog(&mut self) -> bool {
+        if self.skip_flush_count == 0
+            || self.oplog.entries_byte_length >= MAX_OPLOG_ENTRIES_BYTE_SIZE
+        {
+            self.skip_flush_count = 3;
+            true
+        } else {
+            self.skip_flush_count -= 1;
+            false
+        }
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        instrument(err, skip(self), fields(discovery_key = %self.discovery_key))
+    )]
+    async fn flush_bitfield_and_tree_and_oplog(
+        &mut self,
+        clear_traces: bool,
+    ) -> Result<(), HypercoreError> {
+        let infos = self.bitfield.flush();
+        self.storage.flush_infos(&infos).await?;
+        let infos = self.tree.flush();
+        self.storage.flush_infos(&infos).await?;
+        let outcome = self.oplog.flush(&self.header, clear_traces)?;
+        for write in outcome.headers {
+            // The header commits the flush, one dropped before it is redone by the next flush
+            let commit = write.flush(&mut self.storage).await?;
+            self.oplog.commit(commit);
+        }
+        self.bitfield.flushed();
+        self.tree.flushed();
+        self.storage.flush_info(outcome.truncate).await?;
+        Ok(())
+    }
+}
+
+fn update_contiguous_length(
+    header: &mut Header,
+    bitfield: &Bitfield,
+    bitfield_update: &BitfieldUpdate,
+) {
+    let end = bitfield_update.start + bitfield_update.length;
+    let mut c = header.hints.contiguous_length;
+    if bitfield_update.drop {
+        if c <= end && c > bitfield_update.start {
+            c = bitfield_update.start;
+        }
+    } else if c <= end && c >= bitfield_update.start {
+        c = end;
+        while bitfield.get(c) {
+            c += 1;
+        }
+    }
+
+    if c != header.hints.contiguous_length {
+        header.hints.contiguous_length = c;
+    }
+}
+
+impl Hypercore {
+    /// Move the hypercore to disk storage in `dir`, e.g. to keep a core that was created in memory.
+    /// Pending changes are flushed, the stores are copied as they are into the new storage, and
+    /// the hypercore switches to it only once everything is copied. The stores in `dir` must be
+    /// empty. The key and the open handle of the core are unchanged.
+    #[cfg(all(
+        not(target_arch = "wasm32"),
+        any(feature = "tokio", feature = "async-std")
+    ))]
+    #[cfg_attr(feature = "tracing", instrument(err, skip(self)))]
+    pub async fn persist_to_disk(&mut self, dir: &std::path::Path) -> Result<(), HypercoreError> {
+        self.flush_bitfield_and_tree_and_oplog(false).await?;
+        let mut storage = Storage::new_disk(dir, false).await?;
+        self.storage.copy_into(&mut storage).await?;
+        self.storage = storage;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::ErrorKind;
+    use futures::FutureExt;
+    use rand::{SeedableRng, rngs::StdRng};
+    use random_access_memory::RandomAccessMemory;
+    use random_access_storage::{RandomAccess, RandomAccessError};
+    use std::sync::atomic::AtomicBool;
+
+    /// Memory store shared with the stores reopening it, yielding to the executor before every
+    /// operation so that operations can be dropped at each of their await points. Its writes fail
+    /// while `fail_writes` is set.
+    #[derive(Debug, Clone, Default)]
+    struct YieldingStore {
+        memory: Arc<futures::lock::Mutex<RandomAccessMemory>>,
+        fail_writes: Arc<AtomicBool>,
+    }
+
+    impl YieldingStore {
+        async fn yield_now(&self, write: bool) -> Result<(), RandomAccessError> {
+            async_std::task::yield_now().await;
+            if write && self.fail_writes.load(Ordering::Relaxed) {
+                return Err(std::io::Error::other("Failing writes").into());
+            }
+            Ok(())
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl RandomAccess for YieldingStore {
+        async fn write(&mut self, offset: u64, data: &[u8]) -> Result<(), RandomAccessError> {
+            self.yield_now(true).await?;
+            self.memory.lock().await.write(offset, data).await
+        }
+
+        async fn read(&mut self, offset: u64, length: u64) -> Result<Vec<u8>, RandomAccessError> {
+            self.yield_now(false).await?;
+            self.memory.lock().await.read(offset, length).await
+        }
+
+        async fn del(&mut self, offset: u64, length: u64) -> Result<(), RandomAccessError> {
+            self.yield_now(true).await?;
+            self.memory.lock().await.del(offset, length).await
+        }
+
+        async fn truncate(&mut self, length: u64) -> Result<(), RandomAccessError> {
+            self.yield_now(true).await?;
+            self.memory.lock().await.truncate(length).await
+        }
+
+        async fn len(&mut self) -> Result<u64, RandomAccessError> {
+            self.yield_now(false).await?;
+            self.memory.lock().await.len().await
+        }
+
+        async fn is_empty(&mut self) -> Result<bool, RandomAccessError> {
+            self.yield_now(false).await?;
+            self.memory.lock().await.is_empty().await
+        }
+
+        async fn sync_all(&mut self) -> Result<(), RandomAccessError> {
+            self.yield_now(false).await?;
+            self.memory.lock().await.sync_all().await
+        }
+    }
+
+    /// Open the hypercore in the given tree, data, bitfield and oplog stores
+    async fn open_yielding(
+        stores: &[YieldingStore; 4],
+        key_pair: PartialKeypair,
+        deduplicate: bool,
+    ) -> Result<Hypercore<YieldingStore>, HypercoreError> {
+        let storage = Storage::open(
+            |store| {
+                let store = match store {
+                    Store::Tree => stores[0].clone(),
+                    Store::Data => stores[1].clone(),
+                    Store::Bitfield => stores[2].clone(),
+                    Store::Oplog => stores[3].clone(),
+                };
+                async move { Ok(Box::new(store)) }.boxed()
+            },
+            false,
+        )
+        .await?;
+        crate::HypercoreBuilder::new(storage)
+            .key_pair(key_pair)
+            .deduplicate(deduplicate)
+            .build()
+            .await
+    }
+
+    /// Drop `operation` after polling it `polls` times, returning whether it completed
+    async fn poll_and_drop<F: Future>(operation: F, polls: usize) -> bool {
+        let mut operation = std::pin::pin!(operation);
+        for _ in 0..polls {
+            if futures::poll!(operation.as_mut()).is_ready() {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Check that reopening the stores of `core` gives the same hypercore
+    async fn assert_reopens_same(
+        core: Hypercore<YieldingStore>,
+        stores: &[YieldingStore; 4],
+        deduplicate: bool,
+    ) -> Result<(), HypercoreError> {
+        let info = core.info();
+        let mut blocks = vec![];
+        for index in 0..info.length {
+            blocks.push(core.get(index).await?);
+        }
+        let key_pair = core.key_pair().clone();
+        drop(core);
+        let core = open_yielding(stores, key_pair, deduplicate).await?;
+        assert_eq!(core.info(), info);
+        for (index, block) in blocks.into_iter().enumerate() {
+            assert_eq!(core.get(index as u64).await?, block);
+        }
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn core_create_proof_block_only() -> Result<(), HypercoreError> {
+        let hypercore = create_hypercore_with_data(10).await?;
+
+        let proof = hypercore
+            .create_proof(Some(RequestBlock { index: 4, nodes: 2 }), None, None, None)
+            .await?
+            .unwrap();
+        let block = proof.block.unwrap();
+        assert_eq!(proof.upgrade, None);
+        assert_eq!(proof.seek, None);
+        assert_eq!(block.index, 4);
+        assert_eq!(block.nodes.len(), 2);
+        assert_eq!(block.nodes[0].index, 10);
+        assert_eq!(block.nodes[1].index, 13);
+        Ok(())
+    }
+
+    #[async_std::test]
     async fn core_create_proof_block_and_upgrade() -> Result<(), HypercoreError> {
+        let hypercore = create_hypercore_with_data(10).await?;
+        let proof = hypercore
+            .create_proof(
+                Some(RequestBlock { index: 4, nodes: 0 }),
+                None,
+                None,
+                Some(RequestUpgrade {
+                    start: 0,
+                    length: 10,
+                }),
+            )
+            .await?
+            .unwrap();
+        let block = proof.block.unwrap();
+        let upgrade = proof.upgrade.unwrap();
+        assert_eq!(proof.seek, None);
+        assert_eq!(block.index, 4);
+        assert_eq!(block.nodes.len(), 3);
+        assert_eq!(block.nodes[0].index, 10);
+        assert_eq!(block.nodes[1].index, 13);
+        assert_eq!(block.nodes[2].index, 3);
+        assert_eq!(upgrade.start, 0);
+        assert_eq!(upgrade.length, 10);
+        assert_eq!(upgrade.nodes.len(), 1);
+        assert_eq!(upgrade.nodes[0].index, 17);
+        assert_eq!(upgrade.additional_nodes.len(), 0);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn core_create_proof_block_and_upgrade_and_additional() -> Result<(), HypercoreError> {
+        let hypercore = create_hypercore_with_data(10).await?;
+        let proof = hypercore
+            .create_proof(
+                Some(RequestBlock { index: 4, nodes: 0 }),
+                None,
+                None,
+                Some(RequestUpgrade {
+                    start: 0,
+                    length: 8,
+                }),
+            )
+            .await?
+            .unwrap();
+        let block = proof.block.unwrap();
+        let upgrade = proof.upgrade.unwrap();
+        assert_eq!(proof.seek, None);
+        assert_eq!(block.index, 4);
+        assert_eq!(block.nodes.len(), 3);
+        assert_eq!(block.nodes[0].index, 10);
+        assert_eq!(block.nodes[1].index, 13);
+        assert_eq!(block.nodes[2].index, 3);
+        assert_eq!(upgrade.start, 0);
+        assert_eq!(upgrade.length, 8);
+        assert_eq!(upgrade.nodes.len(), 0);
+        assert_eq!(upgrade.additional_nodes.len(), 1);
+        assert_eq!(upgrade.additional_nodes[0].index, 17);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn core_create_proof_block_and_upgrade_from_existing_state() -> Result<(), HypercoreError>
+    {
+        let hypercore = create_hypercore_with_data(10).await?;
+        let proof = hypercore
+            .create_proof(
+                Some(RequestBlock { index: 1, nodes: 0 }),
+                None,
+                None,
+                Some(RequestUpgrade {
+                    start: 1,
+                    length: 9,
+                }),
+            )
+            .await?
+            .unwrap();
+        let block = proof.block.unwrap();
+        let upgrade = proof.upgrade.unwrap();
+        assert_eq!(proof.seek, None);
+        assert_eq!(block.index, 1);
+        assert_eq!(block.nodes.len(), 0);
+        assert_eq!(upgrade.start, 1);
+        assert_eq!(upgrade.length, 9);
+        assert_eq!(upgrade.nodes.len(), 3);
+        assert_eq!(upgrade.nodes[0].index, 5);
+        assert_eq!(upgrade.nodes[1].index, 11);
+        assert_eq!(upgrade.nodes[2].index, 17);
+        assert_eq!(upgrade.additional_nodes.len(), 0);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn core_create_proof_block_and_upgrade_from_existing_state_with_additional()
+    -> Result<(), HypercoreError> {
+        let hypercore = create_hypercore_with_data(10).await?;
+        let proof = hypercore
+            .create_proof(
+                Some(RequestBlock { index: 1, nodes: 0 }),
+                None,
+                None,
+                Some(RequestUpgrade {
+                    start: 1,
+                    length: 5,
+                }),
+            )
+            .await?
+            .unwrap();
+        let block = proof.block.unwrap();
+        let upgrade = proof.upgrade.unwrap();
+        assert_eq!(proof.seek, None);
+        assert_eq!(block.index, 1);
+        assert_eq!(block.nodes.len(), 0);
+        assert_eq!(upgrade.start, 1);
+        assert_eq!(upgrade.length, 5);
+        assert_eq!(upgrade.nodes.len(), 2);
+        assert_eq!(upgrade.nodes[0].index, 5);
+        assert_eq!(upgrade.nodes[1].index, 9);
+        assert_eq!(upgrade.additional_nodes.len(), 2);
+        assert_eq!(upgrade.additional_nodes[0].index, 13);
+        assert_eq!(upgrade.additional_nodes[1].index, 17);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn core_create_proof_block_and_seek_1_no_upgrade() -> Result<(), HypercoreError> {
+        let hypercore = create_hypercore_with_data(10).await?;
+        let proof = hypercore
+            .create_proof(
+                Some(RequestBlock { index: 4, nodes: 2 }),
+                None,
+                Some(RequestSeek { bytes: 8 }),
+                None,
+            )
+            .await?
+            .unwrap();
+        let block = proof.block.unwrap();
+        assert_eq!(proof.seek, None); // seek included in block
+        assert_eq!(proof.upgrade, None);
+        assert_eq!(block.index, 4);
+        assert_eq!(block.nodes.len(), 2);
+        assert_eq!(block.nodes[0].index, 10);
+        assert_eq!(block.nodes[1].index, 13);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn core_create_proof_block_and_seek_2_no_upgrade() -> Result<(), HypercoreError> {
+        let hypercore = create_hypercore_with_data(10).await?;
+        let proof = hypercore
+            .create_proof(
+                Some(RequestBlock { index: 4, nodes: 2 }),
+                None,
+                Some(RequestSeek { bytes: 10 }),
+                None,
+            )
+            .await?
+            .unwrap();
+        let block = proof.block.unwrap();
+        assert_eq!(proof.seek, None); // seek included in block
+        assert_eq!(proof.upgrade, None);
+        assert_eq!(block.index, 4);
+        assert_eq!(block.nodes.len(), 2);
+        assert_eq!(block.nodes[0].index, 10);
+        assert_eq!(block.nodes[1].index, 13);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn core_create_proof_block_and_seek_3_no_upgrade() -> Result<(), HypercoreError> {
+        let hypercore = create_hypercore_with_data(10).await?;
+        let proof = hypercore
+            .create_proof(
+                Some(RequestBlock { index: 4, nodes: 2 }),
+                None,
+                Some(RequestSeek { bytes: 13 }),
+                None,
+            )
+            .await?
+            .unwrap();
+        let block = proof.block.unwrap();
+        let seek = proof.seek.unwrap();
+        assert_eq!(proof.upgrade, None);
+        assert_eq!(block.index, 4);
+        assert_eq!(block.nodes.len(), 1);
+        assert_eq!(block.nodes[0].index, 10);
+        assert_eq!(seek.nodes.len(), 2);
+        assert_eq!(seek.nodes[0].index, 12);
+        assert_eq!(seek.nodes[1].index, 14);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn core_create_proof_block_and_seek_to_tree_no_upgrade() -> Result<(), HypercoreError> {
+        let hypercore = create_hypercore_with_data(16).await?;
+        let proof = hypercore
+            .create_proof(
+                Some(RequestBlock { index: 0, nodes: 4 }),
+                None,
+                Some(RequestSeek { bytes: 26 }),
+                None,
+            )
+            .await?
+            .unwrap();
+        let block = proof.block.unwrap();
+        let seek = proof.seek.unwrap();
+        assert_eq!(proof.upgrade, None);
+        assert_eq!(block.nodes.len(), 3);
+        assert_eq!(block.nodes[0].index, 2);
+        assert_eq!(block.nodes[1].index, 5);
+        assert_eq!(block.nodes[2].index, 11);
+        assert_eq!(seek.nodes.len(), 2);
+        assert_eq!(seek.nodes[0].index, 19);
+        assert_eq!(seek.nodes[1].index, 27);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn core_create_proof_block_and_seek_with_upgrade() -> Result<(), HypercoreError> {
+        let hypercore = create_hypercore_with_data(10).await?;
+        let proof = hypercore
+            .create_proof(
+                Some(RequestBlock { index: 4, nodes: 2 }),
+                None,
+                Some(RequestSeek { bytes: 13 }),
+                Some(RequestUpgrade {
+                    start: 8,
+                    length: 2,
+                }),
+            )
+            .await?
+            .unwrap();
+        let block = proof.block.unwrap();
+        let seek = proof.seek.unwrap();
+        let upgrade = proof.upgrade.unwrap();
+        assert_eq!(block.index, 4);
+        assert_eq!(block.nodes.len(), 1);
+        assert_eq!(block.nodes[0].index, 10);
+        assert_eq!(seek.nodes.len(), 2);
+        assert_eq!(seek.nodes[0].index, 12);
+        assert_eq!(seek.nodes[1].index, 14);
+        assert_eq!(upgrade.nodes.len(), 1);
+        assert_eq!(upgrade.nodes[0].index, 17);
+        assert_eq!(upgrade.additional_nodes.len(), 0);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn core_create_proof_seek_with_upgrade() -> Result<(), HypercoreError> {
+        let hypercore = create_hypercore_with_data(10).await?;
+        let proof = hypercore
+            .create_proof(
+                None,
+                None,
+                Some(RequestSeek { bytes: 13 }),
+                Some(RequestUpgrade {
+                    start: 0,
+                    length: 10,
+                }),
+            )
+            .await?
+            .unwrap();
+        let seek = proof.seek.unwrap();
+        let upgrade = proof.upgrade.unwrap();
+        assert_eq!(proof.block, None);
+        assert_eq!(seek.nodes.len(), 4);
+        assert_eq!(seek.nodes[0].index, 12);
+        assert_eq!(seek.nodes[1].index, 14);
+        assert_eq!(seek.nodes[2].index, 9);
+        assert_eq!(seek.nodes[3].index, 3);
+        assert_eq!(upgrade.nodes.len(), 1);
+        assert_eq!(upgrade.nodes[0].index, 17);
+        assert_eq!(upgrade.additional_nodes.len(), 0);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn core_verify_proof_invalid_signature() -> Result<(), HypercoreError> {
+        let hypercore = create_hypercore_with_data(10).await?;
+        // Invalid clone hypercore with a different public key
+        let mut hypercore_clone = create_hypercore_with_data(0).await?;
+        let proof = hypercore
+            .create_proof(
+                None,
+                Some(RequestBlock { index: 6, nodes: 0 }),
+                None,
+                Some(RequestUpgrade {
+                    start: 0,
+                    length: 10,
+                }),
+            )
+            .await?
+            .unwrap();
+        assert!(
+            hypercore_clone
+                .verify_and_apply_proof(&proof)
+                .await
+                .is_err()
+        );
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn core_verify_and_apply_proof() -> Result<(), HypercoreError> {
+        let main = create_hypercore_with_data(10).await?;
+        let mut clone = create_hypercore_with_data_and_key_pair(
+            0,
+            PartialKeypair {
+                public: main.key_pair.public,
+                secret: None,
+            },
+        )
+        .await?;
+        let index = 6;
+        let nodes = clone.missing_nodes(index).await?;
+        let proof = main
+            .create_proof(
+                None,
+                Some(RequestBlock { index, nodes }),
+                None,
+                Some(RequestUpgrade {
+                    start: 0,
+                    length: 10,
+                }),
+            )
+            .await?
+            .unwrap();
+        assert!(clone.verify_and_apply_proof(&proof).await?);
+        let main_info = main.info();
+        let clone_info = clone.info();
+        assert_eq!(main_info.byte_length, clone_info.byte_length);
+        assert_eq!(main_info.length, clone_info.length);
+        assert!(main.get(6).await?.is_some());
+        assert!(clone.get(6).await?.is_none());
+
+        // Fetch data for index 6 and verify it is found
+        let index = 6;
+        let nodes = clone.missing_nodes(index).await?;
+        let proof = main
+            .create_proof(Some(RequestBlock { index, nodes }), None, None, None)
+            .await?
+            .unwrap();
+        assert!(clone.verify_and_apply_proof(&proof).await?);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn core_reorg() -> Result<(), HypercoreError> {
+        let mut main = create_hypercore_with_data(10).await?;
+        let mut clone = create_hypercore_with_data_and_key_pair(
+            0,
+            PartialKeypair {
+                public: main.key_pair.public,
+                secret: None,
+            },
+        )
+        .await?;
+        for index in 0..10 {
+            let nodes = clone.missing_nodes(index).await?;
+            let proof = main
+                .create_proof(
+                    Some(RequestBlock { index, nodes }),
+                    None,
+                    None,
+                    (index == 0).then_some(RequestUpgrade {
+                        start: 0,
+                        length: 10,
+                    }),
+                )
+                .await?
+                .unwrap();
+            assert!(clone.verify_and_apply_proof(&proof).await?);
+        }
+        #[cfg(feature = "replication")]
+        let mut events = clone.event_subscribe();
+
+        // The writer truncates to 8 blocks and appends 4 others
+        truncate_writer(&mut main, 8).await?;
+        main.append_batch(&[b"a", b"b", b"c", b"d"]).await?;
+        let proof = main
+            .create_proof(
+                None,
+                None,
+                None,
+                Some(RequestUpgrade {
+                    start: 0,
+                    length: 12,
+                }),
+            )
+            .await?
+            .unwrap();
+        assert!(!clone.verify_and_apply_proof(&proof).await?);
+        assert!(matches!(clone.reorg(&proof).await, Ok(8)));
+        assert!(matches!(
+            clone.reorg(&proof).await,
+            Err(HypercoreError::BadArgument { .. })
+        ));
+        let info = clone.info();
+        assert_eq!((info.length, info.fork, info.contiguous_length), (12, 1, 8));
+        assert_eq!(clone.get(7).await?.unwrap(), b"#7");
+        assert!(clone.get(9).await?.is_none());
+
+        #[cfg(feature = "replication")]
+        {
+            use crate::replication::events::{Event, ForkDetected, Truncate};
+            let mut detected = vec![];
+            let mut truncated = vec![];
+            while let Ok(event) = events.try_recv() {
+                match event {
+                    Event::ForkDetected(ForkDetected {
+                        remote_fork,
+                        remote_length,
+                    }) => detected.push((remote_fork, remote_length)),
+                    Event::Truncate(Truncate {
+                        old_length,
+                        new_length,
+                        fork,
+                    }) => truncated.push((old_length, new_length, fork)),
+                    _ => {}
+                }
+            }
+            assert_eq!(detected, vec![(1, 12)]);
+            assert_eq!(truncated, vec![(10, 8, 1)]);
+        }
+
+        // Blocks of the new fork are downloaded as usual
+        let index = 9;
+        let nodes = clone.missing_nodes(index).await?;
+        let proof = main
+            .create_proof(Some(RequestBlock { index, nodes }), None, None, None)
+            .await?
+            .unwrap();
+        assert!(clone.verify_and_apply_proof(&proof).await?);
+        assert_eq!(clone.get(9).await?.unwrap(), b"b");
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn core_equivocation() -> Result<(), HypercoreError> {
+        let mut main = create_hypercore_with_data(3).await?;
+        // Another writer with the same key signs a different third block
+        let mut other = create_hypercore_with_data_and_key_pair(2, main.key_pair.clone()).await?;
+        other.append(b"x").await?;
+        let mut clone = create_hypercore_with_data_and_key_pair(
+            0,
+            PartialKeypair {
+                public: main.key_pair.public,
+                secret: None,
+            },
+        )
+        .await?;
+        let upgrade = RequestUpgrade {
+            start: 0,
+            length: 3,
+        };
+        let proof = main
+            .create_proof(None, None, None, Some(upgrade.clone()))
+            .await?
+            .unwrap();
+        assert!(clone.verify_and_apply_proof(&proof).await?);
+        #[cfg(feature = "replication")]
+        let mut events = clone.event_subscribe();
+
+        let proof = other
+            .create_proof(None, None, None, Some(upgrade))
+            .await?
+            .unwrap();
+        assert!(matches!(
+            clone.verify_and_apply_proof(&proof).await,
+            Err(HypercoreError::Equivocation { fork: 0, length: 3 })
+        ));
+        let equivocation = clone.equivocation().unwrap().clone();
+        equivocation.verify(&main.key_pair.public)?;
+        assert_eq!(equivocation.local, main.checkpoint().unwrap());
+        assert_eq!(equivocation.remote, other.checkpoint().unwrap());
+        assert!(
+            Equivocation {
+                local: equivocation.local.clone(),
+                remote: equivocation.local.clone(),
+                nodes: vec![],
+            }
+            .verify(&main.key_pair.public)
+            .is_err()
+        );
+        #[cfg(feature = "replication")]
+        assert!(matches!(
+            events.try_recv(),
+            Ok(crate::replication::events::Event::Equivocation(event)) if event == equivocation
+        ));
+
+        // Further upgrades of the fork are refused
+        main.append(b"d").await?;
+        let proof = main
+            .create_proof(
+                None,
+                None,
+                None,
+                Some(RequestUpgrade {
+                    start: 3,
+                    length: 1,
+                }),
+            )
+            .await?
+            .unwrap();
+        assert!(matches!(
+            clone.verify_and_apply_proof(&proof).await,
+            Err(HypercoreError::Equivocation { .. })
+        ));
+        assert_eq!(clone.info().length, 3);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn core_equivocation_of_longer_length() -> Result<(), HypercoreError> {
+        let main = create_hypercore_with_data(3).await?;
+        // Another writer with the same key signs a different third block, and a fourth one
+        let mut other = create_hypercore_with_data_and_key_pair(2, main.key_pair.clone()).await?;
+        other.append_batch([b"x", b"y"]).await?;
+        let public = PartialKeypair {
+            public: main.key_pair.public,
+            secret: None,
+        };
+        let mut clone = create_hypercore_with_data_and_key_pair(0, public.clone()).await?;
+        let proof = main
+            .create_proof(
+                None,
+                None,
+                None,
+                Some(RequestUpgrade {
+                    start: 0,
+                    length: 3,
+                }),
+            )
+            .await?
+            .unwrap();
+        assert!(clone.verify_and_apply_proof(&proof).await?);
+
+        // The longer upgrade alone doesn't have the nodes of the remote tree at length 3
+        let upgrade = RequestUpgrade {
+            start: 0,
+            length: 4,
+        };
+        let proof = other
+            .create_proof(None, None, None, Some(upgrade.clone()))
+            .await?
+            .unwrap();
+        assert!(clone.verify_and_apply_proof(&proof).await.is_err());
+        assert!(clone.equivocation().is_none());
+
+        // With the conflicting block, they are compared at the local length
+        let proof = other
+            .create_proof(
+                Some(RequestBlock { index: 2, nodes: 0 }),
+                None,
+                None,
+                Some(upgrade),
+            )
+            .await?
+            .unwrap();
+        assert!(matches!(
+            clone.verify_and_apply_proof(&proof).await,
+            Err(HypercoreError::Equivocation { fork: 0, length: 3 })
+        ));
+        let equivocation = clone.equivocation().unwrap().clone();
+        equivocation.verify(&main.key_pair.public)?;
+        assert_eq!(equivocation.local, main.checkpoint().unwrap());
+        assert_eq!(equivocation.remote, other.checkpoint().unwrap());
+        assert_eq!(clone.info().length, 3);
+        // Without the nodes, the heads don't show a conflict
+        assert!(
+            Equivocation {
+                nodes: vec![],
+                ..equivocation.clone()
+            }
+            .verify(&main.key_pair.public)
+            .is_err()
+        );
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn core_equivocation_of_shorter_length() -> Result<(), HypercoreError> {
+        // A shorter conflicting upgrade is compared with the local nodes
+        let mut main = create_hypercore_with_data(4).await?;
+        let mut other = create_hypercore_with_data_and_key_pair(2, main.key_pair.clone()).await?;
+        other.append(b"x").await?;
+        let proof = other
+            .create_proof(
+                None,
+                None,
+                None,
+                Some(RequestUpgrade {
+                    start: 0,
+                    length: 3,
+                }),
+            )
+            .await?
+            .unwrap();
+        assert!(matches!(
+            main.verify_and_apply_proof(&proof).await,
+            Err(HypercoreError::Equivocation { fork: 0, length: 4 })
+        ));
+        main.equivocation().unwrap().verify(&main.key_pair.public)?;
+
+        // A shorter upgrade of the same tree is no equivocation
+        let mut main = create_hypercore_with_data(4).await?;
+        let proof = create_hypercore_with_data_and_key_pair(3, main.key_pair.clone())
+            .await?
+            .create_proof(
+                None,
+                None,
+                None,
+                Some(RequestUpgrade {
+                    start: 0,
+                    length: 3,
+                }),
+            )
+            .await?
+            .unwrap();
+        let _ = main.verify_and_apply_proof(&proof).await;
+        assert!(main.equivocation().is_none());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn core_pending_proofs() -> Result<(), HypercoreError> {
+        let mut main = create_hypercore_with_data(5).await?;
+        let public = PartialKeypair {
+            public: main.key_pair.public,
+            secret: None,
+        };
+        let upgrade = main
+            .create_proof(
+                None,
+                None,
+                None,
+                Some(RequestUpgrade {
+                    start: 0,
+                    length: 5,
+                }),
+            )
+            .await?
+            .unwrap();
+        for i in 5..10 {
+            main.append(format!("#{}", i).as_bytes()).await?;
+        }
+        let next_upgrade = main
+            .create_proof(
+                None,
+                None,
+                None,
+                Some(RequestUpgrade {
+                    start: 5,
+                    length: 5,
+                }),
+            )
+            .await?
+            .unwrap();
+        let mut reference = create_hypercore_with_data_and_key_pair(0, public.clone()).await?;
+        assert!(reference.verify_and_apply_proof(&upgrade).await?);
+        assert!(reference.verify_and_apply_proof(&next_upgrade).await?);
+        let index = 7;
+        let nodes = reference.missing_nodes(index).await?;
+        let block = main
+            .create_proof(Some(RequestBlock { index, nodes }), None, None, None)
+            .await?
+            .unwrap();
+
+        // The proofs arrive in reverse order
+        let mut clone = create_hypercore_with_data_and_key_pair(0, public.clone()).await?;
+        assert!(!clone.verify_and_apply_proof(&block).await?);
+        assert!(!clone.verify_and_apply_proof(&next_upgrade).await?);
+        assert_eq!(clone.pending_proofs(), 2);
+        assert!(clone.verify_and_apply_proof(&upgrade).await?);
+        assert_eq!(clone.pending_proofs(), 0);
+        assert_eq!(clone.info().length, 10);
+        assert_eq!(clone.get(7).await?.unwrap(), b"#7");
+
+        // The oldest proof is dropped when the buffer is full
+        let mut clone = create_hypercore_with_data_and_key_pair(0, public).await?;
+        clone.pending_proofs_capacity = 1;
+        assert!(!clone.verify_and_apply_proof(&block).await?);
+        assert!(!clone.verify_and_apply_proof(&next_upgrade).await?);
+        assert_eq!(clone.pending_proofs(), 1);
+        assert!(clone.verify_and_apply_proof(&upgrade).await?);
+        assert_eq!(clone.info().length, 10);
+        assert!(clone.get(7).await?.is_none());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn core_pending_proof_rejected() -> Result<(), HypercoreError> {
+        let main = create_hypercore_with_data(5).await?;
+        let public = PartialKeypair {
+            public: main.key_pair.public,
+            secret: None,
+        };
+        let upgrade = main
+            .create_proof(
+                None,
+                None,
+                None,
+                Some(RequestUpgrade {
+                    start: 0,
+                    length: 5,
+                }),
+            )
+            .await?
+            .unwrap();
+        let mut block = main
+            .create_proof(Some(RequestBlock { index: 3, nodes: 0 }), None, None, None)
+            .await?
+            .unwrap();
+        block.block.as_mut().unwrap().value = b"bad".to_vec();
+
+        let mut clone = create_hypercore_with_data_and_key_pair(0, public).await?;
+        #[cfg(feature = "replication")]
+        let mut events = clone.event_subscribe();
+        assert!(!clone.verify_and_apply_proof(&block).await?);
+        assert_eq!(clone.pending_proofs(), 1);
+        assert!(clone.verify_and_apply_proof(&upgrade).await?);
+        assert_eq!(clone.pending_proofs(), 0);
+        assert_eq!(clone.stats().verification_failures, 1);
+        assert!(!clone.has(3));
+        #[cfg(feature = "replication")]
+        {
+            use crate::replication::events::{Event, ProofRejected};
+            let rejected: Vec<ProofRejected> = std::iter::from_fn(|| events.try_recv().ok())
+                .filter_map(|event| match event {
+                    Event::ProofRejected(rejected) => Some(rejected),
+                    _ => None,
+                })
+                .collect();
+            assert_eq!(rejected.len(), 1);
+            assert_eq!(rejected[0].fork, 0);
+            assert_eq!(rejected[0].block, Some(3));
+            assert!(!rejected[0].error.is_empty());
+        }
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn core_verify_and_apply_proofs() -> Result<(), HypercoreError> {
+        let mut main = create_hypercore_with_data(5).await?;
+        let public = PartialKeypair {
+            public: main.key_pair.public,
+            secret: None,
+        };
+        let upgrade = |start, length| RequestUpgrade { start, length };
+        let first = main
+            .create_proof(None, None, None, Some(upgrade(0, 5)))
+            .await?
+            .unwrap();
+        for i in 5..10 {
+            main.append(format!("#{}", i).as_bytes()).await?;
+        }
+        let second = main
+            .create_proof(None, None, None, Some(upgrade(5, 5)))
+            .await?
+            .unwrap();
+        let mut reference = create_hypercore_with_data_and_key_pair(0, public.clone()).await?;
+        reference
+            .verify_and_apply_proofs(&[first.clone(), second.clone()])
+            .await?;
+        let mut blocks = vec![];
+        for index in [7, 2] {
+            let nodes = reference.missing_nodes(index).await?;
+            blocks.push(
+                main.create_proof(Some(RequestBlock { index, nodes }), None, None, None)
+                    .await?
+                    .unwrap(),
+            );
+        }
+
+        // A tampered block rejects the whole batch
+        let mut clone = create_hypercore_with_data_and_key_pair(0, public.clone()).await?;
+        #[cfg(feature = "replication")]
+        let mut events = clone.event_subscribe();
+        let mut tampered = blocks[1].clone();
+        tampered.block.as_mut().unwrap().value = b"#x".to_vec();
+        let batch = [first.clone(), second.clone(), blocks[0].clone(), tampered];
+        assert!(clone.verify_and_apply_proofs(&batch).await.is_err());
+        assert_eq!(clone.info().length, 0);
+        assert!(!clone.is_poisoned());
+        #[cfg(feature = "replication")]
+        assert!(events.try_recv().is_err());
+
+        // Proofs that need an upgrade wait for it
+        let batch = [blocks[0].clone(), second, first, blocks[1].clone()];
+        assert_eq!(clone.verify_and_apply_proofs(&batch).await?, vec![true; 4]);
+        assert_eq!(clone.info().length, 10);
+        assert_eq!(clone.get(7).await?.unwrap(), b"#7");
+        assert_eq!(clone.get(2).await?.unwrap(), b"#2");
+        #[cfg(feature = "replication")]
+        {
+            use crate::replication::events::{Event, Have};
+            let mut have = vec![];
+            while let Ok(event) = events.try_recv() {
+                if let Event::Have(Have { start, .. }) = event {
+                    have.push(start);
+                }
+            }
+            assert_eq!(have, vec![7, 2]);
+        }
+
+        // Or are buffered if the batch lacks it
+        let mut clone = create_hypercore_with_data_and_key_pair(0, public).await?;
+        assert_eq!(
+            clone.verify_and_apply_proofs(&[blocks[0].clone()]).await?,
+            vec![false]
+        );
+        assert_eq!(clone.pending_proofs(), 1);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn core_verify_and_apply_proofs_dropped() -> Result<(), HypercoreError> {
+        let main = create_hypercore_with_data(4).await?;
+        let proofs = vec![
+            main.create_proof(
+                Some(RequestBlock { index: 1, nodes: 0 }),
+                None,
+                None,
+                Some(RequestUpgrade {
+                    start: 0,
+                    length: 4,
+                }),
+            )
+            .await?
+            .unwrap(),
+            main.create_proof(Some(RequestBlock { index: 0, nodes: 0 }), None, None, None)
+                .await?
+                .unwrap(),
+        ];
+        for polls in 0.. {
+            let stores: [YieldingStore; 4] = Default::default();
+            let key_pair = PartialKeypair {
+                public: main.key_pair().public,
+                secret: None,
+            };
+            let mut clone = open_yielding(&stores, key_pair, false).await?;
+            let completed = poll_and_drop(clone.verify_and_apply_proofs(&proofs), polls).await;
+            if clone.is_poisoned() {
+                assert_eq!(
+                    clone
+                        .verify_and_apply_proof(&proofs[0])
+                        .await
+                        .unwrap_err()
+                        .kind(),
+                    ErrorKind::Poisoned
+                );
+                clone.recover().await?;
+            }
+            if !completed {
+                clone.verify_and_apply_proofs(&proofs).await?;
+            }
+            assert_eq!(clone.get(0).await?.unwrap(), b"#0");
+            assert_reopens_same(clone, &stores, false).await?;
+            if completed {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn core_create_proof_stream() -> Result<(), HypercoreError> {
+        let mut main = create_hypercore_with_data(0).await?;
+        for i in 0..10 {
+            main.append(&vec![i; 10 * (i as usize + 1)]).await?;
+        }
+        main.clear(3, 5).await?;
+        let upgrade = main
+            .create_proof(
+                None,
+                None,
+                None,
+                Some(RequestUpgrade {
+                    start: 0,
+                    length: 10,
+                }),
+            )
+            .await?
+            .unwrap();
+        // Windows of one block, of a few blocks, and of the whole range
+        for budget in [0, 100, u64::MAX] {
+            let proofs: Vec<Proof> = main.create_proof_stream(1..9, budget).try_collect().await?;
+            let blocks: Vec<u64> = proofs
+                .iter()
+                .map(|proof| proof.block.as_ref().unwrap().index)
+                .collect();
+            assert_eq!(blocks, vec![1, 2, 5, 6, 7, 8]);
+
+            let mut clone = create_hypercore_with_data_and_key_pair(
+                0,
+                PartialKeypair {
+                    public: main.key_pair.public,
+                    secret: None,
+                },
+            )
+            .await?;
+            assert!(clone.verify_and_apply_proof(&upgrade).await?);
+            assert_eq!(clone.verify_and_apply_proofs(&proofs).await?, vec![true; 6]);
+            assert_eq!(clone.get(8).await?, Some(vec![8; 90]));
+        }
+        let proofs: Vec<Proof> = main.create_proof_stream(3..5, 0).try_collect().await?;
+        assert!(proofs.is_empty());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn core_detailed_info() -> Result<(), HypercoreError> {
+        let main = create_hypercore_with_data(10).await?;
+        let info = main.detailed_info().await?;
+        assert_eq!(
+            (info.info.contiguous_length, info.downloaded_blocks),
+            (10, 10)
+        );
+        // Blocks "#0" to "#9"
+        assert_eq!(info.store_bytes.data, 20);
+        assert!(info.store_bytes.oplog > 0);
+        assert_eq!(info.tree_nodes, info.store_bytes.tree / 40);
+
+        let mut clone = create_hypercore_with_data_and_key_pair(
+            0,
+            PartialKeypair {
+                public: main.key_pair.public,
+                secret: None,
+            },
+        )
+        .await?;
+        let index = 3;
+        let nodes = clone.missing_nodes(index).await?;
+        let proof = main
+            .create_proof(
+                Some(RequestBlock { index, nodes }),
+                None,
+                None,
+                Some(RequestUpgrade {
+                    start: 0,
+                    length: 10,
+                }),
+            )
+            .await?
+            .unwrap();
+        assert!(clone.verify_and_apply_proof(&proof).await?);
+        let info = clone.detailed_info().await?;
+        assert_eq!(info.info.length, 10);
+        assert_eq!(
+            (info.info.contiguous_length, info.downloaded_blocks),
+            (0, 1)
+        );
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn core_dump_state() -> Result<(), HypercoreError> {
+        let mut hypercore = create_hypercore_with_data(3).await?;
+        hypercore.clear(1, 2).await?;
+        let dump = hypercore.dump_state().await?;
+        assert_eq!((dump.length, dump.fork, dump.writeable), (3, 0, true));
+        assert_eq!(dump.stored, vec![0..1, 2..3]);
+        assert_eq!(
+            dump.roots.iter().map(|root| root.index).collect::<Vec<_>>(),
+            vec![1, 4]
+        );
+        assert_eq!(dump.header.length, 3);
+        assert_eq!(dump.oplog_length, dump.oplog_entries.len() as u64);
+        let last = dump.oplog_entries.last().unwrap();
+        assert_eq!((last.blocks.clone(), last.cleared), (Some(1..2), true));
+
+        let report = dump.to_string();
+        assert!(report.starts_with(&format!("hypercore {} (writeable)", dump.key)));
+        assert!(report.contains("stored: 0..1, 2..3"));
+        assert!(report.contains("cleared 1..2"));
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn core_rng_seed() -> Result<(), HypercoreError> {
+        async fn key(seed: [u8; 32]) -> Result<PartialKeypair, HypercoreError> {
+            let hypercore = crate::HypercoreBuilder::new(Storage::new_memory().await?)
+                .rng_seed(seed)
+                .build()
+                .await?;
+            Ok(hypercore.key_pair().clone())
+        }
+        let key_pair = key([1; 32]).await?;
+        assert_eq!(key_pair.public, key([1; 32]).await?.public);
+        assert_ne!(key_pair.public, key([2; 32]).await?.public);
+        assert_eq!(
+            key_pair.secret.unwrap().to_bytes(),
+            generate_signing_key_with(&mut StdRng::from_seed([1; 32])).to_bytes()
+        );
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn core_static_storage() -> Result<(), HypercoreError> {
+        let mut hypercore: Hypercore<RandomAccessMemory> =
+            crate::HypercoreBuilder::new(Storage::new_memory_static().await?)
+                .build()
+                .await?;
+        hypercore.append_batch([b"hello", b"world"]).await?;
+        assert_eq!(hypercore.get(1).await?, Some(b"world".to_vec()));
+
+        let copy = hypercore.copy_to(Storage::new_memory().await?).await?;
+        assert_eq!(copy.info().length, 2);
+        assert_eq!(copy.get(0).await?, Some(b"hello".to_vec()));
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn core_append_cancel_safe() -> Result<(), HypercoreError> {
+        for deduplicate in [false, true] {
+            for polls in 0.. {
+                let stores: [YieldingStore; 4] = Default::default();
+                let signing_key = generate_signing_key();
+                let key_pair = PartialKeypair {
+                    public: signing_key.verifying_key(),
+                    secret: Some(signing_key),
+                };
+                let mut core = open_yielding(&stores, key_pair, deduplicate).await?;
+                core.append_batch([b"a", b"b"]).await?;
+                let completed = poll_and_drop(core.append(b"a"), polls).await;
+                core.append_batch([b"b", b"c"]).await?;
+                assert_reopens_same(core, &stores, deduplicate).await?;
+                if completed {
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn core_apply_proof_cancel_safe() -> Result<(), HypercoreError> {
+        let main = create_hypercore_with_data(4).await?;
+        let first = main
+            .create_proof(
+                Some(RequestBlock { index: 1, nodes: 0 }),
+                None,
+                None,
+                Some(RequestUpgrade {
+                    start: 0,
+                    length: 4,
+                }),
+            )
+            .await?
+            .unwrap();
+        for polls in 0.. {
+            let stores: [YieldingStore; 4] = Default::default();
+            let key_pair = PartialKeypair {
+                public: main.key_pair().public,
+                secret: None,
+            };
+            let mut clone = open_yielding(&stores, key_pair, false).await?;
+            let completed = poll_and_drop(clone.verify_and_apply_proof(&first), polls).await;
+            clone.verify_and_apply_proof(&first).await?;
+            let nodes = clone.missing_nodes(2).await?;
+            let second = main
+                .create_proof(Some(RequestBlock { index: 2, nodes }), None, None, None)
+                .await?
+                .unwrap();
+            clone.verify_and_apply_proof(&second).await?;
+            assert_reopens_same(clone, &stores, false).await?;
+            if completed {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn core_recover_after_failed_write() -> Result<(), HypercoreError> {
+        let stores: [YieldingStore; 4] = Default::default();
+        let signing_key = generate_signing_key();
+        let key_pair = PartialKeypair {
+            public: signing_key.verifying_key(),
+            secret: Some(signing_key),
+        };
+        let mut core = open_yielding(&stores, key_pair, false).await?;
+        core.append_batch([b"a", b"b"]).await?;
+        assert!(!core.is_poisoned());
+
+        let fail_writes = |fail| {
+            for store in &stores {
+                store.fail_writes.store(fail, Ordering::Relaxed);
+            }
+        };
+        fail_writes(true);
+        assert_eq!(core.append(b"c").await.unwrap_err().kind(), ErrorKind::Io);
+        assert!(core.is_poisoned());
+        fail_writes(false);
+        assert_eq!(
+            core.append(b"c").await.unwrap_err().kind(),
+            ErrorKind::Poisoned
+        );
+        assert_eq!(core.get(1).await?, Some(b"b".to_vec()));
+
+        core.recover().await?;
+        assert!(!core.is_poisoned());
+        assert_eq!(core.info().length, 2);
+        core.append(b"c").await?;
+        assert_eq!(core.get(2).await?, Some(b"c".to_vec()));
+        assert_reopens_same(core, &stores, false).await
+    }
+
+    #[async_std::test]
+    async fn core_stats() -> Result<(), HypercoreError> {
+        let main = create_hypercore_with_data(10).await?;
+        let mut clone = create_hypercore_with_data_and_key_pair(
+            0,
+            PartialKeypair {
+                public: main.key_pair.public,
+                secret: None,
+            },
+        )
+        .await?;
+        let nodes = clone.missing_nodes(6).await?;
+        let mut proof = main
+            .create_proof(
+                Some(RequestBlock { index: 6, nodes }),
+                None,
+                None,
+                Some(RequestUpgrade {
+                    start: 0,
+                    length: 10,
+                }),
+            )
+            .await?
+            .unwrap();
+        assert!(clone.verify_and_apply_proof(&proof).await?);
+        assert_eq!(
+            main.stats(),
+            Stats {
+                blocks_appended: 10,
+                bytes_appended: 20,
+                proofs_created: 1,
+                ..Stats::default()
+            }
+        );
+        assert_eq!(
+            clone.stats(),
+            Stats {
+                proofs_verified: 1,
+                blocks_received: 1,
+                bytes_received: 2,
+                ..Stats::default()
+            }
+        );
+
+        proof.upgrade = None;
+        proof.block.as_mut().unwrap().value = b"#x".to_vec();
+        assert!(clone.verify_and_apply_proof(&proof).await.is_err());
+        assert_eq!(clone.stats().verification_failures, 1);
+        assert_eq!(clone.stats().proofs_verified, 1);
+        Ok(())
+    }
+
+    #[cfg(all(feature = "metrics", feature = "cache"))]
+    #[test]
+    fn core_metrics() -> Result<(), HypercoreError> {
+        use metrics_util::debugging::{DebugValue, DebuggingRecorder};
+
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+        ::metrics::with_local_recorder(&recorder, || {
+            async_std::task::block_on(async {
+                let mut main = crate::HypercoreBuilder::new(Storage::new_memory().await?)
+                    .block_cache_options(crate::CacheOptionsBuilder::new())
+                    .build()
+                    .await?;
+                main.append_batch([b"#0", b"#1", b"#2"]).await?;
+                main.get(1).await?;
+                main.get(1).await?;
+                let mut clone = create_hypercore_with_data_and_key_pair(
+                    0,
+                    PartialKeypair {
+                        public: main.key_pair.public,
+                        secret: None,
+                    },
+                )
+                .await?;
+                let nodes = clone.missing_nodes(1).await?;
+                let proof = main
+                    .create_proof(
+                        Some(RequestBlock { index: 1, nodes }),
+                        None,
+                        None,
+                        Some(RequestUpgrade {
+                            start: 0,
+                            length: 3,
+                        }),
+                    )
+                    .await?
+                    .unwrap();
+                clone.verify_and_apply_proof(&proof).await?;
+                Ok::<(), HypercoreError>(())
+            })
+        })?;
+
+        let mut counters: std::collections::HashMap<String, u64> = Default::default();
+        for (key, _, _, value) in snapshotter.snapshot().into_vec() {
+            let name = key.key().name().to_string();
+            let labels: Vec<String> = key.key().labels().map(|l| l.value().to_string()).collect();
+            if let DebugValue::Counter(value) = value {
+                *counters.entry(name).or_default() += value;
+                if let [label] = &labels[..] {
+                    counters.insert(format!("{}{{{label}}}", key.key().name()), value);
+                }
+            }
+        }
+        assert_eq!(counters["hypercore_appended_blocks_total"], 3);
+        assert_eq!(counters["hypercore_appended_bytes_total"], 6);
+        assert_eq!(counters["hypercore_cache_misses_total{block}"], 1);
+        // The proof reads the block from the cache too
+        assert_eq!(counters["hypercore_cache_hits_total{block}"], 2);
+        assert_eq!(counters["hypercore_proofs_created_total"], 1);
+        assert_eq!(counters["hypercore_proofs_verified_total"], 1);
+        assert_eq!(counters["hypercore_received_bytes_total"], 2);
+        assert_eq!(counters["hypercore_storage_written_bytes_total{data}"], 8);
+        assert!(counters["hypercore_storage_read_bytes_total"] > 0);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn core_valueless_proof_attach_value() -> Result<(), HypercoreError> {
+        let main = create_hypercore_with_data(10).await?;
+        let mut clone = create_hypercore_with_data_and_key_pair(
+            0,
+            PartialKeypair {
+                public: main.key_pair.public,
+                secret: None,
+            },
+        )
+        .await?;
+        let valueless_proof = main
+            .create_valueless_proof(
+                Some(RequestBlock { index: 6, nodes: 0 }),
+                None,
+                None,
+                Some(RequestUpgrade {
+                    start: 0,
+                    length: 10,
+                }),
+            )
+            .await?;
+        assert_eq!(valueless_proof.block.as_ref().unwrap().index, 6);
+
+        // Value is required when there is a block, and can't be given without one
+        assert!(valueless_proof.clone().attach_value(None).is_err());
+        let mut no_block = valueless_proof.clone();
+        no_block.block = None;
+        assert!(no_block.attach_value(Some(b"#6".to_vec())).is_err());
+
+        // Value travels separately and is attached on arrival
+        let value = main.get(6).await?;
+        let proof = valueless_proof.attach_value(value)?;
+        let (split_proof, split_value) = ValuelessProof::from_proof(proof.clone());
+        assert_eq!(split_value.as_deref(), Some(&b"#6"[..]));
+        assert_eq!(split_proof.attach_value(split_value)?, proof);
+        assert!(clone.verify_and_apply_proof(&proof).await?);
+        assert_eq!(clone.get(6).await?, Some(b"#6".to_vec()));
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn core_create_and_verify_seek_proof() -> Result<(), HypercoreError> {
+        let main = create_hypercore_with_data(10).await?;
+        let clone = create_hypercore_with_data_and_key_pair(
+            0,
+            PartialKeypair {
+                public: main.key_pair.public,
+                secret: None,
+            },
+        )
+        .await?;
+        // Every block is two bytes long
+        for byte_offset in 0..20 {
+            let proof = main.create_seek_proof(byte_offset).await?;
+            assert!(proof.seek.is_some());
+            assert_eq!(
+                clone.verify_seek_proof(&proof).await?,
+                (byte_offset / 2, byte_offset % 2)
+            );
+            assert_eq!(
+                main.verify_seek_proof(&proof).await?,
+                (byte_offset / 2, byte_offset % 2)
+            );
+        }
+        // Verifying does not apply the proof
+        assert_eq!(clone.info().length, 0);
+        assert!(main.create_seek_proof(20).await.is_err());
+
+        // Tampered seek nodes are rejected
+        let mut proof = main.create_seek_proof(13).await?;
+        proof.seek.as_mut().unwrap().nodes[0].length += 1;
+        assert!(clone.verify_seek_proof(&proof).await.is_err());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn core_verify_range() -> Result<(), HypercoreError> {
         let mut hypercore = create_hypercore_with_data(10).await?;
-        let proof = hypercore
+        let results = hypercore.verify_range(0, 10).await?;
+        assert_eq!(results, (0..10).map(|i| (i, true)).collect::<Vec<_>>());
+
+        // Missing blocks are skipped and the range is capped to the length
+        hypercore.clear(2, 4).await?;
+        let results = hypercore.verify_range(0, 20).await?;
+        let indexes: Vec<u64> = results.iter().map(|(i, _)| *i).collect();
+        assert_eq!(indexes, vec![0, 1, 4, 5, 6, 7, 8, 9]);
+
+        // Corrupt the stored bytes of block 6
+        hypercore
+            .storage
+            .flush_info(StoreInfo::new_content(crate::Store::Data, 12, b"XX"))
+            .await?;
+        let results = hypercore.verify_range(5, 8).await?;
+        assert_eq!(results, vec![(5, true), (6, false), (7, true)]);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn core_get_partial() -> Result<(), HypercoreError> {
+        let mut hypercore = create_hypercore_with_data(2).await?;
+        hypercore.append(b"Hello, World!").await?;
+        assert_eq!(
+            hypercore.get_partial(2, 7, 5).await?,
+            Some(b"World".to_vec())
+        );
+        assert_eq!(hypercore.get_partial(2, 13, 0).await?, Some(vec![]));
+        assert_eq!(hypercore.get_partial(1, 0, 2).await?, Some(b"#1".to_vec()));
+        assert!(matches!(
+            hypercore.get_partial(2, 7, 7).await,
+            Err(HypercoreError::BadArgument { .. })
+        ));
+        assert!(matches!(
+            hypercore.get_partial(2, u64::MAX, 2).await,
+            Err(HypercoreError::BadArgument { .. })
+        ));
+        assert_eq!(hypercore.get_partial(3, 0, 1).await?, None);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn core_get_stream() -> Result<(), HypercoreError> {
+        use futures::io::AsyncReadExt;
+
+        let mut hypercore = create_hypercore_with_data(1).await?;
+        let block: Vec<u8> = (0..200_000u32).map(|i| i as u8).collect();
+        hypercore.append(&block).await?;
+        for (index, expected) in [(0, b"#0".to_vec()), (1, block)] {
+            let mut stream = hypercore.get_stream(index).await?.unwrap();
+            let mut read = vec![];
+            stream.read_to_end(&mut read).await?;
+            assert_eq!(read, expected);
+        }
+        assert!(hypercore.get_stream(2).await?.is_none());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn core_seek() -> Result<(), HypercoreError> {
+        let blocks: [&[u8]; 5] = [b"a", b"", b"bcd", b"ef", b"ghijk"];
+        let mut expected = vec![];
+        for (index, block) in blocks.iter().enumerate() {
+            expected.extend((0..block.len() as u64).map(|offset| (index as u64, offset)));
+        }
+
+        let mut indexed = crate::HypercoreBuilder::new(Storage::new_memory().await?)
+            .byte_index(true)
+            .build()
+            .await?;
+        let mut unindexed = crate::HypercoreBuilder::new(Storage::new_memory().await?)
+            .build()
+            .await?;
+        for hypercore in [&mut indexed, &mut unindexed] {
+            hypercore.append_batch(&blocks[..2]).await?;
+            hypercore.append_batch(&blocks[2..]).await?;
+            for (byte_offset, position) in expected.iter().enumerate() {
+                assert_eq!(hypercore.seek(byte_offset as u64).await?, *position);
+            }
+            assert!(matches!(
+                hypercore.seek(expected.len() as u64).await,
+                Err(HypercoreError::BadArgument { .. })
+            ));
+            assert_eq!(hypercore.get(4).await?, Some(b"ghijk".to_vec()));
+        }
+
+        // Blocks received out of order are indexed once the leaves before them are known
+        let mut clone = crate::HypercoreBuilder::new(Storage::new_memory().await?)
+            .verifying_key(indexed.key_pair.public)
+            .byte_index(true)
+            .build()
+            .await?;
+        for (index, upgrade) in [(0, true), (4, false), (2, false), (1, false)] {
+            let nodes = clone.missing_nodes(index).await?;
+            let proof = indexed
+                .create_proof(
+                    Some(RequestBlock { index, nodes }),
+                    None,
+                    None,
+                    upgrade.then_some(RequestUpgrade {
+                        start: 0,
+                        length: 5,
+                    }),
+                )
+                .await?
+                .unwrap();
+            assert!(clone.verify_and_apply_proof(&proof).await?);
+        }
+        assert_eq!(clone.byte_index.as_ref().map(ByteIndex::len), Some(5));
+        for byte_offset in [0, 1, 3, 6, 7, 10] {
+            assert_eq!(
+                clone.seek(byte_offset).await?,
+                expected[byte_offset as usize]
+            );
+        }
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn core_scrub() -> Result<(), HypercoreError> {
+        let mut hypercore = create_hypercore_with_data(10).await?;
+        #[cfg(feature = "replication")]
+        let mut events = hypercore.event_subscribe();
+        assert_eq!(hypercore.scrub(0..10).await?, Vec::<u64>::new());
+
+        // Corrupt the stored bytes of blocks 3 and 6
+        for offset in [6, 12] {
+            hypercore
+                .storage
+                .flush_info(StoreInfo::new_content(crate::Store::Data, offset, b"XX"))
+                .await?;
+        }
+        assert_eq!(hypercore.scrub(0..5).await?, vec![3]);
+        assert_eq!(hypercore.scrub(5..10).await?, vec![6]);
+        assert!(!hypercore.has(3) && !hypercore.has(6));
+        assert_eq!(hypercore.get(6).await?, None);
+        assert_eq!(hypercore.get(7).await?, Some(b"#7".to_vec()));
+        assert!(hypercore.scrub(0..10).await?.is_empty());
+        #[cfg(feature = "replication")]
+        {
+            use crate::replication::events::{CorruptBlock, Event, Have};
+            let mut corrupt = vec![];
+            while let Ok(event) = events.try_recv() {
+                match event {
+                    Event::CorruptBlock(CorruptBlock { index }) => corrupt.push(index),
+                    Event::Have(Have {
+                        start, drop: true, ..
+                    }) => assert!(corrupt.contains(&start)),
+                    _ => {}
+                }
+            }
+            assert_eq!(corrupt, vec![3, 6]);
+        }
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn core_tree_nodes() -> Result<(), HypercoreError> {
+        let hypercore = create_hypercore_with_data(10).await?;
+        let nodes = hypercore.tree_nodes(0..u64::MAX).await?;
+        let indexes: Vec<u64> = nodes.iter().map(|node| node.index).collect();
+        // Nodes 15 and 19 would need blocks 10 and beyond
+        assert_eq!(
+            indexes,
+            (0..19).filter(|index| *index != 15).collect::<Vec<u64>>()
+        );
+
+        let leaf = hypercore.tree_nodes(4..5).await?;
+        assert_eq!(leaf.len(), 1);
+        assert_eq!(leaf[0].index, 4);
+        assert_eq!(leaf[0].length, 2);
+        assert_eq!(leaf[0].hash, Hash::data(b"#2").as_bytes().to_vec());
+        assert!(hypercore.tree_nodes(20..40).await?.is_empty());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn core_node() -> Result<(), HypercoreError> {
+        let main = create_hypercore_with_data(10).await?;
+        let leaf = main.node(4).await?.unwrap();
+        assert_eq!(leaf.index, 4);
+        assert_eq!(leaf.hash, Hash::data(b"#2").as_bytes().to_vec());
+        assert_eq!(main.node(7).await?.unwrap().length, 8 * 2);
+        // Node 15 would need blocks 10 and beyond
+        assert!(main.node(15).await?.is_none());
+        assert!(main.node(20).await?.is_none());
+
+        // Only the nodes of the proven blocks and the roots are stored by a clone
+        let mut clone = create_hypercore_with_data_and_key_pair(
+            0,
+            PartialKeypair {
+                public: main.key_pair.public,
+                secret: None,
+            },
+        )
+        .await?;
+        let proof = main
             .create_proof(
-                Some(RequestBlock { index: 4, nodes: 0 }),
+                Some(RequestBlock { index: 6, nodes: 0 }),
                 None,
                 None,
                 Some(RequestUpgrade {
@@ -812,298 +5106,853 @@ pub(crate) mod tests {
             )
             .await?
             .unwrap();
-        let block = proof.block.unwrap();
-        let upgrade = proof.upgrade.unwrap();
-        assert_eq!(proof.seek, None);
-        assert_eq!(block.index, 4);
-        assert_eq!(block.nodes.len(), 3);
-        assert_eq!(block.nodes[0].index, 10);
-        assert_eq!(block.nodes[1].index, 13);
-        assert_eq!(block.nodes[2].index, 3);
-        assert_eq!(upgrade.start, 0);
-        assert_eq!(upgrade.length, 10);
-        assert_eq!(upgrade.nodes.len(), 1);
-        assert_eq!(upgrade.nodes[0].index, 17);
-        assert_eq!(upgrade.additional_nodes.len(), 0);
+        assert!(clone.verify_and_apply_proof(&proof).await?);
+        assert_eq!(clone.node(12).await?, main.node(12).await?);
+        assert_eq!(clone.node(7).await?, main.node(7).await?);
+        assert!(clone.node(0).await?.is_none());
         Ok(())
     }
 
     #[async_std::test]
-    async fn core_create_proof_block_and_upgrade_and_additional() -> Result<(), HypercoreError> {
-        let mut hypercore = create_hypercore_with_data(10).await?;
+    async fn core_missing_nodes_range() -> Result<(), HypercoreError> {
+        let main = create_hypercore_with_data(10).await?;
+        let mut clone = create_hypercore_with_data_and_key_pair(
+            0,
+            PartialKeypair {
+                public: main.key_pair.public,
+                secret: None,
+            },
+        )
+        .await?;
+        assert_eq!(clone.missing_nodes_range(0..4).await?, vec![0, 0, 0, 0]);
+
+        for index in [6, 1] {
+            let nodes = clone.missing_nodes(index).await?;
+            let upgrade = (clone.info().length == 0).then_some(RequestUpgrade {
+                start: 0,
+                length: 10,
+            });
+            let proof = main
+                .create_proof(Some(RequestBlock { index, nodes }), None, None, upgrade)
+                .await?
+                .unwrap();
+            assert!(clone.verify_and_apply_proof(&proof).await?);
+        }
+
+        let mut expected = vec![];
+        for index in 0..12 {
+            expected.push(clone.missing_nodes(index).await?);
+        }
+        assert_eq!(clone.missing_nodes_range(0..12).await?, expected);
+        assert_eq!(clone.missing_nodes_range(3..7).await?, expected[3..7]);
+        assert!(clone.missing_nodes_range(5..5).await?.is_empty());
+        Ok(())
+    }
+
+    #[cfg(feature = "cache")]
+    #[async_std::test]
+    async fn core_proof_cache() -> Result<(), HypercoreError> {
+        let mut hypercore = Hypercore::new(
+            Storage::new_memory().await?,
+            HypercoreOptions {
+                proof_cache_options: Some(CacheOptions::new()),
+                ..HypercoreOptions::new()
+            },
+        )
+        .await?;
+        for i in 0..10 {
+            hypercore.append(format!("#{}", i).as_bytes()).await?;
+        }
+        let block = RequestBlock { index: 4, nodes: 2 };
+        let upgrade = RequestUpgrade {
+            start: 0,
+            length: 10,
+        };
+        let key = ProofCacheKey::new(0, Some(&block), None, None, Some(&upgrade));
         let proof = hypercore
-            .create_proof(
-                Some(RequestBlock { index: 4, nodes: 0 }),
-                None,
-                None,
-                Some(RequestUpgrade {
-                    start: 0,
-                    length: 8,
-                }),
-            )
+            .create_proof(Some(block.clone()), None, None, Some(upgrade.clone()))
             .await?
             .unwrap();
-        let block = proof.block.unwrap();
-        let upgrade = proof.upgrade.unwrap();
-        assert_eq!(proof.seek, None);
-        assert_eq!(block.index, 4);
-        assert_eq!(block.nodes.len(), 3);
-        assert_eq!(block.nodes[0].index, 10);
-        assert_eq!(block.nodes[1].index, 13);
-        assert_eq!(block.nodes[2].index, 3);
-        assert_eq!(upgrade.start, 0);
-        assert_eq!(upgrade.length, 8);
-        assert_eq!(upgrade.nodes.len(), 0);
-        assert_eq!(upgrade.additional_nodes.len(), 1);
-        assert_eq!(upgrade.additional_nodes[0].index, 17);
+        let cache = hypercore.proof_cache.clone().unwrap();
+        assert!(cache.contains_key(&key));
+        let cached_proof = hypercore
+            .create_proof(Some(block.clone()), None, None, Some(upgrade.clone()))
+            .await?
+            .unwrap();
+        assert_eq!(cached_proof, proof);
+
+        // Appending changes the upgrade, so cached proofs are dropped
+        hypercore.append(b"#10").await?;
+        assert!(!cache.contains_key(&key));
+        let new_proof = hypercore
+            .create_proof(Some(block), None, None, Some(upgrade))
+            .await?
+            .unwrap();
+        assert_ne!(
+            new_proof.upgrade.unwrap().signature,
+            proof.upgrade.unwrap().signature
+        );
         Ok(())
     }
 
+    #[cfg(feature = "cache")]
     #[async_std::test]
-    async fn core_create_proof_block_and_upgrade_from_existing_state() -> Result<(), HypercoreError>
-    {
-        let mut hypercore = create_hypercore_with_data(10).await?;
-        let proof = hypercore
-            .create_proof(
-                Some(RequestBlock { index: 1, nodes: 0 }),
-                None,
-                None,
-                Some(RequestUpgrade {
-                    start: 1,
-                    length: 9,
-                }),
-            )
+    async fn core_block_cache() -> Result<(), HypercoreError> {
+        let mut hypercore = Hypercore::new(
+            Storage::new_memory().await?,
+            HypercoreOptions {
+                block_cache_options: Some(CacheOptions::new()),
+                ..HypercoreOptions::new()
+            },
+        )
+        .await?;
+        for i in 0..10 {
+            hypercore.append(format!("#{}", i).as_bytes()).await?;
+        }
+        let cache = hypercore.block_cache.clone().unwrap();
+        assert!(!cache.contains_key(&3));
+        assert_eq!(hypercore.get(3).await?, Some(b"#3".to_vec()));
+        assert!(cache.contains_key(&3));
+
+        // Cached values are served without touching storage
+        hypercore
+            .storage
+            .flush_info(StoreInfo::new_content(crate::Store::Data, 6, b"XX"))
+            .await?;
+        assert_eq!(hypercore.get(3).await?, Some(b"#3".to_vec()));
+
+        hypercore.clear(3, 4).await?;
+        assert!(!cache.contains_key(&3));
+        assert_eq!(hypercore.get(3).await?, None);
+        Ok(())
+    }
+
+    #[cfg(feature = "cache")]
+    #[async_std::test]
+    async fn core_preload() -> Result<(), HypercoreError> {
+        let mut hypercore = Hypercore::new(
+            Storage::new_memory().await?,
+            HypercoreOptions {
+                node_cache_options: Some(CacheOptions::new()),
+                block_cache_options: Some(CacheOptions::new()),
+                ..HypercoreOptions::new()
+            },
+        )
+        .await?;
+        for i in 0..10 {
+            hypercore.append(format!("#{}", i).as_bytes()).await?;
+        }
+        hypercore.clear(4, 5).await?;
+        let cache = hypercore.block_cache.clone().unwrap();
+
+        hypercore.preload(2..20, false).await?;
+        assert_eq!(cache.iter().count(), 0);
+
+        hypercore.preload(2..20, true).await?;
+        for index in 0..10 {
+            assert_eq!(cache.contains_key(&index), index >= 2 && index != 4);
+        }
+        Ok(())
+    }
+
+    #[cfg(all(feature = "cache", feature = "replication"))]
+    #[async_std::test]
+    async fn core_prefetch() -> Result<(), HypercoreError> {
+        use crate::replication::Priority;
+
+        let mut hypercore = Hypercore::new(
+            Storage::new_memory().await?,
+            HypercoreOptions {
+                block_cache_options: Some(CacheOptions::new()),
+                ..HypercoreOptions::new()
+            },
+        )
+        .await?;
+        for i in 0..10 {
+            hypercore.append(format!("#{}", i).as_bytes()).await?;
+        }
+        hypercore.clear(4, 5).await?;
+        hypercore.clear(6, 7).await?;
+
+        // Stored blocks are cached, missing ones wanted in the background
+        hypercore.prefetch(2..8).await?;
+        let cache = hypercore.block_cache.clone().unwrap();
+        for index in 0..10 {
+            assert_eq!(
+                cache.contains_key(&index),
+                (2..8).contains(&index) && index != 4 && index != 6
+            );
+        }
+        assert_eq!(hypercore.wants(), &[(4..7, Priority::Background)]);
+
+        // The next hint replaces the previous one
+        hypercore.prefetch(5..12).await?;
+        assert_eq!(hypercore.wants(), &[(6..12, Priority::Background)]);
+        hypercore.prefetch(7..10).await?;
+        assert!(hypercore.wants().is_empty());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn core_restore_checkpoint() -> Result<(), HypercoreError> {
+        let main = create_hypercore_with_data(10).await?;
+        let checkpoint = main.checkpoint().unwrap();
+        assert_eq!(checkpoint.length, 10);
+
+        let mut invalid = checkpoint.clone();
+        invalid.signature[0] ^= 1;
+        assert!(matches!(
+            crate::HypercoreBuilder::new(Storage::new_memory().await?)
+                .verifying_key(main.key_pair().public)
+                .checkpoint(invalid)
+                .build()
+                .await,
+            Err(HypercoreError::InvalidSignature { .. })
+        ));
+
+        let mut clone = crate::HypercoreBuilder::new(Storage::new_memory().await?)
+            .verifying_key(main.key_pair().public)
+            .checkpoint(checkpoint)
+            .build()
+            .await?;
+        assert_eq!(clone.info().length, 10);
+        assert_eq!(clone.info().byte_length, 20);
+        assert_eq!(clone.checkpoint(), main.checkpoint());
+        assert!(!clone.has(5));
+
+        // Blocks can be verified against the checkpoint without an upgrade
+        let nodes = clone.missing_nodes(5).await?;
+        let proof = main
+            .create_proof(Some(RequestBlock { index: 5, nodes }), None, None, None)
             .await?
             .unwrap();
-        let block = proof.block.unwrap();
-        let upgrade = proof.upgrade.unwrap();
-        assert_eq!(proof.seek, None);
-        assert_eq!(block.index, 1);
-        assert_eq!(block.nodes.len(), 0);
-        assert_eq!(upgrade.start, 1);
-        assert_eq!(upgrade.length, 9);
-        assert_eq!(upgrade.nodes.len(), 3);
-        assert_eq!(upgrade.nodes[0].index, 5);
-        assert_eq!(upgrade.nodes[1].index, 11);
-        assert_eq!(upgrade.nodes[2].index, 17);
-        assert_eq!(upgrade.additional_nodes.len(), 0);
+        assert!(clone.verify_and_apply_proof(&proof).await?);
+        assert_eq!(clone.get(5).await?, Some(b"#5".to_vec()));
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn core_copy_to() -> Result<(), HypercoreError> {
+        let mut main = create_hypercore_with_data(10).await?;
+        main.clear(3, 5).await?;
+        let mut copy = main.copy_to(Storage::new_memory().await?).await?;
+        assert_eq!(copy.key_pair().public, main.key_pair().public);
+        assert_eq!(copy.checkpoint(), main.checkpoint());
+        assert_eq!(copy.info().contiguous_length, 3);
+        for index in 0..10 {
+            assert_eq!(copy.get(index).await?, main.get(index).await?);
+        }
+
+        // The copy is writable
+        copy.append(b"#10").await?;
+        assert_eq!(copy.info().length, 11);
+
+        // Copying an empty core gives an empty core
+        let empty = create_hypercore_with_data(0).await?;
+        let copy = empty.copy_to(Storage::new_memory().await?).await?;
+        assert_eq!(copy.info().length, 0);
+        assert_eq!(copy.key_pair().public, empty.key_pair().public);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn core_export_import() -> Result<(), HypercoreError> {
+        let mut main = create_hypercore_with_data(10).await?;
+        main.clear(3, 5).await?;
+        let mut archive = vec![];
+        main.export(&mut archive).await?;
+
+        let imported = Hypercore::import(Storage::new_memory().await?, archive.as_slice()).await?;
+        assert_eq!(imported.key_pair().public, main.key_pair().public);
+        assert!(imported.key_pair().secret.is_none());
+        assert_eq!(imported.manifest(), main.manifest());
+        assert_eq!(imported.checkpoint(), main.checkpoint());
+        assert_eq!(imported.info().contiguous_length, 3);
+        for index in 0..10 {
+            assert_eq!(imported.get(index).await?, main.get(index).await?);
+        }
+
+        // Exporting the import gives the same archive
+        let mut again = vec![];
+        imported.export(&mut again).await?;
+        assert_eq!(again, archive);
+
+        // An empty core round trips
+        let empty = create_hypercore_with_data(0).await?;
+        let mut archive = vec![];
+        empty.export(&mut archive).await?;
+        let imported = Hypercore::import(Storage::new_memory().await?, archive.as_slice()).await?;
+        assert_eq!(imported.info().length, 0);
+        assert_eq!(imported.key_pair().public, empty.key_pair().public);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn core_import_rejects_invalid_archives() -> Result<(), HypercoreError> {
+        let main = create_hypercore_with_data(4).await?;
+        let mut archive = vec![];
+        main.export(&mut archive).await?;
+        let import =
+            async |archive: &[u8]| Hypercore::import(Storage::new_memory().await?, archive).await;
+        assert!(import(&archive).await.is_ok());
+
+        // Tampered block
+        let position = archive
+            .windows(2)
+            .rposition(|bytes| bytes == b"#3")
+            .unwrap();
+        let mut tampered = archive.clone();
+        tampered[position + 1] = b'4';
+        assert!(import(&tampered).await.is_err());
+
+        // Cut before the end record
+        assert!(import(&archive[..archive.len() - 2]).await.is_err());
+
+        let other = create_hypercore_with_data(0).await?;
+        // Another key, also in the manifest, than the one that signed the tree
+        let mut wrong_key = archive.clone();
+        while let Some(position) = wrong_key
+            .windows(32)
+            .position(|bytes| bytes == main.key_pair().public.as_bytes())
+        {
+            wrong_key[position..position + 32].copy_from_slice(other.key_pair().public.as_bytes());
+        }
+        assert!(import(&wrong_key).await.is_err());
+
+        assert!(import(b"not an archive").await.is_err());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn core_incremental_backup() -> Result<(), HypercoreError> {
+        let mut main = create_hypercore_with_data(5).await?;
+        let mut archive = vec![];
+        main.export(&mut archive).await?;
+        let mut restored =
+            Hypercore::import(Storage::new_memory().await?, archive.as_slice()).await?;
+
+        for index in 5..10 {
+            main.append(format!("#{index}").as_bytes()).await?;
+        }
+        main.clear(7, 8).await?;
+        let mut backup = vec![];
+        main.export_since(5, &mut backup).await?;
+        restored.apply_backup(backup.as_slice()).await?;
+        assert_eq!(restored.checkpoint(), main.checkpoint());
+        for index in 0..10 {
+            assert_eq!(restored.get(index).await?, main.get(index).await?);
+        }
+
+        // The backup was already applied
+        assert!(restored.apply_backup(backup.as_slice()).await.is_err());
+
+        // Nothing was appended since
+        let mut empty = vec![];
+        main.export_since(10, &mut empty).await?;
+        restored.apply_backup(empty.as_slice()).await?;
+        assert_eq!(restored.info().length, 10);
+        assert!(main.export_since(11, &mut vec![]).await.is_err());
+
+        // Backups of other cores or with tampered blocks are rejected
+        let other = create_hypercore_with_data(10).await?;
+        let mut other_backup = vec![];
+        other.export_since(10, &mut other_backup).await?;
+        assert!(
+            restored
+                .apply_backup(other_backup.as_slice())
+                .await
+                .is_err()
+        );
+        main.append(b"#10").await?;
+        let mut backup = vec![];
+        main.export_since(10, &mut backup).await?;
+        let position = backup
+            .windows(3)
+            .rposition(|bytes| bytes == b"#10")
+            .unwrap();
+        backup[position + 2] = b'1';
+        assert!(restored.apply_backup(backup.as_slice()).await.is_err());
+
+        // A full archive isn't a backup
+        assert!(restored.apply_backup(archive.as_slice()).await.is_err());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn core_restore_is_verified_and_atomic() -> Result<(), HypercoreError> {
+        let mut main = create_hypercore_with_data(10).await?;
+        let mut archive = vec![];
+        main.export(&mut archive).await?;
+        let mut tampered = archive.clone();
+        let position = tampered
+            .windows(2)
+            .rposition(|bytes| bytes == b"#9")
+            .unwrap();
+        tampered[position + 1] = b'8';
+
+        // Restore lost blocks
+        let mut restored =
+            Hypercore::import(Storage::new_memory().await?, archive.as_slice()).await?;
+        restored.clear(2, 6).await?;
+        assert!(restored.restore(tampered.as_slice()).await.is_err());
+        assert!(
+            restored
+                .restore(&archive[..archive.len() - 1])
+                .await
+                .is_err()
+        );
+        assert!(!restored.has(2));
+        assert_eq!(
+            restored.restore(archive.as_slice()).await?,
+            RestoreOutcome {
+                added: 4,
+                skipped: 6
+            }
+        );
+        for index in 0..10 {
+            assert_eq!(restored.get(index).await?, main.get(index).await?);
+        }
+
+        // Restore into an empty core with the key
+        let public = PartialKeypair {
+            public: main.key_pair().public,
+            secret: None,
+        };
+        let mut empty = create_hypercore_with_data_and_key_pair(0, public.clone()).await?;
+        assert!(empty.restore(tampered.as_slice()).await.is_err());
+        assert_eq!(empty.info().length, 0);
+        assert_eq!(
+            empty.restore(archive.as_slice()).await?,
+            RestoreOutcome {
+                added: 10,
+                skipped: 0
+            }
+        );
+        assert_eq!(empty.checkpoint(), main.checkpoint());
+
+        // Backups are applied all or nothing
+        for index in 10..13 {
+            main.append(format!("#{index}").as_bytes()).await?;
+        }
+        let mut backup = vec![];
+        main.export_since(10, &mut backup).await?;
+        let mut tampered = backup.clone();
+        let position = tampered
+            .windows(3)
+            .rposition(|bytes| bytes == b"#12")
+            .unwrap();
+        tampered[position + 2] = b'1';
+        assert!(restored.apply_backup(tampered.as_slice()).await.is_err());
+        assert_eq!(restored.info().length, 10);
+        assert_eq!(
+            restored.apply_backup(backup.as_slice()).await?,
+            RestoreOutcome {
+                added: 3,
+                skipped: 0
+            }
+        );
+        assert_eq!(restored.checkpoint(), main.checkpoint());
+
+        // An archive of an older head can't be restored
+        assert!(restored.restore(archive.as_slice()).await.is_err());
+        Ok(())
+    }
+
+    #[cfg_attr(feature = "async-std", async_std::test)]
+    #[cfg_attr(feature = "tokio", tokio::test)]
+    async fn core_persist_to_disk() -> Result<(), HypercoreError> {
+        let mut hypercore = create_hypercore_with_data(10).await?;
+        hypercore.clear(3, 5).await?;
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_path_buf();
+        hypercore.persist_to_disk(&path).await?;
+        hypercore.append(b"#10").await?;
+        let key_pair = hypercore.key_pair().clone();
+        drop(hypercore);
+
+        let hypercore = crate::HypercoreBuilder::new(Storage::new_disk(&path, false).await?)
+            .open(true)
+            .build()
+            .await?;
+        assert_eq!(hypercore.key_pair().public, key_pair.public);
+        assert!(hypercore.key_pair().secret.is_some());
+        assert_eq!(hypercore.info().length, 11);
+        for index in 0..11 {
+            let expected = (!(3..5).contains(&index)).then(|| format!("#{index}").into_bytes());
+            assert_eq!(hypercore.get(index).await?, expected);
+        }
+
+        // The target must be empty
+        let mut other = create_hypercore_with_data(1).await?;
+        assert!(matches!(
+            other.persist_to_disk(&path).await,
+            Err(HypercoreError::BadArgument { .. })
+        ));
+        assert_eq!(other.get(0).await?, Some(b"#0".to_vec()));
+        Ok(())
+    }
+
+    #[cfg_attr(feature = "async-std", async_std::test)]
+    #[cfg_attr(feature = "tokio", tokio::test)]
+    async fn core_deduplicate() -> Result<(), HypercoreError> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_path_buf();
+        let mut core = crate::HypercoreBuilder::new(Storage::new_disk(&path, false).await?)
+            .deduplicate(true)
+            .build()
+            .await?;
+        core.append_batch([b"aa", b"bb", b"aa", b"aa"]).await?;
+        core.append_batch([b"cc", b"bb"]).await?;
+        async fn stored(core: &Hypercore, offset: u64) -> Result<Vec<u8>, HypercoreError> {
+            let instruction =
+                crate::common::StoreInfoInstruction::new_content(crate::Store::Data, offset, 2);
+            let info = core.storage.read_info(instruction).await?;
+            Ok(info.data.unwrap().to_vec())
+        }
+        // Repeated blocks are not written, but read from their first copy
+        assert_eq!(stored(&core, 4).await?, vec![0, 0]);
+        // The data store ends before the last block
+        assert!(stored(&core, 10).await.is_err());
+        assert_eq!(core.get(3).await?, Some(b"aa".to_vec()));
+        assert_eq!(core.get(5).await?, Some(b"bb".to_vec()));
+        let key_pair = core.key_pair().clone();
+        drop(core);
+
+        // Reopened without the option, the hypercore stays deduplicated
+        let mut core = crate::HypercoreBuilder::new(Storage::new_disk(&path, false).await?)
+            .open(true)
+            .build()
+            .await?;
+        assert_eq!(core.key_pair().public, key_pair.public);
+        assert_eq!(core.get(2).await?, Some(b"aa".to_vec()));
+        core.append(b"aa").await?;
+        assert!(stored(&core, 12).await.is_err());
+
+        // Clearing the first copy moves its bytes to the next remaining one
+        core.clear(0, 3).await?;
+        assert_eq!(stored(&core, 6).await?, b"aa".to_vec());
+        assert_eq!(stored(&core, 10).await?, b"bb".to_vec());
+        assert_eq!(core.get(2).await?, None);
+        assert_eq!(core.get(3).await?, Some(b"aa".to_vec()));
+        assert_eq!(core.get(6).await?, Some(b"aa".to_vec()));
+        assert_eq!(core.get(5).await?, Some(b"bb".to_vec()));
+        assert!(
+            core.verify_range(0, 7)
+                .await?
+                .iter()
+                .all(|(_, valid)| *valid)
+        );
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn core_persistence_policy() -> Result<(), HypercoreError> {
+        let main = create_hypercore_with_data(6).await?;
+        let policies: [Arc<dyn PersistencePolicy>; 2] = [
+            Arc::new(crate::PersistRanges(vec![2..3, 3..4])),
+            Arc::new(crate::PersistIf(|_, value: &[u8]| {
+                value == b"#2" || value == b"#3"
+            })),
+        ];
+        for policy in policies {
+            let mut mirror = crate::HypercoreBuilder::new(Storage::new_memory().await?)
+                .verifying_key(main.key_pair.public)
+                .persistence_policy(policy)
+                .build()
+                .await?;
+            for index in 0..6 {
+                let nodes = mirror.missing_nodes(index).await?;
+                let upgrade = (index == 0).then_some(RequestUpgrade {
+                    start: 0,
+                    length: 6,
+                });
+                let proof = main
+                    .create_proof(Some(RequestBlock { index, nodes }), None, None, upgrade)
+                    .await?
+                    .unwrap();
+                assert!(mirror.verify_and_apply_proof(&proof).await?);
+            }
+            assert_eq!(mirror.info().length, 6);
+            for index in 0..6 {
+                let expected = (2..4)
+                    .contains(&index)
+                    .then(|| format!("#{index}").into_bytes());
+                assert_eq!(mirror.has(index), expected.is_some());
+                assert_eq!(mirror.get(index).await?, expected);
+            }
+        }
         Ok(())
     }
 
     #[async_std::test]
-    async fn core_create_proof_block_and_upgrade_from_existing_state_with_additional()
-    -> Result<(), HypercoreError> {
-        let mut hypercore = create_hypercore_with_data(10).await?;
-        let proof = hypercore
+    async fn core_retention() -> Result<(), HypercoreError> {
+        let mut core = crate::HypercoreBuilder::new(Storage::new_memory().await?)
+            .retention(crate::Retention::Blocks(3))
+            .build()
+            .await?;
+        for index in 0..5u64 {
+            core.append(format!("#{index}").as_bytes()).await?;
+        }
+        assert_eq!(core.info().length, 5);
+        for index in 0..5 {
+            let expected = (index >= 2).then(|| format!("#{index}").into_bytes());
+            assert_eq!(core.get(index).await?, expected);
+        }
+
+        // The tree is kept, retained blocks can still be proven
+        let mut clone = create_hypercore_with_data_and_key_pair(
+            0,
+            PartialKeypair {
+                public: core.key_pair.public,
+                secret: None,
+            },
+        )
+        .await?;
+        let nodes = clone.missing_nodes(4).await?;
+        let proof = core
             .create_proof(
-                Some(RequestBlock { index: 1, nodes: 0 }),
+                Some(RequestBlock { index: 4, nodes }),
                 None,
                 None,
                 Some(RequestUpgrade {
-                    start: 1,
+                    start: 0,
                     length: 5,
                 }),
             )
             .await?
             .unwrap();
-        let block = proof.block.unwrap();
-        let upgrade = proof.upgrade.unwrap();
-        assert_eq!(proof.seek, None);
-        assert_eq!(block.index, 1);
-        assert_eq!(block.nodes.len(), 0);
-        assert_eq!(upgrade.start, 1);
-        assert_eq!(upgrade.length, 5);
-        assert_eq!(upgrade.nodes.len(), 2);
-        assert_eq!(upgrade.nodes[0].index, 5);
-        assert_eq!(upgrade.nodes[1].index, 9);
-        assert_eq!(upgrade.additional_nodes.len(), 2);
-        assert_eq!(upgrade.additional_nodes[0].index, 13);
-        assert_eq!(upgrade.additional_nodes[1].index, 17);
+        assert!(clone.verify_and_apply_proof(&proof).await?);
+        assert_eq!(clone.get(4).await?, Some(b"#4".to_vec()));
+
+        let mut core = crate::HypercoreBuilder::new(Storage::new_memory().await?)
+            .retention(crate::Retention::Bytes(4))
+            .build()
+            .await?;
+        core.append_batch([b"aa", b"bb", b"c."]).await?;
+        assert_eq!(core.get(0).await?, None);
+        assert_eq!(core.get(1).await?, Some(b"bb".to_vec()));
+        core.append(b"dd").await?;
+        assert!(!core.has(1));
+        assert_eq!(core.get(2).await?, Some(b"c.".to_vec()));
+        assert_eq!(core.info().byte_length, 8);
         Ok(())
     }
 
-    #[async_std::test]
-    async fn core_create_proof_block_and_seek_1_no_upgrade() -> Result<(), HypercoreError> {
-        let mut hypercore = create_hypercore_with_data(10).await?;
-        let proof = hypercore
-            .create_proof(
-                Some(RequestBlock { index: 4, nodes: 2 }),
-                None,
-                Some(RequestSeek { bytes: 8 }),
-                None,
-            )
-            .await?
-            .unwrap();
-        let block = proof.block.unwrap();
-        assert_eq!(proof.seek, None); // seek included in block
-        assert_eq!(proof.upgrade, None);
-        assert_eq!(block.index, 4);
-        assert_eq!(block.nodes.len(), 2);
-        assert_eq!(block.nodes[0].index, 10);
-        assert_eq!(block.nodes[1].index, 13);
+    #[cfg_attr(feature = "async-std", async_std::test)]
+    #[cfg_attr(feature = "tokio", tokio::test)]
+    async fn core_retention_after_reopen() -> Result<(), HypercoreError> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_path_buf();
+        let mut core = crate::HypercoreBuilder::new(Storage::new_disk(&path, false).await?)
+            .retention(crate::Retention::Bytes(3))
+            .build()
+            .await?;
+        // The cutoff is in the middle of "bb", which is cleared
+        core.append_batch([b"aa", b"bb", b"c."]).await?;
+        assert!(!core.has(1));
+        assert_eq!(core.get(2).await?, Some(b"c.".to_vec()));
+        drop(core);
+
+        // Only the blocks past the ones cleared before reopening are cleared
+        let mut core = crate::HypercoreBuilder::new(Storage::new_disk(&path, false).await?)
+            .open(true)
+            .retention(crate::Retention::Bytes(3))
+            .build()
+            .await?;
+        core.append(b"dd").await?;
+        let cleared = core.dump_state().await?.oplog_entries.pop().unwrap();
+        assert!(cleared.cleared);
+        assert_eq!(cleared.blocks, Some(2..3));
+        assert_eq!(core.get(3).await?, Some(b"dd".to_vec()));
         Ok(())
     }
 
     #[async_std::test]
-    async fn core_create_proof_block_and_seek_2_no_upgrade() -> Result<(), HypercoreError> {
-        let mut hypercore = create_hypercore_with_data(10).await?;
-        let proof = hypercore
+    async fn core_retention_of_proofs() -> Result<(), HypercoreError> {
+        let main = create_hypercore_with_data(10).await?;
+        let mut clone = crate::HypercoreBuilder::new(Storage::new_memory().await?)
+            .key_pair(PartialKeypair {
+                public: main.key_pair.public,
+                secret: None,
+            })
+            .retention(crate::Retention::Blocks(3))
+            .build()
+            .await?;
+        let proof = main
             .create_proof(
-                Some(RequestBlock { index: 4, nodes: 2 }),
                 None,
-                Some(RequestSeek { bytes: 10 }),
                 None,
+                None,
+                Some(RequestUpgrade {
+                    start: 0,
+                    length: 10,
+                }),
             )
             .await?
             .unwrap();
-        let block = proof.block.unwrap();
-        assert_eq!(proof.seek, None); // seek included in block
-        assert_eq!(proof.upgrade, None);
-        assert_eq!(block.index, 4);
-        assert_eq!(block.nodes.len(), 2);
-        assert_eq!(block.nodes[0].index, 10);
-        assert_eq!(block.nodes[1].index, 13);
+        assert!(clone.verify_and_apply_proof(&proof).await?);
+
+        let mut proofs = vec![];
+        for index in 0..10 {
+            let nodes = clone.missing_nodes(index).await?;
+            let proof = main
+                .create_proof(Some(RequestBlock { index, nodes }), None, None, None)
+                .await?
+                .unwrap();
+            if index < 5 {
+                assert!(clone.verify_and_apply_proof(&proof).await?);
+            } else {
+                proofs.push(proof);
+            }
+        }
+        assert_eq!(clone.present_ranges(0..10), vec![]);
+        assert_eq!(clone.verify_and_apply_proofs(&proofs).await?, vec![true; 5]);
+        assert_eq!(clone.present_ranges(0..10), vec![7..10]);
         Ok(())
     }
 
     #[async_std::test]
-    async fn core_create_proof_block_and_seek_3_no_upgrade() -> Result<(), HypercoreError> {
-        let mut hypercore = create_hypercore_with_data(10).await?;
-        let proof = hypercore
-            .create_proof(
-                Some(RequestBlock { index: 4, nodes: 2 }),
-                None,
-                Some(RequestSeek { bytes: 13 }),
-                None,
-            )
-            .await?
-            .unwrap();
-        let block = proof.block.unwrap();
-        let seek = proof.seek.unwrap();
-        assert_eq!(proof.upgrade, None);
-        assert_eq!(block.index, 4);
-        assert_eq!(block.nodes.len(), 1);
-        assert_eq!(block.nodes[0].index, 10);
-        assert_eq!(seek.nodes.len(), 2);
-        assert_eq!(seek.nodes[0].index, 12);
-        assert_eq!(seek.nodes[1].index, 14);
+    async fn core_append_chunked() -> Result<(), HypercoreError> {
+        let mut core = create_hypercore_with_data(1).await?;
+        let data: Vec<u8> = (0..10).collect();
+        let range = core.append_chunked(&data, 4).await?;
+        assert_eq!(range, 1..4);
+        assert_eq!(core.get(3).await?, Some(vec![8, 9]));
+        assert_eq!(core.get_chunked(range.clone()).await?, Some(data));
+        assert_eq!(core.append_chunked(&[], 4).await?, 4..4);
+        assert!(matches!(
+            core.append_chunked(&[1], 0).await,
+            Err(HypercoreError::BadArgument { .. })
+        ));
+
+        core.clear(2, 3).await?;
+        assert_eq!(core.get_chunked(range).await?, None);
         Ok(())
     }
 
+    #[cfg(feature = "chunking")]
     #[async_std::test]
-    async fn core_create_proof_block_and_seek_to_tree_no_upgrade() -> Result<(), HypercoreError> {
-        let mut hypercore = create_hypercore_with_data(16).await?;
-        let proof = hypercore
-            .create_proof(
-                Some(RequestBlock { index: 0, nodes: 4 }),
-                None,
-                Some(RequestSeek { bytes: 26 }),
-                None,
-            )
-            .await?
-            .unwrap();
-        let block = proof.block.unwrap();
-        let seek = proof.seek.unwrap();
-        assert_eq!(proof.upgrade, None);
-        assert_eq!(block.nodes.len(), 3);
-        assert_eq!(block.nodes[0].index, 2);
-        assert_eq!(block.nodes[1].index, 5);
-        assert_eq!(block.nodes[2].index, 11);
-        assert_eq!(seek.nodes.len(), 2);
-        assert_eq!(seek.nodes[0].index, 19);
-        assert_eq!(seek.nodes[1].index, 27);
+    async fn core_append_reader() -> Result<(), HypercoreError> {
+        let mut core = create_hypercore_with_data(0).await?;
+        let chunker = crate::chunking::FastCdc::new(64, 256, 1024)?;
+        let data: Vec<u8> = (0..20_000u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 24) as u8)
+            .collect();
+        let range = core
+            .append_reader(futures::io::Cursor::new(&data), &chunker)
+            .await?;
+        let chunks: Vec<&[u8]> = chunker.chunks(&data).collect();
+        assert_eq!(range, 0..chunks.len() as u64);
+        for (index, chunk) in range.clone().zip(chunks) {
+            assert_eq!(core.get(index).await?.as_deref(), Some(chunk));
+        }
+        assert_eq!(core.get_chunked(range).await?, Some(data));
         Ok(())
     }
 
     #[async_std::test]
-    async fn core_create_proof_block_and_seek_with_upgrade() -> Result<(), HypercoreError> {
-        let mut hypercore = create_hypercore_with_data(10).await?;
-        let proof = hypercore
-            .create_proof(
-                Some(RequestBlock { index: 4, nodes: 2 }),
-                None,
-                Some(RequestSeek { bytes: 13 }),
-                Some(RequestUpgrade {
-                    start: 8,
-                    length: 2,
-                }),
-            )
-            .await?
-            .unwrap();
-        let block = proof.block.unwrap();
-        let seek = proof.seek.unwrap();
-        let upgrade = proof.upgrade.unwrap();
-        assert_eq!(block.index, 4);
-        assert_eq!(block.nodes.len(), 1);
-        assert_eq!(block.nodes[0].index, 10);
-        assert_eq!(seek.nodes.len(), 2);
-        assert_eq!(seek.nodes[0].index, 12);
-        assert_eq!(seek.nodes[1].index, 14);
-        assert_eq!(upgrade.nodes.len(), 1);
-        assert_eq!(upgrade.nodes[0].index, 17);
-        assert_eq!(upgrade.additional_nodes.len(), 0);
+    async fn core_quota() -> Result<(), HypercoreError> {
+        let mut core = crate::HypercoreBuilder::new(Storage::new_memory().await?)
+            .quota(crate::Quota::new(Some(4), None).warning_percent(50))
+            .build()
+            .await?;
+        #[cfg(feature = "replication")]
+        let mut events = core.event_subscribe();
+        core.append_batch([b"#0", b"#1"]).await?;
+        core.append_batch([b"#2", b"#3"]).await?;
+        assert!(matches!(
+            core.append(b"#4").await,
+            Err(HypercoreError::QuotaExceeded {
+                length: 5,
+                byte_length: 10,
+                ..
+            })
+        ));
+        assert_eq!(core.info().length, 4);
+        assert_eq!(core.get(3).await?, Some(b"#3".to_vec()));
+        #[cfg(feature = "replication")]
+        {
+            let mut warnings = vec![];
+            while let Ok(event) = events.try_recv() {
+                if let crate::replication::events::Event::QuotaWarning(warning) = event {
+                    warnings.push(warning.length);
+                }
+            }
+            assert_eq!(warnings, vec![2]);
+        }
+
+        // Upgrades from peers past the quota are rejected
+        let mut mirrors = vec![];
+        for max_byte_length in [6, 8] {
+            let mirror = crate::HypercoreBuilder::new(Storage::new_memory().await?)
+                .verifying_key(core.key_pair.public)
+                .quota(crate::Quota::new(None, Some(max_byte_length)))
+                .build()
+                .await?;
+            mirrors.push(mirror);
+        }
+        let upgrade = Some(RequestUpgrade {
+            start: 0,
+            length: 4,
+        });
+        let proof = core.create_proof(None, None, None, upgrade).await?.unwrap();
+        assert!(matches!(
+            mirrors[0].verify_and_apply_proof(&proof).await,
+            Err(HypercoreError::QuotaExceeded {
+                length: 4,
+                byte_length: 8,
+                ..
+            })
+        ));
+        assert_eq!(mirrors[0].info().length, 0);
+        assert!(mirrors[1].verify_and_apply_proof(&proof).await?);
+        assert_eq!(mirrors[1].info().length, 4);
         Ok(())
     }
 
     #[async_std::test]
-    async fn core_create_proof_seek_with_upgrade() -> Result<(), HypercoreError> {
-        let mut hypercore = create_hypercore_with_data(10).await?;
-        let proof = hypercore
+    async fn core_light_mode() -> Result<(), HypercoreError> {
+        let main = create_hypercore_with_data(4).await?;
+        let mut light = crate::HypercoreBuilder::new(Storage::new_memory().await?)
+            .verifying_key(main.key_pair.public)
+            .light(true)
+            .build()
+            .await?;
+        assert!(light.is_light() && !main.is_light());
+
+        // Blocks are verified into the tree and discarded
+        let proof = main
             .create_proof(
+                Some(RequestBlock { index: 1, nodes: 1 }),
                 None,
                 None,
-                Some(RequestSeek { bytes: 13 }),
                 Some(RequestUpgrade {
                     start: 0,
-                    length: 10,
+                    length: 4,
                 }),
             )
             .await?
             .unwrap();
-        let seek = proof.seek.unwrap();
-        let upgrade = proof.upgrade.unwrap();
-        assert_eq!(proof.block, None);
-        assert_eq!(seek.nodes.len(), 4);
-        assert_eq!(seek.nodes[0].index, 12);
-        assert_eq!(seek.nodes[1].index, 14);
-        assert_eq!(seek.nodes[2].index, 9);
-        assert_eq!(seek.nodes[3].index, 3);
-        assert_eq!(upgrade.nodes.len(), 1);
-        assert_eq!(upgrade.nodes[0].index, 17);
-        assert_eq!(upgrade.additional_nodes.len(), 0);
-        Ok(())
-    }
-
-    #[async_std::test]
-    async fn core_verify_proof_invalid_signature() -> Result<(), HypercoreError> {
-        let mut hypercore = create_hypercore_with_data(10).await?;
-        // Invalid clone hypercore with a different public key
-        let mut hypercore_clone = create_hypercore_with_data(0).await?;
-        let proof = hypercore
-            .create_proof(
-                None,
-                Some(RequestBlock { index: 6, nodes: 0 }),
-                None,
-                Some(RequestUpgrade {
-                    start: 0,
-                    length: 10,
-                }),
-            )
+        assert!(light.verify_and_apply_proof(&proof).await?);
+        let nodes = light.missing_nodes(3).await?;
+        let proof = main
+            .create_proof(Some(RequestBlock { index: 3, nodes }), None, None, None)
             .await?
             .unwrap();
-        assert!(
-            hypercore_clone
-                .verify_and_apply_proof(&proof)
-                .await
-                .is_err()
-        );
-        Ok(())
-    }
+        assert!(light.verify_and_apply_proof(&proof).await?);
+        let mut tampered = proof.clone();
+        tampered.block.as_mut().unwrap().value = b"#x".to_vec();
+        assert!(light.verify_and_apply_proof(&tampered).await.is_err());
+        assert_eq!(light.info().length, 4);
+        assert_eq!(light.info().byte_length, main.info().byte_length);
+        assert_eq!(light.info().contiguous_length, 0);
+        for index in 0..4 {
+            assert!(!light.has(index));
+            assert_eq!(light.get(index).await?, None);
+        }
 
-    #[async_std::test]
-    async fn core_verify_and_apply_proof() -> Result<(), HypercoreError> {
-        let mut main = create_hypercore_with_data(10).await?;
-        let mut clone = create_hypercore_with_data_and_key_pair(
+        // The light core attests to the tree, with upgrade proofs other cores verify
+        let mut follower = create_hypercore_with_data_and_key_pair(
             0,
             PartialKeypair {
                 public: main.key_pair.public,
@@ -1111,36 +5960,73 @@ pub(crate) mod tests {
             },
         )
         .await?;
-        let index = 6;
-        let nodes = clone.missing_nodes(index).await?;
-        let proof = main
+        let upgrade = light
             .create_proof(
                 None,
-                Some(RequestBlock { index, nodes }),
+                None,
                 None,
                 Some(RequestUpgrade {
                     start: 0,
-                    length: 10,
+                    length: 4,
                 }),
             )
             .await?
             .unwrap();
-        assert!(clone.verify_and_apply_proof(&proof).await?);
-        let main_info = main.info();
-        let clone_info = clone.info();
-        assert_eq!(main_info.byte_length, clone_info.byte_length);
-        assert_eq!(main_info.length, clone_info.length);
-        assert!(main.get(6).await?.is_some());
-        assert!(clone.get(6).await?.is_none());
+        assert!(follower.verify_and_apply_proof(&upgrade).await?);
+        assert_eq!(follower.info().length, 4);
 
-        // Fetch data for index 6 and verify it is found
-        let index = 6;
-        let nodes = clone.missing_nodes(index).await?;
-        let proof = main
-            .create_proof(Some(RequestBlock { index, nodes }), None, None, None)
-            .await?
-            .unwrap();
-        assert!(clone.verify_and_apply_proof(&proof).await?);
+        // Appending to a light core only stores the tree
+        let mut writer = crate::HypercoreBuilder::new(Storage::new_memory().await?)
+            .light(true)
+            .build()
+            .await?;
+        #[cfg(feature = "replication")]
+        let mut events = writer.event_subscribe();
+        let outcome = writer.append_batch([b"#0", b"#1"]).await?;
+        assert_eq!((outcome.length, outcome.byte_length), (2, 4));
+        assert!(!writer.has(0));
+        assert_eq!(writer.get(1).await?, None);
+        #[cfg(feature = "replication")]
+        {
+            use crate::replication::events::{Event, Have};
+            let mut haves = vec![];
+            while let Ok(event) = events.try_recv() {
+                if let Event::Have(Have {
+                    start,
+                    length,
+                    byte_length,
+                    stored,
+                    ..
+                }) = event
+                {
+                    haves.push((start, length, byte_length, stored));
+                }
+            }
+            assert_eq!(haves, vec![(0, 2, 4, false)]);
+        }
+        Ok(())
+    }
+
+    /// Truncate the tree of a writer to the given length on a new fork, as a writer that
+    /// truncated would have
+    async fn truncate_writer(core: &mut Hypercore, length: u64) -> Result<(), HypercoreError> {
+        let fork = core.tree.fork + 1;
+        let mut changeset = match core.tree.truncate(length, fork, None)? {
+            Either::Right(value) => value,
+            Either::Left(instructions) => {
+                let infos = core.storage.read_infos_to_vec(&instructions).await?;
+                let Either::Right(value) = core.tree.truncate(length, fork, Some(&infos))? else {
+                    panic!("Nodes were read");
+                };
+                value
+            }
+        };
+        changeset.hash_and_sign(core.key_pair.secret.as_ref().unwrap());
+        core.tree.commit(changeset)?;
+        let infos = core.tree.flush();
+        core.storage.flush_infos(&infos).await?;
+        core.tree.flushed();
+        core.invalidate_proof_cache();
         Ok(())
     }
 
@@ -1168,8 +6054,25 @@ pub(crate) mod tests {
             HypercoreOptions {
                 key_pair: Some(key_pair),
                 open: false,
+                overwrite: false,
+                manifest: None,
+                checkpoint: None,
+                light: false,
                 #[cfg(feature = "cache")]
                 node_cache_options: None,
+                #[cfg(feature = "cache")]
+                block_cache_options: None,
+                #[cfg(feature = "cache")]
+                proof_cache_options: None,
+                #[cfg(feature = "replication")]
+                event_queue_capacity: crate::replication::events::DEFAULT_EVENT_QUEUE_CAPACITY,
+                persistence_policy: None,
+                retention: None,
+                quota: None,
+                deduplicate: false,
+                byte_index: false,
+                pending_proofs_capacity: DEFAULT_PENDING_PROOFS_CAPACITY,
+                rng: None,
             },
         )
         .await?;
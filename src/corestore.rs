@@ -0,0 +1,444 @@
+//! Manager of many hypercores sharing a primary key and a storage location, like corestore in
+//! Javascript.
+//!
+//! ```rust
+//! # #[cfg(feature = "tokio")]
+//! # tokio_test::block_on(async {
+//! # example().await;
+//! # });
+//! # #[cfg(feature = "async-std")]
+//! # async_std::task::block_on(async {
+//! # example().await;
+//! # });
+//! # async fn example() {
+//! use hypercore::corestore::Corestore;
+//! use hypercore::replication::CoreInfo;
+//!
+//! let store = Corestore::new_memory();
+//! let core = store.get_named("my-db").await.unwrap();
+//! core.try_append(b"hello").await.unwrap();
+//!
+//! // The same name, or the key of the core, give back the same core
+//! let key = core.key_pair().await.public;
+//! let again = store.get(key).await.unwrap();
+//! assert_eq!(again.info().await.length, 1);
+//! # }
+//! ```
+use async_lock::Mutex;
+use ed25519_dalek::{SigningKey, VerifyingKey};
+use futures::stream::{self, Stream, StreamExt};
+use rand::rngs::OsRng;
+use rand::{CryptoRng, RngCore};
+use std::collections::HashMap;
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use crate::common::to_hex;
+use crate::crypto::{self, hash, keyed_hash};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::oplog::Oplog;
+use crate::replication::events::Event;
+use crate::replication::{CoreInfo, EventStream, ReplicationMethods, SharedCore};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::storage::{CORES_DIR, core_dir};
+use crate::{HypercoreBuilder, HypercoreError, PartialKeypair, Storage};
+
+/// Namespace of the cores of a store that wasn't namespaced, 32 zero bytes like in Javascript
+pub const DEFAULT_NAMESPACE: [u8; 32] = [0; 32];
+
+#[cfg(not(target_arch = "wasm32"))]
+const PRIMARY_KEY_FILE: &str = "primary-key";
+
+#[derive(Debug)]
+enum Location {
+    Memory,
+    #[cfg(not(target_arch = "wasm32"))]
+    Disk(PathBuf),
+}
+
+#[derive(Debug)]
+struct Inner {
+    location: Location,
+    primary_key: [u8; 32],
+    /// Open cores by public key
+    cores: Mutex<HashMap<VerifyingKey, SharedCore>>,
+}
+
+/// Opens and caches many hypercores stored in one place. The key pairs of named cores are derived
+/// from the primary key of the store, so only the primary key needs to be kept to recreate them.
+/// Cores are opened at most once: getting an open core again returns a handle to the same core.
+/// Cloning the store is cheap and shares the open cores.
+///
+/// On disk, the layout is the one of Javascript corestore 6, so a store created by either can be
+/// opened by the other: the primary key is stored in `primary-key` and each core in
+/// `cores/<aa>/<bb>/<aabb...>`, after the hex of its [`crate::discovery_key`], see
+/// [`crate::core_dir`].
+#[derive(Debug, Clone)]
+pub struct Corestore {
+    inner: Arc<Inner>,
+    namespace: [u8; 32],
+}
+
+/// Key pair of the core named `name` in `namespace`, for a store with `primary_key`. The seed of
+/// the key pair is the blake2b hash of the corestore capability, the namespace and the name,
+/// keyed with the primary key, like in Javascript corestore. The same primary key, namespace and
+/// name thus give the same core in both implementations.
+pub fn derive_key_pair(primary_key: &[u8; 32], namespace: &[u8; 32], name: &str) -> PartialKeypair {
+    let seed = keyed_hash(
+        primary_key,
+        &[
+            &crypto::namespace(b"corestore", 0),
+            namespace,
+            name.as_bytes(),
+        ],
+    );
+    let secret = SigningKey::from_bytes(&seed);
+    PartialKeypair {
+        public: secret.verifying_key(),
+        secret: Some(secret),
+    }
+}
+
+/// Namespace `name` nested in `namespace`, the blake2b hash of both like in Javascript corestore
+pub fn derive_namespace(namespace: &[u8; 32], name: &str) -> [u8; 32] {
+    hash(&[namespace, name.as_bytes()])
+}
+
+impl Corestore {
+    /// Create a store keeping its cores in memory, with a random primary key
+    pub fn new_memory() -> Self {
+        Self::new_memory_with_rng(&mut OsRng)
+    }
+
+    /// Create a store keeping its cores in memory, with a primary key from the given random number
+    /// generator, e.g. a seeded one for deterministic tests
+    pub fn new_memory_with_rng<R: RngCore + CryptoRng + ?Sized>(rng: &mut R) -> Self {
+        let mut primary_key = [0; 32];
+        rng.fill_bytes(&mut primary_key);
+        Self::with_location(Location::Memory, primary_key)
+    }
+
+    /// Open the store in `dir`, creating it with a random primary key if it doesn't exist
+    #[cfg(not(target_arch = "wasm32"))]
+    pub async fn open_disk(dir: impl Into<PathBuf>) -> Result<Self, HypercoreError> {
+        let dir = dir.into();
+        std::fs::create_dir_all(dir.join(CORES_DIR))?;
+        let path = dir.join(PRIMARY_KEY_FILE);
+        let primary_key = match std::fs::read(&path) {
+            Ok(bytes) => bytes
+                .try_into()
+                .map_err(|_| HypercoreError::InvalidOperation {
+                    context: format!("Primary key in {} is not 32 bytes", path.display()),
+                })?,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+                let mut primary_key = [0; 32];
+                OsRng.fill_bytes(&mut primary_key);
+                std::fs::write(&path, primary_key)?;
+                primary_key
+            }
+            Err(error) => return Err(error.into()),
+        };
+        Ok(Self::with_location(Location::Disk(dir), primary_key))
+    }
+
+    fn with_location(location: Location, primary_key: [u8; 32]) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                location,
+                primary_key,
+                cores: Mutex::new(HashMap::new()),
+            }),
+            namespace: DEFAULT_NAMESPACE,
+        }
+    }
+
+    /// Primary key the key pairs of named cores are derived from. Keep it secret.
+    pub fn primary_key(&self) -> &[u8; 32] {
+        &self.inner.primary_key
+    }
+
+    /// Namespace the names of [`Corestore::get_named`] are resolved in
+    pub fn namespace_key(&self) -> &[u8; 32] {
+        &self.namespace
+    }
+
+    /// Store sharing the open cores of this one, where names are resolved in the nested namespace
+    /// `name`. Applications can use it to give each component its own names.
+    pub fn namespace(&self, name: &str) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            namespace: derive_namespace(&self.namespace, name),
+        }
+    }
+
+    /// Key pair of the core named `name` in the namespace of the store, see [`derive_key_pair`]
+    pub fn key_pair(&self, name: &str) -> PartialKeypair {
+        derive_key_pair(&self.inner.primary_key, &self.namespace, name)
+    }
+
+    /// Get the writable core named `name`, creating it if needed
+    pub async fn get_named(&self, name: &str) -> Result<SharedCore, HypercoreError> {
+        let key_pair = self.key_pair(name);
+        self.open(key_pair.public, |builder, _| {
+            builder.key_pair(key_pair.clone())
+        })
+        .await
+    }
+
+    /// Get the core with public key `key`. If it's not stored, it's created read-only, to be
+    /// filled via replication.
+    pub async fn get(&self, key: VerifyingKey) -> Result<SharedCore, HypercoreError> {
+        self.open(key, |builder, stored| {
+            if stored {
+                // Keep the secret key of the stored core, if any
+                builder.open(true)
+            } else {
+                builder.verifying_key(key)
+            }
+        })
+        .await
+    }
+
+    async fn open(
+        &self,
+        key: VerifyingKey,
+        configure: impl FnOnce(HypercoreBuilder, bool) -> HypercoreBuilder,
+    ) -> Result<SharedCore, HypercoreError> {
+        // Held while opening, so that a core is never opened twice
+        let mut cores = self.inner.cores.lock().await;
+        if let Some(core) = cores.get(&key) {
+            return Ok(core.clone());
+        }
+        let (storage, stored) = match &self.inner.location {
+            Location::Memory => (Storage::new_memory().await?, false),
+            #[cfg(not(target_arch = "wasm32"))]
+            Location::Disk(dir) => {
+                let path = core_dir(dir, &key);
+                let stored = path.join("oplog").exists();
+                (Storage::new_disk(&path, false).await?, stored)
+            }
+        };
+        let core = SharedCore::from(
+            configure(HypercoreBuilder::new(storage), stored)
+                .build()
+                .await?,
+        );
+        if core.key_pair().await.public != key {
+            return Err(HypercoreError::KeyMismatch {
+                context: format!(
+                    "Core stored under {} has another key",
+                    to_hex(key.as_bytes())
+                ),
+            });
+        }
+        cores.insert(key, core.clone());
+        Ok(core)
+    }
+
+    /// Remove the core from the open cores. It's closed once the handles given out are dropped.
+    /// Returns false if it wasn't open.
+    pub async fn close(&self, key: &VerifyingKey) -> bool {
+        self.inner.cores.lock().await.remove(key).is_some()
+    }
+
+    /// Public keys of the stored cores. For a store in memory, these are the open cores.
+    pub async fn list(&self) -> Result<Vec<VerifyingKey>, HypercoreError> {
+        let mut keys: Vec<VerifyingKey> = match &self.inner.location {
+            Location::Memory => self.inner.cores.lock().await.keys().copied().collect(),
+            #[cfg(not(target_arch = "wasm32"))]
+            Location::Disk(dir) => {
+                let open: HashMap<[u8; 32], VerifyingKey> = self
+                    .inner
+                    .cores
+                    .lock()
+                    .await
+                    .keys()
+                    .map(|key| (crypto::discovery_key(key), *key))
+                    .collect();
+                let mut keys = vec![];
+                for path in stored_core_dirs(&dir.join(CORES_DIR))? {
+                    let key = match path
+                        .file_name()
+                        .and_then(|name| name.to_str())
+                        .and_then(from_hex)
+                        .and_then(|id| open.get(&id))
+                    {
+                        Some(key) => *key,
+                        // The directory is named after the discovery key, the public key is
+                        // only in the oplog header of the core
+                        None => {
+                            Oplog::read_public_key(&Storage::new_disk(&path, false).await?).await?
+                        }
+                    };
+                    // Ignore anything that isn't where the core belongs
+                    if core_dir(dir, &key) == path {
+                        keys.push(key);
+                    }
+                }
+                keys
+            }
+        };
+        keys.sort_by_key(|key| key.to_bytes());
+        Ok(keys)
+    }
+
+    /// Events of all the open cores, with the public key of the core they come from. Cores
+    /// opened after the call are not included.
+    pub async fn events(&self) -> impl Stream<Item = (VerifyingKey, Event)> + use<> {
+        let cores: Vec<(VerifyingKey, SharedCore)> = self
+            .inner
+            .cores
+            .lock()
+            .await
+            .iter()
+            .map(|(key, core)| (*key, core.clone()))
+            .collect();
+        let mut streams = vec![];
+        for (key, core) in cores {
+            let events = EventStream::from(core.event_subscribe().await);
+            streams.push(events.map(move |event| (key, event)));
+        }
+        stream::select_all(streams)
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn from_hex(hex: &str) -> Option<[u8; 32]> {
+    if hex.len() != 64 {
+        return None;
+    }
+    let mut bytes = [0; 32];
+    for (byte, chunk) in bytes.iter_mut().zip(hex.as_bytes().chunks(2)) {
+        *byte = u8::from_str_radix(std::str::from_utf8(chunk).ok()?, 16).ok()?;
+    }
+    Some(bytes)
+}
+
+/// Directories `<aa>/<bb>/<id>` in `cores` that hold a core
+#[cfg(not(target_arch = "wasm32"))]
+fn stored_core_dirs(cores: &std::path::Path) -> Result<Vec<PathBuf>, HypercoreError> {
+    let mut dirs = vec![cores.to_path_buf()];
+    for _ in 0..3 {
+        let mut children = vec![];
+        for dir in dirs {
+            for entry in std::fs::read_dir(dir)? {
+                let entry = entry?;
+                if entry.file_type()?.is_dir() {
+                    children.push(entry.path());
+                }
+            }
+        }
+        dirs = children;
+    }
+    Ok(dirs
+        .into_iter()
+        .filter(|dir| dir.join("oplog").exists())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::replication::CoreMethods;
+    use tempfile::tempdir;
+
+    #[cfg_attr(feature = "async-std", async_std::test)]
+    #[cfg_attr(feature = "tokio", tokio::test)]
+    async fn corestore_caches_cores() -> Result<(), HypercoreError> {
+        let store = Corestore::new_memory();
+        let core = store.get_named("a").await?;
+        core.try_append(b"hello").await.unwrap();
+        let key = store.key_pair("a").public;
+        assert_eq!(core.key_pair().await.public, key);
+        assert_ne!(store.key_pair("b").public, key);
+
+        let again = store.get(key).await?;
+        assert!(Arc::ptr_eq(&core.0, &again.0));
+        assert_eq!(store.list().await?, vec![key]);
+
+        let mut events = store.events().await;
+        again.try_append(b"world").await.unwrap();
+        let (event_key, _) = events.next().await.unwrap();
+        assert_eq!(event_key, key);
+
+        assert!(store.close(&key).await);
+        assert!(store.list().await?.is_empty());
+        Ok(())
+    }
+
+    #[cfg_attr(feature = "async-std", async_std::test)]
+    #[cfg_attr(feature = "tokio", tokio::test)]
+    async fn corestore_on_disk() -> Result<(), HypercoreError> {
+        let dir = tempdir().unwrap();
+        let store = Corestore::open_disk(dir.path()).await?;
+        let primary_key = *store.primary_key();
+        let named = store.get_named("a").await?;
+        named.try_append(b"hello").await.unwrap();
+        let other_key = crate::generate_signing_key().verifying_key();
+        let other = store.get(other_key).await?;
+        assert!(other.key_pair().await.secret.is_none());
+        drop((named, other, store));
+
+        let store = Corestore::open_disk(dir.path()).await?;
+        assert_eq!(store.primary_key(), &primary_key);
+        let key = store.key_pair("a").public;
+        // Stored where Javascript corestore looks for it
+        let id = to_hex(&crypto::discovery_key(&key));
+        let path = format!("cores/{}/{}/{id}/oplog", &id[..2], &id[2..4]);
+        assert!(dir.path().join(path).exists());
+        let mut expected = vec![key, other_key];
+        expected.sort_by_key(|key| key.to_bytes());
+        assert_eq!(store.list().await?, expected);
+
+        // Opened by key, the named core is still writable
+        let named = store.get(key).await?;
+        assert!(named.key_pair().await.secret.is_some());
+        assert_eq!(named.get(0).await.unwrap(), Some(b"hello".to_vec()));
+        Ok(())
+    }
+
+    #[test]
+    fn corestore_key_derivation_vectors() {
+        // Computed independently with the blake2b of Python's hashlib and the ed25519 keys of
+        // Python's cryptography
+        let primary_key: [u8; 32] = std::array::from_fn(|index| index as u8);
+        let key_pair = derive_key_pair(&primary_key, &DEFAULT_NAMESPACE, "my-db");
+        assert_eq!(
+            to_hex(key_pair.public.as_bytes()),
+            "5f1813af0c4aadf111d9970616a428edd2fcc6c9a566864c8d506593530f2c70"
+        );
+        let namespace = derive_namespace(&DEFAULT_NAMESPACE, "app");
+        assert_eq!(
+            to_hex(&namespace),
+            "a8c700f22de9334162cea2d3e12dee09effb489720743f6e498a3c16e9814d8a"
+        );
+        let key_pair = derive_key_pair(&primary_key, &namespace, "my-db");
+        assert_eq!(
+            to_hex(key_pair.public.as_bytes()),
+            "80dfcd26efa1206335c9a7f3039901c654b6fa7408b0cbe804fe3f0769cf11f7"
+        );
+    }
+
+    #[cfg_attr(feature = "async-std", async_std::test)]
+    #[cfg_attr(feature = "tokio", tokio::test)]
+    async fn corestore_namespaces() -> Result<(), HypercoreError> {
+        let store = Corestore::new_memory();
+        let app = store.namespace("app");
+        assert_eq!(
+            app.namespace_key(),
+            &derive_namespace(&DEFAULT_NAMESPACE, "app")
+        );
+        let core = app.get_named("db").await?;
+        let key = core.key_pair().await.public;
+        assert_eq!(
+            key,
+            derive_key_pair(store.primary_key(), app.namespace_key(), "db").public
+        );
+        assert_ne!(key, store.key_pair("db").public);
+        assert_ne!(key, app.namespace("nested").key_pair("db").public);
+        // The namespaces share the open cores
+        assert!(Arc::ptr_eq(&core.0, &store.get(key).await?.0));
+        Ok(())
+    }
+}
@@ -0,0 +1,394 @@
+//! Crash-consistency harness, cutting the power of the storage of a hypercore at every byte
+//! written by a sequence of appends or verified proofs, and checking that the hypercore then
+//! reopens to a consistent state from which it can continue.
+//!
+//! ```rust
+//! # #[cfg(feature = "tokio")]
+//! # tokio_test::block_on(async {
+//! # example().await;
+//! # });
+//! # #[cfg(feature = "async-std")]
+//! # async_std::task::block_on(async {
+//! # example().await;
+//! # });
+//! # async fn example() {
+//! use hypercore::crash::{CrashHarness, CrashMode};
+//!
+//! let batches = vec![vec![b"hello".to_vec()], vec![b"big".to_vec(), b"world".to_vec()]];
+//! let report = CrashHarness::new(CrashMode::Append, batches)
+//!     .stride(64)
+//!     .run()
+//!     .await
+//!     .unwrap();
+//! assert!(report.cuts > 0);
+//! # }
+//! ```
+use futures::FutureExt;
+use futures::lock::Mutex;
+use hypercore_schema::{Proof, RequestBlock, RequestUpgrade};
+use random_access_memory::RandomAccessMemory;
+use random_access_storage::{RandomAccess, RandomAccessError};
+use std::sync::Arc;
+
+use crate::{
+    Hypercore, HypercoreBuilder, HypercoreError, PartialKeypair, Storage, Store,
+    generate_signing_key,
+};
+
+/// Power of the stores of a hypercore, cut after a number of steps. Every byte written, and every
+/// truncate or delete, is a step.
+#[derive(Debug, Default)]
+struct Power {
+    steps: u64,
+    cut_at: Option<u64>,
+}
+
+impl Power {
+    /// Take `wanted` steps, returning how many of them run before the power is cut
+    fn take(&mut self, wanted: u64) -> u64 {
+        let allowed = self.cut_at.map_or(wanted, |cut_at| {
+            wanted.min(cut_at.saturating_sub(self.steps))
+        });
+        self.steps += allowed;
+        allowed
+    }
+}
+
+fn power_cut() -> RandomAccessError {
+    std::io::Error::other("Power cut").into()
+}
+
+/// Memory store keeping only what was written before the power was cut, which tears the write
+/// running at that time
+#[derive(Debug, Clone)]
+struct CrashStore {
+    memory: Arc<Mutex<RandomAccessMemory>>,
+    power: Arc<std::sync::Mutex<Power>>,
+}
+
+impl CrashStore {
+    fn take(&self, wanted: u64) -> u64 {
+        self.power.lock().expect("Power lock poisoned").take(wanted)
+    }
+}
+
+#[async_trait::async_trait]
+impl RandomAccess for CrashStore {
+    async fn write(&mut self, offset: u64, data: &[u8]) -> Result<(), RandomAccessError> {
+        let allowed = self.take(data.len() as u64) as usize;
+        if allowed > 0 {
+            self.memory
+                .lock()
+                .await
+                .write(offset, &data[..allowed])
+                .await?;
+        }
+        if allowed < data.len() {
+            return Err(power_cut());
+        }
+        Ok(())
+    }
+
+    async fn read(&mut self, offset: u64, length: u64) -> Result<Vec<u8>, RandomAccessError> {
+        self.memory.lock().await.read(offset, length).await
+    }
+
+    async fn del(&mut self, offset: u64, length: u64) -> Result<(), RandomAccessError> {
+        if self.take(1) == 0 {
+            return Err(power_cut());
+        }
+        self.memory.lock().await.del(offset, length).await
+    }
+
+    async fn truncate(&mut self, length: u64) -> Result<(), RandomAccessError> {
+        if self.take(1) == 0 {
+            return Err(power_cut());
+        }
+        self.memory.lock().await.truncate(length).await
+    }
+
+    async fn len(&mut self) -> Result<u64, RandomAccessError> {
+        self.memory.lock().await.len().await
+    }
+
+    async fn is_empty(&mut self) -> Result<bool, RandomAccessError> {
+        self.memory.lock().await.is_empty().await
+    }
+
+    async fn sync_all(&mut self) -> Result<(), RandomAccessError> {
+        Ok(())
+    }
+}
+
+/// Tree, data, bitfield and oplog stores sharing their power
+#[derive(Debug)]
+struct CrashStores {
+    stores: [CrashStore; 4],
+    power: Arc<std::sync::Mutex<Power>>,
+}
+
+impl CrashStores {
+    fn new(cut_at: Option<u64>) -> Self {
+        let power = Arc::new(std::sync::Mutex::new(Power { steps: 0, cut_at }));
+        let stores = std::array::from_fn(|_| CrashStore {
+            memory: Arc::default(),
+            power: power.clone(),
+        });
+        Self { stores, power }
+    }
+
+    fn steps(&self) -> u64 {
+        self.power.lock().expect("Power lock poisoned").steps
+    }
+
+    fn restore_power(&self) {
+        self.power.lock().expect("Power lock poisoned").cut_at = None;
+    }
+
+    async fn storage(&self) -> Result<Storage<CrashStore>, HypercoreError> {
+        let stores = self.stores.clone();
+        Storage::open(
+            move |store| {
+                let store = match store {
+                    Store::Tree => stores[0].clone(),
+                    Store::Data => stores[1].clone(),
+                    Store::Bitfield => stores[2].clone(),
+                    Store::Oplog => stores[3].clone(),
+                };
+                async move { Ok(Box::new(store)) }.boxed()
+            },
+            false,
+        )
+        .await
+    }
+}
+
+/// Operations of a [`CrashHarness`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CrashMode {
+    /// Every batch is appended to the hypercore
+    Append,
+    /// Every batch is appended to another hypercore, and the hypercore verifies a proof of each
+    /// of its blocks, the first one with an upgrade
+    Verify,
+}
+
+/// Outcome of [`CrashHarness::run`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CrashReport {
+    /// Number of steps without power cut: bytes written, and truncates or deletes
+    pub steps: u64,
+    /// Number of power cuts checked
+    pub cuts: u64,
+    /// Number of power cuts after which the interrupted operation was applied on reopen
+    pub applied: u64,
+}
+
+/// Harness cutting the power of the storage of a hypercore at every step of a sequence of
+/// operations, then reopening it. The reopened hypercore must be as it was before or after the
+/// interrupted operation, have the right blocks, and reach the same state as without power cut
+/// when running the rest of the operations, also once reopened again.
+#[derive(Debug, Clone)]
+pub struct CrashHarness {
+    mode: CrashMode,
+    batches: Vec<Vec<Vec<u8>>>,
+    stride: u64,
+}
+
+impl CrashHarness {
+    /// Harness of the given batches of blocks
+    pub fn new(mode: CrashMode, batches: Vec<Vec<Vec<u8>>>) -> Self {
+        Self {
+            mode,
+            batches,
+            stride: 1,
+        }
+    }
+
+    /// Cut the power only at every `stride` steps, to check long sequences faster
+    pub fn stride(mut self, stride: u64) -> Self {
+        self.stride = stride.max(1);
+        self
+    }
+
+    /// Run the operations with every power cut. Fails with [`HypercoreError::InvalidOperation`]
+    /// telling the power cut and what was inconsistent.
+    pub async fn run(&self) -> Result<CrashReport, HypercoreError> {
+        let signing_key = generate_signing_key();
+        let key_pair = PartialKeypair {
+            public: signing_key.verifying_key(),
+            secret: Some(signing_key),
+        };
+        let proofs = match self.mode {
+            CrashMode::Append => vec![],
+            CrashMode::Verify => self.proofs(&key_pair).await?,
+        };
+        let operations = match self.mode {
+            CrashMode::Append => self.batches.len(),
+            CrashMode::Verify => proofs.len(),
+        };
+        let blocks = self.batches.concat();
+
+        // Run once without cutting the power, to count the steps and get the state after every
+        // operation
+        let stores = CrashStores::new(None);
+        let mut core = self.open(&stores, &key_pair).await?;
+        let mut states = vec![core.info()];
+        for operation in 0..operations {
+            self.apply(&mut core, &proofs, operation).await?;
+            states.push(core.info());
+        }
+        let steps = stores.steps();
+
+        let mut report = CrashReport {
+            steps,
+            cuts: 0,
+            applied: 0,
+        };
+        for cut in (0..steps).step_by(self.stride as usize) {
+            let inconsistent = |context: String| HypercoreError::InvalidOperation {
+                context: format!("Power cut at step {cut} of {steps}: {context}"),
+            };
+            let stores = CrashStores::new(Some(cut));
+            let mut completed = 0;
+            if let Ok(mut core) = self.open(&stores, &key_pair).await {
+                while completed < operations
+                    && self.apply(&mut core, &proofs, completed).await.is_ok()
+                {
+                    completed += 1;
+                }
+            }
+            stores.restore_power();
+
+            let mut core = self
+                .open(&stores, &key_pair)
+                .await
+                .map_err(|err| inconsistent(format!("Reopening failed: {err}")))?;
+            let info = core.info();
+            let reopened = if info == states[completed] {
+                completed
+            } else if completed < operations && info == states[completed + 1] {
+                report.applied += 1;
+                completed + 1
+            } else {
+                return Err(inconsistent(format!(
+                    "Reopened to {info:?} after {completed} operations, expected {:?}",
+                    states[completed]
+                )));
+            };
+            check_blocks(&core, &blocks)
+                .await
+                .map_err(|err| inconsistent(format!("After reopening: {err}")))?;
+
+            for operation in reopened..operations {
+                self.apply(&mut core, &proofs, operation)
+                    .await
+                    .map_err(|err| inconsistent(format!("Operation {operation} failed: {err}")))?;
+            }
+            drop(core);
+            let core = self
+                .open(&stores, &key_pair)
+                .await
+                .map_err(|err| inconsistent(format!("Reopening again failed: {err}")))?;
+            if core.info() != states[operations] {
+                return Err(inconsistent(format!(
+                    "Reopened again to {:?}, expected {:?}",
+                    core.info(),
+                    states[operations]
+                )));
+            }
+            check_blocks(&core, &blocks)
+                .await
+                .map_err(|err| inconsistent(format!("After reopening again: {err}")))?;
+            report.cuts += 1;
+        }
+        Ok(report)
+    }
+
+    /// Create or open the hypercore, as the writer or as a reader
+    async fn open(
+        &self,
+        stores: &CrashStores,
+        key_pair: &PartialKeypair,
+    ) -> Result<Hypercore<CrashStore>, HypercoreError> {
+        let builder = HypercoreBuilder::new(stores.storage().await?);
+        match self.mode {
+            CrashMode::Append => builder.key_pair(key_pair.clone()),
+            CrashMode::Verify => builder.verifying_key(key_pair.public),
+        }
+        .build()
+        .await
+    }
+
+    async fn apply(
+        &self,
+        core: &mut Hypercore<CrashStore>,
+        proofs: &[Proof],
+        operation: usize,
+    ) -> Result<(), HypercoreError> {
+        match self.mode {
+            CrashMode::Append => {
+                core.append_batch(&self.batches[operation]).await?;
+            }
+            CrashMode::Verify => {
+                core.verify_and_apply_proof(&proofs[operation]).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Proofs of every block of a writer appending the batches, as a reader requests them
+    async fn proofs(&self, key_pair: &PartialKeypair) -> Result<Vec<Proof>, HypercoreError> {
+        let mut writer = HypercoreBuilder::new(Storage::new_memory().await?)
+            .key_pair(key_pair.clone())
+            .build()
+            .await?;
+        let mut reader = HypercoreBuilder::new(Storage::new_memory().await?)
+            .verifying_key(key_pair.public)
+            .build()
+            .await?;
+        let mut proofs = vec![];
+        for batch in &self.batches {
+            let start = writer.info().length;
+            let length = writer.append_batch(batch).await?.length;
+            for index in start..length {
+                let upgrade_start = reader.info().length;
+                let (nodes, upgrade) = if upgrade_start < length {
+                    let upgrade = RequestUpgrade {
+                        start: upgrade_start,
+                        length: length - upgrade_start,
+                    };
+                    (0, Some(upgrade))
+                } else {
+                    (reader.missing_nodes(index).await?, None)
+                };
+                let proof = writer
+                    .create_proof(Some(RequestBlock { index, nodes }), None, None, upgrade)
+                    .await?
+                    .expect("Writer has the block");
+                reader.verify_and_apply_proof(&proof).await?;
+                proofs.push(proof);
+            }
+        }
+        Ok(proofs)
+    }
+}
+
+/// Check that the blocks the hypercore has are the expected ones
+async fn check_blocks(
+    core: &Hypercore<CrashStore>,
+    blocks: &[Vec<u8>],
+) -> Result<(), HypercoreError> {
+    for (index, block) in blocks.iter().enumerate() {
+        let index = index as u64;
+        if index < core.info().length && core.has(index) {
+            let stored = core.get(index).await?;
+            if stored.as_ref() != Some(block) {
+                return Err(HypercoreError::InvalidOperation {
+                    context: format!("Block {index} is {stored:?}, expected {block:?}"),
+                });
+            }
+        }
+    }
+    Ok(())
+}
@@ -1,4 +1,15 @@
+#[cfg(feature = "corestore")]
+use blake2::{Blake2b, digest::Digest};
+use blake2::{
+    Blake2bMac,
+    digest::{KeyInit, Mac, consts::U32},
+};
 use compact_encoding::{EncodingError, FixedWidthEncoding, as_array, to_encoded_bytes};
+use ed25519_dalek::VerifyingKey;
+
+#[cfg(feature = "corestore")]
+type Blake2b256 = Blake2b<U32>;
+type Blake2bMac256 = Blake2bMac<U32>;
 
 // These the output of, see `hash_namespace` test below for how they are produced
 // https://github.com/holepunchto/hypercore/blob/cf08b72f14ed7d9ef6d497ebb3071ee0ae20967e/lib/caps.js#L16
@@ -30,3 +41,70 @@ pub(crate) fn signable_tree(hash: &[u8], length: u64, fork: u64) -> Box<[u8]> {
     })()
     .expect("Encoding should not fail")
 }
+
+/// Namespaced id, the `index`th id of `crypto.namespace(name, count)` of hypercore-crypto in
+/// Javascript. It's the blake2b hash of the blake2b hash of `name` followed by `index`.
+#[cfg(feature = "corestore")]
+pub(crate) fn namespace(name: &[u8], index: u8) -> [u8; 32] {
+    let mut hasher = Blake2b256::new();
+    hasher.update(Blake2b256::digest(name));
+    hasher.update([index]);
+    hasher.finalize().into()
+}
+
+/// Blake2b hash of `parts`, like `crypto_generichash_batch` of libsodium
+#[cfg(feature = "corestore")]
+pub(crate) fn hash(parts: &[&[u8]]) -> [u8; 32] {
+    let mut hasher = Blake2b256::new();
+    for part in parts {
+        hasher.update(part);
+    }
+    hasher.finalize().into()
+}
+
+/// Blake2b hash of `parts` keyed with `key`, like `crypto_generichash_batch` of libsodium
+pub(crate) fn keyed_hash(key: &[u8], parts: &[&[u8]]) -> [u8; 32] {
+    let mut mac = <Blake2bMac256 as KeyInit>::new_from_slice(key).expect("Key is at most 64 bytes");
+    for part in parts {
+        mac.update(part);
+    }
+    mac.finalize().into_bytes().into()
+}
+
+/// Discovery key of the hypercore with public key `key`: the blake2b hash of `hypercore` keyed
+/// with the public key, like `discoveryKey` of hypercore-crypto in Javascript. Peers find each
+/// other with it without revealing the public key, and Javascript corestore names the directory
+/// of a core after it.
+pub fn discovery_key(key: &VerifyingKey) -> [u8; 32] {
+    keyed_hash(key.as_bytes(), &[b"hypercore"])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "corestore")]
+    #[test]
+    fn hash_namespace() {
+        assert_eq!(namespace(b"hypercore", 0), TREE);
+    }
+
+    #[test]
+    fn hash_discovery_key() {
+        // Keyed blake2b-256 of `hypercore`, computed independently of this crate
+        let key = VerifyingKey::from_bytes(&[
+            0x97, 0x60, 0x6c, 0xaa, 0xd2, 0xb0, 0x8c, 0x1d, 0x5f, 0xe1, 0x64, 0x2e, 0xee, 0xa5,
+            0x62, 0xcb, 0x91, 0xd6, 0x55, 0xe2, 0x00, 0xc8, 0xd4, 0x3a, 0x32, 0x09, 0x1d, 0x06,
+            0x4a, 0x33, 0x1e, 0xe3,
+        ])
+        .unwrap();
+        assert_eq!(
+            discovery_key(&key),
+            [
+                0x17, 0xe4, 0x8a, 0xda, 0x51, 0x12, 0x7b, 0x6f, 0xa0, 0xc3, 0x68, 0x9a, 0x37, 0x74,
+                0x12, 0x84, 0x2c, 0xe5, 0x4d, 0x76, 0xd9, 0x36, 0x29, 0xa2, 0x61, 0x76, 0x5f, 0x04,
+                0xd5, 0x8e, 0x4f, 0x7c,
+            ]
+        );
+    }
+}
@@ -2,10 +2,15 @@
 
 use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
 use rand::rngs::OsRng;
+use rand::{CryptoRng, RngCore};
+use std::fmt;
 
 use crate::HypercoreError;
 
 /// Key pair where for read-only hypercores the secret key can also be missing.
+///
+/// With the `serde` feature, only the public key is serialized, and deserialized key pairs have
+/// no secret key.
 #[derive(Debug, Clone)]
 pub struct PartialKeypair {
     /// Public key
@@ -14,10 +19,57 @@ pub struct PartialKeypair {
     pub secret: Option<SigningKey>,
 }
 
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(crate = "serde", rename = "PartialKeypair")]
+struct PublicKeypair {
+    public: [u8; 32],
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for PartialKeypair {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        PublicKeypair {
+            public: self.public.to_bytes(),
+        }
+        .serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for PartialKeypair {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let PublicKeypair { public } = PublicKeypair::deserialize(deserializer)?;
+        let public = VerifyingKey::from_bytes(&public).map_err(serde::de::Error::custom)?;
+        Ok(Self {
+            public,
+            secret: None,
+        })
+    }
+}
+
 /// Generate a new `Ed25519` key pair.
 pub fn generate() -> SigningKey {
-    let mut csprng = OsRng;
-    SigningKey::generate(&mut csprng)
+    generate_with(&mut OsRng)
+}
+
+/// Generate a new `Ed25519` key pair with the given random number generator, e.g. a seeded one
+/// for deterministic tests or one provided by a sandboxed environment.
+pub fn generate_with<R: RngCore + CryptoRng + ?Sized>(rng: &mut R) -> SigningKey {
+    let mut secret = ed25519_dalek::SecretKey::default();
+    rng.fill_bytes(&mut secret);
+    SigningKey::from_bytes(&secret)
+}
+
+/// Random number generator kept by the builder to generate the key pair.
+pub(crate) trait KeyRng: RngCore + CryptoRng + Send + Sync {}
+
+impl<R: RngCore + CryptoRng + Send + Sync> KeyRng for R {}
+
+impl fmt::Debug for dyn KeyRng {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("KeyRng")
+    }
 }
 
 /// Sign a byte slice using a keypair's private key.
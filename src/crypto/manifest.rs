@@ -1,3 +1,7 @@
+use ed25519_dalek::VerifyingKey;
+
+use crate::HypercoreError;
+
 // These the output of the following link:
 // https://github.com/holepunchto/hypercore/blob/cf08b72f14ed7d9ef6d497ebb3071ee0ae20967e/lib/caps.js#L16
 
@@ -14,21 +18,57 @@ const DEFAULT_NAMESPACE: [u8; 32] = [
 //     0x8B, 0x15, 0xB8, 0x2E, 0xC5, 0xED, 0x78, 0xC4, 0xEC, 0x59, 0x7B, 0x03, 0x6E, 0x2A, 0x14, 0x98,
 // ];
 
-#[derive(Debug, Clone)]
-pub(crate) struct Manifest {
-    pub(crate) hash: String,
+/// Manifest of a hypercore, describing how its tree is hashed and signed. Stored in the oplog
+/// header. Only the v10 compatible single signer manifest with `blake2b` hashes and `ed25519`
+/// signatures is supported.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Manifest {
+    /// Hash function of the tree, `blake2b`
+    pub hash: String,
     // TODO: In v11 can be static
     // pub(crate) static_core: Option<bool>,
-    pub(crate) signer: ManifestSigner,
+    /// The signer of the tree
+    pub signer: ManifestSigner,
     // TODO: In v11 can have multiple signers
     // pub(crate) multiple_signers: Option<bool>,
 }
 
-#[derive(Debug, Clone)]
-pub(crate) struct ManifestSigner {
-    pub(crate) signature: String,
-    pub(crate) namespace: [u8; 32],
-    pub(crate) public_key: [u8; 32],
+/// Signer of a [`Manifest`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct ManifestSigner {
+    /// Signature algorithm, `ed25519`
+    pub signature: String,
+    /// Namespace of the signatures
+    pub namespace: [u8; 32],
+    /// Public key of the signer
+    pub public_key: [u8; 32],
+}
+
+impl Manifest {
+    /// Create the default manifest for the given public key.
+    pub fn new(public_key: &VerifyingKey) -> Self {
+        default_signer_manifest(public_key.to_bytes())
+    }
+
+    /// Check that the manifest can be stored and used to sign the tree with the given key.
+    pub(crate) fn validate(&self, public_key: &VerifyingKey) -> Result<(), HypercoreError> {
+        if self.hash != "blake2b" {
+            return Err(HypercoreError::BadArgument {
+                context: format!("Unsupported manifest hash: {}", self.hash),
+            });
+        }
+        if self.signer.signature != "ed25519" {
+            return Err(HypercoreError::BadArgument {
+                context: format!("Unsupported manifest signature: {}", self.signer.signature),
+            });
+        }
+        if self.signer.public_key != public_key.to_bytes() {
+            return Err(HypercoreError::BadArgument {
+                context: "Manifest signer does not match the public key".to_string(),
+            });
+        }
+        Ok(())
+    }
 }
 
 pub(crate) fn default_signer_manifest(public_key: [u8; 32]) -> Manifest {
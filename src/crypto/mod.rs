@@ -4,6 +4,14 @@ mod hash;
 mod key_pair;
 mod manifest;
 
+pub use hash::discovery_key;
 pub(crate) use hash::signable_tree;
-pub use key_pair::{PartialKeypair, generate as generate_signing_key, sign, verify};
-pub(crate) use manifest::{Manifest, ManifestSigner, default_signer_manifest};
+#[cfg(feature = "corestore")]
+pub(crate) use hash::{hash, keyed_hash, namespace};
+pub(crate) use key_pair::KeyRng;
+pub use key_pair::{
+    PartialKeypair, generate as generate_signing_key, generate_with as generate_signing_key_with,
+    sign, verify,
+};
+pub(crate) use manifest::default_signer_manifest;
+pub use manifest::{Manifest, ManifestSigner};
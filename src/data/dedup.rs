@@ -0,0 +1,47 @@
+use std::collections::{BTreeSet, HashMap};
+
+/// Stored blocks by leaf hash, for hypercores built with
+/// [`crate::HypercoreBuilder::deduplicate`]. Of the stored blocks with the same hash, only the
+/// lowest index holds the bytes in the block store, the others reference it.
+#[derive(Debug, Default)]
+pub(crate) struct DedupIndex {
+    blocks: HashMap<Vec<u8>, BTreeSet<u64>>,
+}
+
+impl DedupIndex {
+    /// Index of the block holding the bytes of the blocks with the given hash
+    pub(crate) fn source(&self, hash: &[u8]) -> Option<u64> {
+        self.blocks.get(hash)?.first().copied()
+    }
+
+    /// Add a stored block. Returns true if its bytes need to be written, i.e. it is the source of
+    /// its hash.
+    pub(crate) fn insert(&mut self, hash: &[u8], index: u64) -> bool {
+        let indexes = self.blocks.entry(hash.to_vec()).or_default();
+        indexes.insert(index);
+        indexes.first() == Some(&index)
+    }
+
+    /// Index of the first block at or after `from` with the given hash
+    pub(crate) fn next(&self, hash: &[u8], from: u64) -> Option<u64> {
+        self.blocks.get(hash)?.range(from..).next().copied()
+    }
+
+    /// Remove a block that is no longer stored
+    pub(crate) fn remove(&mut self, hash: &[u8], index: u64) {
+        if let Some(indexes) = self.blocks.get_mut(hash) {
+            indexes.remove(&index);
+            if indexes.is_empty() {
+                self.blocks.remove(hash);
+            }
+        }
+    }
+
+    /// Remove the blocks at and after `length`, e.g. after a truncation
+    pub(crate) fn truncate(&mut self, length: u64) {
+        self.blocks.retain(|_, indexes| {
+            indexes.split_off(&length);
+            !indexes.is_empty()
+        });
+    }
+}
@@ -1,24 +1,15 @@
+mod dedup;
+
 use crate::common::{NodeByteRange, Store, StoreInfo, StoreInfoInstruction};
 use futures::future::Either;
 
+pub(crate) use dedup::DedupIndex;
+
 /// Block store
 #[derive(Debug, Default)]
 pub(crate) struct BlockStore {}
 
 impl BlockStore {
-    pub(crate) fn append_batch<A: AsRef<[u8]>, B: AsRef<[A]>>(
-        &self,
-        batch: B,
-        batch_length: usize,
-        byte_length: u64,
-    ) -> StoreInfo {
-        let mut buffer: Vec<u8> = Vec::with_capacity(batch_length);
-        for data in batch.as_ref().iter() {
-            buffer.extend_from_slice(data.as_ref());
-        }
-        StoreInfo::new_content(Store::Data, byte_length, &buffer)
-    }
-
     pub(crate) fn put(&self, value: &[u8], offset: u64) -> StoreInfo {
         StoreInfo::new_content(Store::Data, offset, value)
     }
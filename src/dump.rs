@@ -0,0 +1,209 @@
+//! Summary of the internal state of a hypercore for bug reports, see
+//! [`crate::Hypercore::dump_state`].
+use std::fmt;
+use std::ops::Range;
+
+use hypercore_schema::Node;
+
+use crate::common::to_hex;
+use crate::oplog::Entry;
+
+/// Summary of the internal state of a hypercore, to attach to bug reports about mismatched state
+/// instead of the raw files. Contains the public key and hashes, but neither the secret key nor
+/// block data. [`fmt::Display`] prints it as a readable report.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde")
+)]
+pub struct StateDump {
+    /// Hex public key
+    pub key: String,
+    /// True if the secret key is known
+    pub writeable: bool,
+    /// Fork of the in-memory tree
+    pub fork: u64,
+    /// Length of the in-memory tree
+    pub length: u64,
+    /// Byte length of the in-memory tree
+    pub byte_length: u64,
+    /// Roots of the in-memory tree
+    pub roots: Vec<NodeDump>,
+    /// Header of the oplog
+    pub header: HeaderDump,
+    /// Ranges of the blocks stored locally
+    pub stored: Vec<Range<u64>>,
+    /// Number of entries in the oplog, not yet flushed to the tree and bitfield
+    pub oplog_length: u64,
+    /// Last entries of the oplog, oldest first
+    pub oplog_entries: Vec<OplogEntryDump>,
+}
+
+/// Tree node of a [`StateDump`]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde")
+)]
+pub struct NodeDump {
+    /// Flat tree index
+    pub index: u64,
+    /// Byte length of the blocks under the node
+    pub length: u64,
+    /// Hex hash
+    pub hash: String,
+}
+
+impl From<&Node> for NodeDump {
+    fn from(node: &Node) -> Self {
+        Self {
+            index: node.index,
+            length: node.length,
+            hash: to_hex(&node.hash),
+        }
+    }
+}
+
+/// Oplog header of a [`StateDump`]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde")
+)]
+pub struct HeaderDump {
+    /// Fork of the tree
+    pub fork: u64,
+    /// Length of the tree
+    pub length: u64,
+    /// Hex hash of the roots of the tree
+    pub root_hash: String,
+    /// Hex signature of the tree
+    pub signature: String,
+    /// Contiguous length hint
+    pub contiguous_length: u64,
+    /// User data entries
+    pub user_data: Vec<String>,
+}
+
+/// Oplog entry of a [`StateDump`]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde")
+)]
+pub struct OplogEntryDump {
+    /// Indexes of the tree nodes added by the entry
+    pub tree_nodes: Vec<u64>,
+    /// Tree upgrade of the entry
+    pub tree_upgrade: Option<TreeUpgradeDump>,
+    /// Blocks marked as stored, or as cleared when `cleared`
+    pub blocks: Option<Range<u64>>,
+    /// True if `blocks` were cleared
+    pub cleared: bool,
+}
+
+impl From<&Entry> for OplogEntryDump {
+    fn from(entry: &Entry) -> Self {
+        Self {
+            tree_nodes: entry.tree_nodes.iter().map(|node| node.index).collect(),
+            tree_upgrade: entry.tree_upgrade.as_ref().map(|upgrade| TreeUpgradeDump {
+                fork: upgrade.fork,
+                ancestors: upgrade.ancestors,
+                length: upgrade.length,
+            }),
+            blocks: entry
+                .bitfield
+                .as_ref()
+                .map(|update| update.start..update.start + update.length),
+            cleared: entry.bitfield.as_ref().is_some_and(|update| update.drop),
+        }
+    }
+}
+
+/// Tree upgrade of an [`OplogEntryDump`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde")
+)]
+pub struct TreeUpgradeDump {
+    /// Fork of the upgraded tree
+    pub fork: u64,
+    /// Length of the tree shared with the previous fork
+    pub ancestors: u64,
+    /// Length of the upgraded tree
+    pub length: u64,
+}
+
+fn write_ranges(f: &mut fmt::Formatter<'_>, ranges: &[Range<u64>]) -> fmt::Result {
+    if ranges.is_empty() {
+        return write!(f, "none");
+    }
+    for (i, range) in ranges.iter().enumerate() {
+        if i > 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "{}..{}", range.start, range.end)?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for StateDump {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let access = if self.writeable {
+            "writeable"
+        } else {
+            "read-only"
+        };
+        writeln!(f, "hypercore {} ({access})", self.key)?;
+        writeln!(
+            f,
+            "tree: fork {}, length {}, byte length {}",
+            self.fork, self.length, self.byte_length
+        )?;
+        for root in &self.roots {
+            writeln!(
+                f,
+                "  root {}: length {}, hash {}",
+                root.index, root.length, root.hash
+            )?;
+        }
+        let header = &self.header;
+        writeln!(
+            f,
+            "header: fork {}, length {}, contiguous length {}, root hash {}, signature {}",
+            header.fork,
+            header.length,
+            header.contiguous_length,
+            header.root_hash,
+            header.signature
+        )?;
+        for user_data in &header.user_data {
+            writeln!(f, "  user data {user_data}")?;
+        }
+        write!(f, "stored: ")?;
+        write_ranges(f, &self.stored)?;
+        writeln!(f)?;
+        write!(f, "oplog: {} entries", self.oplog_length)?;
+        for entry in &self.oplog_entries {
+            write!(f, "\n  nodes {:?}", entry.tree_nodes)?;
+            if let Some(upgrade) = &entry.tree_upgrade {
+                write!(
+                    f,
+                    ", upgrade to fork {} length {} from ancestors {}",
+                    upgrade.fork, upgrade.length, upgrade.ancestors
+                )?;
+            }
+            if let Some(blocks) = &entry.blocks {
+                let action = if entry.cleared { "cleared" } else { "stored" };
+                write!(f, ", {action} {}..{}", blocks.start, blocks.end)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,7 +1,27 @@
 //! Hypercore-specific compact encodings
+//!
+//! The replication payload types ([`Node`], [`RequestBlock`], [`RequestSeek`],
+//! [`RequestUpgrade`], [`DataBlock`], [`DataHash`], [`DataSeek`] and [`DataUpgrade`])
+//! implement [`CompactEncoding`] directly. [`Proof`] is encoded through
+//! [`EncodableProof`].
+//!
+//! The `decode_*` functions decode a whole buffer and are safe on adversarial input: they return
+//! an error instead of panicking, and don't allocate more than a few times the size of the
+//! buffer, so they can be used as fuzz targets.
+use crate::OplogEntryDump;
 use crate::crypto::{Manifest, ManifestSigner};
+use crate::oplog::Entry;
 use compact_encoding::{
-    CompactEncoding, EncodingError, EncodingErrorKind, encode_bytes_fixed, take_array, write_slice,
+    EncodingErrorKind, decode_usize, encode_bytes_fixed, map_decode, take_array, write_slice,
+};
+
+pub use compact_encoding::{CompactEncoding, EncodingError};
+
+#[cfg(feature = "serde")]
+pub mod serde;
+pub use hypercore_schema::{
+    DataBlock, DataHash, DataSeek, DataUpgrade, Node, Proof, RequestBlock, RequestSeek,
+    RequestUpgrade,
 };
 
 impl CompactEncoding for ManifestSigner {
@@ -79,7 +99,10 @@ impl CompactEncoding for Manifest {
     {
         let ([version], rest) = take_array::<1>(buffer)?;
         if version != 0 {
-            panic!("Unknown manifest version {}", version);
+            return Err(EncodingError::new(
+                EncodingErrorKind::InvalidData,
+                &format!("Unknown manifest version: {version}"),
+            ));
         }
         let ([hash_id], rest) = take_array::<1>(rest)?;
         let hash: String = if hash_id != 0 {
@@ -101,3 +124,439 @@ impl CompactEncoding for Manifest {
         Ok((Manifest { hash, signer }, rest))
     }
 }
+
+/// Borrowed [`Proof`] that can be encoded with [`CompactEncoding`]. Decoding gives back an owned
+/// [`Proof`]. The layout, used by archives, is the fork, a flags byte and then each of the present
+/// parts. It isn't the one of the `data` wire message in Javascript, which starts with the flags.
+#[derive(Debug, Clone, Copy)]
+pub struct EncodableProof<'a>(pub &'a Proof);
+
+impl CompactEncoding<Proof> for EncodableProof<'_> {
+    fn encoded_size(&self) -> Result<usize, EncodingError> {
+        // flags and fork
+        Ok(1 + self.0.fork.encoded_size()? + proof_parts_encoded_size(self.0)?)
+    }
+
+    fn encode<'a>(&self, buffer: &'a mut [u8]) -> Result<&'a mut [u8], EncodingError> {
+        let rest = self.0.fork.encode(buffer)?;
+        let rest = write_slice(&[proof_flags(self.0)], rest)?;
+        encode_proof_parts(self.0, rest)
+    }
+
+    fn decode(buffer: &[u8]) -> Result<(Proof, &[u8]), EncodingError> {
+        let (fork, rest) = u64::decode(buffer)?;
+        let ([flags], rest) = take_array::<1>(rest)?;
+        decode_proof_parts(fork, flags, rest)
+    }
+}
+
+/// Flags byte telling which parts of `proof` are present
+pub(crate) fn proof_flags(proof: &Proof) -> u8 {
+    u8::from(proof.block.is_some())
+        | u8::from(proof.hash.is_some()) << 1
+        | u8::from(proof.seek.is_some()) << 2
+        | u8::from(proof.upgrade.is_some()) << 3
+}
+
+/// Encoded size of the present parts of `proof`, without its fork and flags
+pub(crate) fn proof_parts_encoded_size(proof: &Proof) -> Result<usize, EncodingError> {
+    let mut out = 0;
+    if let Some(block) = &proof.block {
+        out += block.encoded_size()?;
+    }
+    if let Some(hash) = &proof.hash {
+        out += hash.encoded_size()?;
+    }
+    if let Some(seek) = &proof.seek {
+        out += seek.encoded_size()?;
+    }
+    if let Some(upgrade) = &proof.upgrade {
+        out += upgrade.encoded_size()?;
+    }
+    Ok(out)
+}
+
+/// Encodes the present parts of `proof`, without its fork and flags
+pub(crate) fn encode_proof_parts<'a>(
+    proof: &Proof,
+    buffer: &'a mut [u8],
+) -> Result<&'a mut [u8], EncodingError> {
+    let mut rest = buffer;
+    if let Some(block) = &proof.block {
+        rest = block.encode(rest)?;
+    }
+    if let Some(hash) = &proof.hash {
+        rest = hash.encode(rest)?;
+    }
+    if let Some(seek) = &proof.seek {
+        rest = seek.encode(rest)?;
+    }
+    if let Some(upgrade) = &proof.upgrade {
+        rest = upgrade.encode(rest)?;
+    }
+    Ok(rest)
+}
+
+/// Decodes the parts of a proof of `fork` that `flags` tell are present
+pub(crate) fn decode_proof_parts(
+    fork: u64,
+    flags: u8,
+    rest: &[u8],
+) -> Result<(Proof, &[u8]), EncodingError> {
+    let (block, rest) = if flags & 1 != 0 {
+        let (index, rest) = u64::decode(rest)?;
+        let (value, rest) = <Vec<u8>>::decode(rest)?;
+        let (nodes, rest) = decode_tree_nodes(rest)?;
+        (
+            Some(DataBlock {
+                index,
+                value,
+                nodes,
+            }),
+            rest,
+        )
+    } else {
+        (None, rest)
+    };
+    let (hash, rest) = if flags & 2 != 0 {
+        let (index, rest) = u64::decode(rest)?;
+        let (nodes, rest) = decode_tree_nodes(rest)?;
+        (Some(DataHash { index, nodes }), rest)
+    } else {
+        (None, rest)
+    };
+    let (seek, rest) = if flags & 4 != 0 {
+        let (bytes, rest) = u64::decode(rest)?;
+        let (nodes, rest) = decode_tree_nodes(rest)?;
+        (Some(DataSeek { bytes, nodes }), rest)
+    } else {
+        (None, rest)
+    };
+    let (upgrade, rest) = if flags & 8 != 0 {
+        let (start, rest) = u64::decode(rest)?;
+        let (length, rest) = u64::decode(rest)?;
+        let (nodes, rest) = decode_tree_nodes(rest)?;
+        let (additional_nodes, rest) = decode_tree_nodes(rest)?;
+        let (signature, rest) = <Vec<u8>>::decode(rest)?;
+        (
+            Some(DataUpgrade {
+                start,
+                length,
+                nodes,
+                additional_nodes,
+                signature,
+            }),
+            rest,
+        )
+    } else {
+        (None, rest)
+    };
+    Ok((
+        Proof {
+            fork,
+            block,
+            hash,
+            seek,
+            upgrade,
+        },
+        rest,
+    ))
+}
+
+/// Checks that the length prefix of the vector at the start of `buffer` fits in the rest of the
+/// buffer with elements of at least `element_size` bytes. The vector decoding of
+/// [`CompactEncoding`] allocates the length upfront, so otherwise adversarial input could make it
+/// allocate any amount of memory.
+pub(crate) fn check_vec_length(buffer: &[u8], element_size: usize) -> Result<(), EncodingError> {
+    let (length, rest) = decode_usize(buffer)?;
+    if length
+        .checked_mul(element_size)
+        .is_none_or(|size| size > rest.len())
+    {
+        return Err(EncodingError::new(
+            EncodingErrorKind::OutOfBounds,
+            &format!(
+                "Vector of {length} elements in {} remaining bytes",
+                rest.len()
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Decodes a length prefixed vector like [`CompactEncoding`] does for `Vec<T>`, decoding each
+/// element with `decode`, after checking its length with [`check_vec_length`].
+pub(crate) fn decode_bounded_vec<'a, T>(
+    buffer: &'a [u8],
+    decode: impl Fn(&'a [u8]) -> Result<(T, &'a [u8]), EncodingError>,
+) -> Result<(Vec<T>, &'a [u8]), EncodingError> {
+    check_vec_length(buffer, 1)?;
+    let (length, mut rest) = decode_usize(buffer)?;
+    let mut out = Vec::with_capacity(length);
+    for _ in 0..length {
+        let (x, next) = decode(rest)?;
+        out.push(x);
+        rest = next;
+    }
+    Ok((out, rest))
+}
+
+/// Decodes a tree node like [`Node`] does, refusing indexes of nodes at depth 62 or more, whose
+/// parent overflows in [`Node::new`]. Such trees would have more than 2^61 blocks.
+pub(crate) fn decode_tree_node(buffer: &[u8]) -> Result<(Node, &[u8]), EncodingError> {
+    let ((index, length, hash), rest) = map_decode!(buffer, [u64, u64, [u8; 32]]);
+    if index.trailing_ones() >= 62 {
+        return Err(EncodingError::new(
+            EncodingErrorKind::InvalidData,
+            &format!("Tree node index out of range: {index}"),
+        ));
+    }
+    Ok((Node::new(index, hash.to_vec(), length), rest))
+}
+
+/// Decodes a vector of tree nodes with [`decode_tree_node`].
+pub(crate) fn decode_tree_nodes(buffer: &[u8]) -> Result<(Vec<Node>, &[u8]), EncodingError> {
+    decode_bounded_vec(buffer, decode_tree_node)
+}
+
+fn decode_all<'a, T>(
+    buffer: &'a [u8],
+    decode: impl FnOnce(&'a [u8]) -> Result<(T, &'a [u8]), EncodingError>,
+) -> Result<T, EncodingError> {
+    let (x, rest) = decode(buffer)?;
+    if !rest.is_empty() {
+        return Err(EncodingError::new(
+            EncodingErrorKind::InvalidData,
+            &format!("{} trailing bytes", rest.len()),
+        ));
+    }
+    Ok(x)
+}
+
+/// Decodes a tree node, as stored in the tree.
+pub fn decode_node(buffer: &[u8]) -> Result<Node, EncodingError> {
+    decode_all(buffer, decode_tree_node)
+}
+
+/// Decodes a manifest, as stored in the oplog header.
+pub fn decode_manifest(buffer: &[u8]) -> Result<Manifest, EncodingError> {
+    decode_all(buffer, Manifest::decode)
+}
+
+/// Decodes a proof encoded with [`EncodableProof`].
+pub fn decode_proof(buffer: &[u8]) -> Result<Proof, EncodingError> {
+    decode_all(buffer, EncodableProof::decode)
+}
+
+/// Decodes an oplog entry, without the length and checksum that precede it in the oplog.
+pub fn decode_oplog_entry(buffer: &[u8]) -> Result<OplogEntryDump, EncodingError> {
+    decode_all(buffer, Entry::decode).map(|entry| OplogEntryDump::from(&entry))
+}
+
+/// Decodes a replication message, see [`crate::replication::messages::Message`].
+#[cfg(feature = "replication")]
+pub fn decode_message(
+    buffer: &[u8],
+) -> Result<crate::replication::messages::Message, EncodingError> {
+    decode_all(buffer, crate::replication::messages::Message::decode)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        Hypercore,
+        core::tests::create_hypercore_with_data,
+        crypto::{default_signer_manifest, generate_signing_key},
+    };
+    use compact_encoding::{map_decode, to_encoded_bytes};
+
+    async fn create_proof(
+        hypercore: &Hypercore,
+        block: Option<RequestBlock>,
+        hash: Option<RequestBlock>,
+        seek: Option<RequestSeek>,
+        upgrade: Option<RequestUpgrade>,
+    ) -> Proof {
+        hypercore
+            .create_proof(block, hash, seek, upgrade)
+            .await
+            .unwrap()
+            .unwrap()
+    }
+
+    fn assert_proof_round_trip(proof: &Proof) -> Result<(), EncodingError> {
+        let encoded = EncodableProof(proof).to_encoded_bytes()?;
+        let (decoded, rest) = EncodableProof::decode(&encoded)?;
+        assert!(rest.is_empty());
+        assert_eq!(&decoded, proof);
+        Ok(())
+    }
+
+    #[test]
+    fn encode_manifest() -> Result<(), EncodingError> {
+        let public_key = generate_signing_key().verifying_key().to_bytes();
+        let manifest = default_signer_manifest(public_key);
+        let encoded = manifest.to_encoded_bytes()?;
+        let ((decoded,), rest) = map_decode!(&encoded, [Manifest]);
+        assert!(rest.is_empty());
+        assert_eq!(decoded.hash, manifest.hash);
+        assert_eq!(decoded.signer.signature, manifest.signer.signature);
+        assert_eq!(decoded.signer.namespace, manifest.signer.namespace);
+        assert_eq!(decoded.signer.public_key, public_key);
+        Ok(())
+    }
+
+    #[test]
+    fn encode_requests() -> Result<(), EncodingError> {
+        let block = RequestBlock { index: 4, nodes: 2 };
+        let seek = RequestSeek { bytes: 1234 };
+        let upgrade = RequestUpgrade {
+            start: 0,
+            length: 10,
+        };
+        let encoded = to_encoded_bytes!(&block, &seek, &upgrade);
+        let ((dec_block, dec_seek, dec_upgrade), rest) =
+            map_decode!(&encoded, [RequestBlock, RequestSeek, RequestUpgrade]);
+        assert!(rest.is_empty());
+        assert_eq!(dec_block, block);
+        assert_eq!(dec_seek, seek);
+        assert_eq!(dec_upgrade, upgrade);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn encode_proof_block_and_upgrade() -> Result<(), EncodingError> {
+        let hypercore = create_hypercore_with_data(10).await.unwrap();
+        let proof = create_proof(
+            &hypercore,
+            Some(RequestBlock { index: 4, nodes: 0 }),
+            None,
+            None,
+            Some(RequestUpgrade {
+                start: 0,
+                length: 10,
+            }),
+        )
+        .await;
+        assert!(proof.block.is_some() && proof.upgrade.is_some());
+        assert_proof_round_trip(&proof)
+    }
+
+    #[async_std::test]
+    async fn encode_proof_hash_and_seek() -> Result<(), EncodingError> {
+        let hypercore = create_hypercore_with_data(10).await.unwrap();
+        let proof = create_proof(
+            &hypercore,
+            None,
+            Some(RequestBlock { index: 8, nodes: 2 }),
+            None,
+            None,
+        )
+        .await;
+        assert!(proof.hash.is_some());
+        assert_proof_round_trip(&proof)?;
+
+        let proof = create_proof(
+            &hypercore,
+            None,
+            None,
+            Some(RequestSeek { bytes: 13 }),
+            Some(RequestUpgrade {
+                start: 0,
+                length: 10,
+            }),
+        )
+        .await;
+        assert!(proof.seek.is_some());
+        assert_proof_round_trip(&proof)
+    }
+
+    #[test]
+    fn encode_empty_proof() -> Result<(), EncodingError> {
+        let proof = Proof {
+            fork: 3,
+            block: None,
+            hash: None,
+            seek: None,
+            upgrade: None,
+        };
+        let encoded = EncodableProof(&proof).to_encoded_bytes()?;
+        // sizeof(3) + flags
+        assert_eq!(encoded.len(), 2);
+        assert_proof_round_trip(&proof)
+    }
+
+    #[test]
+    fn decode_truncated_proof_fails() -> Result<(), EncodingError> {
+        let proof = Proof {
+            fork: 0,
+            block: Some(DataBlock {
+                index: 1,
+                value: b"hello".to_vec(),
+                nodes: vec![],
+            }),
+            hash: None,
+            seek: None,
+            upgrade: None,
+        };
+        let encoded = EncodableProof(&proof).to_encoded_bytes()?;
+        assert!(EncodableProof::decode(&encoded[..encoded.len() - 1]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn decode_adversarial_input_fails() {
+        let mut manifest = default_signer_manifest([1; 32]).to_encoded_bytes().unwrap();
+        manifest[0] = 1;
+        assert!(decode_manifest(&manifest).is_err());
+        assert!(decode_manifest(&[]).is_err());
+
+        // Fork 0, a hash part with index 0 and a vector of u64::MAX nodes
+        let mut proof = vec![0, 2, 0, 0xff];
+        proof.extend(u64::MAX.to_le_bytes());
+        let error = decode_proof(&proof).unwrap_err();
+        assert_eq!(error.kind, EncodingErrorKind::OutOfBounds);
+        // Tree nodes flag and a vector of 2^32 - 1 nodes
+        assert!(decode_oplog_entry(&[2, 0xfe, 0xff, 0xff, 0xff, 0xff]).is_err());
+        // Bitfield message starting at 0 with 200 words in 4 bytes
+        #[cfg(feature = "replication")]
+        assert!(decode_message(&[7, 0, 200, 0, 0, 0, 0]).is_err());
+        // Node with the largest index, whose parent overflows
+        let mut node = vec![0xff];
+        node.extend(u64::MAX.to_le_bytes());
+        node.extend([0; 33]);
+        assert!(decode_node(&node).is_err());
+
+        let node = Node::new(2, vec![1; 32], 10);
+        let mut encoded = node.to_encoded_bytes().unwrap().to_vec();
+        assert_eq!(decode_node(&encoded).unwrap(), node);
+        encoded.push(0);
+        assert!(decode_node(&encoded).is_err());
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn decode_arbitrary_bytes(bytes in proptest::collection::vec(proptest::prelude::any::<u8>(), 0..512)) {
+            let _ = decode_node(&bytes);
+            let _ = decode_manifest(&bytes);
+            let _ = decode_proof(&bytes);
+            let _ = decode_oplog_entry(&bytes);
+            #[cfg(feature = "replication")]
+            let _ = decode_message(&bytes);
+        }
+
+        #[test]
+        fn proof_round_trip(proof in crate::strategies::proof()) {
+            assert_proof_round_trip(&proof)?;
+        }
+
+        #[test]
+        fn manifest_round_trip(manifest in crate::strategies::manifest()) {
+            let encoded = manifest.to_encoded_bytes()?;
+            let (decoded, rest) = Manifest::decode(&encoded)?;
+            proptest::prop_assert!(rest.is_empty());
+            proptest::prop_assert_eq!(decoded, manifest);
+        }
+    }
+}
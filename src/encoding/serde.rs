@@ -0,0 +1,370 @@
+//! [`serde`](::serde) support for the schema types defined by the `hypercore_schema` crate, which
+//! can't implement `Serialize` and `Deserialize` themselves. Each module is meant for
+//! `#[serde(with = ...)]` on fields of the type:
+//!
+//! ```rust
+//! use hypercore::encoding::Proof;
+//! use serde::{Deserialize, Serialize};
+//!
+//! #[derive(Serialize, Deserialize)]
+//! struct Message {
+//!     #[serde(with = "hypercore::encoding::serde::proof")]
+//!     proof: Proof,
+//! }
+//!
+//! let message = Message {
+//!     proof: Proof {
+//!         fork: 0,
+//!         block: None,
+//!         hash: None,
+//!         seek: None,
+//!         upgrade: None,
+//!     },
+//! };
+//! let json = serde_json::to_string(&message).unwrap();
+//! let message: Message = serde_json::from_str(&json).unwrap();
+//! assert_eq!(message.proof.fork, 0);
+//! ```
+use ::serde::{Deserialize, Deserializer, Serialize, Serializer};
+use hypercore_schema::{DataBlock, DataHash, DataSeek, DataUpgrade, Node, Proof, RequestBlock};
+
+// The derives in this crate name the serde crate: otherwise they emit an `extern crate`, which the
+// forbidden `rust_2018_idioms` lints reject
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "::serde")]
+struct NodeRepr {
+    index: u64,
+    hash: Vec<u8>,
+    length: u64,
+    blank: bool,
+}
+
+impl From<&Node> for NodeRepr {
+    fn from(node: &Node) -> Self {
+        Self {
+            index: node.index,
+            hash: node.hash.clone(),
+            length: node.length,
+            blank: node.blank,
+        }
+    }
+}
+
+impl From<NodeRepr> for Node {
+    fn from(node: NodeRepr) -> Self {
+        if node.blank {
+            Node::new_blank(node.index)
+        } else {
+            Node::new(node.index, node.hash, node.length)
+        }
+    }
+}
+
+fn to_reprs(nodes: &[Node]) -> Vec<NodeRepr> {
+    nodes.iter().map(NodeRepr::from).collect()
+}
+
+fn from_reprs(nodes: Vec<NodeRepr>) -> Vec<Node> {
+    nodes.into_iter().map(Node::from).collect()
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "::serde")]
+struct RequestBlockRepr {
+    index: u64,
+    nodes: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "::serde")]
+struct DataBlockRepr {
+    index: u64,
+    value: Vec<u8>,
+    nodes: Vec<NodeRepr>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "::serde")]
+struct DataHashRepr {
+    index: u64,
+    nodes: Vec<NodeRepr>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "::serde")]
+struct DataSeekRepr {
+    bytes: u64,
+    nodes: Vec<NodeRepr>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "::serde")]
+struct DataUpgradeRepr {
+    start: u64,
+    length: u64,
+    nodes: Vec<NodeRepr>,
+    additional_nodes: Vec<NodeRepr>,
+    signature: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "::serde")]
+struct ProofRepr {
+    fork: u64,
+    block: Option<DataBlockRepr>,
+    hash: Option<DataHashRepr>,
+    seek: Option<DataSeekRepr>,
+    upgrade: Option<DataUpgradeRepr>,
+}
+
+impl From<&Proof> for ProofRepr {
+    fn from(proof: &Proof) -> Self {
+        Self {
+            fork: proof.fork,
+            block: proof.block.as_ref().map(|block| DataBlockRepr {
+                index: block.index,
+                value: block.value.clone(),
+                nodes: to_reprs(&block.nodes),
+            }),
+            hash: proof.hash.as_ref().map(|hash| DataHashRepr {
+                index: hash.index,
+                nodes: to_reprs(&hash.nodes),
+            }),
+            seek: proof.seek.as_ref().map(|seek| DataSeekRepr {
+                bytes: seek.bytes,
+                nodes: to_reprs(&seek.nodes),
+            }),
+            upgrade: proof.upgrade.as_ref().map(|upgrade| DataUpgradeRepr {
+                start: upgrade.start,
+                length: upgrade.length,
+                nodes: to_reprs(&upgrade.nodes),
+                additional_nodes: to_reprs(&upgrade.additional_nodes),
+                signature: upgrade.signature.clone(),
+            }),
+        }
+    }
+}
+
+impl From<ProofRepr> for Proof {
+    fn from(proof: ProofRepr) -> Self {
+        Self {
+            fork: proof.fork,
+            block: proof.block.map(|block| DataBlock {
+                index: block.index,
+                value: block.value,
+                nodes: from_reprs(block.nodes),
+            }),
+            hash: proof.hash.map(|hash| DataHash {
+                index: hash.index,
+                nodes: from_reprs(hash.nodes),
+            }),
+            seek: proof.seek.map(|seek| DataSeek {
+                bytes: seek.bytes,
+                nodes: from_reprs(seek.nodes),
+            }),
+            upgrade: proof.upgrade.map(|upgrade| DataUpgrade {
+                start: upgrade.start,
+                length: upgrade.length,
+                nodes: from_reprs(upgrade.nodes),
+                additional_nodes: from_reprs(upgrade.additional_nodes),
+                signature: upgrade.signature,
+            }),
+        }
+    }
+}
+
+/// Serialize a [`Node`] as its index, hash, length and whether it's blank
+pub mod node {
+    use super::*;
+
+    /// Serialize a [`Node`]
+    pub fn serialize<S: Serializer>(node: &Node, serializer: S) -> Result<S::Ok, S::Error> {
+        NodeRepr::from(node).serialize(serializer)
+    }
+
+    /// Deserialize a [`Node`]
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Node, D::Error> {
+        NodeRepr::deserialize(deserializer).map(Node::from)
+    }
+}
+
+/// Serialize a `Vec<Node>` as a sequence of [`node`]s
+pub mod nodes {
+    use super::*;
+
+    /// Serialize nodes
+    pub fn serialize<S: Serializer>(nodes: &[Node], serializer: S) -> Result<S::Ok, S::Error> {
+        to_reprs(nodes).serialize(serializer)
+    }
+
+    /// Deserialize nodes
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Node>, D::Error> {
+        Vec::<NodeRepr>::deserialize(deserializer).map(from_reprs)
+    }
+}
+
+/// Serialize a [`Proof`] with its fork and optional block, hash, seek and upgrade
+pub mod proof {
+    use super::*;
+
+    /// Serialize a [`Proof`]
+    pub fn serialize<S: Serializer>(proof: &Proof, serializer: S) -> Result<S::Ok, S::Error> {
+        ProofRepr::from(proof).serialize(serializer)
+    }
+
+    /// Deserialize a [`Proof`]
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Proof, D::Error> {
+        ProofRepr::deserialize(deserializer).map(Proof::from)
+    }
+}
+
+/// Serialize a [`RequestBlock`] as its index and nodes
+pub mod request_block {
+    use super::*;
+
+    /// Serialize a [`RequestBlock`]
+    pub fn serialize<S: Serializer>(
+        request: &RequestBlock,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        RequestBlockRepr {
+            index: request.index,
+            nodes: request.nodes,
+        }
+        .serialize(serializer)
+    }
+
+    /// Deserialize a [`RequestBlock`]
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<RequestBlock, D::Error> {
+        RequestBlockRepr::deserialize(deserializer).map(|request| RequestBlock {
+            index: request.index,
+            nodes: request.nodes,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AppendOutcome, Info, PartialKeypair, Stats, generate_signing_key};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    #[serde(crate = "::serde")]
+    struct Message {
+        #[serde(with = "proof")]
+        proof: Proof,
+        #[serde(with = "request_block")]
+        request: RequestBlock,
+        #[serde(with = "nodes")]
+        roots: Vec<Node>,
+        info: Info,
+        outcome: AppendOutcome,
+        stats: Stats,
+    }
+
+    #[test]
+    fn serde_round_trip() -> Result<(), serde_json::Error> {
+        let node = Node::new(2, vec![1; 32], 10);
+        let message = Message {
+            proof: Proof {
+                fork: 1,
+                block: Some(DataBlock {
+                    index: 1,
+                    value: b"#1".to_vec(),
+                    nodes: vec![node.clone()],
+                }),
+                hash: None,
+                seek: None,
+                upgrade: Some(DataUpgrade {
+                    start: 0,
+                    length: 2,
+                    nodes: vec![Node::new_blank(4)],
+                    additional_nodes: vec![],
+                    signature: vec![2; 64],
+                }),
+            },
+            request: RequestBlock { index: 1, nodes: 2 },
+            roots: vec![node],
+            info: Info {
+                length: 2,
+                byte_length: 4,
+                contiguous_length: 2,
+                fork: 1,
+                writeable: false,
+            },
+            outcome: AppendOutcome {
+                length: 2,
+                byte_length: 4,
+                blocks: 1..2,
+                byte_offsets: vec![2],
+                fork: 1,
+                signature: vec![2; 64],
+            },
+            stats: Stats {
+                blocks_appended: 2,
+                bytes_appended: 4,
+                verification_failures: 1,
+                ..Default::default()
+            },
+        };
+        let json = serde_json::to_string(&message)?;
+        assert_eq!(serde_json::from_str::<Message>(&json)?, message);
+        Ok(())
+    }
+
+    #[cfg(feature = "replication")]
+    #[test]
+    fn serde_events() -> Result<(), serde_json::Error> {
+        use crate::replication::events::{Event, Get, Have};
+
+        let event = Event::from(Have {
+            start: 1,
+            length: 2,
+            byte_length: 7,
+            stored: true,
+            drop: false,
+        });
+        let json = serde_json::to_string(&event)?;
+        let Event::Have(have) = serde_json::from_str(&json)? else {
+            panic!("Expected a have event");
+        };
+        assert_eq!(
+            (
+                have.start,
+                have.length,
+                have.byte_length,
+                have.stored,
+                have.drop
+            ),
+            (1, 2, 7, true, false)
+        );
+
+        // Get holds a channel
+        let (get_result, _) = async_broadcast::broadcast(1);
+        let get = Event::Get(Get {
+            index: 0,
+            priority: Default::default(),
+            get_result,
+        });
+        assert!(serde_json::to_string(&get).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn serde_key_pair_keeps_secret_out() -> Result<(), serde_json::Error> {
+        let signing_key = generate_signing_key();
+        let key_pair = PartialKeypair {
+            public: signing_key.verifying_key(),
+            secret: Some(signing_key),
+        };
+        let json = serde_json::to_string(&key_pair)?;
+        let public: PartialKeypair = serde_json::from_str(&json)?;
+        assert_eq!(public.public, key_pair.public);
+        assert!(public.secret.is_none());
+        assert!(serde_json::from_str::<PartialKeypair>(r#"{"public":[1]}"#).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,443 @@
+//! C API to embed hypercores in native hosts, declared in `include/hypercore.h`.
+//!
+//! A hypercore is used through an opaque [`HypercoreHandle`]. Each handle owns a thread running
+//! a [`BlockingHypercore`], and operations are queued to it: they return at once, and their
+//! callback is called from that thread when they complete, in the order they were queued. The
+//! `user_data` pointer given with an operation is passed back to its callback untouched.
+//!
+//! Build a C library with e.g. `cargo rustc --release --features ffi --crate-type cdylib`.
+#![allow(unsafe_code)]
+
+use std::ffi::{CStr, c_char, c_void};
+use std::path::PathBuf;
+use std::sync::mpsc::{Sender, channel};
+use std::thread::JoinHandle;
+
+use crate::blocking::BlockingHypercore;
+use crate::{HypercoreBuilder, HypercoreError, Storage};
+
+/// The operation succeeded, or was queued
+pub const HYPERCORE_OK: i32 = 0;
+/// The block asked with [`hypercore_get`] isn't stored
+pub const HYPERCORE_NOT_FOUND: i32 = 1;
+/// An argument was invalid, e.g. a null pointer or a path that isn't UTF-8
+pub const HYPERCORE_ERR_ARGUMENT: i32 = -1;
+/// The hypercore returned an error without a more specific status, see the codes of
+/// [`crate::ErrorKind::code`]
+pub const HYPERCORE_ERR_CORE: i32 = -2;
+/// The hypercore isn't writable
+pub const HYPERCORE_ERR_NOT_WRITABLE: i32 = -3;
+/// A signature was invalid
+pub const HYPERCORE_ERR_INVALID_SIGNATURE: i32 = -4;
+/// The storage or a checksum was corrupt
+pub const HYPERCORE_ERR_CORRUPT: i32 = -5;
+/// A store was empty
+pub const HYPERCORE_ERR_EMPTY_STORAGE: i32 = -6;
+/// The key doesn't match the stored hypercore
+pub const HYPERCORE_ERR_KEY_MISMATCH: i32 = -7;
+/// The hypercore would grow past its quota
+pub const HYPERCORE_ERR_QUOTA_EXCEEDED: i32 = -8;
+/// The hypercore was closed
+pub const HYPERCORE_ERR_CLOSED: i32 = -9;
+/// The storage timed out
+pub const HYPERCORE_ERR_TIMEOUT: i32 = -10;
+/// The storage is full
+pub const HYPERCORE_ERR_STORAGE_FULL: i32 = -11;
+/// The storage returned another IO error
+pub const HYPERCORE_ERR_IO: i32 = -12;
+/// The writer signed two conflicting trees on the same fork
+pub const HYPERCORE_ERR_EQUIVOCATION: i32 = -13;
+/// A write failed before, the hypercore needs to be recovered or reopened
+pub const HYPERCORE_ERR_POISONED: i32 = -14;
+/// The storage is locked by another process
+pub const HYPERCORE_ERR_LOCKED: i32 = -15;
+
+/// Info about a hypercore, see [`crate::Info`]
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct HypercoreInfo {
+    /// Length of the hypercore
+    pub length: u64,
+    /// Byte length of the hypercore
+    pub byte_length: u64,
+    /// Length of the blocks stored contiguously from index 0
+    pub contiguous_length: u64,
+    /// Fork of the hypercore
+    pub fork: u64,
+    /// True if the hypercore is writable
+    pub writeable: bool,
+}
+
+/// Called with the status of [`hypercore_append`] and the length and byte length after it
+pub type HypercoreAppendCallback =
+    extern "C" fn(user_data: *mut c_void, status: i32, length: u64, byte_length: u64);
+
+/// Called with the status of [`hypercore_get`] and the block, which is only valid during the call
+pub type HypercoreGetCallback =
+    extern "C" fn(user_data: *mut c_void, status: i32, data: *const u8, data_len: usize);
+
+/// Called with the info asked with [`hypercore_info`]
+pub type HypercoreInfoCallback = extern "C" fn(user_data: *mut c_void, info: HypercoreInfo);
+
+/// Pointer of the host, passed back to its callback on the thread of the handle
+struct UserData(*mut c_void);
+
+// The host owns the pointer and is responsible for using it from the thread of the handle
+unsafe impl Send for UserData {}
+
+type Job = Box<dyn FnOnce(&mut BlockingHypercore) + Send>;
+
+/// Opaque handle of a hypercore, created with [`hypercore_create`] or [`hypercore_open`] and
+/// freed with [`hypercore_close`]
+#[derive(Debug)]
+pub struct HypercoreHandle {
+    jobs: Sender<Job>,
+    worker: JoinHandle<()>,
+}
+
+impl HypercoreHandle {
+    /// Start the thread of the handle, running the hypercore built in `dir`, or in memory if
+    /// None
+    fn spawn(dir: Option<PathBuf>, open: bool) -> Result<Self, HypercoreError> {
+        let (jobs, receiver) = channel::<Job>();
+        let (built, build_result) = channel();
+        let worker = std::thread::spawn(move || {
+            let core = BlockingHypercore::new(async {
+                let storage = match &dir {
+                    Some(dir) => Storage::new_disk(dir, false).await?,
+                    None => Storage::new_memory().await?,
+                };
+                HypercoreBuilder::new(storage).open(open).build().await
+            });
+            let mut core = match core {
+                Ok(core) => {
+                    let _ = built.send(Ok(()));
+                    core
+                }
+                Err(error) => {
+                    let _ = built.send(Err(error));
+                    return;
+                }
+            };
+            // Runs until the handle is closed, after the queued jobs
+            for job in receiver {
+                job(&mut core);
+            }
+        });
+        match build_result.recv() {
+            Ok(Ok(())) => Ok(Self { jobs, worker }),
+            Ok(Err(error)) => Err(error),
+            Err(_) => Err(HypercoreError::Closed {
+                context: "Hypercore thread stopped".to_string(),
+            }),
+        }
+    }
+
+    fn queue(&self, job: Job) -> i32 {
+        match self.jobs.send(job) {
+            Ok(()) => HYPERCORE_OK,
+            Err(_) => HYPERCORE_ERR_CLOSED,
+        }
+    }
+}
+
+/// Path given by the host, None for a null pointer
+unsafe fn path_arg(path: *const c_char) -> Result<Option<PathBuf>, i32> {
+    if path.is_null() {
+        return Ok(None);
+    }
+    // SAFETY: the caller guarantees that a non-null path is a nul-terminated string
+    let path = unsafe { CStr::from_ptr(path) };
+    path.to_str()
+        .map(|path| Some(PathBuf::from(path)))
+        .map_err(|_| HYPERCORE_ERR_ARGUMENT)
+}
+
+unsafe fn spawn_handle(dir: Option<PathBuf>, open: bool, out: *mut *mut HypercoreHandle) -> i32 {
+    if out.is_null() {
+        return HYPERCORE_ERR_ARGUMENT;
+    }
+    match HypercoreHandle::spawn(dir, open) {
+        Ok(handle) => {
+            // SAFETY: the caller guarantees that `out` is valid for writes
+            unsafe { *out = Box::into_raw(Box::new(handle)) };
+            HYPERCORE_OK
+        }
+        Err(error) => error.kind().code(),
+    }
+}
+
+/// Create a new hypercore with a new key pair, on disk in the directory `path`, or in memory if
+/// `path` is null. The handle is written to `out`.
+///
+/// # Safety
+///
+/// `path` must be null or a nul-terminated string, and `out` valid for writes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn hypercore_create(
+    path: *const c_char,
+    out: *mut *mut HypercoreHandle,
+) -> i32 {
+    // SAFETY: forwarded from the caller
+    match unsafe { path_arg(path) } {
+        Ok(dir) => unsafe { spawn_handle(dir, false, out) },
+        Err(status) => status,
+    }
+}
+
+/// Open the hypercore stored in the directory `path`. The handle is written to `out`.
+///
+/// # Safety
+///
+/// `path` must be a nul-terminated string, and `out` valid for writes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn hypercore_open(
+    path: *const c_char,
+    out: *mut *mut HypercoreHandle,
+) -> i32 {
+    // SAFETY: forwarded from the caller
+    match unsafe { path_arg(path) } {
+        Ok(Some(dir)) => unsafe { spawn_handle(Some(dir), true, out) },
+        Ok(None) => HYPERCORE_ERR_ARGUMENT,
+        Err(status) => status,
+    }
+}
+
+/// Queue appending the `data_len` bytes at `data` as a block. The data is copied before the call
+/// returns.
+///
+/// # Safety
+///
+/// `handle` must be a handle that wasn't closed, and `data` valid for reads of `data_len` bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn hypercore_append(
+    handle: *const HypercoreHandle,
+    data: *const u8,
+    data_len: usize,
+    callback: HypercoreAppendCallback,
+    user_data: *mut c_void,
+) -> i32 {
+    if handle.is_null() || (data.is_null() && data_len > 0) {
+        return HYPERCORE_ERR_ARGUMENT;
+    }
+    let data = if data_len == 0 {
+        vec![]
+    } else {
+        // SAFETY: the caller guarantees that `data` is valid for `data_len` bytes
+        unsafe { std::slice::from_raw_parts(data, data_len) }.to_vec()
+    };
+    let user_data = UserData(user_data);
+    // SAFETY: the caller guarantees that the handle is valid
+    let handle = unsafe { &*handle };
+    handle.queue(Box::new(move |core| {
+        let user_data = user_data;
+        match core.append(&data) {
+            Ok(outcome) => callback(
+                user_data.0,
+                HYPERCORE_OK,
+                outcome.length,
+                outcome.byte_length,
+            ),
+            Err(error) => callback(user_data.0, error.kind().code(), 0, 0),
+        }
+    }))
+}
+
+/// Queue reading the block at `index`. The callback gets [`HYPERCORE_NOT_FOUND`] and a null
+/// pointer if the block isn't stored.
+///
+/// # Safety
+///
+/// `handle` must be a handle that wasn't closed.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn hypercore_get(
+    handle: *const HypercoreHandle,
+    index: u64,
+    callback: HypercoreGetCallback,
+    user_data: *mut c_void,
+) -> i32 {
+    if handle.is_null() {
+        return HYPERCORE_ERR_ARGUMENT;
+    }
+    let user_data = UserData(user_data);
+    // SAFETY: the caller guarantees that the handle is valid
+    let handle = unsafe { &*handle };
+    handle.queue(Box::new(move |core| {
+        let user_data = user_data;
+        match core.get(index) {
+            Ok(Some(block)) => callback(user_data.0, HYPERCORE_OK, block.as_ptr(), block.len()),
+            Ok(None) => callback(user_data.0, HYPERCORE_NOT_FOUND, std::ptr::null(), 0),
+            Err(error) => callback(user_data.0, error.kind().code(), std::ptr::null(), 0),
+        }
+    }))
+}
+
+/// Queue getting the info of the hypercore, after the operations queued before
+///
+/// # Safety
+///
+/// `handle` must be a handle that wasn't closed.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn hypercore_info(
+    handle: *const HypercoreHandle,
+    callback: HypercoreInfoCallback,
+    user_data: *mut c_void,
+) -> i32 {
+    if handle.is_null() {
+        return HYPERCORE_ERR_ARGUMENT;
+    }
+    let user_data = UserData(user_data);
+    // SAFETY: the caller guarantees that the handle is valid
+    let handle = unsafe { &*handle };
+    handle.queue(Box::new(move |core| {
+        let user_data = user_data;
+        let info = core.info();
+        callback(
+            user_data.0,
+            HypercoreInfo {
+                length: info.length,
+                byte_length: info.byte_length,
+                contiguous_length: info.contiguous_length,
+                fork: info.fork,
+                writeable: info.writeable,
+            },
+        )
+    }))
+}
+
+/// Close the hypercore and free the handle. Blocks until the queued operations are done and
+/// their callbacks called. Does nothing for a null handle.
+///
+/// # Safety
+///
+/// `handle` must be null or a handle that wasn't closed, and not be used afterwards.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn hypercore_close(handle: *mut HypercoreHandle) {
+    if handle.is_null() {
+        return;
+    }
+    // SAFETY: the caller guarantees that the handle came from `hypercore_create` or
+    // `hypercore_open` and is closed once
+    let handle = unsafe { Box::from_raw(handle) };
+    let HypercoreHandle { jobs, worker } = *handle;
+    drop(jobs);
+    let _ = worker.join();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ErrorKind;
+    use std::sync::Mutex;
+
+    #[derive(Debug, Default)]
+    struct Results {
+        appended: Vec<(i32, u64, u64)>,
+        blocks: Vec<(i32, Option<Vec<u8>>)>,
+        info: Option<(u64, bool)>,
+    }
+
+    extern "C" fn on_append(user_data: *mut c_void, status: i32, length: u64, byte_length: u64) {
+        let results = unsafe { &*(user_data as *const Mutex<Results>) };
+        results
+            .lock()
+            .unwrap()
+            .appended
+            .push((status, length, byte_length));
+    }
+
+    extern "C" fn on_get(user_data: *mut c_void, status: i32, data: *const u8, data_len: usize) {
+        let results = unsafe { &*(user_data as *const Mutex<Results>) };
+        let block = (!data.is_null())
+            .then(|| unsafe { std::slice::from_raw_parts(data, data_len) }.to_vec());
+        results.lock().unwrap().blocks.push((status, block));
+    }
+
+    extern "C" fn on_info(user_data: *mut c_void, info: HypercoreInfo) {
+        let results = unsafe { &*(user_data as *const Mutex<Results>) };
+        results.lock().unwrap().info = Some((info.length, info.writeable));
+    }
+
+    #[test]
+    fn ffi_create_append_get_close() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = std::ffi::CString::new(dir.path().to_str().unwrap()).unwrap();
+        let results = Mutex::new(Results::default());
+        let user_data = &results as *const Mutex<Results> as *mut c_void;
+
+        let mut handle = std::ptr::null_mut();
+        unsafe {
+            assert_eq!(hypercore_create(path.as_ptr(), &mut handle), HYPERCORE_OK);
+            assert_eq!(
+                hypercore_append(handle, b"hello".as_ptr(), 5, on_append, user_data),
+                HYPERCORE_OK
+            );
+            hypercore_append(handle, b"world".as_ptr(), 5, on_append, user_data);
+            hypercore_get(handle, 1, on_get, user_data);
+            hypercore_get(handle, 2, on_get, user_data);
+            hypercore_info(handle, on_info, user_data);
+            hypercore_close(handle);
+        }
+        let results = results.into_inner().unwrap();
+        assert_eq!(
+            results.appended,
+            vec![(HYPERCORE_OK, 1, 5), (HYPERCORE_OK, 2, 10)]
+        );
+        assert_eq!(
+            results.blocks,
+            vec![
+                (HYPERCORE_OK, Some(b"world".to_vec())),
+                (HYPERCORE_NOT_FOUND, None)
+            ]
+        );
+        assert_eq!(results.info, Some((2, true)));
+
+        // Reopened from disk
+        let results = Mutex::new(Results::default());
+        let user_data = &results as *const Mutex<Results> as *mut c_void;
+        unsafe {
+            assert_eq!(hypercore_open(path.as_ptr(), &mut handle), HYPERCORE_OK);
+            hypercore_get(handle, 0, on_get, user_data);
+            hypercore_close(handle);
+            assert_eq!(
+                hypercore_open(std::ptr::null(), &mut handle),
+                HYPERCORE_ERR_ARGUMENT
+            );
+        }
+        assert_eq!(
+            results.into_inner().unwrap().blocks,
+            vec![(HYPERCORE_OK, Some(b"hello".to_vec()))]
+        );
+    }
+
+    #[test]
+    fn ffi_error_codes() {
+        let statuses = [
+            (ErrorKind::BadArgument, HYPERCORE_ERR_ARGUMENT),
+            (ErrorKind::InvalidOperation, HYPERCORE_ERR_CORE),
+            (ErrorKind::NotWritable, HYPERCORE_ERR_NOT_WRITABLE),
+            (ErrorKind::InvalidSignature, HYPERCORE_ERR_INVALID_SIGNATURE),
+            (ErrorKind::Corrupt, HYPERCORE_ERR_CORRUPT),
+            (ErrorKind::EmptyStorage, HYPERCORE_ERR_EMPTY_STORAGE),
+            (ErrorKind::KeyMismatch, HYPERCORE_ERR_KEY_MISMATCH),
+            (ErrorKind::QuotaExceeded, HYPERCORE_ERR_QUOTA_EXCEEDED),
+            (ErrorKind::Closed, HYPERCORE_ERR_CLOSED),
+            (ErrorKind::Timeout, HYPERCORE_ERR_TIMEOUT),
+            (ErrorKind::StorageFull, HYPERCORE_ERR_STORAGE_FULL),
+            (ErrorKind::Io, HYPERCORE_ERR_IO),
+            (ErrorKind::Equivocation, HYPERCORE_ERR_EQUIVOCATION),
+            (ErrorKind::Poisoned, HYPERCORE_ERR_POISONED),
+            (ErrorKind::Locked, HYPERCORE_ERR_LOCKED),
+        ];
+        for (kind, status) in statuses {
+            assert_eq!(kind.code(), status, "{kind:?}");
+        }
+
+        // Opening a directory without a hypercore fails with the code of the error
+        let dir = tempfile::tempdir().unwrap();
+        let path = std::ffi::CString::new(dir.path().to_str().unwrap()).unwrap();
+        let mut handle = std::ptr::null_mut();
+        let status = unsafe { hypercore_open(path.as_ptr(), &mut handle) };
+        assert!(status < HYPERCORE_ERR_ARGUMENT, "{status}");
+    }
+}
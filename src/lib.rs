@@ -1,5 +1,6 @@
+#![cfg_attr(not(feature = "ffi"), forbid(unsafe_code))]
+#![cfg_attr(feature = "ffi", deny(unsafe_code))]
 #![forbid(
-    unsafe_code,
     future_incompatible,
     rust_2018_idioms,
     rust_2018_compatibility,
@@ -25,7 +26,9 @@
 //! crate is through the [Hypercore] struct, which can be created using the
 //! [HypercoreBuilder].
 //!
-//! This crate supports WASM with `cargo build --target=wasm32-unknown-unknown`.
+//! This crate supports WASM with `cargo build --target=wasm32-unknown-unknown`. Proofs can be
+//! checked against a public key without storage with [`verify`], which also builds in `no_std`
+//! crates.
 //!
 //! ## Features
 //!
@@ -35,15 +38,94 @@
 //!
 //! ### `async-std` (default)
 //!
-//! Use the async-std runtime, on by default. Either this or `tokio` is mandatory.
+//! Use the async-std runtime, on by default. Either this or `tokio` is needed for disk storage.
 //!
 //! ### `tokio`
 //!
-//! Use the tokio runtime. Either this or `async_std` is mandatory.
+//! Use the tokio runtime. Either this or `async_std` is needed for disk storage.
 //!
 //! ### `cache`
 //!
-//! Use a moka cache for merkle tree nodes to speed-up reading.
+//! Use a moka cache for merkle tree nodes to speed-up reading, and optionally for data blocks and
+//! created proofs. On wasm32, a least recently used cache bounded by capacity is used instead,
+//! whose entries don't expire.
+//!
+//! ### `tracing` (default)
+//!
+//! Record spans and events of the operations of a hypercore and of its storage with
+//! [tracing](https://docs.rs/tracing).
+//!
+//! ### `send` (default)
+//!
+//! Require the futures of the replication traits, like [`replication::CoreMethods`], and their
+//! implementors to be `Send`, see [`replication::MaybeSend`]. Without it, cores holding `!Send`
+//! state, e.g. storage handles with `wasm-bindgen-futures`, can implement the traits.
+//!
+//! ### `test-utils`
+//!
+//! Provide [`replication::MockCore`], an in-memory implementation of the replication traits for
+//! testing code written against them.
+//!
+//! ### `shared-core`
+//!
+//! Provide [`replication::SharedCore`], a hypercore that can be shared between tasks.
+//!
+//! ### `corestore`
+//!
+//! Provide [`corestore::Corestore`] to manage many hypercores stored in one place, with key pairs
+//! derived from a primary key. Enables `shared-core`.
+//!
+//! ### `serde`
+//!
+//! Implement `Serialize` and `Deserialize` of [serde](https://serde.rs) for public types like
+//! [`Info`], [`AppendOutcome`], the public key of [`PartialKeypair`] and the replication events,
+//! and provide [`encoding::serde`] for the schema types like [`encoding::Proof`].
+//!
+//! ### `blocking`
+//!
+//! Provide [`blocking::BlockingHypercore`], a synchronous wrapper of [`Hypercore`] for code that
+//! doesn't run in an async executor.
+//!
+//! ### `ffi`
+//!
+//! Provide [`ffi`], a C API with opaque handles and callbacks declared in `include/hypercore.h`,
+//! to embed hypercores in native hosts. Enables `blocking`, and is the only module allowed to use
+//! unsafe code.
+//!
+//! ### `wasm`
+//!
+//! Provide [`wasm`], JavaScript bindings with [wasm-bindgen](https://rustwasm.github.io/wasm-bindgen/)
+//! exporting a promise-based `Hypercore` class for browser apps.
+//!
+//! ### `chunking`
+//!
+//! Provide [`chunking::FastCdc`], a content-defined chunker, and [`Hypercore::append_reader`] to
+//! append a stream as blocks whose boundaries depend on their content, for file sync.
+//!
+//! ### `metrics`
+//!
+//! Record counters with the [metrics](https://docs.rs/metrics) facade, to be exported e.g. to
+//! Prometheus by the recorder installed by the application. The counters of all hypercores of a
+//! process are added together:
+//!
+//! - `hypercore_storage_reads_total`, `hypercore_storage_read_bytes_total`,
+//!   `hypercore_storage_writes_total` and `hypercore_storage_written_bytes_total`, labeled with
+//!   the `store`
+//! - `hypercore_cache_hits_total` and `hypercore_cache_misses_total`, labeled with the `cache`:
+//!   `node`, `block` or `proof`
+//! - `hypercore_appended_blocks_total` and `hypercore_appended_bytes_total`
+//! - `hypercore_proofs_created_total`, `hypercore_proofs_verified_total`,
+//!   `hypercore_proof_verification_failures_total`, `hypercore_received_blocks_total` and
+//!   `hypercore_received_bytes_total`
+//! - `hypercore_event_queue_depth`, a histogram of the number of events queued for subscribers
+//!   when sending an event, with the `replication` feature
+//!
+//! ## Minimal builds
+//!
+//! With `default-features = false`, the crate builds without an async runtime, caches, tracing
+//! and the replication traits and events, e.g. for embedded use of hypercores in memory or on a
+//! custom [`StorageTraits`] implementation. The events of a hypercore come with `replication`,
+//! which pulls in `async-broadcast`.
 //!
 //! ## Example
 //! ```rust
@@ -82,17 +164,43 @@
 //! [HypercoreBuilder]: crate::builder::HypercoreBuilder
 //! [examples]: https://github.com/datrs/hypercore/tree/master/examples
 
+#[cfg(all(
+    any(feature = "corestore", feature = "ffi"),
+    not(any(feature = "tokio", feature = "async-std")),
+    not(target_arch = "wasm32")
+))]
+compile_error!("The `corestore` and `ffi` features need either the `tokio` or `async-std` feature");
+
+#[cfg(feature = "blocking")]
+pub mod blocking;
+#[cfg(feature = "chunking")]
+pub mod chunking;
+#[cfg(feature = "corestore")]
+pub mod corestore;
+#[cfg(feature = "test-utils")]
+pub mod crash;
 pub mod encoding;
+#[cfg(feature = "ffi")]
+pub mod ffi;
 pub mod prelude;
 #[cfg(feature = "replication")]
 pub mod replication;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod strategies;
+pub mod verify;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+extern crate alloc;
 
+mod archive;
 mod bitfield;
 mod builder;
 mod common;
 mod core;
 mod crypto;
 mod data;
+mod dump;
 mod oplog;
 mod storage;
 mod tree;
@@ -100,9 +208,23 @@ mod tree;
 #[cfg(feature = "cache")]
 pub use crate::builder::CacheOptionsBuilder;
 pub use crate::builder::HypercoreBuilder;
-pub use crate::common::{HypercoreError, Store};
-pub use crate::core::{AppendOutcome, Hypercore, Info};
-pub use crate::crypto::{PartialKeypair, generate_signing_key, sign, verify};
+#[cfg(any(feature = "tokio", feature = "async-std"))]
+pub use crate::common::TokenBucket;
+pub use crate::common::{
+    ErrorKind, HypercoreError, PersistIf, PersistRanges, PersistencePolicy, RateLimiter, Store,
+    ValuelessProof,
+};
+pub use crate::core::{
+    AppendOutcome, BuildOutcome, Checkpoint, DetailedInfo, Equivocation, Hypercore, Info, Quota,
+    RestoreOutcome, Retention, Stats, StoreBytes,
+};
+pub use crate::crypto::{
+    Manifest, ManifestSigner, PartialKeypair, discovery_key, generate_signing_key,
+    generate_signing_key_with, sign, verify,
+};
+pub use crate::dump::{HeaderDump, NodeDump, OplogEntryDump, StateDump, TreeUpgradeDump};
+#[cfg(not(target_arch = "wasm32"))]
+pub use crate::storage::core_dir;
 pub use crate::storage::{Storage, StorageTraits};
 pub use ed25519_dalek::{
     KEYPAIR_LENGTH, PUBLIC_KEY_LENGTH, SECRET_KEY_LENGTH, SecretKey, Signature, SigningKey,
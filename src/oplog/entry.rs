@@ -4,10 +4,11 @@ use compact_encoding::{
 };
 
 use crate::common::BitfieldUpdate;
+use crate::encoding::{decode_bounded_vec, decode_tree_nodes};
 use hypercore_schema::Node;
 
 /// Entry tree upgrade
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub(crate) struct EntryTreeUpgrade {
     pub(crate) fork: u64,
     pub(crate) ancestors: u64,
@@ -82,7 +83,7 @@ impl CompactEncoding for BitfieldUpdate {
 }
 
 /// Oplog Entry
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub(crate) struct Entry {
     // TODO: This is a keyValueArray in JS
     pub(crate) user_data: Vec<String>,
@@ -138,25 +139,25 @@ impl CompactEncoding for Entry {
     {
         let ([flags], rest) = take_array::<1>(buffer)?;
         let (user_data, rest) = if flags & 1 != 0 {
-            <Vec<String>>::decode(rest)?
+            decode_bounded_vec(rest, String::decode)?
         } else {
             (Default::default(), rest)
         };
 
         let (tree_nodes, rest) = if flags & 2 != 0 {
-            <Vec<Node>>::decode(rest)?
+            decode_tree_nodes(rest)?
         } else {
             (Default::default(), rest)
         };
 
-        let (tree_upgrade, rest) = if flags & 2 != 0 {
+        let (tree_upgrade, rest) = if flags & 4 != 0 {
             let (x, rest) = EntryTreeUpgrade::decode(rest)?;
             (Some(x), rest)
         } else {
             (Default::default(), rest)
         };
 
-        let (bitfield, rest) = if flags & 2 != 0 {
+        let (bitfield, rest) = if flags & 8 != 0 {
             let (x, rest) = BitfieldUpdate::decode(rest)?;
             (Some(x), rest)
         } else {
@@ -174,3 +175,19 @@ impl CompactEncoding for Entry {
         ))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    proptest! {
+        #[test]
+        fn entry_round_trip(entry in crate::strategies::entry()) {
+            let encoded = entry.to_encoded_bytes()?;
+            let (decoded, rest) = Entry::decode(&encoded)?;
+            prop_assert!(rest.is_empty());
+            prop_assert_eq!(decoded, entry);
+        }
+    }
+}
@@ -26,10 +26,11 @@ pub(crate) struct Header {
 }
 
 impl Header {
-    /// Creates a new Header from given key pair
-    pub(crate) fn new(key_pair: PartialKeypair) -> Self {
+    /// Creates a new Header from given key pair and manifest, defaulting to a single signer
+    /// manifest of the key pair.
+    pub(crate) fn new(key_pair: PartialKeypair, manifest: Option<Manifest>) -> Self {
         let key = key_pair.public.to_bytes();
-        let manifest = default_signer_manifest(key);
+        let manifest = manifest.unwrap_or_else(|| default_signer_manifest(key));
         Self {
             key,
             manifest,
@@ -334,7 +335,7 @@ mod tests {
             public: signing_key.verifying_key(),
             secret: Some(signing_key),
         };
-        let header = Header::new(signing_key);
+        let header = Header::new(signing_key, None);
         let encoded = to_encoded_bytes!(&header);
         let ((dec_header,), rest) = map_decode!(&encoded, [Header]);
         assert!(rest.is_empty());
@@ -5,10 +5,14 @@ use compact_encoding::{
 use futures::future::Either;
 use std::convert::{TryFrom, TryInto};
 
-use crate::common::{BitfieldUpdate, Store, StoreInfo, StoreInfoInstruction};
+use crate::common::{BitfieldUpdate, BufferPool, Store, StoreInfo, StoreInfoInstruction};
+use crate::crypto::Manifest;
+use crate::storage::{Storage, StorageTraits};
 use crate::tree::MerkleTreeChangeset;
 use crate::{HypercoreError, PartialKeypair};
 
+#[cfg(all(feature = "corestore", not(target_arch = "wasm32")))]
+use ed25519_dalek::VerifyingKey;
 use hypercore_schema::Node;
 
 pub(crate) mod entry;
@@ -37,6 +41,8 @@ pub(crate) struct Oplog {
     header_bits: [bool; 2],
     pub(crate) entries_length: u64,
     pub(crate) entries_byte_length: u64,
+    /// Buffers to encode appended entries in
+    pool: BufferPool,
 }
 
 /// Oplog create header outcome
@@ -46,6 +52,60 @@ pub(crate) struct OplogCreateHeaderOutcome {
     pub(crate) infos_to_flush: Box<[StoreInfo]>,
 }
 
+/// State of the [`Oplog`] after writing infos to storage, applied with [`Oplog::commit`]. Only
+/// an [`OplogWrite`] gives it out, once its infos are flushed, so that dropping an operation
+/// while flushing leaves the oplog as it was.
+#[derive(Debug)]
+#[must_use = "the oplog is behind its storage until committed"]
+pub(crate) struct OplogCommit {
+    header_bits: [bool; 2],
+    entries_length: u64,
+    entries_byte_length: u64,
+}
+
+/// Infos of the [`Oplog`] to write to storage, along with the [`OplogCommit`] they lead to
+#[derive(Debug)]
+#[must_use = "the oplog is unchanged until the write is flushed and committed"]
+pub(crate) struct OplogWrite {
+    infos: Box<[StoreInfo]>,
+    commit: OplogCommit,
+}
+
+impl OplogWrite {
+    /// Flush the infos to storage, returns the commit to apply once written
+    pub(crate) async fn flush<T: StorageTraits + Send + ?Sized>(
+        self,
+        storage: &mut Storage<T>,
+    ) -> Result<OplogCommit, HypercoreError> {
+        storage.flush_infos(&self.infos).await?;
+        Ok(self.commit)
+    }
+
+    /// Move the infos to `deferred`, to flush together with others, returns the commit to apply
+    /// right away. Only for poisoned storage, see [`Storage::poison`], where nothing else is
+    /// written until the deferred infos are.
+    pub(crate) fn defer(self, deferred: &mut Vec<StoreInfo>) -> OplogCommit {
+        deferred.extend(self.infos);
+        self.commit
+    }
+}
+
+/// Oplog append outcome
+#[derive(Debug)]
+pub(crate) struct OplogAppendOutcome {
+    pub(crate) header: Header,
+    pub(crate) write: OplogWrite,
+}
+
+/// Oplog flush outcome
+#[derive(Debug)]
+pub(crate) struct OplogFlushOutcome {
+    /// Writes of the header, each committing the flush once written
+    pub(crate) headers: Vec<OplogWrite>,
+    /// Truncation of the entries, stale once a header is written
+    pub(crate) truncate: StoreInfo,
+}
+
 /// Oplog open outcome
 #[derive(Debug)]
 pub(crate) struct OplogOpenOutcome {
@@ -53,6 +113,8 @@ pub(crate) struct OplogOpenOutcome {
     pub(crate) header: Header,
     pub(crate) infos_to_flush: Box<[StoreInfo]>,
     pub(crate) entries: Option<Box<[Entry]>>,
+    /// True if the oplog was empty and a new header was created
+    pub(crate) created: bool,
 }
 
 impl OplogOpenOutcome {
@@ -62,6 +124,7 @@ impl OplogOpenOutcome {
             header,
             infos_to_flush,
             entries: None,
+            created: false,
         }
     }
     pub(crate) fn from_create_header_outcome(
@@ -73,6 +136,7 @@ impl OplogOpenOutcome {
             header: create_header_outcome.header,
             infos_to_flush: create_header_outcome.infos_to_flush,
             entries: None,
+            created: true,
         }
     }
 }
@@ -99,6 +163,7 @@ impl Oplog {
     /// Opens an existing Oplog from existing byte buffer or creates a new one.
     pub(crate) fn open(
         key_pair: &Option<PartialKeypair>,
+        manifest: &Option<Manifest>,
         info: Option<StoreInfo>,
     ) -> Result<Either<StoreInfoInstruction, OplogOpenOutcome>, HypercoreError> {
         match info {
@@ -107,21 +172,25 @@ impl Oplog {
             ))),
             Some(info) => {
                 let existing = info.data.expect("Could not get data of existing oplog");
-                // First read and validate both headers stored in the existing oplog
-                let h1_outcome = if let Some(h1) =
-                    existing.get(OplogSlot::FirstHeader as usize..OplogSlot::SecondHeader as usize)
-                {
-                    Self::validate_leader(h1)?
-                } else {
-                    None
-                };
-                let h2_outcome = if let Some(h2) =
-                    existing.get(OplogSlot::SecondHeader as usize..OplogSlot::Entries as usize)
-                {
-                    Self::validate_leader(h2)?
-                } else {
-                    None
-                };
+                // First read and validate both headers stored in the existing oplog. Only one
+                // header is written at a time, so one of them failing its checksum was torn by a
+                // crash while writing it, and the other one is used.
+                let h1 = existing
+                    .get(OplogSlot::FirstHeader as usize..OplogSlot::SecondHeader as usize)
+                    .map(Self::validate_leader);
+                let h2 = existing
+                    .get(OplogSlot::SecondHeader as usize..OplogSlot::Entries as usize)
+                    .map(Self::validate_leader);
+                let (h1_outcome, h2_outcome) =
+                    match (h1.unwrap_or(Ok(None)), h2.unwrap_or(Ok(None))) {
+                        (Err(err), Err(_)) => return Err(err),
+                        (Err(err), Ok(None)) | (Ok(None), Err(err))
+                            if existing.len() > HEADER_SIZE * 2 =>
+                        {
+                            return Err(err);
+                        }
+                        (h1, h2) => (h1.ok().flatten(), h2.ok().flatten()),
+                    };
                 // Depending on what is stored, the state needs to be set accordingly.
                 // See `get_next_header_oplog_slot_and_bit_value` for details on header_bits.
                 let mut outcome: OplogOpenOutcome = if let Some(h1_outcome) = h1_outcome {
@@ -144,6 +213,7 @@ impl Oplog {
                         header_bits,
                         entries_length: 0,
                         entries_byte_length: 0,
+                        pool: BufferPool::default(),
                     };
                     OplogOpenOutcome::new(oplog, header, Box::new([]))
                 } else if let Some(h2_outcome) = h2_outcome {
@@ -154,11 +224,12 @@ impl Oplog {
                         header_bits,
                         entries_length: 0,
                         entries_byte_length: 0,
+                        pool: BufferPool::default(),
                     };
                     OplogOpenOutcome::new(oplog, Header::decode(h2_outcome.state)?.0, Box::new([]))
                 } else if let Some(key_pair) = key_pair {
                     // There is nothing in the oplog, start from fresh given key pair.
-                    Self::fresh(key_pair.clone())?
+                    Self::fresh(key_pair.clone(), manifest.clone())?
                 } else {
                     // The storage is empty and no key pair given, erroring
                     return Err(HypercoreError::EmptyStorage {
@@ -166,23 +237,24 @@ impl Oplog {
                     });
                 };
 
-                // Read headers that might be stored in the existing content
-                if existing.len() > OplogSlot::Entries as usize {
-                    let mut entries_buff =
-                        get_slices_checked(&existing, OplogSlot::Entries as usize)?.1;
-                    let mut entries: Vec<Entry> = Vec::new();
-                    let mut partials: Vec<bool> = Vec::new();
-                    while let Some(entry_outcome) = Self::validate_leader(entries_buff)? {
-                        let res = Entry::decode(entry_outcome.state)?;
-                        entries.push(res.0);
-                        entries_buff = res.1;
-                        partials.push(entry_outcome.partial_bit);
-                    }
-
-                    // Remove all trailing partial entries
-                    while !partials.is_empty() && partials[partials.len() - 1] {
-                        entries.pop();
-                    }
+                // Continue after the entries written since the last header, dropping what follows
+                // them, i.e. an entry torn by a crash or stale entries from before the header
+                let header_bit = outcome.oplog.get_current_header_bit();
+                let (entries, entries_byte_length) = Self::entries(&existing, header_bit)?;
+                let end = OplogSlot::Entries as u64 + entries_byte_length;
+                if existing.len() as u64 > end {
+                    #[cfg(feature = "tracing")]
+                    tracing::warn!(
+                        dropped = existing.len() as u64 - end,
+                        "Truncating torn or stale oplog entries"
+                    );
+                    let mut infos_to_flush = outcome.infos_to_flush.into_vec();
+                    infos_to_flush.push(StoreInfo::new_truncate(Store::Oplog, end));
+                    outcome.infos_to_flush = infos_to_flush.into_boxed_slice();
+                }
+                outcome.oplog.entries_length = entries.len() as u64;
+                outcome.oplog.entries_byte_length = entries_byte_length;
+                if !entries.is_empty() {
                     outcome.entries = Some(entries.into_boxed_slice());
                 }
                 Ok(Either::Right(outcome))
@@ -190,21 +262,85 @@ impl Oplog {
         }
     }
 
-    /// Appends an upgraded changeset to the Oplog.
+    /// Decodes the entries stored after the headers of the given oplog content that were written
+    /// since the header with the given bit, returning them and their byte length. Decoding stops
+    /// at an entry that is incomplete or fails its checksum, i.e. was torn by a crash while
+    /// writing it, or that has another header bit, i.e. was written before the header. Trailing
+    /// partial entries are dropped.
+    pub(crate) fn entries(
+        existing: &[u8],
+        header_bit: bool,
+    ) -> Result<(Vec<Entry>, u64), HypercoreError> {
+        if existing.len() <= OplogSlot::Entries as usize {
+            return Ok((vec![], 0));
+        }
+        let all_entries = get_slices_checked(existing, OplogSlot::Entries as usize)?.1;
+        let mut entries_buff = all_entries;
+        let mut entries: Vec<Entry> = Vec::new();
+        // Byte length after each entry, and whether it is partial
+        let mut ends: Vec<(u64, bool)> = Vec::new();
+        loop {
+            let entry_outcome = match Self::validate_leader(entries_buff) {
+                Ok(Some(entry_outcome)) if entry_outcome.header_bit == header_bit => entry_outcome,
+                Ok(_) | Err(HypercoreError::InvalidChecksum { .. }) => break,
+                Err(err) => return Err(err),
+            };
+            let res = Entry::decode(entry_outcome.state)?;
+            entries.push(res.0);
+            entries_buff = res.1;
+            ends.push((
+                (all_entries.len() - entries_buff.len()) as u64,
+                entry_outcome.partial_bit,
+            ));
+        }
+
+        // Remove all trailing partial entries
+        while ends.last().is_some_and(|(_, partial)| *partial) {
+            ends.pop();
+            entries.pop();
+        }
+        Ok((entries, ends.last().map_or(0, |(end, _)| *end)))
+    }
+
+    /// Read the public key from the header of the oplog in `storage`, without opening the rest of
+    /// the hypercore
+    #[cfg(all(feature = "corestore", not(target_arch = "wasm32")))]
+    pub(crate) async fn read_public_key<T: StorageTraits + Send + ?Sized>(
+        storage: &Storage<T>,
+    ) -> Result<VerifyingKey, HypercoreError> {
+        let info = storage
+            .read_info(StoreInfoInstruction::new_all_content(Store::Oplog))
+            .await?;
+        match Self::open(&None, &None, Some(info))? {
+            Either::Right(outcome) => Ok(outcome.header.key_pair.public),
+            Either::Left(_) => Err(HypercoreError::InvalidOperation {
+                context: "Could not open oplog".to_string(),
+            }),
+        }
+    }
+
+    /// Appends an upgraded changeset to the Oplog, to commit once flushed.
     pub(crate) fn append_changeset(
         &mut self,
         changeset: &MerkleTreeChangeset,
         bitfield_update: Option<BitfieldUpdate>,
         atomic: bool,
         header: &Header,
-    ) -> Result<OplogCreateHeaderOutcome, HypercoreError> {
+    ) -> Result<OplogAppendOutcome, HypercoreError> {
         let mut header: Header = header.clone();
         let entry = self.update_header_with_changeset(changeset, bitfield_update, &mut header)?;
+        let write = self.append_entries(&[entry], atomic)?;
 
-        Ok(OplogCreateHeaderOutcome {
-            header,
-            infos_to_flush: self.append_entries(&[entry], atomic)?,
-        })
+        Ok(OplogAppendOutcome { header, write })
+    }
+
+    /// Apply the state of a flushed [`OplogWrite`]
+    // Taking the commit by value consumes it, so that it's applied only once
+    #[allow(clippy::needless_pass_by_value)]
+    pub(crate) fn commit(&mut self, commit: OplogCommit) {
+        self.header_bits = commit.header_bits;
+        self.entries_length = commit.entries_length;
+        self.entries_byte_length = commit.entries_byte_length;
     }
 
     pub(crate) fn update_header_with_changeset(
@@ -226,6 +362,7 @@ impl Oplog {
             header.tree.root_hash = hash.clone();
             header.tree.signature = signature.clone();
             header.tree.length = changeset.length;
+            header.tree.fork = changeset.fork;
 
             Entry {
                 user_data: vec![],
@@ -249,12 +386,8 @@ impl Oplog {
         Ok(entry)
     }
 
-    /// Clears a segment, returns infos to write to storage.
-    pub(crate) fn clear(
-        &mut self,
-        start: u64,
-        end: u64,
-    ) -> Result<Box<[StoreInfo]>, HypercoreError> {
+    /// Clears a segment, returns the write to flush to storage and commit.
+    pub(crate) fn clear(&mut self, start: u64, end: u64) -> Result<OplogWrite, HypercoreError> {
         let entry: Entry = Entry {
             user_data: vec![],
             tree_nodes: vec![],
@@ -270,29 +403,36 @@ impl Oplog {
 
     /// Flushes pending changes, returns infos to write to storage.
     pub(crate) fn flush(
-        &mut self,
+        &self,
         header: &Header,
         clear_traces: bool,
-    ) -> Result<Box<[StoreInfo]>, HypercoreError> {
-        let (new_header_bits, infos_to_flush) = if clear_traces {
-            // When clearing traces, both slots need to be cleared, hence
-            // do this twice, but for the first time, ignore the truncate
-            // store info, to end up with three StoreInfos.
-            let (new_header_bits, infos_to_flush) =
-                Self::insert_header(header, 0, self.header_bits, clear_traces)?;
-            let mut combined_infos_to_flush: Vec<StoreInfo> =
-                infos_to_flush.into_vec().drain(0..1).collect();
+    ) -> Result<OplogFlushOutcome, HypercoreError> {
+        // When clearing traces, both slots need to be cleared, hence write the header twice
+        let slots = if clear_traces { 2 } else { 1 };
+        let mut header_bits = self.header_bits;
+        let mut headers = Vec::with_capacity(slots);
+        let mut truncate = None;
+        for _ in 0..slots {
             let (new_header_bits, infos_to_flush) =
-                Self::insert_header(header, 0, new_header_bits, clear_traces)?;
-            combined_infos_to_flush.extend(infos_to_flush.into_vec());
-            (new_header_bits, combined_infos_to_flush.into_boxed_slice())
-        } else {
-            Self::insert_header(header, 0, self.header_bits, clear_traces)?
-        };
-        self.entries_byte_length = 0;
-        self.entries_length = 0;
-        self.header_bits = new_header_bits;
-        Ok(infos_to_flush)
+                Self::insert_header(header, 0, header_bits, clear_traces)?;
+            header_bits = new_header_bits;
+            let [info, truncate_info]: [StoreInfo; 2] = infos_to_flush
+                .into_vec()
+                .try_into()
+                .expect("Header is written and followed by a truncate");
+            let commit = OplogCommit {
+                header_bits,
+                entries_length: 0,
+                entries_byte_length: 0,
+            };
+            headers.push(OplogWrite {
+                infos: vec![info].into_boxed_slice(),
+                commit,
+            });
+            truncate = Some(truncate_info);
+        }
+        let truncate = truncate.expect("Header is written");
+        Ok(OplogFlushOutcome { headers, truncate })
     }
 
     /// Appends a batch of entries to the Oplog.
@@ -300,7 +440,7 @@ impl Oplog {
         &mut self,
         batch: &[Entry],
         atomic: bool,
-    ) -> Result<Box<[StoreInfo]>, HypercoreError> {
+    ) -> Result<OplogWrite, HypercoreError> {
         let len = batch.len();
         let header_bit = self.get_current_header_bit();
 
@@ -310,29 +450,41 @@ impl Oplog {
             size += e.encoded_size()?;
         }
 
-        let mut buffer = vec![0; size];
+        let mut buffer = self.pool.take_zeroed(size);
         let mut rest = buffer.as_mut_slice();
         for (i, entry) in batch.iter().enumerate() {
             let partial_bit: bool = atomic && i < len - 1;
             rest = encode_with_leader(entry, partial_bit, header_bit, rest)?;
         }
         let index = OplogSlot::Entries as u64 + self.entries_byte_length;
-        self.entries_length += len as u64;
-        self.entries_byte_length += size as u64;
+        let commit = OplogCommit {
+            header_bits: self.header_bits,
+            entries_length: self.entries_length + len as u64,
+            entries_byte_length: self.entries_byte_length + size as u64,
+        };
 
-        Ok(vec![StoreInfo::new_content(Store::Oplog, index, &buffer)].into_boxed_slice())
+        let info = StoreInfo::new_content(Store::Oplog, index, &buffer);
+        self.pool.put(buffer);
+        Ok(OplogWrite {
+            infos: vec![info].into_boxed_slice(),
+            commit,
+        })
     }
 
-    fn fresh(key_pair: PartialKeypair) -> Result<OplogOpenOutcome, HypercoreError> {
+    fn fresh(
+        key_pair: PartialKeypair,
+        manifest: Option<Manifest>,
+    ) -> Result<OplogOpenOutcome, HypercoreError> {
         let entries_length: u64 = 0;
         let entries_byte_length: u64 = 0;
-        let header = Header::new(key_pair);
+        let header = Header::new(key_pair, manifest);
         let (header_bits, infos_to_flush) =
             Self::insert_header(&header, entries_byte_length, INITIAL_HEADER_BITS, false)?;
         let oplog = Oplog {
             header_bits,
             entries_length,
             entries_byte_length,
+            pool: BufferPool::default(),
         };
         Ok(OplogOpenOutcome::from_create_header_outcome(
             oplog,
@@ -427,7 +579,7 @@ impl Oplog {
     }
 
     /// Gets the current header bit
-    fn get_current_header_bit(&self) -> bool {
+    pub(crate) fn get_current_header_bit(&self) -> bool {
         self.header_bits[0] != self.header_bits[1]
     }
 
@@ -0,0 +1,285 @@
+//! Object-safe versions of the hypercore traits. The methods of [`CoreInfo`], [`CoreMethods`] and
+//! [`ReplicationMethods`] return `impl Future`, so they can't be used as trait objects. The traits
+//! here return a [`BoxFuture`] instead, a `LocalBoxFuture` without the `send` feature, and are
+//! implemented for everything that implements the original traits. This allows storing different
+//! kinds of cores together, e.g. in a `Vec<Box<dyn DynCoreMethods>>`.
+//!
+//! The trait objects implement the original traits in turn, so they can be passed to code that
+//! is generic over them. Because of that, method calls on e.g. a `Box<dyn DynCoreMethods>` are
+//! ambiguous when both [`CoreMethods`] and [`DynCoreMethods`] are in scope, so import only one.
+use crate::{AppendOutcome, Info, PartialKeypair};
+#[cfg(feature = "send")]
+use futures::future::BoxFuture;
+#[cfg(not(feature = "send"))]
+use futures::future::LocalBoxFuture as BoxFuture;
+use hypercore_schema::{Proof, RequestBlock, RequestSeek, RequestUpgrade};
+use std::ops::Range;
+
+use super::{
+    CoreInfo, CoreMethods, CoreMethodsError, EventReceiver, MaybeSend, MaybeSync, Priority,
+    ReplicationMethods, ReplicationMethodsError,
+};
+
+/// Object-safe version of [`CoreInfo`]
+pub trait DynCoreInfo: MaybeSend + MaybeSync {
+    /// See [`CoreInfo::info`]
+    fn info(&self) -> BoxFuture<'_, Info>;
+    /// See [`CoreInfo::key_pair`]
+    fn key_pair(&self) -> BoxFuture<'_, PartialKeypair>;
+}
+
+/// Object-safe version of [`CoreMethods`]
+pub trait DynCoreMethods: DynCoreInfo {
+    /// See [`CoreMethods::has`]
+    fn has(&self, index: u64) -> BoxFuture<'_, bool>;
+    /// See [`CoreMethods::get`]
+    fn get(&self, index: u64) -> BoxFuture<'_, Result<Option<Vec<u8>>, CoreMethodsError>>;
+    /// See [`CoreMethods::append`]
+    fn append<'a>(
+        &'a self,
+        data: &'a [u8],
+    ) -> BoxFuture<'a, Result<AppendOutcome, CoreMethodsError>>;
+    /// See [`CoreMethods::append_batch`]
+    fn append_batch<'a>(
+        &'a self,
+        batch: &'a [&'a [u8]],
+    ) -> BoxFuture<'a, Result<AppendOutcome, CoreMethodsError>>;
+    /// See [`CoreMethods::clear`]
+    fn clear(&self, start: u64, end: u64) -> BoxFuture<'_, Result<(), CoreMethodsError>>;
+    /// See [`CoreMethods::seek`]
+    fn seek(&self, byte_offset: u64) -> BoxFuture<'_, Result<(u64, u64), CoreMethodsError>>;
+}
+
+/// Object-safe version of [`ReplicationMethods`]
+pub trait DynReplicationMethods: DynCoreInfo {
+    /// See [`ReplicationMethods::verify_and_apply_proof`]
+    fn verify_and_apply_proof<'a>(
+        &'a self,
+        proof: &'a Proof,
+    ) -> BoxFuture<'a, Result<bool, ReplicationMethodsError>>;
+    /// See [`ReplicationMethods::verify_and_apply_proofs`]
+    fn verify_and_apply_proofs<'a>(
+        &'a self,
+        proofs: &'a [Proof],
+    ) -> BoxFuture<'a, Result<Vec<bool>, ReplicationMethodsError>>;
+    /// See [`ReplicationMethods::missing_nodes`]
+    fn missing_nodes(&self, index: u64) -> BoxFuture<'_, Result<u64, ReplicationMethodsError>>;
+    /// See [`ReplicationMethods::missing_nodes_range`]
+    fn missing_nodes_range(
+        &self,
+        range: Range<u64>,
+    ) -> BoxFuture<'_, Result<Vec<u64>, ReplicationMethodsError>>;
+    /// See [`ReplicationMethods::create_proof`]
+    fn create_proof(
+        &self,
+        block: Option<RequestBlock>,
+        hash: Option<RequestBlock>,
+        seek: Option<RequestSeek>,
+        upgrade: Option<RequestUpgrade>,
+    ) -> BoxFuture<'_, Result<Option<Proof>, ReplicationMethodsError>>;
+    /// See [`ReplicationMethods::event_subscribe`]
+    fn event_subscribe(&self) -> BoxFuture<'_, EventReceiver>;
+    /// See [`ReplicationMethods::want`]
+    fn want(&self, range: Range<u64>, priority: Priority) -> BoxFuture<'_, ()>;
+    /// See [`ReplicationMethods::unwant`]
+    fn unwant(&self, range: Range<u64>) -> BoxFuture<'_, bool>;
+    /// See [`ReplicationMethods::wants`]
+    fn wants(&self) -> BoxFuture<'_, Vec<(Range<u64>, Priority)>>;
+}
+
+impl<T: CoreInfo + MaybeSend + MaybeSync> DynCoreInfo for T {
+    fn info(&self) -> BoxFuture<'_, Info> {
+        Box::pin(CoreInfo::info(self))
+    }
+
+    fn key_pair(&self) -> BoxFuture<'_, PartialKeypair> {
+        Box::pin(CoreInfo::key_pair(self))
+    }
+}
+
+impl<T: CoreMethods + MaybeSend + MaybeSync> DynCoreMethods for T {
+    fn has(&self, index: u64) -> BoxFuture<'_, bool> {
+        Box::pin(CoreMethods::has(self, index))
+    }
+
+    fn get(&self, index: u64) -> BoxFuture<'_, Result<Option<Vec<u8>>, CoreMethodsError>> {
+        Box::pin(CoreMethods::get(self, index))
+    }
+
+    fn append<'a>(
+        &'a self,
+        data: &'a [u8],
+    ) -> BoxFuture<'a, Result<AppendOutcome, CoreMethodsError>> {
+        Box::pin(CoreMethods::append(self, data))
+    }
+
+    fn append_batch<'a>(
+        &'a self,
+        batch: &'a [&'a [u8]],
+    ) -> BoxFuture<'a, Result<AppendOutcome, CoreMethodsError>> {
+        Box::pin(CoreMethods::append_batch(self, batch))
+    }
+
+    fn clear(&self, start: u64, end: u64) -> BoxFuture<'_, Result<(), CoreMethodsError>> {
+        Box::pin(CoreMethods::clear(self, start, end))
+    }
+
+    fn seek(&self, byte_offset: u64) -> BoxFuture<'_, Result<(u64, u64), CoreMethodsError>> {
+        Box::pin(CoreMethods::seek(self, byte_offset))
+    }
+}
+
+impl<T: ReplicationMethods + MaybeSync> DynReplicationMethods for T {
+    fn verify_and_apply_proof<'a>(
+        &'a self,
+        proof: &'a Proof,
+    ) -> BoxFuture<'a, Result<bool, ReplicationMethodsError>> {
+        Box::pin(ReplicationMethods::verify_and_apply_proof(self, proof))
+    }
+
+    fn verify_and_apply_proofs<'a>(
+        &'a self,
+        proofs: &'a [Proof],
+    ) -> BoxFuture<'a, Result<Vec<bool>, ReplicationMethodsError>> {
+        Box::pin(ReplicationMethods::verify_and_apply_proofs(self, proofs))
+    }
+
+    fn missing_nodes(&self, index: u64) -> BoxFuture<'_, Result<u64, ReplicationMethodsError>> {
+        Box::pin(ReplicationMethods::missing_nodes(self, index))
+    }
+
+    fn missing_nodes_range(
+        &self,
+        range: Range<u64>,
+    ) -> BoxFuture<'_, Result<Vec<u64>, ReplicationMethodsError>> {
+        Box::pin(ReplicationMethods::missing_nodes_range(self, range))
+    }
+
+    fn create_proof(
+        &self,
+        block: Option<RequestBlock>,
+        hash: Option<RequestBlock>,
+        seek: Option<RequestSeek>,
+        upgrade: Option<RequestUpgrade>,
+    ) -> BoxFuture<'_, Result<Option<Proof>, ReplicationMethodsError>> {
+        Box::pin(ReplicationMethods::create_proof(
+            self, block, hash, seek, upgrade,
+        ))
+    }
+
+    fn event_subscribe(&self) -> BoxFuture<'_, EventReceiver> {
+        Box::pin(ReplicationMethods::event_subscribe(self))
+    }
+
+    fn want(&self, range: Range<u64>, priority: Priority) -> BoxFuture<'_, ()> {
+        Box::pin(ReplicationMethods::want(self, range, priority))
+    }
+
+    fn unwant(&self, range: Range<u64>) -> BoxFuture<'_, bool> {
+        Box::pin(ReplicationMethods::unwant(self, range))
+    }
+
+    fn wants(&self) -> BoxFuture<'_, Vec<(Range<u64>, Priority)>> {
+        Box::pin(ReplicationMethods::wants(self))
+    }
+}
+
+impl CoreInfo for dyn DynCoreMethods + '_ {
+    async fn info(&self) -> Info {
+        DynCoreInfo::info(self).await
+    }
+
+    async fn key_pair(&self) -> PartialKeypair {
+        DynCoreInfo::key_pair(self).await
+    }
+}
+
+impl CoreMethods for dyn DynCoreMethods + '_ {
+    async fn has(&self, index: u64) -> bool {
+        DynCoreMethods::has(self, index).await
+    }
+
+    async fn get(&self, index: u64) -> Result<Option<Vec<u8>>, CoreMethodsError> {
+        DynCoreMethods::get(self, index).await
+    }
+
+    async fn append(&self, data: &[u8]) -> Result<AppendOutcome, CoreMethodsError> {
+        DynCoreMethods::append(self, data).await
+    }
+
+    async fn append_batch<A: AsRef<[u8]>, B: AsRef<[A]> + MaybeSend>(
+        &self,
+        batch: B,
+    ) -> Result<AppendOutcome, CoreMethodsError> {
+        let batch: Vec<&[u8]> = batch.as_ref().iter().map(|data| data.as_ref()).collect();
+        DynCoreMethods::append_batch(self, &batch).await
+    }
+
+    async fn clear(&self, start: u64, end: u64) -> Result<(), CoreMethodsError> {
+        DynCoreMethods::clear(self, start, end).await
+    }
+
+    async fn seek(&self, byte_offset: u64) -> Result<(u64, u64), CoreMethodsError> {
+        DynCoreMethods::seek(self, byte_offset).await
+    }
+}
+
+impl CoreInfo for dyn DynReplicationMethods + '_ {
+    async fn info(&self) -> Info {
+        DynCoreInfo::info(self).await
+    }
+
+    async fn key_pair(&self) -> PartialKeypair {
+        DynCoreInfo::key_pair(self).await
+    }
+}
+
+impl ReplicationMethods for dyn DynReplicationMethods + '_ {
+    async fn verify_and_apply_proof(&self, proof: &Proof) -> Result<bool, ReplicationMethodsError> {
+        DynReplicationMethods::verify_and_apply_proof(self, proof).await
+    }
+
+    async fn verify_and_apply_proofs(
+        &self,
+        proofs: &[Proof],
+    ) -> Result<Vec<bool>, ReplicationMethodsError> {
+        DynReplicationMethods::verify_and_apply_proofs(self, proofs).await
+    }
+
+    async fn missing_nodes(&self, index: u64) -> Result<u64, ReplicationMethodsError> {
+        DynReplicationMethods::missing_nodes(self, index).await
+    }
+
+    async fn missing_nodes_range(
+        &self,
+        range: Range<u64>,
+    ) -> Result<Vec<u64>, ReplicationMethodsError> {
+        DynReplicationMethods::missing_nodes_range(self, range).await
+    }
+
+    async fn create_proof(
+        &self,
+        block: Option<RequestBlock>,
+        hash: Option<RequestBlock>,
+        seek: Option<RequestSeek>,
+        upgrade: Option<RequestUpgrade>,
+    ) -> Result<Option<Proof>, ReplicationMethodsError> {
+        DynReplicationMethods::create_proof(self, block, hash, seek, upgrade).await
+    }
+
+    async fn event_subscribe(&self) -> EventReceiver {
+        DynReplicationMethods::event_subscribe(self).await
+    }
+
+    async fn want(&self, range: Range<u64>, priority: Priority) {
+        DynReplicationMethods::want(self, range, priority).await
+    }
+
+    async fn unwant(&self, range: Range<u64>) -> bool {
+        DynReplicationMethods::unwant(self, range).await
+    }
+
+    async fn wants(&self) -> Vec<(Range<u64>, Priority)> {
+        DynReplicationMethods::wants(self).await
+    }
+}
@@ -1,8 +1,80 @@
 //! events related to replication
-use crate::{HypercoreError, common::BitfieldUpdate};
-use async_broadcast::{InactiveReceiver, Receiver, Sender, broadcast};
+use crate::{
+    Equivocation, HypercoreError, Quota,
+    common::{BitfieldUpdate, metrics},
+};
+use async_broadcast::{InactiveReceiver, Receiver, RecvError, Sender, broadcast};
+use futures::Stream;
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::task::{Context, Poll, ready};
 
-static MAX_EVENT_QUEUE_CAPACITY: usize = 32;
+pub(crate) const DEFAULT_EVENT_QUEUE_CAPACITY: usize = 32;
+
+/// Receiver of the [`Event`]s of a core, returned by `event_subscribe`. The channel doesn't depend
+/// on an executor, so it works with any runtime and on wasm.
+pub type EventReceiver = Receiver<Event>;
+
+/// Stream of the [`Event`]s of a core, returned by [`crate::Hypercore::event_stream`]. When the
+/// stream is read slower than events are emitted, the oldest events are dropped. The stream then
+/// yields an [`EventsDropped`] event, and counts the dropped events in [`EventStream::missed`].
+#[derive(Debug)]
+pub struct EventStream {
+    receiver: EventReceiver,
+    missed: u64,
+}
+
+impl EventStream {
+    /// Number of events dropped because the stream lagged behind
+    pub fn missed(&self) -> u64 {
+        self.missed
+    }
+}
+
+impl From<EventReceiver> for EventStream {
+    fn from(receiver: EventReceiver) -> Self {
+        Self {
+            receiver,
+            missed: 0,
+        }
+    }
+}
+
+impl Stream for EventStream {
+    type Item = Event;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Event>> {
+        Poll::Ready(match ready!(Pin::new(&mut self.receiver).poll_recv(cx)) {
+            Some(Ok(event)) => Some(event),
+            Some(Err(RecvError::Overflowed(count))) => {
+                self.missed += count;
+                Some(EventsDropped { count }.into())
+            }
+            Some(Err(RecvError::Closed)) | None => None,
+        })
+    }
+}
+
+/// Priority of the blocks of a [`Get`] or a [`Want`]. Replicators request the blocks of higher
+/// priorities first, e.g. the block a reader seeks to before a range downloaded in the background.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde")
+)]
+pub enum Priority {
+    /// Downloaded when nothing else is
+    Background,
+    /// The priority of blocks read with [`crate::Hypercore::get`]
+    #[default]
+    Normal,
+    /// Needed right now
+    High,
+}
 
 /// Event emitted by [`crate::Hypercore::event_subscribe`]
 #[derive(Debug, Clone)]
@@ -10,50 +82,263 @@ static MAX_EVENT_QUEUE_CAPACITY: usize = 32;
 pub struct Get {
     /// Index of the requested block
     pub index: u64,
-    /// When the block is gotten this emits an event
+    /// Priority of the block
+    pub priority: Priority,
+    /// When the block is gotten this emits an event. The core emits it when the block is
+    /// appended or verified, replicators may also emit it after supplying the block.
     pub get_result: Sender<()>,
 }
 
 /// Emitted when
 #[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde")
+)]
 pub struct DataUpgrade {}
 
+/// Emitted when a proof with a fork truncates the core before applying its upgrade
+#[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde")
+)]
+pub struct Truncate {
+    /// Length of the core before the truncation
+    pub old_length: u64,
+    /// Length the core was truncated to
+    pub new_length: u64,
+    /// Fork of the core after the truncation
+    pub fork: u64,
+}
+
+/// Emitted when [`crate::Hypercore::verify_and_apply_proof`] is given a proof of a newer fork, e.g.
+/// after the writer truncated the core. The proof isn't applied nor verified, a proof of the fork
+/// from the remote can be given to [`crate::Hypercore::reorg`] to move the core onto it.
+#[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde")
+)]
+pub struct ForkDetected {
+    /// Fork of the remote core
+    pub remote_fork: u64,
+    /// Length of the remote core on its fork
+    pub remote_length: u64,
+}
+
+/// Emitted when the changes to the core have been flushed to storage with
+/// [`crate::Hypercore::flush`]
+#[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde")
+)]
+pub struct Flush {
+    /// Length of the core that was flushed
+    pub length: u64,
+}
+
+/// Emitted when the core is closed, i.e. dropped
+#[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde")
+)]
+pub struct Close {}
+
+/// Emitted when a replicator announces that a remote peer has blocks, see
+/// [`crate::Hypercore::peer_have`]
+#[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde")
+)]
+pub struct PeerHave {
+    /// Starting index of the blocks the peer has
+    pub start: u64,
+    /// The number of blocks
+    pub length: u64,
+}
+
+/// Emitted when interest in a range of blocks is registered with [`crate::Hypercore::want`]
+#[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde")
+)]
+pub struct Want {
+    /// Starting index of the wanted blocks
+    pub start: u64,
+    /// The number of blocks
+    pub length: u64,
+    /// Priority of the blocks
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub priority: Priority,
+}
+
+/// Emitted when interest in a range of blocks is withdrawn with [`crate::Hypercore::unwant`]
+#[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde")
+)]
+pub struct Unwant {
+    /// Starting index of the blocks no longer wanted
+    pub start: u64,
+    /// The number of blocks
+    pub length: u64,
+}
+
+/// Emitted by [`EventStream`] when it lagged behind and the oldest events were dropped. After
+/// this, replicators should resync their view of the core, e.g. with [`crate::Hypercore::info`].
+#[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde")
+)]
+pub struct EventsDropped {
+    /// Number of dropped events
+    pub count: u64,
+}
+
+/// Emitted when the length or byte length of the core reaches the warning percentage of its
+/// [`Quota`]
+#[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde")
+)]
+pub struct QuotaWarning {
+    /// Length of the core
+    pub length: u64,
+    /// Byte length of the core
+    pub byte_length: u64,
+    /// Quota of the core
+    pub quota: Quota,
+}
+
+/// Emitted when [`crate::Hypercore::scrub`] finds a stored block that no longer matches the tree
+/// and clears it
+#[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde")
+)]
+pub struct CorruptBlock {
+    /// Index of the corrupt block
+    pub index: u64,
+}
+
+/// Emitted when a proof buffered by [`crate::Hypercore::verify_and_apply_proof`] until the upgrade
+/// it needed fails once the upgrade is applied. The proof is dropped.
+#[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde")
+)]
+pub struct ProofRejected {
+    /// Fork of the proof
+    pub fork: u64,
+    /// Index of the block of the proof, if any
+    pub block: Option<u64>,
+    /// Error of the proof
+    pub error: String,
+}
+
 /// Emitted when core gets new blocks
 #[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde")
+)]
 pub struct Have {
     /// Starting index of the blocks we have
     pub start: u64,
     /// The number of blocks
     pub length: u64,
+    /// Byte length of the blocks
+    pub byte_length: u64,
+    /// Whether the data of the blocks is stored locally. False when the blocks are dropped, or
+    /// only added to the tree, e.g. by a light hypercore or a persistence policy.
+    pub stored: bool,
     /// TODO
     pub drop: bool,
 }
 
-impl From<&BitfieldUpdate> for Have {
-    fn from(
+impl Have {
+    pub(crate) fn new(
         BitfieldUpdate {
             start,
             length,
             drop,
         }: &BitfieldUpdate,
+        byte_length: u64,
+        stored: bool,
     ) -> Self {
         Have {
             start: *start,
             length: *length,
+            byte_length,
+            stored: stored && !*drop,
             drop: *drop,
         }
     }
 }
 
 #[derive(Debug, Clone)]
-/// Core events relevant to replication
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde")
+)]
+/// Core events relevant to replication. With the `serde` feature, all events but [`Event::Get`],
+/// which holds a channel, can be serialized.
 pub enum Event {
     /// Emmited when core.get(i) happens for a missing block
+    #[cfg_attr(feature = "serde", serde(skip))]
     Get(Get),
     /// Emmitted when data.upgrade applied
     DataUpgrade(DataUpgrade),
     /// Emmitted when core gets new blocks
     Have(Have),
+    /// Emitted when events were dropped
+    EventsDropped(EventsDropped),
+    /// Emitted when the core is truncated
+    Truncate(Truncate),
+    /// Emitted when a proof of a newer fork is received
+    ForkDetected(ForkDetected),
+    /// Emitted when the writer is found to have signed two conflicting trees on the same fork
+    Equivocation(Equivocation),
+    /// Emitted when the core is flushed to storage
+    Flush(Flush),
+    /// Emitted when the core is closed
+    Close(Close),
+    /// Emitted when a remote peer has new blocks
+    PeerHave(PeerHave),
+    /// Emitted when a range of blocks is wanted
+    Want(Want),
+    /// Emitted when a range of blocks is no longer wanted
+    Unwant(Unwant),
+    /// Emitted when the core reaches the warning percentage of its quota
+    QuotaWarning(QuotaWarning),
+    /// Emitted when scrubbing clears a corrupt block
+    CorruptBlock(CorruptBlock),
+    /// Emitted when a buffered proof fails
+    ProofRejected(ProofRejected),
 }
 
 /// Derive From<msg> for Enum where enum variant and msg have the same name
@@ -70,6 +355,18 @@ macro_rules! impl_from_for_enum_variant {
 impl_from_for_enum_variant!(Event, Get);
 impl_from_for_enum_variant!(Event, DataUpgrade);
 impl_from_for_enum_variant!(Event, Have);
+impl_from_for_enum_variant!(Event, EventsDropped);
+impl_from_for_enum_variant!(Event, Truncate);
+impl_from_for_enum_variant!(Event, ForkDetected);
+impl_from_for_enum_variant!(Event, Equivocation);
+impl_from_for_enum_variant!(Event, Flush);
+impl_from_for_enum_variant!(Event, Close);
+impl_from_for_enum_variant!(Event, PeerHave);
+impl_from_for_enum_variant!(Event, Want);
+impl_from_for_enum_variant!(Event, Unwant);
+impl_from_for_enum_variant!(Event, QuotaWarning);
+impl_from_for_enum_variant!(Event, CorruptBlock);
+impl_from_for_enum_variant!(Event, ProofRejected);
 
 #[derive(Debug)]
 pub(crate) struct Events {
@@ -77,40 +374,120 @@ pub(crate) struct Events {
     pub(crate) channel: Sender<Event>,
     /// Kept around so `Events::channel` stays open.
     _receiver: InactiveReceiver<Event>,
+    /// Senders of [`Get::get_result`] by block index, notified when the block arrives
+    waiters: Mutex<BTreeMap<u64, Vec<Sender<()>>>>,
+    /// Number of events dropped from the channel
+    dropped: AtomicU64,
+    /// Events held back until the changes they announce are written, see [`Events::hold`]
+    held: Mutex<Option<Vec<Event>>>,
 }
 
 impl Events {
-    pub(crate) fn new() -> Self {
-        let (mut channel, receiver) = broadcast(MAX_EVENT_QUEUE_CAPACITY);
+    pub(crate) fn new(capacity: usize) -> Self {
+        let (mut channel, receiver) = broadcast(capacity);
         channel.set_await_active(false);
         let mut _receiver = receiver.deactivate();
         // Message sending is best effort. Is msg queue fills up, remove old messages to make place
         // for new ones.
         _receiver.set_overflow(true);
-        Self { channel, _receiver }
+        Self {
+            channel,
+            _receiver,
+            waiters: Mutex::new(BTreeMap::new()),
+            dropped: AtomicU64::new(0),
+            held: Mutex::new(None),
+        }
+    }
+
+    /// Hold back the events sent from now on, until they are released or discarded
+    pub(crate) fn hold(&self) {
+        *self.held.lock().expect("Events held poisoned") = Some(vec![]);
+    }
+
+    /// Send the held events
+    pub(crate) fn release(&self) {
+        let held = self.held.lock().expect("Events held poisoned").take();
+        for evt in held.into_iter().flatten() {
+            let _ = self.send(evt);
+        }
+    }
+
+    /// Forget the held events, whose changes were reverted
+    pub(crate) fn discard(&self) {
+        self.held.lock().expect("Events held poisoned").take();
     }
 
     /// The internal channel errors on send when no replicators are subscribed,
     /// For now we don't consider that an error, but just in case, we return a Result in case
     /// we want to change this or add another fail path later.
     pub(crate) fn send<T: Into<Event>>(&self, evt: T) -> Result<(), HypercoreError> {
-        let _errs_when_no_replicators_subscribed = self.channel.try_broadcast(evt.into());
+        let evt = evt.into();
+        if let Some(held) = self.held.lock().expect("Events held poisoned").as_mut() {
+            held.push(evt);
+            return Ok(());
+        }
+        if let Event::Have(Have {
+            start,
+            length,
+            stored: true,
+            ..
+        }) = &evt
+        {
+            self.notify_waiters(*start..*start + *length);
+        }
+        if let Ok(Some(_dropped)) = self.channel.try_broadcast(evt) {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+        metrics::event_queue_depth(self.channel.len());
         Ok(())
     }
 
+    /// Number of events dropped from the channel because it was full
+    pub(crate) fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Notify the waiters of the blocks in `range`
+    fn notify_waiters(&self, range: Range<u64>) {
+        let mut waiters = self.waiters.lock().expect("Events waiters poisoned");
+        let indexes: Vec<u64> = waiters.range(range).map(|(index, _)| *index).collect();
+        for index in indexes {
+            for get_result in waiters.remove(&index).into_iter().flatten() {
+                let _ = get_result.try_broadcast(());
+            }
+        }
+    }
+
     /// Send a [`Get`] messages and return [`Receiver`] that will receive a message when block is
     /// gotten.
-    pub(crate) fn send_on_get(&self, index: u64) -> Receiver<()> {
+    pub(crate) fn send_on_get(&self, index: u64, priority: Priority) -> Receiver<()> {
         let (mut tx, rx) = broadcast(1);
         tx.set_await_active(false);
+        {
+            let mut waiters = self.waiters.lock().expect("Events waiters poisoned");
+            // Forget waiters that gave up
+            waiters.retain(|_, senders| {
+                senders.retain(|sender| sender.receiver_count() > 0);
+                !senders.is_empty()
+            });
+            waiters.entry(index).or_default().push(tx.clone());
+        }
         let _ = self.send(Get {
             index,
+            priority,
             get_result: tx,
         });
         rx
     }
 }
 
+impl Drop for Events {
+    fn drop(&mut self) {
+        self.discard();
+        let _ = self.send(Close {});
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -142,6 +519,8 @@ mod test {
             Event::Have(Have {
                 start: 0,
                 length: 1,
+                byte_length: 3,
+                stored: true,
                 drop: false
             })
         ));
@@ -167,6 +546,7 @@ mod test {
             res[0],
             Event::Get(Get {
                 index: 1,
+                priority: Priority::Normal,
                 get_result: _
             })
         ));
@@ -174,4 +554,94 @@ mod test {
         assert!(rx.is_empty());
         Ok(())
     }
+
+    #[async_std::test]
+    async fn test_event_stream() -> Result<(), CoreMethodsError> {
+        use futures::StreamExt;
+
+        let mut core = crate::core::tests::create_hypercore_with_data(0).await?;
+        let mut stream = core.event_stream();
+        core.append(b"foo").await?;
+        assert!(matches!(stream.next().await, Some(Event::DataUpgrade(_))));
+        assert!(matches!(
+            stream.next().await,
+            Some(Event::Have(Have { start: 0, .. }))
+        ));
+
+        // Lagging behind drops the oldest events
+        for _ in 0..DEFAULT_EVENT_QUEUE_CAPACITY {
+            core.append(b"bar").await?;
+        }
+        let capacity = DEFAULT_EVENT_QUEUE_CAPACITY as u64;
+        assert!(matches!(
+            stream.next().await,
+            Some(Event::EventsDropped(EventsDropped { count })) if count == capacity
+        ));
+        assert!(matches!(stream.next().await, Some(Event::DataUpgrade(_))));
+        assert_eq!(stream.missed(), capacity);
+        assert_eq!(core.events_dropped(), capacity);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_event_queue_capacity() -> Result<(), CoreMethodsError> {
+        use crate::{HypercoreBuilder, Storage};
+
+        let mut core = HypercoreBuilder::new(Storage::new_memory().await?)
+            .event_queue_capacity(2)
+            .build()
+            .await?;
+        core.append(b"foo").await?;
+        assert_eq!(
+            core.events_dropped(),
+            0,
+            "No events queued without subscribers"
+        );
+        let mut rx = core.event_subscribe();
+        core.append_batch([b"foo", b"bar"]).await?;
+        assert_eq!(core.events_dropped(), 0);
+        core.append(b"baz").await?;
+        assert_eq!(core.events_dropped(), 2);
+        assert!(matches!(rx.recv().await, Err(RecvError::Overflowed(2))));
+
+        assert!(
+            HypercoreBuilder::new(Storage::new_memory().await?)
+                .event_queue_capacity(0)
+                .build()
+                .await
+                .is_err()
+        );
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_flush_peer_have_and_close_events() -> Result<(), CoreMethodsError> {
+        let mut core = crate::core::tests::create_hypercore_with_data(0).await?;
+        let mut rx = core.event_subscribe();
+        core.append(b"foo").await?;
+        core.append(b"bar").await?;
+        assert!(matches!(rx.recv().await, Ok(Event::DataUpgrade(_))));
+        assert!(matches!(rx.recv().await, Ok(Event::Have(_))));
+        assert!(matches!(rx.recv().await, Ok(Event::DataUpgrade(_))));
+        assert!(matches!(rx.recv().await, Ok(Event::Have(_))));
+        core.flush().await?;
+        assert!(matches!(
+            rx.recv().await,
+            Ok(Event::Flush(Flush { length: 2 }))
+        ));
+
+        core.peer_have(3, 5);
+        assert!(matches!(
+            rx.recv().await,
+            Ok(Event::PeerHave(PeerHave {
+                start: 3,
+                length: 5
+            }))
+        ));
+
+        drop(core);
+        assert!(matches!(rx.recv().await, Ok(Event::Close(_))));
+        assert!(matches!(rx.recv().await, Err(RecvError::Closed)));
+        Ok(())
+    }
 }
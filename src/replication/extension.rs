@@ -0,0 +1,56 @@
+//! Named protocol extensions, for sending application messages alongside replication
+use futures::StreamExt;
+use futures::channel::mpsc::{UnboundedReceiver, UnboundedSender};
+
+use super::messages::Extension;
+
+/// Handle of an extension registered with [`super::Peer::register_extension`]. Messages sent
+/// with it are queued as [`Extension`] messages of the peer, and [`Extension`] messages received
+/// by the peer with the extension's name are delivered to it. The payloads are opaque to the
+/// peer.
+#[derive(Debug)]
+pub struct PeerExtension {
+    name: String,
+    outgoing: UnboundedSender<Extension>,
+    incoming: UnboundedReceiver<Vec<u8>>,
+}
+
+impl PeerExtension {
+    pub(crate) fn new(
+        name: String,
+        outgoing: UnboundedSender<Extension>,
+        incoming: UnboundedReceiver<Vec<u8>>,
+    ) -> Self {
+        Self {
+            name,
+            outgoing,
+            incoming,
+        }
+    }
+
+    /// Name of the extension
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Send a message to the remote. Returns false if the peer was dropped.
+    pub fn send(&self, message: Vec<u8>) -> bool {
+        self.outgoing
+            .unbounded_send(Extension {
+                name: self.name.clone(),
+                message,
+            })
+            .is_ok()
+    }
+
+    /// Receive the next message from the remote. Returns `None` once the peer was dropped, or the
+    /// extension registered again.
+    pub async fn recv(&mut self) -> Option<Vec<u8>> {
+        self.incoming.next().await
+    }
+
+    /// Like [`PeerExtension::recv`], but returns `None` right away if no message is queued
+    pub fn try_recv(&mut self) -> Option<Vec<u8>> {
+        self.incoming.try_recv().ok()
+    }
+}
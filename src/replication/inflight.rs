@@ -0,0 +1,256 @@
+//! Tracking of the requests sent to a remote peer
+use async_broadcast::Sender;
+use hypercore_schema::{RequestBlock, RequestUpgrade};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use super::messages::Request;
+
+/// Default time after which an unanswered request is reissued
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// Default number of times a timed out request is reissued before giving up on it
+pub const DEFAULT_REQUEST_RETRIES: u32 = 3;
+
+#[derive(Debug)]
+struct InflightRequest {
+    request: Request,
+    /// Set when the request is first seen by [`InflightRequests::poll_timeouts`]
+    sent_at: Option<Instant>,
+    retries: u32,
+    /// `get_result` senders of the [`super::events::Get`] events waiting for the block
+    waiters: Vec<Sender<()>>,
+}
+
+/// Outcome of a timed out request, from [`InflightRequests::poll_timeouts`]
+#[derive(Debug, Clone, PartialEq)]
+pub enum RequestTimeout {
+    /// The request was reissued with a new id, and should be sent again
+    Reissued(Request),
+    /// The request ran out of retries and was removed. Callers waiting for its block were woken
+    /// up to check for it again.
+    Failed(Request),
+}
+
+/// Requests sent to a remote and not yet answered. Assigns request ids, keeps a single request per
+/// block however many [`super::events::Get`] events ask for it, and reissues requests that go
+/// unanswered for too long.
+///
+/// It doesn't read the clock itself: the caller passes the current time to
+/// [`InflightRequests::poll_timeouts`], and a request's timeout starts at the first call after it
+/// was added. The timeout is thus only as precise as the interval between calls.
+#[derive(Debug)]
+pub struct InflightRequests {
+    next_id: u64,
+    timeout: Duration,
+    max_retries: u32,
+    requests: HashMap<u64, InflightRequest>,
+    /// Request id by block index
+    blocks: HashMap<u64, u64>,
+}
+
+impl Default for InflightRequests {
+    fn default() -> Self {
+        Self::new(DEFAULT_REQUEST_TIMEOUT, DEFAULT_REQUEST_RETRIES)
+    }
+}
+
+impl InflightRequests {
+    /// Create a tracker that reissues requests unanswered after `timeout`, at most `max_retries`
+    /// times
+    pub fn new(timeout: Duration, max_retries: u32) -> Self {
+        Self {
+            next_id: 1,
+            timeout,
+            max_retries,
+            requests: HashMap::new(),
+            blocks: HashMap::new(),
+        }
+    }
+
+    /// Add a request for a block, an upgrade or both, and return it with its assigned id. If the
+    /// block is already requested,
+    /// `waiter` is added to the existing request and `None` is returned.
+    pub fn add(
+        &mut self,
+        fork: u64,
+        block: Option<RequestBlock>,
+        upgrade: Option<RequestUpgrade>,
+        waiter: Option<Sender<()>>,
+    ) -> Option<Request> {
+        if let Some(block) = &block
+            && let Some(id) = self.blocks.get(&block.index)
+        {
+            let inflight = self.requests.get_mut(id).expect("Block request is tracked");
+            inflight.waiters.extend(waiter);
+            return None;
+        }
+        Some(self.add_request(fork, block, upgrade, waiter.into_iter().collect()))
+    }
+
+    fn add_request(
+        &mut self,
+        fork: u64,
+        block: Option<RequestBlock>,
+        upgrade: Option<RequestUpgrade>,
+        waiters: Vec<Sender<()>>,
+    ) -> Request {
+        let id = self.next_id;
+        self.next_id += 1;
+        let request = Request {
+            id,
+            fork,
+            block,
+            hash: None,
+            seek: None,
+            upgrade,
+        };
+        self.insert(request.clone(), None, 0, waiters);
+        request
+    }
+
+    fn insert(
+        &mut self,
+        request: Request,
+        sent_at: Option<Instant>,
+        retries: u32,
+        waiters: Vec<Sender<()>>,
+    ) {
+        if let Some(block) = &request.block {
+            self.blocks.insert(block.index, request.id);
+        }
+        self.requests.insert(
+            request.id,
+            InflightRequest {
+                request,
+                sent_at,
+                retries,
+                waiters,
+            },
+        );
+    }
+
+    /// Remove an answered request. Returns `None` for ids that aren't inflight, e.g. answers to
+    /// requests that were reissued.
+    pub fn remove(&mut self, id: u64) -> Option<Request> {
+        let inflight = self.requests.remove(&id)?;
+        if let Some(block) = &inflight.request.block {
+            self.blocks.remove(&block.index);
+        }
+        Some(inflight.request)
+    }
+
+    /// Whether the block at `index` is requested
+    pub fn has_block(&self, index: u64) -> bool {
+        self.blocks.contains_key(&index)
+    }
+
+    /// Whether an upgrade is requested
+    pub fn has_upgrade(&self) -> bool {
+        self.requests
+            .values()
+            .any(|inflight| inflight.request.upgrade.is_some())
+    }
+
+    /// Number of requests inflight
+    pub fn len(&self) -> usize {
+        self.requests.len()
+    }
+
+    /// Whether no requests are inflight
+    pub fn is_empty(&self) -> bool {
+        self.requests.is_empty()
+    }
+
+    /// Reissue the requests that were added at least `timeout` before `now`, or give up on them
+    /// when they are out of retries
+    pub fn poll_timeouts(&mut self, now: Instant) -> Vec<RequestTimeout> {
+        let mut timed_out: Vec<u64> = vec![];
+        for (id, inflight) in self.requests.iter_mut() {
+            let sent_at = *inflight.sent_at.get_or_insert(now);
+            if now.saturating_duration_since(sent_at) >= self.timeout {
+                timed_out.push(*id);
+            }
+        }
+        timed_out.sort_unstable();
+
+        let mut out = vec![];
+        for id in timed_out {
+            let mut inflight = self.requests.remove(&id).expect("Timed out request exists");
+            if let Some(block) = &inflight.request.block {
+                self.blocks.remove(&block.index);
+            }
+            if inflight.retries >= self.max_retries {
+                for waiter in inflight.waiters {
+                    let _ = waiter.try_broadcast(());
+                }
+                out.push(RequestTimeout::Failed(inflight.request));
+                continue;
+            }
+            inflight.request.id = self.next_id;
+            self.next_id += 1;
+            let request = inflight.request.clone();
+            self.insert(
+                inflight.request,
+                Some(now),
+                inflight.retries + 1,
+                inflight.waiters,
+            );
+            out.push(RequestTimeout::Reissued(request));
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_broadcast::broadcast;
+
+    #[test]
+    fn inflight_requests_deduplicate_blocks() {
+        let mut inflight = InflightRequests::default();
+        let block = RequestBlock { index: 3, nodes: 0 };
+        let request = inflight.add(0, Some(block.clone()), None, None).unwrap();
+        assert_eq!(request.id, 1);
+        assert!(inflight.add(0, Some(block.clone()), None, None).is_none());
+        assert!(inflight.has_block(3) && !inflight.has_upgrade());
+        let upgrade = RequestUpgrade {
+            start: 0,
+            length: 10,
+        };
+        assert_eq!(inflight.add(0, None, Some(upgrade), None).unwrap().id, 2);
+        assert!(inflight.has_upgrade());
+        assert_eq!(inflight.len(), 2);
+        assert_eq!(inflight.remove(1), Some(request));
+        assert_eq!(inflight.remove(1), None);
+        assert!(!inflight.has_block(3));
+    }
+
+    #[test]
+    fn inflight_requests_timeout_and_retry() {
+        let timeout = Duration::from_secs(1);
+        let mut inflight = InflightRequests::new(timeout, 1);
+        let (sender, mut receiver) = broadcast(1);
+        let block = RequestBlock { index: 3, nodes: 0 };
+        inflight.add(0, Some(block), None, Some(sender));
+
+        let start = Instant::now();
+        assert!(inflight.poll_timeouts(start).is_empty());
+        let timeouts = inflight.poll_timeouts(start + timeout);
+        let [RequestTimeout::Reissued(request)] = &timeouts[..] else {
+            panic!("Expected a reissued request, got {timeouts:?}");
+        };
+        assert_eq!(request.id, 2);
+        // The answer to the original request is ignored
+        assert_eq!(inflight.remove(1), None);
+        assert!(inflight.has_block(3));
+
+        assert!(inflight.poll_timeouts(start + timeout).is_empty());
+        assert!(receiver.try_recv().is_err());
+        let timeouts = inflight.poll_timeouts(start + timeout * 2);
+        assert!(matches!(&timeouts[..], [RequestTimeout::Failed(_)]));
+        assert!(inflight.is_empty());
+        // The waiter was woken up
+        assert!(receiver.try_recv().is_ok());
+    }
+}
@@ -0,0 +1,678 @@
+//! Messages of the hypercore replication protocol. Each of them implements [`CompactEncoding`]
+//! with the same layout as the wire messages of the Javascript hypercore, so transports can
+//! encode and decode them here instead of keeping their own copy of the schema. [`Message`]
+//! prefixes a message with its type id, framing is left to the transport.
+use crate::encoding::{
+    check_vec_length, decode_proof_parts, encode_proof_parts, proof_flags, proof_parts_encoded_size,
+};
+use compact_encoding::{
+    CompactEncoding, EncodingError, EncodingErrorKind, map_decode, map_encode, map_first,
+    sum_encoded_size, take_array, take_array_mut, write_slice,
+};
+use hypercore_schema::{Proof, RequestBlock, RequestSeek, RequestUpgrade};
+
+/// Sent to tell the remote peer about the state of the local core, and whenever it changes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Synchronize {
+    /// Fork of the local core
+    pub fork: u64,
+    /// Length of the local core
+    pub length: u64,
+    /// Length of the remote core as last seen by the sender
+    pub remote_length: u64,
+    /// Whether the sender is downloading
+    pub downloading: bool,
+    /// Whether the sender is uploading
+    pub uploading: bool,
+    /// Whether the sender can upgrade the remote core
+    pub can_upgrade: bool,
+}
+
+impl CompactEncoding for Synchronize {
+    fn encoded_size(&self) -> Result<usize, EncodingError> {
+        Ok(1 + sum_encoded_size!(self.fork, self.length, self.remote_length))
+    }
+
+    fn encode<'a>(&self, buffer: &'a mut [u8]) -> Result<&'a mut [u8], EncodingError> {
+        let mut flags = 0u8;
+        if self.can_upgrade {
+            flags |= 1;
+        }
+        if self.uploading {
+            flags |= 2;
+        }
+        if self.downloading {
+            flags |= 4;
+        }
+        let rest = write_slice(&[flags], buffer)?;
+        Ok(map_encode!(
+            rest,
+            self.fork,
+            self.length,
+            self.remote_length
+        ))
+    }
+
+    fn decode(buffer: &[u8]) -> Result<(Self, &[u8]), EncodingError> {
+        let ([flags], rest) = take_array::<1>(buffer)?;
+        let ((fork, length, remote_length), rest) = map_decode!(rest, [u64, u64, u64]);
+        Ok((
+            Synchronize {
+                fork,
+                length,
+                remote_length,
+                can_upgrade: flags & 1 != 0,
+                uploading: flags & 2 != 0,
+                downloading: flags & 4 != 0,
+            },
+            rest,
+        ))
+    }
+}
+
+/// Request for the parts of a proof, answered with [`Data`] or [`NoData`]. The parts are the
+/// arguments of [`Hypercore::create_proof`](crate::Hypercore::create_proof).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Request {
+    /// Id of the request, echoed in the answer
+    pub id: u64,
+    /// Fork the request is for
+    pub fork: u64,
+    /// Requested block
+    pub block: Option<RequestBlock>,
+    /// Requested hash
+    pub hash: Option<RequestBlock>,
+    /// Requested seek
+    pub seek: Option<RequestSeek>,
+    /// Requested upgrade
+    pub upgrade: Option<RequestUpgrade>,
+}
+
+impl CompactEncoding for Request {
+    fn encoded_size(&self) -> Result<usize, EncodingError> {
+        let mut out = 1 + sum_encoded_size!(self.id, self.fork); // flags, id and fork
+        if let Some(block) = &self.block {
+            out += block.encoded_size()?;
+        }
+        if let Some(hash) = &self.hash {
+            out += hash.encoded_size()?;
+        }
+        if let Some(seek) = &self.seek {
+            out += seek.encoded_size()?;
+        }
+        if let Some(upgrade) = &self.upgrade {
+            out += upgrade.encoded_size()?;
+        }
+        Ok(out)
+    }
+
+    fn encode<'a>(&self, buffer: &'a mut [u8]) -> Result<&'a mut [u8], EncodingError> {
+        let (flag_buf, rest) = take_array_mut::<1>(buffer)?;
+        let mut rest = map_encode!(rest, self.id, self.fork);
+        let mut flags = 0u8;
+        if let Some(block) = &self.block {
+            flags |= 1;
+            rest = block.encode(rest)?;
+        }
+        if let Some(hash) = &self.hash {
+            flags |= 2;
+            rest = hash.encode(rest)?;
+        }
+        if let Some(seek) = &self.seek {
+            flags |= 4;
+            rest = seek.encode(rest)?;
+        }
+        if let Some(upgrade) = &self.upgrade {
+            flags |= 8;
+            rest = upgrade.encode(rest)?;
+        }
+        flag_buf[0] = flags;
+        Ok(rest)
+    }
+
+    fn decode(buffer: &[u8]) -> Result<(Self, &[u8]), EncodingError> {
+        let ([flags], rest) = take_array::<1>(buffer)?;
+        let ((id, fork), rest) = map_decode!(rest, [u64, u64]);
+        let (block, rest) = if flags & 1 != 0 {
+            let (x, rest) = RequestBlock::decode(rest)?;
+            (Some(x), rest)
+        } else {
+            (None, rest)
+        };
+        let (hash, rest) = if flags & 2 != 0 {
+            let (x, rest) = RequestBlock::decode(rest)?;
+            (Some(x), rest)
+        } else {
+            (None, rest)
+        };
+        let (seek, rest) = if flags & 4 != 0 {
+            let (x, rest) = RequestSeek::decode(rest)?;
+            (Some(x), rest)
+        } else {
+            (None, rest)
+        };
+        let (upgrade, rest) = if flags & 8 != 0 {
+            let (x, rest) = RequestUpgrade::decode(rest)?;
+            (Some(x), rest)
+        } else {
+            (None, rest)
+        };
+        Ok((
+            Request {
+                id,
+                fork,
+                block,
+                hash,
+                seek,
+                upgrade,
+            },
+            rest,
+        ))
+    }
+}
+
+/// Answer to a [`Request`] carrying the created proof
+#[derive(Debug, Clone, PartialEq)]
+pub struct Data {
+    /// Id of the answered request
+    pub request: u64,
+    /// The proof, to be given to
+    /// [`Hypercore::verify_and_apply_proof`](crate::Hypercore::verify_and_apply_proof)
+    pub proof: Proof,
+}
+
+impl CompactEncoding for Data {
+    fn encoded_size(&self) -> Result<usize, EncodingError> {
+        Ok(1 // flags
+            + sum_encoded_size!(self.request, self.proof.fork)
+            + proof_parts_encoded_size(&self.proof)?)
+    }
+
+    fn encode<'a>(&self, buffer: &'a mut [u8]) -> Result<&'a mut [u8], EncodingError> {
+        let rest = write_slice(&[proof_flags(&self.proof)], buffer)?;
+        let rest = map_encode!(rest, self.request, self.proof.fork);
+        encode_proof_parts(&self.proof, rest)
+    }
+
+    fn decode(buffer: &[u8]) -> Result<(Self, &[u8]), EncodingError> {
+        let ([flags], rest) = take_array::<1>(buffer)?;
+        let ((request, fork), rest) = map_decode!(rest, [u64, u64]);
+        let (proof, rest) = decode_proof_parts(fork, flags, rest)?;
+        Ok((Data { request, proof }, rest))
+    }
+}
+
+/// Answer to a [`Request`] that can't be served
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NoData {
+    /// Id of the answered request
+    pub request: u64,
+}
+
+impl CompactEncoding for NoData {
+    fn encoded_size(&self) -> Result<usize, EncodingError> {
+        self.request.encoded_size()
+    }
+
+    fn encode<'a>(&self, buffer: &'a mut [u8]) -> Result<&'a mut [u8], EncodingError> {
+        self.request.encode(buffer)
+    }
+
+    fn decode(buffer: &[u8]) -> Result<(Self, &[u8]), EncodingError> {
+        let (request, rest) = u64::decode(buffer)?;
+        Ok((NoData { request }, rest))
+    }
+}
+
+/// Asks the remote peer to send [`Range`] and [`Bitfield`] updates for a range of blocks
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Want {
+    /// First block of the range
+    pub start: u64,
+    /// Number of blocks in the range
+    pub length: u64,
+}
+
+impl CompactEncoding for Want {
+    fn encoded_size(&self) -> Result<usize, EncodingError> {
+        Ok(sum_encoded_size!(self.start, self.length))
+    }
+
+    fn encode<'a>(&self, buffer: &'a mut [u8]) -> Result<&'a mut [u8], EncodingError> {
+        Ok(map_encode!(buffer, self.start, self.length))
+    }
+
+    fn decode(buffer: &[u8]) -> Result<(Self, &[u8]), EncodingError> {
+        let ((start, length), rest) = map_decode!(buffer, [u64, u64]);
+        Ok((Want { start, length }, rest))
+    }
+}
+
+/// Withdraws a [`Want`] for a range of blocks
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Unwant {
+    /// First block of the range
+    pub start: u64,
+    /// Number of blocks in the range
+    pub length: u64,
+}
+
+impl CompactEncoding for Unwant {
+    fn encoded_size(&self) -> Result<usize, EncodingError> {
+        Ok(sum_encoded_size!(self.start, self.length))
+    }
+
+    fn encode<'a>(&self, buffer: &'a mut [u8]) -> Result<&'a mut [u8], EncodingError> {
+        Ok(map_encode!(buffer, self.start, self.length))
+    }
+
+    fn decode(buffer: &[u8]) -> Result<(Self, &[u8]), EncodingError> {
+        let ((start, length), rest) = map_decode!(buffer, [u64, u64]);
+        Ok((Unwant { start, length }, rest))
+    }
+}
+
+/// Bitfield of the blocks the sender has, starting at a block index
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bitfield {
+    /// Index of the block of the first bit
+    pub start: u64,
+    /// The bits, least significant bit first
+    pub bitfield: Vec<u32>,
+}
+
+impl CompactEncoding for Bitfield {
+    fn encoded_size(&self) -> Result<usize, EncodingError> {
+        Ok(sum_encoded_size!(self.start, self.bitfield))
+    }
+
+    fn encode<'a>(&self, buffer: &'a mut [u8]) -> Result<&'a mut [u8], EncodingError> {
+        Ok(map_encode!(buffer, self.start, self.bitfield))
+    }
+
+    fn decode(buffer: &[u8]) -> Result<(Self, &[u8]), EncodingError> {
+        let (start, rest) = u64::decode(buffer)?;
+        // The words are encoded in 4 bytes each
+        check_vec_length(rest, 4)?;
+        let (bitfield, rest) = <Vec<u32>>::decode(rest)?;
+        Ok((Bitfield { start, bitfield }, rest))
+    }
+}
+
+/// Range of blocks the sender has gained, or dropped
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Range {
+    /// Whether the blocks were dropped instead of gained
+    pub drop: bool,
+    /// First block of the range
+    pub start: u64,
+    /// Number of blocks in the range
+    pub length: u64,
+}
+
+impl CompactEncoding for Range {
+    fn encoded_size(&self) -> Result<usize, EncodingError> {
+        let mut out = 1 + self.start.encoded_size()?;
+        if self.length != 1 {
+            out += self.length.encoded_size()?;
+        }
+        Ok(out)
+    }
+
+    fn encode<'a>(&self, buffer: &'a mut [u8]) -> Result<&'a mut [u8], EncodingError> {
+        let mut flags = 0u8;
+        if self.drop {
+            flags |= 1;
+        }
+        if self.length == 1 {
+            flags |= 2;
+        }
+        let rest = write_slice(&[flags], buffer)?;
+        let rest = self.start.encode(rest)?;
+        if self.length != 1 {
+            return self.length.encode(rest);
+        }
+        Ok(rest)
+    }
+
+    fn decode(buffer: &[u8]) -> Result<(Self, &[u8]), EncodingError> {
+        let ([flags], rest) = take_array::<1>(buffer)?;
+        let (start, rest) = u64::decode(rest)?;
+        let (length, rest) = if flags & 2 != 0 {
+            (1, rest)
+        } else {
+            u64::decode(rest)?
+        };
+        Ok((
+            Range {
+                drop: flags & 1 != 0,
+                start,
+                length,
+            },
+            rest,
+        ))
+    }
+}
+
+/// Message of a named protocol extension. The message is not length prefixed and takes the rest
+/// of the buffer when decoding.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Extension {
+    /// Name of the extension
+    pub name: String,
+    /// Extension specific payload
+    pub message: Vec<u8>,
+}
+
+impl CompactEncoding for Extension {
+    fn encoded_size(&self) -> Result<usize, EncodingError> {
+        Ok(self.name.encoded_size()? + self.message.len())
+    }
+
+    fn encode<'a>(&self, buffer: &'a mut [u8]) -> Result<&'a mut [u8], EncodingError> {
+        let rest = self.name.encode(buffer)?;
+        write_slice(&self.message, rest)
+    }
+
+    fn decode(buffer: &[u8]) -> Result<(Self, &[u8]), EncodingError> {
+        let (name, rest) = String::decode(buffer)?;
+        Ok((
+            Extension {
+                name,
+                message: rest.to_vec(),
+            },
+            &[],
+        ))
+    }
+}
+
+/// Any of the replication messages, as handled and produced by [`super::Peer`]
+#[derive(Debug, Clone, PartialEq)]
+pub enum Message {
+    /// See [`Synchronize`]
+    Synchronize(Synchronize),
+    /// See [`Request`]
+    Request(Request),
+    /// See [`Data`]
+    Data(Data),
+    /// See [`NoData`]
+    NoData(NoData),
+    /// See [`Want`]
+    Want(Want),
+    /// See [`Unwant`]
+    Unwant(Unwant),
+    /// See [`Bitfield`]
+    Bitfield(Bitfield),
+    /// See [`Range`]
+    Range(Range),
+    /// See [`Extension`]
+    Extension(Extension),
+}
+
+/// Derive From<msg> for Enum where enum variant and msg have the same name
+macro_rules! impl_from_for_enum_variant {
+    ($enum_name:ident, $variant_and_msg_name:ident) => {
+        impl From<$variant_and_msg_name> for $enum_name {
+            fn from(value: $variant_and_msg_name) -> Self {
+                $enum_name::$variant_and_msg_name(value)
+            }
+        }
+    };
+}
+
+impl_from_for_enum_variant!(Message, Synchronize);
+impl_from_for_enum_variant!(Message, Request);
+impl_from_for_enum_variant!(Message, Data);
+impl_from_for_enum_variant!(Message, NoData);
+impl_from_for_enum_variant!(Message, Want);
+impl_from_for_enum_variant!(Message, Unwant);
+impl_from_for_enum_variant!(Message, Bitfield);
+impl_from_for_enum_variant!(Message, Range);
+impl_from_for_enum_variant!(Message, Extension);
+
+/// Encoded as the message type id, in one byte, followed by the message. The type ids match the
+/// order of the wire messages in Javascript, where 2 is the `cancel` message.
+impl CompactEncoding for Message {
+    fn encoded_size(&self) -> Result<usize, EncodingError> {
+        Ok(1 + match self {
+            Message::Synchronize(m) => m.encoded_size()?,
+            Message::Request(m) => m.encoded_size()?,
+            Message::Data(m) => m.encoded_size()?,
+            Message::NoData(m) => m.encoded_size()?,
+            Message::Want(m) => m.encoded_size()?,
+            Message::Unwant(m) => m.encoded_size()?,
+            Message::Bitfield(m) => m.encoded_size()?,
+            Message::Range(m) => m.encoded_size()?,
+            Message::Extension(m) => m.encoded_size()?,
+        })
+    }
+
+    fn encode<'a>(&self, buffer: &'a mut [u8]) -> Result<&'a mut [u8], EncodingError> {
+        match self {
+            Message::Synchronize(m) => m.encode(write_slice(&[0], buffer)?),
+            Message::Request(m) => m.encode(write_slice(&[1], buffer)?),
+            Message::Data(m) => m.encode(write_slice(&[3], buffer)?),
+            Message::NoData(m) => m.encode(write_slice(&[4], buffer)?),
+            Message::Want(m) => m.encode(write_slice(&[5], buffer)?),
+            Message::Unwant(m) => m.encode(write_slice(&[6], buffer)?),
+            Message::Bitfield(m) => m.encode(write_slice(&[7], buffer)?),
+            Message::Range(m) => m.encode(write_slice(&[8], buffer)?),
+            Message::Extension(m) => m.encode(write_slice(&[9], buffer)?),
+        }
+    }
+
+    fn decode(buffer: &[u8]) -> Result<(Self, &[u8]), EncodingError> {
+        let ([type_id], rest) = take_array::<1>(buffer)?;
+        Ok(match type_id {
+            0 => map_first!(Synchronize::decode(rest)?, Message::from),
+            1 => map_first!(Request::decode(rest)?, Message::from),
+            3 => map_first!(Data::decode(rest)?, Message::from),
+            4 => map_first!(NoData::decode(rest)?, Message::from),
+            5 => map_first!(Want::decode(rest)?, Message::from),
+            6 => map_first!(Unwant::decode(rest)?, Message::from),
+            7 => map_first!(Bitfield::decode(rest)?, Message::from),
+            8 => map_first!(Range::decode(rest)?, Message::from),
+            9 => map_first!(Extension::decode(rest)?, Message::from),
+            _ => {
+                return Err(EncodingError::new(
+                    EncodingErrorKind::InvalidData,
+                    &format!("Unknown message type: {type_id}"),
+                ));
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hypercore_schema::{DataBlock, DataUpgrade, Node};
+
+    fn assert_round_trip<T: CompactEncoding + PartialEq + std::fmt::Debug>(
+        message: &T,
+    ) -> Result<Box<[u8]>, EncodingError> {
+        let encoded = message.to_encoded_bytes()?;
+        let (decoded, rest) = T::decode(&encoded)?;
+        assert!(rest.is_empty());
+        assert_eq!(&decoded, message);
+        Ok(encoded)
+    }
+
+    #[test]
+    fn encode_synchronize_and_request() -> Result<(), EncodingError> {
+        let encoded = assert_round_trip(&Synchronize {
+            fork: 1,
+            length: 2,
+            remote_length: 3,
+            downloading: true,
+            uploading: false,
+            can_upgrade: true,
+        })?;
+        assert_eq!(&*encoded, &[5, 1, 2, 3]);
+
+        assert_round_trip(&Request {
+            id: 7,
+            fork: 0,
+            block: Some(RequestBlock { index: 4, nodes: 2 }),
+            hash: None,
+            seek: Some(RequestSeek { bytes: 1000 }),
+            upgrade: Some(RequestUpgrade {
+                start: 0,
+                length: 10,
+            }),
+        })?;
+        let encoded = assert_round_trip(&Request {
+            id: 1,
+            fork: 2,
+            block: None,
+            hash: None,
+            seek: None,
+            upgrade: None,
+        })?;
+        assert_eq!(&*encoded, &[0, 1, 2]);
+        Ok(())
+    }
+
+    #[test]
+    fn encode_data_and_no_data() -> Result<(), EncodingError> {
+        let data = Data {
+            request: 300,
+            proof: Proof {
+                fork: 0,
+                block: Some(DataBlock {
+                    index: 1,
+                    value: b"hello".to_vec(),
+                    nodes: vec![],
+                }),
+                hash: None,
+                seek: None,
+                upgrade: None,
+            },
+        };
+        let encoded = assert_round_trip(&data)?;
+        assert!(Data::decode(&encoded[..encoded.len() - 1]).is_err());
+        assert_eq!(&*assert_round_trip(&NoData { request: 3 })?, &[3]);
+        Ok(())
+    }
+
+    #[test]
+    fn encode_data_like_javascript() -> Result<(), EncodingError> {
+        let data = Data {
+            request: 300,
+            proof: Proof {
+                fork: 2,
+                block: Some(DataBlock {
+                    index: 1,
+                    value: b"hi".to_vec(),
+                    nodes: vec![Node::new(2, vec![7; 32], 2)],
+                }),
+                hash: None,
+                seek: None,
+                upgrade: Some(DataUpgrade {
+                    start: 0,
+                    length: 2,
+                    nodes: vec![],
+                    additional_nodes: vec![],
+                    signature: vec![9, 9],
+                }),
+            },
+        };
+        // The `data` message of lib/messages.js in Javascript hypercore 10
+        let mut expected = vec![
+            9, // flags: block and upgrade
+            0xfd, 0x2c, 0x01, // request
+            2,    // fork
+            1, 2, b'h', b'i', // block index and value
+            1, 2, 2, // one node, its index and size
+        ];
+        expected.extend([7; 32]);
+        expected.extend([0, 2, 0, 0, 2, 9, 9]); // upgrade
+        assert_eq!(&*assert_round_trip(&data)?, &expected[..]);
+        Ok(())
+    }
+
+    #[test]
+    fn encode_want_bitfield_and_range() -> Result<(), EncodingError> {
+        assert_eq!(
+            &*assert_round_trip(&Want {
+                start: 1,
+                length: 2
+            })?,
+            &[1, 2]
+        );
+        assert_eq!(
+            &*assert_round_trip(&Unwant {
+                start: 1,
+                length: 2
+            })?,
+            &[1, 2]
+        );
+        let encoded = assert_round_trip(&Bitfield {
+            start: 0,
+            bitfield: vec![1, u32::MAX],
+        })?;
+        assert_eq!(&*encoded, &[0, 2, 1, 0, 0, 0, 255, 255, 255, 255]);
+
+        // A length of one is stored in the flags
+        let single = Range {
+            drop: true,
+            start: 5,
+            length: 1,
+        };
+        assert_eq!(&*assert_round_trip(&single)?, &[3, 5]);
+        let range = Range {
+            drop: false,
+            start: 5,
+            length: 0,
+        };
+        assert_eq!(&*assert_round_trip(&range)?, &[0, 5, 0]);
+        Ok(())
+    }
+
+    #[test]
+    fn encode_message() -> Result<(), EncodingError> {
+        let encoded = assert_round_trip(&Message::from(NoData { request: 3 }))?;
+        assert_eq!(&*encoded, &[4, 3]);
+        let encoded = assert_round_trip(&Message::from(Extension {
+            name: "ext".to_string(),
+            message: b"payload".to_vec(),
+        }))?;
+        assert_eq!(encoded[0], 9);
+        assert!(Message::decode(&[2, 0]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn encode_extension() -> Result<(), EncodingError> {
+        let encoded = assert_round_trip(&Extension {
+            name: "ext".to_string(),
+            message: b"payload".to_vec(),
+        })?;
+        assert_eq!(&encoded[..4], b"\x03ext");
+        assert_round_trip(&Extension {
+            name: "empty".to_string(),
+            message: vec![],
+        })?;
+        Ok(())
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn request_and_data_round_trip(
+            block in proptest::option::of(crate::strategies::request_block()),
+            seek in proptest::option::of(crate::strategies::request_seek()),
+            upgrade in proptest::option::of(crate::strategies::request_upgrade()),
+            proof in crate::strategies::proof(),
+        ) {
+            assert_round_trip(&Request {
+                id: 1,
+                fork: proof.fork,
+                block,
+                hash: None,
+                seek,
+                upgrade,
+            })?;
+            assert_round_trip(&Data { request: 1, proof })?;
+        }
+    }
+}
@@ -0,0 +1,484 @@
+//! In-memory mock of the hypercore traits, for testing code written against them without real
+//! storage or crypto.
+use crate::{AppendOutcome, HypercoreError, Info, PartialKeypair, generate_signing_key};
+use futures::future::BoxFuture;
+use hypercore_schema::{DataBlock, DataUpgrade, Proof, RequestBlock, RequestSeek, RequestUpgrade};
+use std::fmt::Debug;
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+
+use super::events::{DEFAULT_EVENT_QUEUE_CAPACITY, Events, Have};
+use super::{
+    CoreInfo, CoreMethods, CoreMethodsError, EventReceiver, MaybeSend, Priority,
+    ReplicationMethods, ReplicationMethodsError,
+};
+
+/// Fallible operation of a [`MockCore`], used to script failures with [`MockCore::fail_next`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MockOperation {
+    /// [`CoreMethods::get`]
+    Get,
+    /// [`CoreMethods::append`] and [`CoreMethods::append_batch`]
+    Append,
+    /// [`CoreMethods::clear`]
+    Clear,
+    /// [`ReplicationMethods::verify_and_apply_proof`] and
+    /// [`ReplicationMethods::verify_and_apply_proofs`]
+    VerifyAndApplyProof,
+    /// [`ReplicationMethods::missing_nodes`] and [`ReplicationMethods::missing_nodes_range`]
+    MissingNodes,
+    /// [`ReplicationMethods::create_proof`]
+    CreateProof,
+}
+
+/// Creates the future a [`MockCore`] awaits at the start of every trait method, e.g.
+/// `Arc::new(|| Box::pin(tokio::time::sleep(latency)))`.
+pub type MockDelay = Arc<dyn Fn() -> BoxFuture<'static, ()> + Send + Sync>;
+
+#[derive(Debug, Default)]
+struct MockBlock {
+    byte_length: Option<u64>,
+    data: Option<Vec<u8>>,
+}
+
+#[derive(Debug, Default)]
+struct MockState {
+    blocks: Vec<MockBlock>,
+    failures: Vec<(MockOperation, HypercoreError)>,
+    wants: Vec<(Range<u64>, Priority)>,
+}
+
+impl MockState {
+    fn take_failure(&mut self, operation: MockOperation) -> Result<(), HypercoreError> {
+        match self.failures.iter().position(|(op, _)| *op == operation) {
+            Some(position) => Err(self.failures.remove(position).1),
+            None => Ok(()),
+        }
+    }
+
+    fn info(&self) -> Info {
+        Info {
+            length: self.blocks.len() as u64,
+            byte_length: self
+                .blocks
+                .iter()
+                .filter_map(|block| block.byte_length)
+                .sum(),
+            contiguous_length: self
+                .blocks
+                .iter()
+                .take_while(|block| block.data.is_some())
+                .count() as u64,
+            fork: 0,
+            writeable: true,
+        }
+    }
+}
+
+/// Hypercore-like core that keeps its blocks in a `Vec` and implements [`CoreInfo`],
+/// [`CoreMethods`] and [`ReplicationMethods`] without hashing or signing. Proofs created by it
+/// contain the requested block and upgrade length, but no merkle tree nodes or signature, and are
+/// applied by other mocks as is. Latency can be added with [`MockCore::with_delay`] and failures
+/// scripted with [`MockCore::fail_next`].
+pub struct MockCore {
+    key_pair: PartialKeypair,
+    state: Mutex<MockState>,
+    delay: Option<MockDelay>,
+    events: Events,
+}
+
+impl Debug for MockCore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MockCore")
+            .field("key_pair", &self.key_pair)
+            .field("state", &self.state)
+            .field("delay", &self.delay.is_some())
+            .finish()
+    }
+}
+
+impl Default for MockCore {
+    fn default() -> Self {
+        Self::new(vec![])
+    }
+}
+
+impl MockCore {
+    /// Create a mock core with the given blocks and a random key pair
+    pub fn new(blocks: Vec<Vec<u8>>) -> Self {
+        let signing_key = generate_signing_key();
+        let blocks = blocks
+            .into_iter()
+            .map(|data| MockBlock {
+                byte_length: Some(data.len() as u64),
+                data: Some(data),
+            })
+            .collect();
+        Self {
+            key_pair: PartialKeypair {
+                public: signing_key.verifying_key(),
+                secret: Some(signing_key),
+            },
+            state: Mutex::new(MockState {
+                blocks,
+                failures: vec![],
+                wants: vec![],
+            }),
+            delay: None,
+            events: Events::new(DEFAULT_EVENT_QUEUE_CAPACITY),
+        }
+    }
+
+    /// Await the future created by `delay` at the start of every trait method
+    pub fn with_delay(mut self, delay: MockDelay) -> Self {
+        self.delay = Some(delay);
+        self
+    }
+
+    /// Make the next call of `operation` fail with `error`. Failures scripted for the same
+    /// operation are returned in order.
+    pub fn fail_next(&self, operation: MockOperation, error: HypercoreError) {
+        self.lock().failures.push((operation, error));
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, MockState> {
+        self.state.lock().expect("MockCore state poisoned")
+    }
+
+    async fn delay(&self) {
+        if let Some(delay) = &self.delay {
+            delay().await;
+        }
+    }
+}
+
+impl CoreInfo for MockCore {
+    async fn info(&self) -> Info {
+        self.delay().await;
+        self.lock().info()
+    }
+
+    async fn key_pair(&self) -> PartialKeypair {
+        self.delay().await;
+        self.key_pair.clone()
+    }
+}
+
+impl CoreMethods for MockCore {
+    async fn has(&self, index: u64) -> bool {
+        self.delay().await;
+        self.lock()
+            .blocks
+            .get(index as usize)
+            .is_some_and(|block| block.data.is_some())
+    }
+
+    async fn get(&self, index: u64) -> Result<Option<Vec<u8>>, CoreMethodsError> {
+        self.delay().await;
+        let mut state = self.lock();
+        state.take_failure(MockOperation::Get)?;
+        let data = state
+            .blocks
+            .get(index as usize)
+            .and_then(|block| block.data.clone());
+        if data.is_none() {
+            self.events.send_on_get(index, Priority::Normal);
+        }
+        Ok(data)
+    }
+
+    async fn append(&self, data: &[u8]) -> Result<AppendOutcome, CoreMethodsError> {
+        self.append_batch([data]).await
+    }
+
+    async fn append_batch<A: AsRef<[u8]>, B: AsRef<[A]> + MaybeSend>(
+        &self,
+        batch: B,
+    ) -> Result<AppendOutcome, CoreMethodsError> {
+        self.delay().await;
+        let mut state = self.lock();
+        state.take_failure(MockOperation::Append)?;
+        let start = state.blocks.len() as u64;
+        let mut byte_offsets = vec![];
+        let old_byte_length = state.info().byte_length;
+        let mut offset = old_byte_length;
+        for data in batch.as_ref() {
+            let data = data.as_ref();
+            byte_offsets.push(offset);
+            offset += data.len() as u64;
+            state.blocks.push(MockBlock {
+                byte_length: Some(data.len() as u64),
+                data: Some(data.to_vec()),
+            });
+        }
+        let info = state.info();
+        if info.length > start {
+            let _ = self.events.send(super::events::DataUpgrade {});
+            let _ = self.events.send(Have {
+                start,
+                length: info.length - start,
+                byte_length: info.byte_length - old_byte_length,
+                stored: true,
+                drop: false,
+            });
+        }
+        Ok(AppendOutcome {
+            length: info.length,
+            byte_length: info.byte_length,
+            blocks: start..info.length,
+            byte_offsets,
+            fork: info.fork,
+            signature: vec![],
+        })
+    }
+
+    async fn clear(&self, start: u64, end: u64) -> Result<(), CoreMethodsError> {
+        self.delay().await;
+        let mut state = self.lock();
+        state.take_failure(MockOperation::Clear)?;
+        for block in state
+            .blocks
+            .iter_mut()
+            .take(end as usize)
+            .skip(start as usize)
+        {
+            block.data = None;
+        }
+        Ok(())
+    }
+
+    async fn seek(&self, byte_offset: u64) -> Result<(u64, u64), CoreMethodsError> {
+        self.delay().await;
+        let state = self.lock();
+        let mut offset = byte_offset;
+        for (index, block) in state.blocks.iter().enumerate() {
+            let Some(byte_length) = block.byte_length else {
+                break;
+            };
+            if offset < byte_length {
+                return Ok((index as u64, offset));
+            }
+            offset -= byte_length;
+        }
+        Err(HypercoreError::BadArgument {
+            context: format!("Could not seek to byte offset {byte_offset}"),
+        }
+        .into())
+    }
+}
+
+impl ReplicationMethods for MockCore {
+    async fn verify_and_apply_proof(&self, proof: &Proof) -> Result<bool, ReplicationMethodsError> {
+        let applied = self
+            .verify_and_apply_proofs(std::slice::from_ref(proof))
+            .await?;
+        Ok(applied[0])
+    }
+
+    async fn verify_and_apply_proofs(
+        &self,
+        proofs: &[Proof],
+    ) -> Result<Vec<bool>, ReplicationMethodsError> {
+        self.delay().await;
+        let mut state = self.lock();
+        state.take_failure(MockOperation::VerifyAndApplyProof)?;
+        // Like a hypercore, apply either the whole batch or nothing
+        let length = proofs
+            .iter()
+            .filter_map(|proof| proof.upgrade.as_ref())
+            .map(|upgrade| (upgrade.start + upgrade.length) as usize)
+            .fold(state.blocks.len(), usize::max);
+        if let Some(block) = proofs
+            .iter()
+            .filter_map(|proof| proof.block.as_ref())
+            .find(|block| block.index as usize >= length)
+        {
+            return Err(HypercoreError::InvalidOperation {
+                context: format!("MockCore has no block {} to apply", block.index),
+            }
+            .into());
+        }
+        for proof in proofs {
+            if let Some(upgrade) = &proof.upgrade {
+                let length = (upgrade.start + upgrade.length) as usize;
+                if length > state.blocks.len() {
+                    state.blocks.resize_with(length, MockBlock::default);
+                }
+                let _ = self.events.send(super::events::DataUpgrade {});
+            }
+            if let Some(block) = &proof.block {
+                let index = block.index as usize;
+                if index >= state.blocks.len() {
+                    state.blocks.resize_with(index + 1, MockBlock::default);
+                }
+                state.blocks[index] = MockBlock {
+                    byte_length: Some(block.value.len() as u64),
+                    data: Some(block.value.clone()),
+                };
+                let _ = self.events.send(Have {
+                    start: block.index,
+                    length: 1,
+                    byte_length: block.value.len() as u64,
+                    stored: true,
+                    drop: false,
+                });
+            }
+        }
+        Ok(vec![true; proofs.len()])
+    }
+
+    async fn missing_nodes(&self, _index: u64) -> Result<u64, ReplicationMethodsError> {
+        self.delay().await;
+        self.lock().take_failure(MockOperation::MissingNodes)?;
+        Ok(0)
+    }
+
+    async fn missing_nodes_range(
+        &self,
+        range: Range<u64>,
+    ) -> Result<Vec<u64>, ReplicationMethodsError> {
+        self.delay().await;
+        self.lock().take_failure(MockOperation::MissingNodes)?;
+        Ok(range.map(|_| 0).collect())
+    }
+
+    async fn create_proof(
+        &self,
+        block: Option<RequestBlock>,
+        _hash: Option<RequestBlock>,
+        _seek: Option<RequestSeek>,
+        upgrade: Option<RequestUpgrade>,
+    ) -> Result<Option<Proof>, ReplicationMethodsError> {
+        self.delay().await;
+        let mut state = self.lock();
+        state.take_failure(MockOperation::CreateProof)?;
+        let block = match block {
+            Some(RequestBlock { index, .. }) => {
+                let Some(value) = state
+                    .blocks
+                    .get(index as usize)
+                    .and_then(|block| block.data.clone())
+                else {
+                    return Ok(None);
+                };
+                Some(DataBlock {
+                    index,
+                    value,
+                    nodes: vec![],
+                })
+            }
+            None => None,
+        };
+        let upgrade = upgrade.map(|RequestUpgrade { start, length }| DataUpgrade {
+            start,
+            length: length.min((state.blocks.len() as u64).saturating_sub(start)),
+            nodes: vec![],
+            additional_nodes: vec![],
+            signature: vec![],
+        });
+        Ok(Some(Proof {
+            fork: 0,
+            block,
+            hash: None,
+            seek: None,
+            upgrade,
+        }))
+    }
+
+    async fn event_subscribe(&self) -> EventReceiver {
+        self.events.channel.new_receiver()
+    }
+
+    async fn want(&self, range: Range<u64>, priority: Priority) {
+        self.delay().await;
+        let _ = self.events.send(super::events::Want {
+            start: range.start,
+            length: range.end.saturating_sub(range.start),
+            priority,
+        });
+        self.lock().wants.push((range, priority));
+    }
+
+    async fn unwant(&self, range: Range<u64>) -> bool {
+        self.delay().await;
+        let mut state = self.lock();
+        let Some(position) = state.wants.iter().position(|(want, _)| *want == range) else {
+            return false;
+        };
+        state.wants.remove(position);
+        let _ = self.events.send(super::events::Unwant {
+            start: range.start,
+            length: range.end.saturating_sub(range.start),
+        });
+        true
+    }
+
+    async fn wants(&self) -> Vec<(Range<u64>, Priority)> {
+        self.delay().await;
+        self.lock().wants.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[async_std::test]
+    async fn mock_core_methods() -> Result<(), ReplicationMethodsError> {
+        let core = MockCore::new(vec![b"a".to_vec()]);
+        assert_eq!(core.append_batch([b"bb", b"cc"]).await?.byte_length, 5);
+        assert!(core.has(2).await);
+        assert_eq!(core.get(1).await?, Some(b"bb".to_vec()));
+        core.clear(0, 2).await?;
+        assert_eq!(core.get(0).await?, None);
+        let info = core.info().await;
+        assert_eq!((info.length, info.byte_length), (3, 5));
+        assert_eq!(info.contiguous_length, 0);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn mock_core_replication() -> Result<(), ReplicationMethodsError> {
+        let main = MockCore::new(vec![b"a".to_vec(), b"b".to_vec()]);
+        let clone = MockCore::default();
+        let proof = main
+            .create_proof(
+                Some(RequestBlock { index: 1, nodes: 0 }),
+                None,
+                None,
+                Some(RequestUpgrade {
+                    start: 0,
+                    length: 2,
+                }),
+            )
+            .await?
+            .unwrap();
+        assert!(clone.verify_and_apply_proof(&proof).await?);
+        assert_eq!(clone.info().await.length, 2);
+        assert!(!clone.has(0).await);
+        assert_eq!(clone.get(1).await?, Some(b"b".to_vec()));
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn mock_core_scripted_failures_and_delay() -> Result<(), CoreMethodsError> {
+        let delays = Arc::new(AtomicUsize::new(0));
+        let counter = delays.clone();
+        let core = MockCore::default().with_delay(Arc::new(move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+            Box::pin(futures::future::ready(()))
+        }));
+        core.fail_next(MockOperation::Append, HypercoreError::NotWritable);
+        assert!(matches!(
+            core.append(b"a").await,
+            Err(CoreMethodsError::HypercoreError(
+                HypercoreError::NotWritable
+            ))
+        ));
+        core.append(b"a").await?;
+        assert_eq!(core.get(0).await?, Some(b"a".to_vec()));
+        assert_eq!(delays.load(Ordering::SeqCst), 3);
+        Ok(())
+    }
+}
@@ -1,8 +1,24 @@
 //! Hypercore to Hypercore replication
+pub mod dyn_core;
 pub mod events;
+pub mod extension;
+pub mod inflight;
+pub mod messages;
+#[cfg(feature = "test-utils")]
+pub mod mock_core;
+pub mod peer;
+pub mod planner;
+pub mod remote_bitfield;
 #[cfg(feature = "shared-core")]
 pub mod shared_core;
+pub mod strategy;
+pub mod transport;
+pub mod upgrade;
 
+#[cfg(feature = "test-utils")]
+pub use mock_core::{MockCore, MockDelay, MockOperation};
+#[cfg(all(feature = "shared-core", any(feature = "tokio", feature = "async-std")))]
+pub use shared_core::ScrubOptions;
 #[cfg(feature = "shared-core")]
 pub use shared_core::SharedCore;
 
@@ -10,17 +26,58 @@ use crate::{AppendOutcome, HypercoreError, Info, PartialKeypair};
 
 use hypercore_schema::{Proof, RequestBlock, RequestSeek, RequestUpgrade};
 
-pub use events::Event;
+pub use dyn_core::{DynCoreInfo, DynCoreMethods, DynReplicationMethods};
+pub use events::{Event, EventReceiver, EventStream, Priority};
+pub use extension::PeerExtension;
+pub use inflight::{InflightRequests, RequestTimeout};
+pub use peer::Peer;
+pub use planner::{Download, PlannedRequest, plan_requests};
+pub use remote_bitfield::RemoteBitfield;
+pub use strategy::{
+    Availability, Candidate, FastestFirst, PeerId, RarestFirst, RequestScheduler, RequestStrategy,
+    RoundRobin, SyncStrategy,
+};
+#[cfg(any(feature = "tokio", feature = "async-std"))]
+pub use transport::replicate;
+pub use transport::{MemoryTransport, ReplicateError, ReplicationTransport, StreamTransport};
+pub use upgrade::UpgradeNegotiator;
 
-use async_broadcast::Receiver;
 use std::future::Future;
+use std::ops::Range;
+use std::sync::Arc;
+
+/// `Send` with the `send` feature, on by default, and no bound without it. The futures of the
+/// hypercore traits and their implementors are bound by it, so that cores holding `!Send` state,
+/// e.g. storage handles on single-threaded wasm, can implement them.
+#[cfg(feature = "send")]
+pub trait MaybeSend: Send {}
+#[cfg(feature = "send")]
+impl<T: Send + ?Sized> MaybeSend for T {}
+/// `Send` with the `send` feature, on by default, and no bound without it. The futures of the
+/// hypercore traits and their implementors are bound by it, so that cores holding `!Send` state,
+/// e.g. storage handles on single-threaded wasm, can implement them.
+#[cfg(not(feature = "send"))]
+pub trait MaybeSend {}
+#[cfg(not(feature = "send"))]
+impl<T: ?Sized> MaybeSend for T {}
+
+/// `Sync` with the `send` feature, on by default, and no bound without it, see [`MaybeSend`]
+#[cfg(feature = "send")]
+pub trait MaybeSync: Sync {}
+#[cfg(feature = "send")]
+impl<T: Sync + ?Sized> MaybeSync for T {}
+/// `Sync` with the `send` feature, on by default, and no bound without it, see [`MaybeSend`]
+#[cfg(not(feature = "send"))]
+pub trait MaybeSync {}
+#[cfg(not(feature = "send"))]
+impl<T: ?Sized> MaybeSync for T {}
 
 /// Methods related to just this core's information
 pub trait CoreInfo {
     /// Get core info (see: [`crate::Hypercore::info`]
-    fn info(&self) -> impl Future<Output = Info> + Send;
+    fn info(&self) -> impl Future<Output = Info> + MaybeSend;
     /// Get the key_pair (see: [`crate::Hypercore::key_pair`]
-    fn key_pair(&self) -> impl Future<Output = PartialKeypair> + Send;
+    fn key_pair(&self) -> impl Future<Output = PartialKeypair> + MaybeSend;
 }
 
 /// Error for ReplicationMethods trait
@@ -35,17 +92,27 @@ pub enum ReplicationMethodsError {
 }
 
 /// Methods needed for replication
-pub trait ReplicationMethods: CoreInfo + Send {
+pub trait ReplicationMethods: CoreInfo + MaybeSend {
     /// ref Core::verify_and_apply_proof
     fn verify_and_apply_proof(
         &self,
         proof: &Proof,
-    ) -> impl Future<Output = Result<bool, ReplicationMethodsError>> + Send;
+    ) -> impl Future<Output = Result<bool, ReplicationMethodsError>> + MaybeSend;
+    /// ref Core::verify_and_apply_proofs
+    fn verify_and_apply_proofs(
+        &self,
+        proofs: &[Proof],
+    ) -> impl Future<Output = Result<Vec<bool>, ReplicationMethodsError>> + MaybeSend;
     /// ref Core::missing_nodes
     fn missing_nodes(
         &self,
         index: u64,
-    ) -> impl Future<Output = Result<u64, ReplicationMethodsError>> + Send;
+    ) -> impl Future<Output = Result<u64, ReplicationMethodsError>> + MaybeSend;
+    /// ref Core::missing_nodes_range
+    fn missing_nodes_range(
+        &self,
+        range: Range<u64>,
+    ) -> impl Future<Output = Result<Vec<u64>, ReplicationMethodsError>> + MaybeSend;
     /// ref Core::create_proof
     fn create_proof(
         &self,
@@ -53,9 +120,15 @@ pub trait ReplicationMethods: CoreInfo + Send {
         hash: Option<RequestBlock>,
         seek: Option<RequestSeek>,
         upgrade: Option<RequestUpgrade>,
-    ) -> impl Future<Output = Result<Option<Proof>, ReplicationMethodsError>> + Send;
+    ) -> impl Future<Output = Result<Option<Proof>, ReplicationMethodsError>> + MaybeSend;
     /// subscribe to core events
-    fn event_subscribe(&self) -> impl Future<Output = Receiver<Event>>;
+    fn event_subscribe(&self) -> impl Future<Output = EventReceiver> + MaybeSend;
+    /// ref Core::want
+    fn want(&self, range: Range<u64>, priority: Priority) -> impl Future<Output = ()> + MaybeSend;
+    /// ref Core::unwant
+    fn unwant(&self, range: Range<u64>) -> impl Future<Output = bool> + MaybeSend;
+    /// ref Core::wants
+    fn wants(&self) -> impl Future<Output = Vec<(Range<u64>, Priority)>> + MaybeSend;
 }
 
 /// Error for CoreMethods trait
@@ -64,29 +137,293 @@ pub enum CoreMethodsError {
     /// Error from hypercore
     #[error("Got a hypercore error [{0}]")]
     HypercoreError(#[from] HypercoreError),
+    /// The core is locked by another task. Returned by the `try_` methods of `SharedCore`
+    /// instead of waiting for the lock.
+    #[error("The core is locked by another task")]
+    WouldBlock,
+    /// Waiting for a block took longer than the given timeout. Returned by
+    /// `SharedCore::get_wait_timeout`.
+    #[error("Timed out waiting for block {index}")]
+    Timeout {
+        /// Index of the block that was waited for
+        index: u64,
+    },
+}
+
+impl CoreMethodsError {
+    /// Whether the same operation may succeed when retried later, see
+    /// [`HypercoreError::is_retryable`]
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Self::HypercoreError(err) => err.is_retryable(),
+            Self::WouldBlock | Self::Timeout { .. } => true,
+        }
+    }
 }
 
 /// Trait for things that consume [`crate::Hypercore`] can instead use this trait
 /// so they can use all Hypercore-like things such as `SharedCore`.
 pub trait CoreMethods: CoreInfo {
     /// Check if the core has the block at the given index locally
-    fn has(&self, index: u64) -> impl Future<Output = bool> + Send;
+    fn has(&self, index: u64) -> impl Future<Output = bool> + MaybeSend;
 
     /// get a block
     fn get(
         &self,
         index: u64,
-    ) -> impl Future<Output = Result<Option<Vec<u8>>, CoreMethodsError>> + Send;
+    ) -> impl Future<Output = Result<Option<Vec<u8>>, CoreMethodsError>> + MaybeSend;
 
     /// Append data to the core
     fn append(
         &self,
         data: &[u8],
-    ) -> impl Future<Output = Result<AppendOutcome, CoreMethodsError>> + Send;
+    ) -> impl Future<Output = Result<AppendOutcome, CoreMethodsError>> + MaybeSend;
 
     /// Append a batch of data to the core
-    fn append_batch<A: AsRef<[u8]>, B: AsRef<[A]> + Send>(
+    fn append_batch<A: AsRef<[u8]>, B: AsRef<[A]> + MaybeSend>(
         &self,
         batch: B,
-    ) -> impl Future<Output = Result<AppendOutcome, CoreMethodsError>> + Send;
+    ) -> impl Future<Output = Result<AppendOutcome, CoreMethodsError>> + MaybeSend;
+
+    /// Clear data for entries between start and end (exclusive) indexes (see:
+    /// [`crate::Hypercore::clear`])
+    fn clear(
+        &self,
+        start: u64,
+        end: u64,
+    ) -> impl Future<Output = Result<(), CoreMethodsError>> + MaybeSend;
+
+    /// Find the block containing the byte at `byte_offset`, returns the index of the block and
+    /// the offset within it (see: [`crate::Hypercore::seek`])
+    fn seek(
+        &self,
+        byte_offset: u64,
+    ) -> impl Future<Output = Result<(u64, u64), CoreMethodsError>> + MaybeSend;
+}
+
+/// Implement the hypercore traits for a pointer type by forwarding to the pointee, so e.g.
+/// `Arc<SharedCore>` can be passed where `impl CoreMethods` is expected. The bounds are the extra
+/// bounds `T` needs for the pointer to be [`MaybeSend`], as required by [`ReplicationMethods`].
+macro_rules! impl_traits_for_pointer {
+    ($pointer:ty, $($bounds:tt)*) => {
+        impl<T: CoreInfo + ?Sized> CoreInfo for $pointer {
+            fn info(&self) -> impl Future<Output = Info> + MaybeSend {
+                T::info(&**self)
+            }
+
+            fn key_pair(&self) -> impl Future<Output = PartialKeypair> + MaybeSend {
+                T::key_pair(&**self)
+            }
+        }
+
+        impl<T: CoreMethods + ?Sized> CoreMethods for $pointer {
+            fn has(&self, index: u64) -> impl Future<Output = bool> + MaybeSend {
+                T::has(&**self, index)
+            }
+
+            fn get(
+                &self,
+                index: u64,
+            ) -> impl Future<Output = Result<Option<Vec<u8>>, CoreMethodsError>> + MaybeSend {
+                T::get(&**self, index)
+            }
+
+            fn append(
+                &self,
+                data: &[u8],
+            ) -> impl Future<Output = Result<AppendOutcome, CoreMethodsError>> + MaybeSend {
+                T::append(&**self, data)
+            }
+
+            fn append_batch<A: AsRef<[u8]>, B: AsRef<[A]> + MaybeSend>(
+                &self,
+                batch: B,
+            ) -> impl Future<Output = Result<AppendOutcome, CoreMethodsError>> + MaybeSend {
+                T::append_batch(&**self, batch)
+            }
+
+            fn clear(
+                &self,
+                start: u64,
+                end: u64,
+            ) -> impl Future<Output = Result<(), CoreMethodsError>> + MaybeSend {
+                T::clear(&**self, start, end)
+            }
+
+            fn seek(
+                &self,
+                byte_offset: u64,
+            ) -> impl Future<Output = Result<(u64, u64), CoreMethodsError>> + MaybeSend {
+                T::seek(&**self, byte_offset)
+            }
+        }
+
+        impl<T: ReplicationMethods + ?Sized $($bounds)*> ReplicationMethods for $pointer {
+            fn verify_and_apply_proof(
+                &self,
+                proof: &Proof,
+            ) -> impl Future<Output = Result<bool, ReplicationMethodsError>> + MaybeSend {
+                T::verify_and_apply_proof(&**self, proof)
+            }
+
+            fn verify_and_apply_proofs(
+                &self,
+                proofs: &[Proof],
+            ) -> impl Future<Output = Result<Vec<bool>, ReplicationMethodsError>> + MaybeSend {
+                T::verify_and_apply_proofs(&**self, proofs)
+            }
+
+            fn missing_nodes(
+                &self,
+                index: u64,
+            ) -> impl Future<Output = Result<u64, ReplicationMethodsError>> + MaybeSend {
+                T::missing_nodes(&**self, index)
+            }
+
+            fn missing_nodes_range(
+                &self,
+                range: Range<u64>,
+            ) -> impl Future<Output = Result<Vec<u64>, ReplicationMethodsError>> + MaybeSend {
+                T::missing_nodes_range(&**self, range)
+            }
+
+            fn create_proof(
+                &self,
+                block: Option<RequestBlock>,
+                hash: Option<RequestBlock>,
+                seek: Option<RequestSeek>,
+                upgrade: Option<RequestUpgrade>,
+            ) -> impl Future<Output = Result<Option<Proof>, ReplicationMethodsError>> + MaybeSend {
+                T::create_proof(&**self, block, hash, seek, upgrade)
+            }
+
+            fn event_subscribe(&self) -> impl Future<Output = EventReceiver> + MaybeSend {
+                T::event_subscribe(&**self)
+            }
+
+            fn want(
+                &self,
+                range: Range<u64>,
+                priority: Priority,
+            ) -> impl Future<Output = ()> + MaybeSend {
+                T::want(&**self, range, priority)
+            }
+
+            fn unwant(&self, range: Range<u64>) -> impl Future<Output = bool> + MaybeSend {
+                T::unwant(&**self, range)
+            }
+
+            fn wants(&self) -> impl Future<Output = Vec<(Range<u64>, Priority)>> + MaybeSend {
+                T::wants(&**self)
+            }
+        }
+    };
+}
+
+impl_traits_for_pointer!(&T, + MaybeSync);
+impl_traits_for_pointer!(Arc<T>, + MaybeSync);
+impl_traits_for_pointer!(Box<T>,);
+
+#[cfg(all(test, not(feature = "send")))]
+mod tests {
+    use super::{CoreInfo, CoreMethods, CoreMethodsError, DynCoreMethods, MaybeSend};
+    use crate::{AppendOutcome, HypercoreError, Info, PartialKeypair, generate_signing_key};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// Core holding `!Send` state, like a storage handle on single-threaded wasm
+    struct LocalCore {
+        blocks: Rc<RefCell<Vec<Vec<u8>>>>,
+        key_pair: PartialKeypair,
+    }
+
+    impl CoreInfo for LocalCore {
+        async fn info(&self) -> Info {
+            let blocks = self.blocks.borrow();
+            Info {
+                length: blocks.len() as u64,
+                byte_length: blocks.iter().map(|block| block.len() as u64).sum(),
+                contiguous_length: blocks.len() as u64,
+                fork: 0,
+                writeable: true,
+            }
+        }
+
+        async fn key_pair(&self) -> PartialKeypair {
+            self.key_pair.clone()
+        }
+    }
+
+    impl CoreMethods for LocalCore {
+        async fn has(&self, index: u64) -> bool {
+            (index as usize) < self.blocks.borrow().len()
+        }
+
+        async fn get(&self, index: u64) -> Result<Option<Vec<u8>>, CoreMethodsError> {
+            Ok(self.blocks.borrow().get(index as usize).cloned())
+        }
+
+        async fn append(&self, data: &[u8]) -> Result<AppendOutcome, CoreMethodsError> {
+            CoreMethods::append_batch(self, [data]).await
+        }
+
+        async fn append_batch<A: AsRef<[u8]>, B: AsRef<[A]> + MaybeSend>(
+            &self,
+            batch: B,
+        ) -> Result<AppendOutcome, CoreMethodsError> {
+            let start = CoreInfo::info(self).await;
+            let mut byte_offsets = vec![];
+            let mut offset = start.byte_length;
+            for data in batch.as_ref() {
+                byte_offsets.push(offset);
+                offset += data.as_ref().len() as u64;
+                self.blocks.borrow_mut().push(data.as_ref().to_vec());
+            }
+            let info = CoreInfo::info(self).await;
+            Ok(AppendOutcome {
+                length: info.length,
+                byte_length: info.byte_length,
+                blocks: start.length..info.length,
+                byte_offsets,
+                fork: 0,
+                signature: vec![],
+            })
+        }
+
+        async fn clear(&self, _start: u64, _end: u64) -> Result<(), CoreMethodsError> {
+            Ok(())
+        }
+
+        async fn seek(&self, mut byte_offset: u64) -> Result<(u64, u64), CoreMethodsError> {
+            for (index, block) in self.blocks.borrow().iter().enumerate() {
+                if byte_offset < block.len() as u64 {
+                    return Ok((index as u64, byte_offset));
+                }
+                byte_offset -= block.len() as u64;
+            }
+            Err(HypercoreError::BadArgument {
+                context: "Byte offset is not before byte length".to_string(),
+            }
+            .into())
+        }
+    }
+
+    #[async_std::test]
+    async fn local_core_without_send() -> Result<(), CoreMethodsError> {
+        let signing_key = generate_signing_key();
+        let core = LocalCore {
+            blocks: Rc::default(),
+            key_pair: PartialKeypair {
+                public: signing_key.verifying_key(),
+                secret: Some(signing_key),
+            },
+        };
+        assert_eq!(CoreMethods::append(&core, b"hello").await?.blocks, 0..1);
+        let core: Box<dyn DynCoreMethods> = Box::new(core);
+        assert_eq!(
+            DynCoreMethods::get(&*core, 0).await?,
+            Some(b"hello".to_vec())
+        );
+        Ok(())
+    }
 }
@@ -0,0 +1,984 @@
+//! Transport-agnostic replication state machine for one remote peer
+use async_broadcast::Sender;
+use futures::channel::mpsc::{UnboundedReceiver, UnboundedSender, unbounded};
+use hypercore_schema::RequestBlock;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::Arc;
+use std::time::Instant;
+
+use super::events::{Event, Get, Have, Priority};
+use super::extension::PeerExtension;
+use super::inflight::{InflightRequests, RequestTimeout};
+use super::messages::{
+    Bitfield, Data, Extension, Message, NoData, Range, Request, Synchronize, Unwant, Want,
+};
+use super::upgrade::UpgradeNegotiator;
+use super::{CoreMethods, RemoteBitfield, ReplicationMethods, ReplicationMethodsError};
+use crate::RateLimiter;
+#[cfg(any(feature = "tokio", feature = "async-std"))]
+use {
+    super::events::EventStream,
+    super::transport::{ReplicateError, ReplicationTransport},
+    crate::common::time::sleep,
+};
+
+/// Maximum number of inflight requests after which wanted blocks are no longer requested. Blocks
+/// that are read, i.e. that have a [`Get`] event, are requested regardless.
+const MAX_INFLIGHT_WANTED: usize = 16;
+
+/// Interval at which [`Peer::run`] handles request timeouts
+#[cfg(any(feature = "tokio", feature = "async-std"))]
+pub const TIMEOUT_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// A range of blocks wanted with [`ReplicationMethods::want`]
+#[derive(Debug, Clone)]
+struct Wanted {
+    range: std::ops::Range<u64>,
+    priority: Priority,
+    /// Blocks before this are present or were requested
+    cursor: u64,
+}
+
+/// Replication state machine for one remote peer. It doesn't do any IO: the caller feeds it the
+/// messages received from the remote with [`Peer::handle_message`] and the events of the local
+/// core with [`Peer::handle_event`], and sends the messages taken with [`Peer::next_message`] to
+/// the remote.
+///
+/// The peer tracks the remote's fork, length and the blocks it has. Missing blocks are requested
+/// when a [`Get`] event is handled for them, or eagerly when they are in a range registered with
+/// [`ReplicationMethods::want`], higher [`Priority`] blocks first. [`Priority::High`] requests are
+/// sent ahead of the requests already queued. Local [`Have`] events are announced to the remote
+/// with [`Range`] messages. Received proofs are verified and applied to the core.
+#[derive(Debug)]
+pub struct Peer<T> {
+    core: T,
+    negotiator: UpgradeNegotiator,
+    remote_bitfield: RemoteBitfield,
+    /// Blocks wanted locally that the remote doesn't have yet, and their priority
+    pending: BTreeMap<u64, Priority>,
+    /// Priority of the blocks requested from the remote
+    requested: HashMap<u64, Priority>,
+    wants: Vec<Wanted>,
+    inflight: InflightRequests,
+    outgoing: VecDeque<Message>,
+    /// Senders of incoming extension messages by extension name
+    extensions: HashMap<String, UnboundedSender<Vec<u8>>>,
+    extension_sender: UnboundedSender<Extension>,
+    extension_receiver: UnboundedReceiver<Extension>,
+    upload_rate_limiter: Option<Arc<dyn RateLimiter>>,
+    download_rate_limiter: Option<Arc<dyn RateLimiter>>,
+}
+
+impl<T: CoreMethods + ReplicationMethods> Peer<T> {
+    /// Create a peer replicating the given core
+    pub fn new(core: T) -> Self {
+        let (extension_sender, extension_receiver) = unbounded();
+        Self {
+            core,
+            negotiator: UpgradeNegotiator::new(),
+            remote_bitfield: RemoteBitfield::new(),
+            pending: BTreeMap::new(),
+            requested: HashMap::new(),
+            wants: vec![],
+            inflight: InflightRequests::default(),
+            outgoing: VecDeque::new(),
+            extensions: HashMap::new(),
+            extension_sender,
+            extension_receiver,
+            upload_rate_limiter: None,
+            download_rate_limiter: None,
+        }
+    }
+
+    /// Use `inflight` to track the requests sent to the remote, e.g. to change the request
+    /// timeout. The default is [`InflightRequests::default`].
+    pub fn with_inflight_requests(mut self, inflight: InflightRequests) -> Self {
+        self.inflight = inflight;
+        self
+    }
+
+    /// Limit the block data sent to the remote with `limiter`, see [`crate::TokenBucket`]. The
+    /// data is throttled after its proof is created, without holding on to the core.
+    pub fn with_upload_rate_limiter(mut self, limiter: Arc<dyn RateLimiter>) -> Self {
+        self.upload_rate_limiter = Some(limiter);
+        self
+    }
+
+    /// Limit the block data received from the remote with `limiter`, see [`crate::TokenBucket`].
+    /// The data is throttled before its proof is verified and applied, so reading and writing
+    /// the core isn't blocked meanwhile.
+    pub fn with_download_rate_limiter(mut self, limiter: Arc<dyn RateLimiter>) -> Self {
+        self.download_rate_limiter = Some(limiter);
+        self
+    }
+
+    /// The replicated core
+    pub fn core(&self) -> &T {
+        &self.core
+    }
+
+    /// Fork of the remote core
+    pub fn remote_fork(&self) -> u64 {
+        self.negotiator.remote_fork()
+    }
+
+    /// Length of the remote core
+    pub fn remote_length(&self) -> u64 {
+        self.negotiator.remote_length()
+    }
+
+    /// Whether the remote has announced having the block at `index`
+    pub fn remote_has(&self, index: u64) -> bool {
+        self.remote_bitfield.remote_has(index)
+    }
+
+    /// The blocks the remote has announced having
+    pub fn remote_bitfield(&self) -> &RemoteBitfield {
+        &self.remote_bitfield
+    }
+
+    /// Number of requests sent to the remote and not yet answered
+    pub fn inflight_requests(&self) -> usize {
+        self.inflight.len()
+    }
+
+    /// Take the next message to send to the remote
+    pub fn next_message(&mut self) -> Option<Message> {
+        self.outgoing
+            .pop_front()
+            .or_else(|| self.extension_receiver.try_recv().ok().map(Message::from))
+    }
+
+    /// Register an extension to exchange application messages with the remote. Registering the
+    /// same name again replaces the previous handle.
+    pub fn register_extension(&mut self, name: impl Into<String>) -> PeerExtension {
+        let name = name.into();
+        let (sender, receiver) = unbounded();
+        self.extensions.insert(name.clone(), sender);
+        PeerExtension::new(name, self.extension_sender.clone(), receiver)
+    }
+
+    /// Start replicating by announcing the local core and the wanted ranges to the remote
+    pub async fn start(&mut self) -> Result<(), ReplicationMethodsError> {
+        self.send_synchronize().await;
+        let contiguous_length = self.core.info().await.contiguous_length;
+        if contiguous_length > 0 {
+            self.send(Range {
+                drop: false,
+                start: 0,
+                length: contiguous_length,
+            });
+        }
+        for (range, priority) in self.core.wants().await {
+            self.add_want(range, priority);
+        }
+        Ok(())
+    }
+
+    /// Handle a message received from the remote
+    pub async fn handle_message(
+        &mut self,
+        message: Message,
+    ) -> Result<(), ReplicationMethodsError> {
+        match message {
+            Message::Synchronize(synchronize) => self.on_synchronize(synchronize).await,
+            Message::Request(request) => self.on_request(request).await,
+            Message::Data(data) => self.on_data(data).await,
+            Message::NoData(NoData { request }) => {
+                if let Some(request) = self.inflight.remove(request)
+                    && let Some(RequestBlock { index, .. }) = request.block
+                {
+                    // Wait for the remote to announce the block again
+                    self.remote_bitfield.set_range(index, index + 1, false);
+                    self.repend(index);
+                }
+                Ok(())
+            }
+            Message::Want(want) => self.on_want(want).await,
+            // Wants of the remote are answered right away, so there is nothing to withdraw
+            Message::Unwant(_) => Ok(()),
+            Message::Bitfield(bitfield) => self.on_bitfield(bitfield).await,
+            Message::Range(range) => self.on_range(range).await,
+            Message::Extension(Extension { name, message }) => {
+                // Messages of unknown extensions are ignored
+                if let Some(sender) = self.extensions.get(&name)
+                    && sender.unbounded_send(message).is_err()
+                {
+                    self.extensions.remove(&name);
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Reissue the requests the remote didn't answer in time, see
+    /// [`InflightRequests::poll_timeouts`]. Should be called periodically. When a block request
+    /// runs out of retries, the remote is considered not to have the block, and it is requested
+    /// again once the remote announces it.
+    pub fn handle_timeouts(&mut self, now: Instant) {
+        for timeout in self.inflight.poll_timeouts(now) {
+            match timeout {
+                RequestTimeout::Reissued(request) => self.send(request),
+                RequestTimeout::Failed(Request {
+                    block: Some(RequestBlock { index, .. }),
+                    ..
+                }) => {
+                    self.remote_bitfield.set_range(index, index + 1, false);
+                    self.repend(index);
+                }
+                RequestTimeout::Failed(_) => {}
+            }
+        }
+    }
+
+    /// Handle an event of the local core, from [`ReplicationMethods::event_subscribe`]
+    pub async fn handle_event(&mut self, event: Event) -> Result<(), ReplicationMethodsError> {
+        match event {
+            Event::Get(Get {
+                index,
+                priority,
+                get_result,
+            }) => self.request_block(index, priority, Some(get_result)).await,
+            Event::Have(Have {
+                start,
+                length,
+                stored,
+                drop,
+                ..
+            }) => {
+                // Blocks only in the tree can't be served to the remote
+                if stored || drop {
+                    self.send(Range {
+                        drop,
+                        start,
+                        length,
+                    });
+                }
+                Ok(())
+            }
+            Event::DataUpgrade(_) => {
+                self.send_synchronize().await;
+                self.request_wanted().await
+            }
+            Event::Truncate(_) => {
+                self.send_synchronize().await;
+                Ok(())
+            }
+            Event::EventsDropped(_) => {
+                // Dropped events may have changed the core, announce it again
+                self.send_synchronize().await;
+                self.request_wanted().await
+            }
+            Event::Want(want) => {
+                self.add_want(
+                    want.start..want.start.saturating_add(want.length),
+                    want.priority,
+                );
+                self.request_wanted().await
+            }
+            Event::Unwant(unwant) => {
+                let range = unwant.start..unwant.start.saturating_add(unwant.length);
+                if let Some(position) = self.wants.iter().position(|want| want.range == range) {
+                    self.wants.remove(position);
+                    self.send(Unwant {
+                        start: unwant.start,
+                        length: unwant.length,
+                    });
+                }
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Replicate with the remote over `transport`, until either the transport or the core is
+    /// closed. Messages are sent as soon as they are queued, and timeouts are handled every
+    /// [`TIMEOUT_INTERVAL`].
+    #[cfg(any(feature = "tokio", feature = "async-std"))]
+    pub async fn run(
+        mut self,
+        mut transport: impl ReplicationTransport,
+    ) -> Result<(), ReplicateError> {
+        use futures::{FutureExt, StreamExt};
+
+        let mut events = EventStream::from(self.core.event_subscribe().await);
+        self.start().await?;
+        let mut next_timeouts = Instant::now() + TIMEOUT_INTERVAL;
+        loop {
+            while let Some(message) = self.outgoing.pop_front() {
+                transport.send(message).await?;
+            }
+            let now = Instant::now();
+            if now >= next_timeouts {
+                self.handle_timeouts(now);
+                next_timeouts = now + TIMEOUT_INTERVAL;
+                continue;
+            }
+            futures::select_biased! {
+                event = events.next().fuse() => match event {
+                    Some(Event::Close(_)) | None => return Ok(()),
+                    Some(event) => self.handle_event(event).await?,
+                },
+                message = transport.recv().fuse() => match message {
+                    Some(message) => self.handle_message(message?).await?,
+                    None => return Ok(()),
+                },
+                extension = self.extension_receiver.next() => {
+                    // The peer holds a sender, so the channel never ends
+                    self.outgoing.extend(extension.map(Message::from));
+                }
+                _ = sleep(next_timeouts - now).fuse() => {}
+            }
+        }
+    }
+
+    fn send(&mut self, message: impl Into<Message>) {
+        self.outgoing.push_back(message.into());
+    }
+
+    async fn send_synchronize(&mut self) {
+        let info = self.core.info().await;
+        self.send(Synchronize {
+            fork: info.fork,
+            length: info.length,
+            remote_length: self.negotiator.remote_length(),
+            downloading: true,
+            uploading: true,
+            can_upgrade: true,
+        });
+    }
+
+    /// Request the block at `index` from the remote, or remember it until the remote has it
+    async fn request_block(
+        &mut self,
+        index: u64,
+        priority: Priority,
+        waiter: Option<Sender<()>>,
+    ) -> Result<(), ReplicationMethodsError> {
+        if self.core.has(index).await {
+            return Ok(());
+        }
+        let info = self.core.info().await;
+        let upgrade = self.negotiator.upgrade(info.fork, info.length);
+        let length = info.length + upgrade.as_ref().map_or(0, |upgrade| upgrade.length);
+        if !self.remote_bitfield.remote_has(index) || index >= length {
+            self.add_pending(index, priority);
+            return Ok(());
+        }
+        let priority = self
+            .pending
+            .remove(&index)
+            .map_or(priority, |p| p.max(priority));
+        let priority = *self
+            .requested
+            .entry(index)
+            .and_modify(|requested| *requested = priority.max(*requested))
+            .or_insert(priority);
+        let (block, upgrade) = if index < info.length {
+            let nodes = self.core.missing_nodes(index).await?;
+            (RequestBlock { index, nodes }, None)
+        } else {
+            // The block is past our length, so it comes with an upgrade
+            (RequestBlock { index, nodes: 0 }, upgrade)
+        };
+        if let Some(request) = self.inflight.add(info.fork, Some(block), upgrade, waiter) {
+            if priority == Priority::High {
+                // Ahead of the queued requests of lower priority blocks
+                let position = self
+                    .outgoing
+                    .iter()
+                    .position(|message| {
+                        matches!(
+                            message,
+                            Message::Request(Request { block: Some(block), .. })
+                                if self.requested.get(&block.index) != Some(&Priority::High)
+                        )
+                    })
+                    .unwrap_or(self.outgoing.len());
+                self.outgoing.insert(position, request.into());
+            } else {
+                self.send(request);
+            }
+        }
+        Ok(())
+    }
+
+    fn add_pending(&mut self, index: u64, priority: Priority) {
+        let pending = self.pending.entry(index).or_insert(priority);
+        *pending = priority.max(*pending);
+    }
+
+    /// Request the block at `index` again once the remote has it
+    fn repend(&mut self, index: u64) {
+        let priority = self.requested.remove(&index).unwrap_or_default();
+        self.add_pending(index, priority);
+    }
+
+    fn add_want(&mut self, range: std::ops::Range<u64>, priority: Priority) {
+        self.send(Want {
+            start: range.start,
+            length: range.end.saturating_sub(range.start),
+        });
+        self.wants.push(Wanted {
+            cursor: range.start,
+            range,
+            priority,
+        });
+    }
+
+    /// Request missing blocks of the wanted ranges that the remote has, the ranges of higher
+    /// priority first, up to [`MAX_INFLIGHT_WANTED`] inflight requests
+    async fn request_wanted(&mut self) -> Result<(), ReplicationMethodsError> {
+        let length = self.core.info().await.length;
+        let mut by_priority: Vec<usize> = (0..self.wants.len()).collect();
+        by_priority.sort_by_key(|want_index| std::cmp::Reverse(self.wants[*want_index].priority));
+        for want_index in by_priority {
+            let Wanted {
+                range,
+                priority,
+                cursor,
+            } = self.wants[want_index].clone();
+            let runs: Vec<_> = self
+                .remote_bitfield
+                .runs(cursor..range.end.min(length))
+                .collect();
+            for run in runs {
+                for index in run {
+                    if self.inflight.len() >= MAX_INFLIGHT_WANTED {
+                        return Ok(());
+                    }
+                    if !self.inflight.has_block(index) && !self.core.has(index).await {
+                        self.request_block(index, priority, None).await?;
+                    }
+                    self.wants[want_index].cursor = index + 1;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Request the pending blocks that the remote has, higher priorities first
+    async fn request_pending(&mut self) -> Result<(), ReplicationMethodsError> {
+        let mut requestable: Vec<(u64, Priority)> = self
+            .pending
+            .iter()
+            .map(|(index, priority)| (*index, *priority))
+            .filter(|(index, _)| self.remote_bitfield.remote_has(*index))
+            .collect();
+        requestable.sort_by_key(|(_, priority)| std::cmp::Reverse(*priority));
+        for (index, priority) in requestable {
+            self.request_block(index, priority, None).await?;
+        }
+        Ok(())
+    }
+
+    async fn on_synchronize(
+        &mut self,
+        synchronize: Synchronize,
+    ) -> Result<(), ReplicationMethodsError> {
+        let first = !self.negotiator.is_synchronized();
+        if self.negotiator.remote_synchronized(&synchronize) {
+            // The remote was truncated, it no longer has blocks past its new length
+            self.remote_bitfield
+                .set_range(synchronize.length, u64::MAX, false);
+        }
+        let info = self.core.info().await;
+        if first || synchronize.remote_length != info.length {
+            self.send_synchronize().await;
+        }
+        if !self.inflight.has_upgrade()
+            && let Some(upgrade) = self.negotiator.upgrade(info.fork, info.length)
+            && let Some(request) = self.inflight.add(info.fork, None, Some(upgrade), None)
+        {
+            self.send(request);
+        }
+        Ok(())
+    }
+
+    async fn on_request(&mut self, request: Request) -> Result<(), ReplicationMethodsError> {
+        let proof = self
+            .core
+            .create_proof(request.block, request.hash, request.seek, request.upgrade)
+            .await?;
+        if let (Some(limiter), Some(block)) = (
+            &self.upload_rate_limiter,
+            proof.as_ref().and_then(|proof| proof.block.as_ref()),
+        ) {
+            limiter.acquire(block.value.len() as u64).await;
+        }
+        match proof {
+            Some(proof) => self.send(Data {
+                request: request.id,
+                proof,
+            }),
+            None => self.send(NoData {
+                request: request.id,
+            }),
+        }
+        Ok(())
+    }
+
+    async fn on_data(&mut self, data: Data) -> Result<(), ReplicationMethodsError> {
+        // Data that wasn't requested, or was already answered, is ignored
+        let Some(request) = self.inflight.remove(data.request) else {
+            return Ok(());
+        };
+        self.negotiator.validate(&request, &data.proof)?;
+        if let (Some(limiter), Some(block)) = (&self.download_rate_limiter, &data.proof.block) {
+            limiter.acquire(block.value.len() as u64).await;
+        }
+        self.core.verify_and_apply_proof(&data.proof).await?;
+        if let Some(block) = &data.proof.block {
+            self.pending.remove(&block.index);
+            self.requested.remove(&block.index);
+        }
+        self.request_pending().await?;
+        self.request_wanted().await
+    }
+
+    async fn on_want(&mut self, want: Want) -> Result<(), ReplicationMethodsError> {
+        let end = want
+            .start
+            .saturating_add(want.length)
+            .min(self.core.info().await.length);
+        let mut run_start = None;
+        for index in want.start..end {
+            match (self.core.has(index).await, run_start) {
+                (true, None) => run_start = Some(index),
+                (false, Some(start)) => {
+                    self.send(Range {
+                        drop: false,
+                        start,
+                        length: index - start,
+                    });
+                    run_start = None;
+                }
+                _ => {}
+            }
+        }
+        if let Some(start) = run_start {
+            self.send(Range {
+                drop: false,
+                start,
+                length: end - start,
+            });
+        }
+        Ok(())
+    }
+
+    async fn on_bitfield(&mut self, bitfield: Bitfield) -> Result<(), ReplicationMethodsError> {
+        self.remote_bitfield.apply_bitfield(&bitfield);
+        self.rewind_wants(bitfield.start);
+        self.request_pending().await?;
+        self.request_wanted().await
+    }
+
+    async fn on_range(&mut self, range: Range) -> Result<(), ReplicationMethodsError> {
+        self.remote_bitfield.apply_range(&range);
+        if range.drop {
+            return Ok(());
+        }
+        self.rewind_wants(range.start);
+        self.request_pending().await?;
+        self.request_wanted().await
+    }
+
+    /// Look again at wanted blocks from `start`, after the remote announced having them
+    fn rewind_wants(&mut self, start: u64) {
+        for want in self.wants.iter_mut() {
+            want.cursor = want.cursor.min(start.max(want.range.start));
+        }
+    }
+}
+
+#[cfg(all(test, feature = "shared-core"))]
+mod tests {
+    use super::*;
+    use crate::PartialKeypair;
+    use crate::core::tests::{create_hypercore_with_data, create_hypercore_with_data_and_key_pair};
+    use crate::replication::{CoreInfo, EventReceiver, SharedCore};
+    use std::time::Duration;
+
+    async fn create_peers() -> Result<(Peer<SharedCore>, Peer<SharedCore>), ReplicationMethodsError>
+    {
+        let main = create_hypercore_with_data(10).await?;
+        let clone = create_hypercore_with_data_and_key_pair(
+            0,
+            PartialKeypair {
+                public: main.key_pair.public,
+                secret: None,
+            },
+        )
+        .await?;
+        Ok((
+            Peer::new(SharedCore::from(main)),
+            Peer::new(SharedCore::from(clone)),
+        ))
+    }
+
+    /// Shuttle messages and events between the peers until both are idle
+    async fn run(
+        a: &mut Peer<SharedCore>,
+        a_events: &mut EventReceiver,
+        b: &mut Peer<SharedCore>,
+        b_events: &mut EventReceiver,
+    ) -> Result<(), ReplicationMethodsError> {
+        loop {
+            let mut idle = true;
+            while let Ok(event) = a_events.try_recv() {
+                a.handle_event(event).await?;
+            }
+            while let Ok(event) = b_events.try_recv() {
+                b.handle_event(event).await?;
+            }
+            while let Some(message) = a.next_message() {
+                idle = false;
+                b.handle_message(message).await?;
+            }
+            while let Some(message) = b.next_message() {
+                idle = false;
+                a.handle_message(message).await?;
+            }
+            if idle {
+                return Ok(());
+            }
+        }
+    }
+
+    #[async_std::test]
+    async fn peer_replicates_requested_blocks() -> Result<(), ReplicationMethodsError> {
+        let (mut main, mut clone) = create_peers().await?;
+        let mut main_events = main.core().event_subscribe().await;
+        let mut clone_events = clone.core().event_subscribe().await;
+        main.start().await?;
+        clone.start().await?;
+        run(&mut main, &mut main_events, &mut clone, &mut clone_events).await?;
+
+        // The clone upgraded to the remote length, but has no blocks yet
+        assert_eq!(clone.remote_length(), 10);
+        assert_eq!(clone.core().info().await.length, 10);
+        assert!(clone.remote_has(9));
+        assert!(!clone.core().has(3).await);
+
+        // Getting a missing block emits a Get event, which the peer turns into a request
+        assert_eq!(clone.core().get(3).await?, None);
+        run(&mut main, &mut main_events, &mut clone, &mut clone_events).await?;
+        assert_eq!(clone.core().get(3).await?, Some(b"#3".to_vec()));
+        assert_eq!(clone.inflight_requests(), 0);
+
+        // The clone announced the new block to the main core
+        assert!(main.remote_has(3));
+        assert!(!main.remote_has(4));
+
+        // Appended blocks are announced, and upgrade the clone
+        main.core().append(b"#10").await?;
+        run(&mut main, &mut main_events, &mut clone, &mut clone_events).await?;
+        assert_eq!(clone.core().info().await.length, 11);
+        assert!(clone.remote_has(10));
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn peer_rate_limiters_do_not_block_core() -> Result<(), ReplicationMethodsError> {
+        use futures::future::{BoxFuture, Either, pending, select};
+        use std::pin::pin;
+        use std::sync::Mutex;
+
+        /// Limiter that never lets the data through
+        #[derive(Debug, Default)]
+        struct Stalled(Mutex<Vec<u64>>);
+        impl RateLimiter for Stalled {
+            fn acquire(&self, bytes: u64) -> BoxFuture<'_, ()> {
+                self.0.lock().unwrap().push(bytes);
+                Box::pin(pending())
+            }
+        }
+
+        let upload = Arc::new(Stalled::default());
+        let download = Arc::new(Stalled::default());
+        let (main, clone) = create_peers().await?;
+        let mut main = main.with_upload_rate_limiter(upload.clone());
+        let mut clone = clone.with_download_rate_limiter(download.clone());
+        let mut main_events = main.core().event_subscribe().await;
+        let mut clone_events = clone.core().event_subscribe().await;
+        main.start().await?;
+        clone.start().await?;
+        // Upgrades carry no block data, so they aren't throttled
+        run(&mut main, &mut main_events, &mut clone, &mut clone_events).await?;
+        assert_eq!(clone.core().info().await.length, 10);
+
+        assert_eq!(clone.core().get(3).await?, None);
+        while let Ok(event) = clone_events.try_recv() {
+            clone.handle_event(event).await?;
+        }
+        let request = clone.next_message().expect("request");
+
+        // While the upload is throttled the core can be read and appended to
+        let main_core = main.core().clone();
+        {
+            let throttled = pin!(main.handle_message(request.clone()));
+            let used = pin!(async {
+                assert_eq!(main_core.get(3).await?, Some(b"#3".to_vec()));
+                main_core.append(b"#10").await?;
+                Ok::<_, ReplicationMethodsError>(())
+            });
+            match select(throttled, used).await {
+                Either::Left(_) => panic!("upload wasn't throttled"),
+                Either::Right((result, _)) => result?,
+            }
+        }
+        assert_eq!(*upload.0.lock().unwrap(), vec![2]);
+
+        // Answer the request without the upload limiter
+        main.upload_rate_limiter = None;
+        main.handle_message(request).await?;
+        let data = std::iter::from_fn(|| main.next_message())
+            .find(|message| matches!(message, Message::Data(_)))
+            .expect("data");
+
+        // While the download is throttled the core can be read
+        let clone_core = clone.core().clone();
+        let throttled = pin!(clone.handle_message(data));
+        let used = pin!(async {
+            assert_eq!(clone_core.get(3).await?, None);
+            assert_eq!(clone_core.info().await.length, 10);
+            Ok::<_, ReplicationMethodsError>(())
+        });
+        match select(throttled, used).await {
+            Either::Left(_) => panic!("download wasn't throttled"),
+            Either::Right((result, _)) => result?,
+        }
+        assert_eq!(*download.0.lock().unwrap(), vec![2]);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn peer_downloads_wanted_ranges() -> Result<(), ReplicationMethodsError> {
+        let (mut main, mut clone) = create_peers().await?;
+        let mut main_events = main.core().event_subscribe().await;
+        let mut clone_events = clone.core().event_subscribe().await;
+        clone.core().want(2..8, Priority::Normal).await;
+        assert_eq!(clone.core().wants().await, vec![(2..8, Priority::Normal)]);
+        main.start().await?;
+        clone.start().await?;
+        run(&mut main, &mut main_events, &mut clone, &mut clone_events).await?;
+        for index in 0..10 {
+            assert_eq!(clone.core().has(index).await, (2..8).contains(&index));
+        }
+
+        // Wants registered while replicating are downloaded too, and announced to the remote
+        clone.core().want(0..20, Priority::Normal).await;
+        while let Ok(event) = clone_events.try_recv() {
+            clone.handle_event(event).await?;
+        }
+        assert_eq!(
+            clone.next_message(),
+            Some(Message::Want(Want {
+                start: 0,
+                length: 20
+            }))
+        );
+        run(&mut main, &mut main_events, &mut clone, &mut clone_events).await?;
+        assert_eq!(clone.core().info().await.contiguous_length, 10);
+
+        assert!(clone.core().unwant(0..20).await);
+        assert!(!clone.core().unwant(0..20).await);
+        while let Ok(event) = clone_events.try_recv() {
+            clone.handle_event(event).await?;
+        }
+        assert_eq!(
+            clone.next_message(),
+            Some(Message::Unwant(Unwant {
+                start: 0,
+                length: 20
+            }))
+        );
+        assert_eq!(clone.core().wants().await, vec![(2..8, Priority::Normal)]);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn peer_requests_higher_priorities_first() -> Result<(), ReplicationMethodsError> {
+        let (mut main, mut clone) = create_peers().await?;
+        let mut main_events = main.core().event_subscribe().await;
+        let mut clone_events = clone.core().event_subscribe().await;
+        main.start().await?;
+        clone.start().await?;
+        run(&mut main, &mut main_events, &mut clone, &mut clone_events).await?;
+
+        // High priority blocks are requested ahead of the queued background ones
+        clone.core().want(0..4, Priority::Background).await;
+        clone.core().want(4..8, Priority::High).await;
+        let mut arrived = clone
+            .core()
+            .0
+            .read()
+            .await
+            .wait_for_block(9, Priority::High);
+        while let Ok(event) = clone_events.try_recv() {
+            clone.handle_event(event).await?;
+        }
+        let mut requested = vec![];
+        let mut messages = vec![];
+        while let Some(message) = clone.next_message() {
+            if let Message::Request(Request {
+                block: Some(block), ..
+            }) = &message
+            {
+                requested.push(block.index);
+            }
+            messages.push(message);
+        }
+        assert_eq!(requested, vec![4, 5, 6, 7, 9, 0, 1, 2, 3]);
+        for message in messages {
+            main.handle_message(message).await?;
+        }
+        run(&mut main, &mut main_events, &mut clone, &mut clone_events).await?;
+        for index in [0, 1, 2, 3, 4, 5, 6, 7, 9] {
+            assert!(clone.core().has(index).await);
+        }
+        assert!(arrived.try_recv().is_ok());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn peer_reissues_unanswered_requests() -> Result<(), ReplicationMethodsError> {
+        let (mut main, clone) = create_peers().await?;
+        let timeout = Duration::from_secs(1);
+        let mut clone = clone.with_inflight_requests(InflightRequests::new(timeout, 1));
+        let mut main_events = main.core().event_subscribe().await;
+        let mut clone_events = clone.core().event_subscribe().await;
+        main.start().await?;
+        clone.start().await?;
+        run(&mut main, &mut main_events, &mut clone, &mut clone_events).await?;
+
+        let mut arrived = clone
+            .core()
+            .0
+            .read()
+            .await
+            .wait_for_block(3, Priority::Normal);
+        while let Ok(event) = clone_events.try_recv() {
+            clone.handle_event(event).await?;
+        }
+        let Some(Message::Request(request)) = clone.next_message() else {
+            panic!("Expected a request");
+        };
+
+        // The request is lost, and reissued after the timeout
+        let start = Instant::now();
+        clone.handle_timeouts(start);
+        assert_eq!(clone.next_message(), None);
+        clone.handle_timeouts(start + timeout);
+        let Some(Message::Request(reissued)) = clone.next_message() else {
+            panic!("Expected a reissued request");
+        };
+        assert_eq!(reissued.block, request.block);
+        assert_ne!(reissued.id, request.id);
+
+        // Out of retries the waiter is woken up without the block
+        clone.handle_timeouts(start + timeout * 2);
+        assert_eq!(clone.inflight_requests(), 0);
+        assert!(!clone.remote_has(3));
+        assert!(arrived.try_recv().is_ok());
+        assert!(!clone.core().has(3).await);
+
+        // The block is requested again when the remote announces it
+        clone
+            .handle_message(Message::Range(Range {
+                drop: false,
+                start: 3,
+                length: 1,
+            }))
+            .await?;
+        assert_eq!(clone.inflight_requests(), 1);
+        run(&mut main, &mut main_events, &mut clone, &mut clone_events).await?;
+        assert!(clone.core().has(3).await);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn peer_extensions() -> Result<(), ReplicationMethodsError> {
+        let (mut main, mut clone) = create_peers().await?;
+        let main_extension = main.register_extension("chat");
+        let mut clone_extension = clone.register_extension("chat");
+        let mut clone_other = clone.register_extension("other");
+        assert_eq!(main_extension.name(), "chat");
+
+        assert!(main_extension.send(b"hello".to_vec()));
+        let message = main.next_message().unwrap();
+        assert_eq!(
+            message,
+            Message::Extension(Extension {
+                name: "chat".to_string(),
+                message: b"hello".to_vec()
+            })
+        );
+        clone.handle_message(message).await?;
+        assert_eq!(clone_extension.recv().await, Some(b"hello".to_vec()));
+        assert_eq!(clone_other.try_recv(), None);
+
+        // Unknown extensions are ignored
+        clone
+            .handle_message(Message::Extension(Extension {
+                name: "unknown".to_string(),
+                message: vec![],
+            }))
+            .await?;
+
+        drop(main);
+        assert!(!main_extension.send(b"bye".to_vec()));
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn peer_waits_for_remote_to_have_block() -> Result<(), ReplicationMethodsError> {
+        let (mut main, mut clone) = create_peers().await?;
+        main.core().clear(5, 6).await?;
+        let mut main_events = main.core().event_subscribe().await;
+        let mut clone_events = clone.core().event_subscribe().await;
+        main.start().await?;
+        clone.start().await?;
+        run(&mut main, &mut main_events, &mut clone, &mut clone_events).await?;
+        assert!(!clone.remote_has(5));
+
+        clone.core().get(5).await?;
+        run(&mut main, &mut main_events, &mut clone, &mut clone_events).await?;
+        assert_eq!(clone.inflight_requests(), 0);
+        assert!(!clone.core().has(5).await);
+
+        // The remote answers a want with ranges of the blocks it has
+        clone
+            .handle_message(Message::Want(Want {
+                start: 0,
+                length: 10,
+            }))
+            .await?;
+        assert_eq!(clone.next_message(), None);
+        main.handle_message(Message::Want(Want {
+            start: 0,
+            length: 10,
+        }))
+        .await?;
+        let mut ranges = vec![];
+        while let Some(message) = main.next_message() {
+            ranges.push(message);
+        }
+        assert_eq!(
+            ranges,
+            vec![
+                Message::Range(Range {
+                    drop: false,
+                    start: 0,
+                    length: 5
+                }),
+                Message::Range(Range {
+                    drop: false,
+                    start: 6,
+                    length: 4
+                }),
+            ]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,370 @@
+//! Planning of the requests to send a remote peer for the blocks of a range
+use hypercore_schema::{RequestBlock, RequestUpgrade};
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::Range;
+
+use super::{
+    Availability, CoreMethods, Priority, RemoteBitfield, ReplicationMethods,
+    ReplicationMethodsError, SyncStrategy,
+};
+
+/// Default [`Download::max_requests`]
+const DEFAULT_MAX_REQUESTS: usize = 16;
+/// Number of missing blocks, per request to plan, that the random and rarest-first strategies
+/// choose from
+const CANDIDATES_PER_REQUEST: usize = 64;
+
+/// Blocks to download and how, see [`plan_requests`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Download {
+    /// Range of the blocks
+    pub range: Range<u64>,
+    /// Order in which the missing blocks are requested
+    pub strategy: SyncStrategy,
+    /// Maximum number of requests planned at once
+    pub max_requests: usize,
+    /// Priority of the download, given to its requests
+    pub priority: Priority,
+}
+
+impl Download {
+    /// Download the blocks of `range` sequentially, at normal priority
+    pub fn new(range: Range<u64>) -> Self {
+        Self {
+            range,
+            strategy: SyncStrategy::default(),
+            max_requests: DEFAULT_MAX_REQUESTS,
+            priority: Priority::default(),
+        }
+    }
+}
+
+/// Request to send to a remote peer, as planned by [`plan_requests`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlannedRequest {
+    /// Block to request, with the number of tree nodes the local core misses to verify it
+    pub block: Option<RequestBlock>,
+    /// Upgrade to the length of the remote
+    pub upgrade: Option<RequestUpgrade>,
+    /// Priority of the download the request is for. Requests of higher priority downloads
+    /// should be sent first.
+    pub priority: Priority,
+}
+
+/// Plan the next requests of `download` for the blocks that the remote has and the local `core`
+/// misses, given the blocks the remote announced and its length. The blocks are requested in
+/// the order of the download's [`SyncStrategy`]: the random and rarest-first ones choose among
+/// the first missing blocks of the range, and rarest-first counts the blocks the `others` peers
+/// announced.
+///
+/// Nearby missing blocks are coalesced: the request of a block only asks for the nodes that the
+/// proofs of the blocks planned before it don't bring, so the proofs must be applied in the
+/// order of the requests, e.g. with [`ReplicationMethods::verify_and_apply_proofs`]. When the
+/// remote is longer, the upgrade to its length is requested with the first missing block past
+/// the local length, or alone if the remote has none of those. The blocks after that one are
+/// left for the next plan, made once the upgrade is applied.
+pub async fn plan_requests(
+    core: &(impl ReplicationMethods + CoreMethods),
+    remote: &RemoteBitfield,
+    remote_length: u64,
+    download: &Download,
+    others: &[&RemoteBitfield],
+) -> Result<Vec<PlannedRequest>, ReplicationMethodsError> {
+    let max_requests = download.max_requests;
+    let max_candidates = match download.strategy {
+        SyncStrategy::Sequential => max_requests,
+        _ => max_requests.saturating_mul(CANDIDATES_PER_REQUEST),
+    };
+    let length = core.info().await.length;
+    let upgrade = (remote_length > length).then(|| RequestUpgrade {
+        start: length,
+        length: remote_length - length,
+    });
+    let end = download.range.end.min(length.max(remote_length));
+    let mut candidates = vec![];
+    let mut past_length = None;
+    'runs: for run in remote.runs(download.range.start..end) {
+        for index in run {
+            if candidates.len() >= max_candidates {
+                break 'runs;
+            }
+            if core.has(index).await {
+                continue;
+            }
+            if index >= length {
+                past_length = Some(index);
+                break 'runs;
+            }
+            candidates.push(Availability {
+                index,
+                peers: 1 + others
+                    .iter()
+                    .filter(|other| other.remote_has(index))
+                    .count(),
+            });
+        }
+    }
+    download.strategy.order(&mut candidates);
+    candidates.truncate(max_requests);
+
+    // Missing nodes of the chosen blocks, by runs of consecutive blocks
+    let mut chosen: Vec<u64> = candidates.iter().map(|block| block.index).collect();
+    chosen.sort_unstable();
+    let mut missing = BTreeMap::new();
+    for blocks in chosen.chunk_by(|a, b| a + 1 == *b) {
+        let nodes = core
+            .missing_nodes_range(blocks[0]..blocks[blocks.len() - 1] + 1)
+            .await?;
+        missing.extend(blocks.iter().copied().zip(nodes));
+    }
+
+    let mut planned_blocks = BTreeSet::new();
+    let mut planned = vec![];
+    for Availability { index, .. } in candidates {
+        // The proof of a planned block brings the sibling of the subtree holding this block but
+        // not that one, and the nodes above, the most coming from the closest planned block
+        let closest = [
+            planned_blocks.range(..index).next_back(),
+            planned_blocks.range(index..).next(),
+        ];
+        let nodes = closest
+            .into_iter()
+            .flatten()
+            .map(|other| u64::from((index ^ other).ilog2()))
+            .fold(missing[&index], u64::min);
+        planned_blocks.insert(index);
+        planned.push(PlannedRequest {
+            block: Some(RequestBlock { index, nodes }),
+            upgrade: None,
+            priority: download.priority,
+        });
+    }
+    if planned.len() < max_requests
+        && let Some(upgrade) = upgrade
+        && end > length
+    {
+        planned.push(PlannedRequest {
+            // Verified with the upgrade nodes
+            block: past_length.map(|index| RequestBlock { index, nodes: 0 }),
+            upgrade: Some(upgrade),
+            priority: download.priority,
+        });
+    }
+    Ok(planned)
+}
+
+#[cfg(all(test, feature = "shared-core"))]
+mod tests {
+    use super::*;
+    use crate::PartialKeypair;
+    use crate::core::tests::{create_hypercore_with_data, create_hypercore_with_data_and_key_pair};
+    use crate::replication::{CoreInfo, SharedCore};
+
+    async fn proofs(
+        main: &SharedCore,
+        planned: &[PlannedRequest],
+    ) -> Result<Vec<hypercore_schema::Proof>, ReplicationMethodsError> {
+        let mut proofs = vec![];
+        for request in planned {
+            let proof = main
+                .create_proof(request.block.clone(), None, None, request.upgrade.clone())
+                .await?;
+            proofs.push(proof.unwrap());
+        }
+        Ok(proofs)
+    }
+
+    #[async_std::test]
+    async fn plan_requests_coalesces_blocks() -> Result<(), ReplicationMethodsError> {
+        let main = SharedCore::from(create_hypercore_with_data(10).await?);
+        let clone = SharedCore::from(
+            create_hypercore_with_data_and_key_pair(
+                0,
+                PartialKeypair {
+                    public: main.key_pair().await.public,
+                    secret: None,
+                },
+            )
+            .await?,
+        );
+        let mut remote = RemoteBitfield::new();
+        remote.set_range(0, 10, true);
+
+        // The upgrade comes with the first block, the others wait for it
+        let planned = plan_requests(&clone, &remote, 10, &Download::new(2..8), &[]).await?;
+        assert_eq!(
+            planned,
+            vec![PlannedRequest {
+                block: Some(RequestBlock { index: 2, nodes: 0 }),
+                upgrade: Some(RequestUpgrade {
+                    start: 0,
+                    length: 10
+                }),
+                priority: Priority::Normal,
+            }]
+        );
+        assert_eq!(
+            clone
+                .verify_and_apply_proofs(&proofs(&main, &planned).await?)
+                .await?,
+            vec![true]
+        );
+
+        // Blocks only ask for the nodes the blocks planned before them don't bring
+        remote.set_range(5, 6, false);
+        let planned = plan_requests(&clone, &remote, 10, &Download::new(0..10), &[]).await?;
+        let blocks: Vec<(u64, u64)> = planned
+            .iter()
+            .map(|request| request.block.as_ref().unwrap())
+            .map(|block| (block.index, block.nodes))
+            .collect();
+        assert_eq!(
+            blocks,
+            vec![
+                (0, 1),
+                (1, 0),
+                (3, 0),
+                (4, 2),
+                (6, 1),
+                (7, 0),
+                (8, 1),
+                (9, 0)
+            ]
+        );
+        assert!(planned.iter().all(|request| request.upgrade.is_none()));
+        let applied = clone
+            .verify_and_apply_proofs(&proofs(&main, &planned).await?)
+            .await?;
+        assert_eq!(applied, vec![true; 8]);
+        for index in [0, 1, 3, 4, 6, 7, 8, 9] {
+            assert!(clone.has(index).await);
+        }
+
+        // At most the given number of requests
+        let fresh = SharedCore::from(
+            create_hypercore_with_data_and_key_pair(
+                0,
+                PartialKeypair {
+                    public: main.key_pair().await.public,
+                    secret: None,
+                },
+            )
+            .await?,
+        );
+        let upgrade = plan_requests(
+            &fresh,
+            &RemoteBitfield::new(),
+            10,
+            &Download::new(0..10),
+            &[],
+        )
+        .await?;
+        assert_eq!(
+            upgrade,
+            vec![PlannedRequest {
+                block: None,
+                upgrade: Some(RequestUpgrade {
+                    start: 0,
+                    length: 10
+                }),
+                priority: Priority::Normal,
+            }]
+        );
+        fresh
+            .verify_and_apply_proofs(&proofs(&main, &upgrade).await?)
+            .await?;
+        let download = Download {
+            max_requests: 3,
+            ..Download::new(0..10)
+        };
+        let planned = plan_requests(&fresh, &remote, 10, &download, &[]).await?;
+        assert_eq!(planned.len(), 3);
+        assert_eq!(
+            fresh
+                .verify_and_apply_proofs(&proofs(&main, &planned).await?)
+                .await?,
+            vec![true; 3]
+        );
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn plan_requests_in_strategy_order() -> Result<(), ReplicationMethodsError> {
+        let main = SharedCore::from(create_hypercore_with_data(16).await?);
+        let clone = || async {
+            let upgraded = SharedCore::from(
+                create_hypercore_with_data_and_key_pair(
+                    0,
+                    PartialKeypair {
+                        public: main.key_pair().await.public,
+                        secret: None,
+                    },
+                )
+                .await?,
+            );
+            let upgrade = plan_requests(
+                &upgraded,
+                &RemoteBitfield::new(),
+                16,
+                &Download::new(0..16),
+                &[],
+            )
+            .await?;
+            upgraded
+                .verify_and_apply_proofs(&proofs(&main, &upgrade).await?)
+                .await?;
+            Ok::<_, ReplicationMethodsError>(upgraded)
+        };
+        let mut remote = RemoteBitfield::new();
+        remote.set_range(0, 16, true);
+
+        // Out of order proofs still verify when applied in the planned order
+        let random = clone().await?;
+        let download = Download {
+            strategy: SyncStrategy::Random { seed: 7 },
+            ..Download::new(0..16)
+        };
+        let planned = plan_requests(&random, &remote, 16, &download, &[]).await?;
+        let indexes: Vec<u64> = planned
+            .iter()
+            .map(|request| request.block.as_ref().unwrap().index)
+            .collect();
+        assert_ne!(indexes, (0..16).collect::<Vec<_>>());
+        assert_eq!(
+            random
+                .verify_and_apply_proofs(&proofs(&main, &planned).await?)
+                .await?,
+            vec![true; 16]
+        );
+
+        // Blocks the other peers lack first
+        let rarest = clone().await?;
+        let mut other = RemoteBitfield::new();
+        other.set_range(0, 12, true);
+        let download = Download {
+            strategy: SyncStrategy::RarestFirst,
+            max_requests: 4,
+            priority: Priority::High,
+            ..Download::new(0..16)
+        };
+        let planned = plan_requests(&rarest, &remote, 16, &download, &[&other]).await?;
+        let blocks: Vec<(u64, u64)> = planned
+            .iter()
+            .map(|request| request.block.as_ref().unwrap())
+            .map(|block| (block.index, block.nodes))
+            .collect();
+        assert_eq!(blocks, vec![(12, 4), (13, 0), (14, 1), (15, 0)]);
+        assert!(
+            planned
+                .iter()
+                .all(|request| request.priority == Priority::High)
+        );
+        assert_eq!(
+            rarest
+                .verify_and_apply_proofs(&proofs(&main, &planned).await?)
+                .await?,
+            vec![true; 4]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,195 @@
+//! Record of the blocks a remote peer has
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+use super::messages;
+
+/// Blocks a remote peer claims to have, built from the [`messages::Range`] and
+/// [`messages::Bitfield`] messages it sends. Blocks are stored as disjoint runs, so memory grows
+/// with the number of gaps instead of the number of blocks. A remote that has its whole core, or a
+/// few contiguous parts of it, takes a handful of entries regardless of its length.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct RemoteBitfield {
+    /// Runs of blocks keyed by start, with exclusive ends. Runs never overlap or touch.
+    runs: BTreeMap<u64, u64>,
+}
+
+impl RemoteBitfield {
+    /// Create an empty remote bitfield
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether the remote has the block at `index`
+    pub fn remote_has(&self, index: u64) -> bool {
+        self.runs
+            .range(..=index)
+            .next_back()
+            .is_some_and(|(_, &end)| index < end)
+    }
+
+    /// Mark the blocks from `start` to `end` (exclusive) as had, or not had when `value` is false
+    pub fn set_range(&mut self, start: u64, end: u64, value: bool) {
+        if start >= end {
+            return;
+        }
+        // Runs overlapping or touching start..end. Ends are sorted like starts.
+        let touching: Vec<(u64, u64)> = self
+            .runs
+            .range(..=end)
+            .rev()
+            .take_while(|(_, run_end)| **run_end >= start)
+            .map(|(&run_start, &run_end)| (run_start, run_end))
+            .collect();
+        let (mut new_start, mut new_end) = (start, end);
+        for (run_start, run_end) in touching {
+            self.runs.remove(&run_start);
+            if value {
+                new_start = new_start.min(run_start);
+                new_end = new_end.max(run_end);
+            } else {
+                if run_start < start {
+                    self.runs.insert(run_start, start);
+                }
+                if run_end > end {
+                    self.runs.insert(end, run_end);
+                }
+            }
+        }
+        if value {
+            self.runs.insert(new_start, new_end);
+        }
+    }
+
+    /// Apply a [`messages::Range`] announced by the remote
+    pub fn apply_range(&mut self, range: &messages::Range) {
+        self.set_range(
+            range.start,
+            range.start.saturating_add(range.length),
+            !range.drop,
+        );
+    }
+
+    /// Apply a [`messages::Bitfield`] announced by the remote. Cleared bits mark blocks the remote
+    /// doesn't have.
+    pub fn apply_bitfield(&mut self, bitfield: &messages::Bitfield) {
+        let mut run: Option<(u64, bool)> = None;
+        let mut index = bitfield.start;
+        for word in &bitfield.bitfield {
+            for bit in 0..32 {
+                let value = word & (1 << bit) != 0;
+                match run {
+                    Some((_, run_value)) if run_value == value => {}
+                    Some((run_start, run_value)) => {
+                        self.set_range(run_start, index, run_value);
+                        run = Some((index, value));
+                    }
+                    None => run = Some((index, value)),
+                }
+                index += 1;
+            }
+        }
+        if let Some((run_start, run_value)) = run {
+            self.set_range(run_start, index, run_value);
+        }
+    }
+
+    /// First block in `range` the remote has and `local_has` returns false for
+    pub fn first_remote_block_we_miss(
+        &self,
+        range: Range<u64>,
+        mut local_has: impl FnMut(u64) -> bool,
+    ) -> Option<u64> {
+        self.runs(range).flatten().find(|index| !local_has(*index))
+    }
+
+    /// Runs of blocks the remote has in `range`, clamped to it
+    pub fn runs(&self, range: Range<u64>) -> impl Iterator<Item = Range<u64>> + '_ {
+        let range = range.start..range.end.max(range.start);
+        // The run containing range.start starts before it
+        let first = self
+            .runs
+            .range(..range.start)
+            .next_back()
+            .filter(|(_, end)| **end > range.start);
+        first
+            .into_iter()
+            .chain(self.runs.range(range.start..range.end))
+            .map(move |(&start, &end)| start.max(range.start)..end.min(range.end))
+            .filter(|run| !run.is_empty())
+    }
+
+    /// Number of blocks the remote has
+    pub fn len(&self) -> u64 {
+        self.runs.iter().map(|(start, end)| end - start).sum()
+    }
+
+    /// Whether the remote has no blocks
+    pub fn is_empty(&self) -> bool {
+        self.runs.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::replication::messages::Bitfield;
+
+    #[test]
+    fn remote_bitfield_ranges() {
+        let mut bitfield = RemoteBitfield::new();
+        bitfield.set_range(0, 5, true);
+        bitfield.set_range(10, 15, true);
+        bitfield.set_range(5, 10, true);
+        assert_eq!(bitfield.runs.len(), 1);
+        bitfield.apply_range(&messages::Range {
+            drop: true,
+            start: 3,
+            length: 9,
+        });
+        assert!(bitfield.remote_has(2) && !bitfield.remote_has(3));
+        assert!(!bitfield.remote_has(11) && bitfield.remote_has(12));
+        assert!(!bitfield.remote_has(15));
+        assert_eq!(bitfield.len(), 6);
+        assert_eq!(bitfield.runs(1..13).collect::<Vec<_>>(), vec![1..3, 12..13]);
+        assert_eq!(bitfield.runs(3..12).count(), 0);
+        let end = 1;
+        assert_eq!(bitfield.runs(13..end).count(), 0);
+    }
+
+    #[test]
+    fn remote_bitfield_from_bitfield_message() {
+        let mut bitfield = RemoteBitfield::new();
+        bitfield.set_range(0, 100, true);
+        bitfield.apply_bitfield(&Bitfield {
+            start: 32,
+            bitfield: vec![0b1010, u32::MAX],
+        });
+        assert!(bitfield.remote_has(31));
+        assert!(!bitfield.remote_has(32) && bitfield.remote_has(33));
+        assert!(!bitfield.remote_has(34) && bitfield.remote_has(35));
+        assert!(!bitfield.remote_has(36) && !bitfield.remote_has(63));
+        assert!(bitfield.remote_has(64) && bitfield.remote_has(99));
+        assert_eq!(bitfield.runs.len(), 4);
+    }
+
+    #[test]
+    fn remote_bitfield_first_block_we_miss() {
+        let mut bitfield = RemoteBitfield::new();
+        bitfield.set_range(2, 6, true);
+        bitfield.set_range(8, 10, true);
+        let local = [0, 1, 2, 3, 4, 5];
+        assert_eq!(
+            bitfield.first_remote_block_we_miss(0..20, |index| local.contains(&index)),
+            Some(8)
+        );
+        assert_eq!(
+            bitfield.first_remote_block_we_miss(3..20, |_| false),
+            Some(3)
+        );
+        assert_eq!(
+            bitfield.first_remote_block_we_miss(0..8, |index| local.contains(&index)),
+            None
+        );
+    }
+}
@@ -1,50 +1,237 @@
 //! Implementation of a Hypercore that can have multiple owners. Along with implementations of all
 //! the hypercore traits.
-use crate::{AppendOutcome, Hypercore, Info, PartialKeypair};
-use async_broadcast::Receiver;
-use async_lock::Mutex;
+use crate::{AppendOutcome, Hypercore, HypercoreError, Info, PartialKeypair};
+use async_lock::RwLock;
+#[cfg(any(feature = "tokio", feature = "async-std"))]
+use futures::future::{Either, select};
 use hypercore_schema::{Proof, RequestBlock, RequestSeek, RequestUpgrade};
+use std::ops::Range;
 use std::sync::Arc;
+#[cfg(any(feature = "tokio", feature = "async-std"))]
+use std::{pin::pin, time::Duration};
 
 use super::{
-    CoreInfo, CoreMethods, CoreMethodsError, Event, ReplicationMethods, ReplicationMethodsError,
+    CoreInfo, CoreMethods, CoreMethodsError, EventReceiver, MaybeSend, Priority,
+    ReplicationMethods, ReplicationMethodsError,
 };
 
-/// Hypercore that can have multiple owners
+/// Options of the background scrubbing started with [`crate::HypercoreBuilder::scrub`]
+#[cfg(any(feature = "tokio", feature = "async-std"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScrubOptions {
+    /// Number of blocks verified at once, while holding the write lock of the core
+    pub blocks_per_step: u64,
+    /// Pause between steps
+    pub interval: Duration,
+}
+
+#[cfg(any(feature = "tokio", feature = "async-std"))]
+impl Default for ScrubOptions {
+    /// 16 blocks every second
+    fn default() -> Self {
+        Self {
+            blocks_per_step: 16,
+            interval: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Hypercore that can have multiple owners. Reads that don't change the core, like
+/// [`CoreMethods::get`], run concurrently under a read lock.
 #[derive(Debug, Clone)]
-pub struct SharedCore(pub Arc<Mutex<Hypercore>>);
+pub struct SharedCore(pub Arc<RwLock<Hypercore>>);
 
 impl From<Hypercore> for SharedCore {
     fn from(core: Hypercore) -> Self {
-        SharedCore(Arc::new(Mutex::new(core)))
+        SharedCore(Arc::new(RwLock::new(core)))
     }
 }
 impl SharedCore {
     /// Create a shared core from a [`Hypercore`]
     pub fn from_hypercore(core: Hypercore) -> Self {
-        SharedCore(Arc::new(Mutex::new(core)))
+        SharedCore(Arc::new(RwLock::new(core)))
+    }
+
+    /// Spawn the scrubbing task, which starts over from the first block after reaching the end.
+    /// It only holds a weak reference to the core, so it ends when the core is dropped.
+    #[cfg(any(feature = "tokio", feature = "async-std"))]
+    pub(crate) fn spawn_scrubber(&self, options: ScrubOptions) {
+        let core = Arc::downgrade(&self.0);
+        crate::common::time::spawn(async move {
+            let mut index = 0;
+            loop {
+                crate::common::time::sleep(options.interval).await;
+                let Some(core) = core.upgrade() else {
+                    return;
+                };
+                let mut core = core.write().await;
+                let length = core.info().length;
+                if index >= length {
+                    index = 0;
+                }
+                let end = length.min(index + options.blocks_per_step);
+                if let Err(_err) = core.scrub(index..end).await {
+                    #[cfg(feature = "tracing")]
+                    tracing::warn!(err = ?_err, "Scrubbing blocks {index}..{end} failed");
+                }
+                index = end;
+            }
+        });
+    }
+
+    /// Read value at given index, waiting for it to be replicated if it's missing. Emits a
+    /// [`super::events::Get`] event with `priority` for a missing block, and returns when the
+    /// block is appended or verified from a proof, or when a replicator emits the event's
+    /// `get_result`. Fails for light hypercores, which never store blocks.
+    pub async fn get_wait(
+        &self,
+        index: u64,
+        priority: Priority,
+    ) -> Result<Vec<u8>, CoreMethodsError> {
+        loop {
+            let mut arrived = {
+                let core = self.0.read().await;
+                if core.is_light() {
+                    return Err(HypercoreError::InvalidOperation {
+                        context: "Light hypercores do not store blocks".to_string(),
+                    }
+                    .into());
+                }
+                if core.has(index)
+                    && let Some(data) = core.get(index).await?
+                {
+                    return Ok(data);
+                }
+                core.wait_for_block(index, priority)
+            };
+            // An error means the event was dropped without the block arriving, so check again
+            let _ = arrived.recv().await;
+        }
+    }
+
+    /// Like [`SharedCore::get_wait`], but fails with [`CoreMethodsError::Timeout`] if the block
+    /// doesn't arrive within `timeout`.
+    #[cfg(any(feature = "tokio", feature = "async-std"))]
+    pub async fn get_wait_timeout(
+        &self,
+        index: u64,
+        priority: Priority,
+        timeout: Duration,
+    ) -> Result<Vec<u8>, CoreMethodsError> {
+        let get_wait = pin!(self.get_wait(index, priority));
+        let sleep = pin!(crate::common::time::sleep(timeout));
+        match select(get_wait, sleep).await {
+            Either::Left((result, _)) => result,
+            Either::Right(_) => Err(CoreMethodsError::Timeout { index }),
+        }
+    }
+
+    /// Download the blocks in `range` and return when they are all stored locally. The range is
+    /// registered with [`Hypercore::want`] at `priority` so replicators fetch it, and withdrawn
+    /// once it's downloaded. Like `core.download({ start, end }).done()` in Javascript.
+    ///
+    /// If the returned future is dropped before completion the range stays wanted, until
+    /// withdrawn with [`ReplicationMethods::unwant`]. Light hypercores never store blocks, so
+    /// for them this only returns for an empty range, use [`SharedCore::download_timeout`].
+    pub async fn download(&self, range: Range<u64>, priority: Priority) {
+        self.0.write().await.want(range.clone(), priority);
+        self.wait_for_range(range.clone(), priority).await;
+        self.0.write().await.unwant(range);
+    }
+
+    /// Like [`SharedCore::download`], but fails with [`CoreMethodsError::Timeout`] for the first
+    /// missing block if the range isn't downloaded within `timeout`. The range is withdrawn either
+    /// way.
+    #[cfg(any(feature = "tokio", feature = "async-std"))]
+    pub async fn download_timeout(
+        &self,
+        range: Range<u64>,
+        priority: Priority,
+        timeout: Duration,
+    ) -> Result<(), CoreMethodsError> {
+        let download = pin!(self.download(range.clone(), priority));
+        let sleep = pin!(crate::common::time::sleep(timeout));
+        match select(download, sleep).await {
+            Either::Left(((), _)) => Ok(()),
+            Either::Right(_) => {
+                let mut core = self.0.write().await;
+                core.unwant(range.clone());
+                match core.first_missing(range) {
+                    Some(index) => Err(CoreMethodsError::Timeout { index }),
+                    // Downloaded just as the timeout hit
+                    None => Ok(()),
+                }
+            }
+        }
+    }
+
+    async fn wait_for_range(&self, range: Range<u64>, priority: Priority) {
+        loop {
+            let mut arrived = {
+                let core = self.0.read().await;
+                let Some(index) = core.first_missing(range.clone()) else {
+                    return;
+                };
+                core.wait_for_block(index, priority)
+            };
+            // An error means the event was dropped without the block arriving, so check again
+            let _ = arrived.recv().await;
+        }
+    }
+
+    /// Like [`CoreMethods::get`], but fails with [`CoreMethodsError::WouldBlock`] instead of
+    /// waiting when the core is being written to.
+    pub async fn try_get(&self, index: u64) -> Result<Option<Vec<u8>>, CoreMethodsError> {
+        let core = self.0.try_read().ok_or(CoreMethodsError::WouldBlock)?;
+        Ok(core.get(index).await?)
+    }
+
+    /// Like [`CoreMethods::append`], but fails with [`CoreMethodsError::WouldBlock`] instead of
+    /// waiting when the core is being read from or written to.
+    pub async fn try_append(&self, data: &[u8]) -> Result<AppendOutcome, CoreMethodsError> {
+        let mut core = self.0.try_write().ok_or(CoreMethodsError::WouldBlock)?;
+        Ok(core.append(data).await?)
+    }
+
+    /// See [`Hypercore::reorg`]
+    pub async fn reorg(&self, proof: &Proof) -> Result<u64, CoreMethodsError> {
+        Ok(self.0.write().await.reorg(proof).await?)
     }
 }
 
 impl CoreInfo for SharedCore {
     async fn info(&self) -> Info {
-        let core = &self.0.lock().await;
+        let core = &self.0.read().await;
         core.info()
     }
 
     async fn key_pair(&self) -> PartialKeypair {
-        let core = &self.0.lock().await;
+        let core = &self.0.read().await;
         core.key_pair().clone()
     }
 }
 
 impl ReplicationMethods for SharedCore {
     async fn verify_and_apply_proof(&self, proof: &Proof) -> Result<bool, ReplicationMethodsError> {
-        Ok(self.0.lock().await.verify_and_apply_proof(proof).await?)
+        Ok(self.0.write().await.verify_and_apply_proof(proof).await?)
+    }
+
+    async fn verify_and_apply_proofs(
+        &self,
+        proofs: &[Proof],
+    ) -> Result<Vec<bool>, ReplicationMethodsError> {
+        Ok(self.0.write().await.verify_and_apply_proofs(proofs).await?)
     }
 
     async fn missing_nodes(&self, index: u64) -> Result<u64, ReplicationMethodsError> {
-        Ok(self.0.lock().await.missing_nodes(index).await?)
+        Ok(self.0.read().await.missing_nodes(index).await?)
+    }
+
+    async fn missing_nodes_range(
+        &self,
+        range: Range<u64>,
+    ) -> Result<Vec<u64>, ReplicationMethodsError> {
+        Ok(self.0.read().await.missing_nodes_range(range).await?)
     }
 
     async fn create_proof(
@@ -56,34 +243,54 @@ impl ReplicationMethods for SharedCore {
     ) -> Result<Option<Proof>, ReplicationMethodsError> {
         Ok(self
             .0
-            .lock()
+            .read()
             .await
             .create_proof(block, hash, seek, upgrade)
             .await?)
     }
 
-    async fn event_subscribe(&self) -> Receiver<Event> {
-        self.0.lock().await.event_subscribe()
+    async fn event_subscribe(&self) -> EventReceiver {
+        self.0.read().await.event_subscribe()
+    }
+
+    async fn want(&self, range: Range<u64>, priority: Priority) {
+        self.0.write().await.want(range, priority)
+    }
+
+    async fn unwant(&self, range: Range<u64>) -> bool {
+        self.0.write().await.unwant(range)
+    }
+
+    async fn wants(&self) -> Vec<(Range<u64>, Priority)> {
+        self.0.read().await.wants().to_vec()
     }
 }
 
 impl CoreMethods for SharedCore {
     async fn has(&self, index: u64) -> bool {
-        self.0.lock().await.has(index)
+        self.0.read().await.has(index)
     }
     async fn get(&self, index: u64) -> Result<Option<Vec<u8>>, CoreMethodsError> {
-        Ok(self.0.lock().await.get(index).await?)
+        Ok(self.0.read().await.get(index).await?)
     }
 
     async fn append(&self, data: &[u8]) -> Result<AppendOutcome, CoreMethodsError> {
-        Ok(self.0.lock().await.append(data).await?)
+        Ok(self.0.write().await.append(data).await?)
     }
 
-    async fn append_batch<A: AsRef<[u8]>, B: AsRef<[A]> + Send>(
+    async fn append_batch<A: AsRef<[u8]>, B: AsRef<[A]> + MaybeSend>(
         &self,
         batch: B,
     ) -> Result<AppendOutcome, CoreMethodsError> {
-        Ok(self.0.lock().await.append_batch(batch).await?)
+        Ok(self.0.write().await.append_batch(batch).await?)
+    }
+
+    async fn clear(&self, start: u64, end: u64) -> Result<(), CoreMethodsError> {
+        Ok(self.0.write().await.clear(start, end).await?)
+    }
+
+    async fn seek(&self, byte_offset: u64) -> Result<(u64, u64), CoreMethodsError> {
+        Ok(self.0.read().await.seek(byte_offset).await?)
     }
 }
 
@@ -93,6 +300,7 @@ mod tests {
     use super::*;
 
     use crate::core::tests::{create_hypercore_with_data, create_hypercore_with_data_and_key_pair};
+    use crate::replication::Event;
     #[async_std::test]
     async fn shared_core_methods() -> Result<(), CoreMethodsError> {
         let core = crate::core::tests::create_hypercore_with_data(0).await?;
@@ -122,7 +330,11 @@ mod tests {
             res,
             AppendOutcome {
                 length: 1,
-                byte_length: 3
+                byte_length: 3,
+                blocks: 0..1,
+                byte_offsets: vec![0],
+                fork: 0,
+                signature: res.signature.clone(),
             }
         );
         assert_eq!(core.has(0).await, true);
@@ -132,7 +344,11 @@ mod tests {
             res,
             AppendOutcome {
                 length: 3,
-                byte_length: 13
+                byte_length: 13,
+                blocks: 1..3,
+                byte_offsets: vec![3, 8],
+                fork: 0,
+                signature: res.signature.clone(),
             }
         );
         assert_eq!(core.has(2).await, true);
@@ -140,6 +356,26 @@ mod tests {
         Ok(())
     }
 
+    #[async_std::test]
+    async fn shared_core_clear_and_seek() -> Result<(), CoreMethodsError> {
+        let core = SharedCore::from(create_hypercore_with_data(0).await?);
+        core.append(b"foo").await?;
+        core.append_batch([b"hello", b"world"]).await?;
+
+        assert_eq!(core.seek(0).await?, (0, 0));
+        assert_eq!(core.seek(4).await?, (1, 1));
+        assert_eq!(core.seek(12).await?, (2, 4));
+        assert!(core.seek(13).await.is_err());
+
+        core.clear(1, 2).await?;
+        assert!(!core.has(1).await);
+        assert_eq!(core.get(1).await?, None);
+        assert_eq!(core.get(2).await?, Some(b"world".into()));
+        // Seeking walks the tree, so it isn't affected by cleared blocks
+        assert_eq!(core.seek(4).await?, (1, 1));
+        Ok(())
+    }
+
     #[async_std::test]
     async fn shared_core_replication_methods() -> Result<(), ReplicationMethodsError> {
         let main = create_hypercore_with_data(10).await?;
@@ -187,4 +423,322 @@ mod tests {
         assert!(clone.verify_and_apply_proof(&proof).await?);
         Ok(())
     }
+
+    #[async_std::test]
+    async fn shared_core_missing_nodes_range() -> Result<(), ReplicationMethodsError> {
+        let main = SharedCore::from(create_hypercore_with_data(10).await?);
+        let clone = SharedCore::from(
+            create_hypercore_with_data_and_key_pair(
+                0,
+                PartialKeypair {
+                    public: main.key_pair().await.public,
+                    secret: None,
+                },
+            )
+            .await?,
+        );
+        assert!(clone.missing_nodes_range(0..0).await?.is_empty());
+        assert_eq!(clone.missing_nodes_range(0..3).await?, vec![0, 0, 0]);
+
+        // Only block 6 is stored locally
+        let index = 6;
+        let nodes = clone.missing_nodes(index).await?;
+        let proof = main
+            .create_proof(
+                Some(RequestBlock { index, nodes }),
+                None,
+                None,
+                Some(RequestUpgrade {
+                    start: 0,
+                    length: 10,
+                }),
+            )
+            .await?
+            .unwrap();
+        assert!(clone.verify_and_apply_proof(&proof).await?);
+
+        assert!(clone.missing_nodes_range(4..4).await?.is_empty());
+        // The nodes of block 6 and its sibling come with the proof
+        assert_eq!(clone.missing_nodes_range(4..8).await?, vec![1, 1, 0, 0]);
+        assert_eq!(
+            clone.missing_nodes_range(2..4).await?,
+            vec![clone.missing_nodes(2).await?, clone.missing_nodes(3).await?]
+        );
+        // Nothing is missing past the length
+        assert_eq!(
+            clone.missing_nodes_range(8..14).await?,
+            vec![1, 1, 0, 0, 0, 0]
+        );
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn shared_core_concurrent_reads() -> Result<(), ReplicationMethodsError> {
+        let core = SharedCore::from(create_hypercore_with_data(3).await?);
+        // Reads don't wait for each other
+        let _guard = core.0.read().await;
+        let (first, second) = futures::join!(core.get(0), core.get(2));
+        assert_eq!(first?, Some(b"#0".to_vec()));
+        assert_eq!(second?, Some(b"#2".to_vec()));
+        assert!(core.has(1).await);
+        assert_eq!(core.missing_nodes(1).await?, 0);
+        assert_eq!(core.missing_nodes_range(0..3).await?, vec![0, 0, 0]);
+
+        // So do proofs served to many peers
+        let request = || Some(RequestBlock { index: 1, nodes: 0 });
+        let (first, second) = futures::join!(
+            core.create_proof(request(), None, None, None),
+            core.create_proof(request(), None, None, None)
+        );
+        let first = first?;
+        assert!(first.as_ref().is_some_and(|proof| proof.block.is_some()));
+        assert_eq!(first, second?);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn shared_core_as_trait_objects() -> Result<(), ReplicationMethodsError> {
+        async fn first_block<C: CoreMethods + ?Sized>(
+            core: &C,
+        ) -> Result<Option<Vec<u8>>, CoreMethodsError> {
+            core.get(0).await
+        }
+
+        let main = SharedCore::from(create_hypercore_with_data(0).await?);
+        let other = SharedCore::from(create_hypercore_with_data(0).await?);
+        let cores: Vec<Box<dyn crate::replication::DynCoreMethods>> =
+            vec![Box::new(main.clone()), Box::new(other)];
+        for core in &cores {
+            core.append_batch(&[b"hello", b"world"]).await?;
+        }
+        for core in &cores {
+            assert_eq!(core.info().await.length, 2);
+            assert_eq!(first_block(core.as_ref()).await?, Some(b"hello".to_vec()));
+        }
+
+        let main: Box<dyn crate::replication::DynReplicationMethods> = Box::new(main);
+        let proof = main
+            .create_proof(None, Some(RequestBlock { index: 1, nodes: 0 }), None, None)
+            .await?;
+        assert!(proof.is_some());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn shared_core_behind_pointers() -> Result<(), ReplicationMethodsError> {
+        async fn append_and_get(
+            core: impl CoreMethods + ReplicationMethods,
+            data: &[u8],
+        ) -> Result<Option<Vec<u8>>, ReplicationMethodsError> {
+            let outcome = core.append(data).await?;
+            assert_eq!(core.missing_nodes(outcome.length - 1).await?, 0);
+            Ok(core.get(outcome.length - 1).await?)
+        }
+
+        let core = SharedCore::from(create_hypercore_with_data(0).await?);
+        assert_eq!(append_and_get(&core, b"a").await?, Some(b"a".to_vec()));
+        let core = Arc::new(core);
+        assert_eq!(
+            append_and_get(core.clone(), b"b").await?,
+            Some(b"b".to_vec())
+        );
+        assert_eq!(
+            append_and_get(Box::new(core.clone()), b"c").await?,
+            Some(b"c".to_vec())
+        );
+
+        let boxed: Box<dyn crate::replication::DynCoreMethods> = Box::new(core);
+        assert_eq!(boxed.append(b"d").await?.length, 4);
+        assert_eq!(boxed.get(3).await?, Some(b"d".to_vec()));
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn shared_core_try_methods() -> Result<(), CoreMethodsError> {
+        let core = SharedCore::from(create_hypercore_with_data(1).await?);
+        {
+            let _read = core.0.read().await;
+            assert_eq!(core.try_get(0).await?, Some(b"#0".to_vec()));
+            assert!(matches!(
+                core.try_append(b"a").await,
+                Err(CoreMethodsError::WouldBlock)
+            ));
+        }
+        {
+            let _write = core.0.write().await;
+            assert!(matches!(
+                core.try_get(0).await,
+                Err(CoreMethodsError::WouldBlock)
+            ));
+        }
+        assert_eq!(core.try_append(b"a").await?.length, 2);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn shared_core_get_wait() -> Result<(), ReplicationMethodsError> {
+        let main = create_hypercore_with_data(10).await?;
+        let clone = create_hypercore_with_data_and_key_pair(
+            0,
+            PartialKeypair {
+                public: main.key_pair.public,
+                secret: None,
+            },
+        )
+        .await?;
+        let main = SharedCore::from(main);
+        let clone = SharedCore::from(clone);
+        assert_eq!(main.get_wait(1, Priority::Normal).await?, b"#1".to_vec());
+
+        let mut events = clone.event_subscribe().await;
+        let replicate = async {
+            let Ok(Event::Get(get)) = events.recv().await else {
+                panic!("Expected a Get event");
+            };
+            let nodes = clone.missing_nodes(get.index).await?;
+            let proof = main
+                .create_proof(
+                    Some(RequestBlock {
+                        index: get.index,
+                        nodes,
+                    }),
+                    None,
+                    None,
+                    Some(RequestUpgrade {
+                        start: 0,
+                        length: 10,
+                    }),
+                )
+                .await?
+                .unwrap();
+            clone.verify_and_apply_proof(&proof).await
+        };
+        let (data, applied) = futures::join!(clone.get_wait(6, Priority::Normal), replicate);
+        assert!(applied?);
+        assert_eq!(data?, b"#6".to_vec());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn shared_core_download() -> Result<(), ReplicationMethodsError> {
+        let main = create_hypercore_with_data(10).await?;
+        let clone = create_hypercore_with_data_and_key_pair(
+            0,
+            PartialKeypair {
+                public: main.key_pair.public,
+                secret: None,
+            },
+        )
+        .await?;
+        let main = SharedCore::from(main);
+        let clone = SharedCore::from(clone);
+
+        let mut events = clone.event_subscribe().await;
+        let replicate = async {
+            loop {
+                match events.recv().await {
+                    Ok(Event::Get(get)) => {
+                        let (nodes, upgrade) = if clone.info().await.length == 0 {
+                            let upgrade = RequestUpgrade {
+                                start: 0,
+                                length: 10,
+                            };
+                            (0, Some(upgrade))
+                        } else {
+                            (clone.missing_nodes(get.index).await?, None)
+                        };
+                        let block = RequestBlock {
+                            index: get.index,
+                            nodes,
+                        };
+                        let proof = main
+                            .create_proof(Some(block), None, None, upgrade)
+                            .await?
+                            .unwrap();
+                        clone.verify_and_apply_proof(&proof).await?;
+                    }
+                    Ok(Event::Unwant(_)) => return Ok::<_, ReplicationMethodsError>(()),
+                    _ => {}
+                }
+            }
+        };
+        let ((), replicated) = futures::join!(clone.download(2..5, Priority::Normal), replicate);
+        replicated?;
+        for index in 0..10 {
+            assert_eq!(clone.has(index).await, (2..5).contains(&index));
+        }
+        assert!(clone.wants().await.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn shared_core_download_timeout() -> Result<(), CoreMethodsError> {
+        let core = SharedCore::from(create_hypercore_with_data(1).await?);
+        let timeout = Duration::from_millis(10);
+        core.download_timeout(0..1, Priority::Normal, timeout)
+            .await?;
+        assert!(matches!(
+            core.download_timeout(0..3, Priority::Normal, timeout).await,
+            Err(CoreMethodsError::Timeout { index: 1 })
+        ));
+        assert!(core.wants().await.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn shared_core_get_wait_timeout() -> Result<(), CoreMethodsError> {
+        let core = SharedCore::from(create_hypercore_with_data(1).await?);
+        let timeout = Duration::from_millis(10);
+        assert_eq!(
+            core.get_wait_timeout(0, Priority::Normal, timeout).await?,
+            b"#0".to_vec()
+        );
+        assert!(matches!(
+            core.get_wait_timeout(1, Priority::Normal, timeout).await,
+            Err(CoreMethodsError::Timeout { index: 1 })
+        ));
+        Ok(())
+    }
+
+    #[cfg(any(feature = "tokio", feature = "async-std"))]
+    #[tokio::test]
+    async fn shared_core_scrub_in_background() -> Result<(), CoreMethodsError> {
+        let core = crate::HypercoreBuilder::new(crate::Storage::new_memory().await?)
+            .scrub(ScrubOptions {
+                blocks_per_step: 4,
+                interval: Duration::from_millis(1),
+            })
+            .build_shared()
+            .await?;
+        let mut events = core.event_subscribe().await;
+        for index in 0..10 {
+            core.append(format!("#{index}").as_bytes()).await?;
+        }
+        // Corrupt the stored bytes of block 6
+        core.0
+            .write()
+            .await
+            .storage
+            .flush_info(crate::common::StoreInfo::new_content(
+                crate::Store::Data,
+                12,
+                b"XX",
+            ))
+            .await?;
+        loop {
+            if let Event::CorruptBlock(corrupt) = events.recv().await.unwrap() {
+                assert_eq!(corrupt.index, 6);
+                break;
+            }
+        }
+        assert!(!core.has(6).await);
+        assert!(core.has(7).await);
+
+        // The task ends with the core
+        let weak = Arc::downgrade(&core.0);
+        drop(core);
+        crate::common::time::sleep(Duration::from_millis(20)).await;
+        assert!(weak.upgrade().is_none());
+        Ok(())
+    }
 }
@@ -0,0 +1,373 @@
+//! Strategies choosing which blocks to request from which remote peers
+use rand::seq::SliceRandom;
+use rand::{SeedableRng, rngs::StdRng};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use super::RemoteBitfield;
+
+/// Identifier of a peer in a [`RequestScheduler`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PeerId(u64);
+
+/// A peer that has a block, as seen by [`RequestStrategy::select_peer`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Candidate {
+    /// The peer
+    pub peer: PeerId,
+    /// Requests sent to the peer and not answered yet
+    pub inflight: usize,
+    /// Moving average of the time the peer takes to answer, `None` until it answered once
+    pub latency: Option<Duration>,
+}
+
+/// A missing block, as seen by [`RequestStrategy::prioritize`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Availability {
+    /// Index of the block
+    pub index: u64,
+    /// Number of peers that have the block
+    pub peers: usize,
+}
+
+/// Policy of a [`RequestScheduler`]: the order in which missing blocks are requested, and the peer
+/// each is requested from when several have it.
+pub trait RequestStrategy: Send {
+    /// Sort the blocks to request, most important first. Blocks come in index order, which is
+    /// kept by default.
+    fn prioritize(&mut self, blocks: &mut [Availability]) {
+        let _ = blocks;
+    }
+
+    /// Choose the peer to request the block at `index` from, by position in `candidates`. Only
+    /// called with candidates, all below the scheduler's inflight limit.
+    fn select_peer(&mut self, index: u64, candidates: &[Candidate]) -> usize;
+}
+
+/// Spread requests over the peers in turn
+#[derive(Debug, Clone, Default)]
+pub struct RoundRobin {
+    last: Option<PeerId>,
+}
+
+impl RequestStrategy for RoundRobin {
+    fn select_peer(&mut self, _index: u64, candidates: &[Candidate]) -> usize {
+        // The first candidate after the last chosen peer, wrapping around
+        let position = self
+            .last
+            .and_then(|last| {
+                candidates
+                    .iter()
+                    .position(|candidate| candidate.peer > last)
+            })
+            .unwrap_or(0);
+        self.last = Some(candidates[position].peer);
+        position
+    }
+}
+
+/// Request from the peer answering the fastest. Peers that didn't answer yet are tried first, to
+/// measure them.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FastestFirst;
+
+impl RequestStrategy for FastestFirst {
+    fn select_peer(&mut self, _index: u64, candidates: &[Candidate]) -> usize {
+        (0..candidates.len())
+            .min_by_key(|&position| {
+                let candidate = &candidates[position];
+                (candidate.latency, candidate.inflight)
+            })
+            .expect("Called with candidates")
+    }
+}
+
+/// Request the blocks the fewest peers have first, so they are saved before those peers leave.
+/// Each block is requested from the least busy peer having it.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RarestFirst;
+
+impl RequestStrategy for RarestFirst {
+    fn prioritize(&mut self, blocks: &mut [Availability]) {
+        // Stable, so equally rare blocks stay in index order
+        blocks.sort_by_key(|block| block.peers);
+    }
+
+    fn select_peer(&mut self, _index: u64, candidates: &[Candidate]) -> usize {
+        (0..candidates.len())
+            .min_by_key(|&position| candidates[position].inflight)
+            .expect("Called with candidates")
+    }
+}
+
+/// Built-in order of the blocks of a download, selected per [`super::Download`]. As a
+/// [`RequestStrategy`], each block is requested from the least busy peer having it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SyncStrategy {
+    /// In index order, e.g. to stream a core for playback
+    #[default]
+    Sequential,
+    /// In a random order drawn from `seed`, so that peers downloading the same core fetch
+    /// different blocks and can share them with each other sooner
+    Random {
+        /// Seed of the order, which should differ between peers
+        seed: u64,
+    },
+    /// The blocks the fewest peers have first, like [`RarestFirst`]
+    RarestFirst,
+}
+
+impl SyncStrategy {
+    /// Sort `blocks`, given in index order, in the order of the strategy
+    pub fn order(&self, blocks: &mut [Availability]) {
+        match self {
+            Self::Sequential => {}
+            Self::Random { seed } => blocks.shuffle(&mut StdRng::seed_from_u64(*seed)),
+            Self::RarestFirst => RarestFirst.prioritize(blocks),
+        }
+    }
+}
+
+impl RequestStrategy for SyncStrategy {
+    fn prioritize(&mut self, blocks: &mut [Availability]) {
+        self.order(blocks);
+    }
+
+    fn select_peer(&mut self, index: u64, candidates: &[Candidate]) -> usize {
+        RarestFirst.select_peer(index, candidates)
+    }
+}
+
+#[derive(Debug)]
+struct ScheduledPeer {
+    bitfield: RemoteBitfield,
+    inflight: usize,
+    latency: Option<Duration>,
+}
+
+/// Assigns missing blocks to the peers having them, following a [`RequestStrategy`]. It only
+/// plans: the caller sends the requests, and reports back with [`RequestScheduler::request_done`]
+/// so the strategy sees how busy and how fast each peer is.
+#[derive(Debug)]
+pub struct RequestScheduler<S> {
+    strategy: S,
+    max_inflight: usize,
+    next_peer: u64,
+    peers: BTreeMap<PeerId, ScheduledPeer>,
+}
+
+impl<S: RequestStrategy> RequestScheduler<S> {
+    /// Create a scheduler sending at most `max_inflight` concurrent requests to each peer
+    pub fn new(strategy: S, max_inflight: usize) -> Self {
+        Self {
+            strategy,
+            max_inflight,
+            next_peer: 0,
+            peers: BTreeMap::new(),
+        }
+    }
+
+    /// The strategy
+    pub fn strategy(&self) -> &S {
+        &self.strategy
+    }
+
+    /// Add a peer, with nothing known of its blocks
+    pub fn add_peer(&mut self) -> PeerId {
+        let peer = PeerId(self.next_peer);
+        self.next_peer += 1;
+        self.peers.insert(
+            peer,
+            ScheduledPeer {
+                bitfield: RemoteBitfield::new(),
+                inflight: 0,
+                latency: None,
+            },
+        );
+        peer
+    }
+
+    /// Remove a peer. Returns false if it wasn't added.
+    pub fn remove_peer(&mut self, peer: PeerId) -> bool {
+        self.peers.remove(&peer).is_some()
+    }
+
+    /// Blocks the peer has, to update from its [`super::messages::Range`] and
+    /// [`super::messages::Bitfield`] messages
+    pub fn remote_bitfield_mut(&mut self, peer: PeerId) -> Option<&mut RemoteBitfield> {
+        self.peers.get_mut(&peer).map(|peer| &mut peer.bitfield)
+    }
+
+    /// Number of requests inflight to the peer
+    pub fn inflight(&self, peer: PeerId) -> usize {
+        self.peers.get(&peer).map_or(0, |peer| peer.inflight)
+    }
+
+    /// Record that a request scheduled to the peer was answered after `latency`, or failed when
+    /// `None`
+    pub fn request_done(&mut self, peer: PeerId, latency: Option<Duration>) {
+        let Some(peer) = self.peers.get_mut(&peer) else {
+            return;
+        };
+        peer.inflight = peer.inflight.saturating_sub(1);
+        if let Some(latency) = latency {
+            // Exponential moving average, weighing the new sample by a quarter
+            peer.latency = Some(match peer.latency {
+                Some(average) => (average * 3 + latency) / 4,
+                None => latency,
+            });
+        }
+    }
+
+    /// Assign the missing `blocks` to peers having them, in the strategy's order, until peers
+    /// are at their inflight limit. Blocks no peer has, or that only busy peers have, are left
+    /// out. Assigned requests count as inflight until [`RequestScheduler::request_done`].
+    pub fn schedule(&mut self, blocks: impl IntoIterator<Item = u64>) -> Vec<(u64, PeerId)> {
+        Self::assign(
+            &mut self.peers,
+            self.max_inflight,
+            &mut self.strategy,
+            blocks,
+        )
+    }
+
+    /// [`RequestScheduler::schedule`] with another strategy than the scheduler's, e.g. the
+    /// [`SyncStrategy`] of a download
+    pub fn schedule_with(
+        &mut self,
+        blocks: impl IntoIterator<Item = u64>,
+        strategy: &mut impl RequestStrategy,
+    ) -> Vec<(u64, PeerId)> {
+        Self::assign(&mut self.peers, self.max_inflight, strategy, blocks)
+    }
+
+    fn assign(
+        peers: &mut BTreeMap<PeerId, ScheduledPeer>,
+        max_inflight: usize,
+        strategy: &mut impl RequestStrategy,
+        blocks: impl IntoIterator<Item = u64>,
+    ) -> Vec<(u64, PeerId)> {
+        let mut available: Vec<Availability> = blocks
+            .into_iter()
+            .map(|index| Availability {
+                index,
+                peers: peers
+                    .values()
+                    .filter(|peer| peer.bitfield.remote_has(index))
+                    .count(),
+            })
+            .filter(|block| block.peers > 0)
+            .collect();
+        strategy.prioritize(&mut available);
+
+        let mut scheduled = vec![];
+        let mut candidates = vec![];
+        for Availability { index, .. } in available {
+            candidates.clear();
+            candidates.extend(
+                peers
+                    .iter()
+                    .filter(|(_, peer)| {
+                        peer.inflight < max_inflight && peer.bitfield.remote_has(index)
+                    })
+                    .map(|(&id, peer)| Candidate {
+                        peer: id,
+                        inflight: peer.inflight,
+                        latency: peer.latency,
+                    }),
+            );
+            if candidates.is_empty() {
+                continue;
+            }
+            let peer = candidates[strategy.select_peer(index, &candidates)].peer;
+            peers.get_mut(&peer).expect("Candidate exists").inflight += 1;
+            scheduled.push((index, peer));
+        }
+        scheduled
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scheduler<S: RequestStrategy>(strategy: S) -> (RequestScheduler<S>, PeerId, PeerId) {
+        let mut scheduler = RequestScheduler::new(strategy, 2);
+        let a = scheduler.add_peer();
+        let b = scheduler.add_peer();
+        scheduler
+            .remote_bitfield_mut(a)
+            .unwrap()
+            .set_range(0, 10, true);
+        scheduler
+            .remote_bitfield_mut(b)
+            .unwrap()
+            .set_range(0, 5, true);
+        (scheduler, a, b)
+    }
+
+    #[test]
+    fn round_robin_alternates_peers() {
+        let (mut scheduler, a, b) = scheduler(RoundRobin::default());
+        assert_eq!(
+            scheduler.schedule(0..6),
+            vec![(0, a), (1, b), (2, a), (3, b)]
+        );
+        assert_eq!(scheduler.schedule(0..6), vec![]);
+        scheduler.request_done(b, Some(Duration::from_millis(10)));
+        // Only a has block 6, and it is busy
+        assert_eq!(scheduler.schedule([6, 4]), vec![(4, b)]);
+    }
+
+    #[test]
+    fn fastest_first_prefers_fast_peers() {
+        let (mut scheduler, a, b) = scheduler(FastestFirst);
+        // Unmeasured peers are tried first
+        assert_eq!(scheduler.schedule([0, 1]), vec![(0, a), (1, b)]);
+        scheduler.request_done(a, Some(Duration::from_millis(100)));
+        scheduler.request_done(b, Some(Duration::from_millis(10)));
+        assert_eq!(scheduler.schedule([2, 3, 4]), vec![(2, b), (3, b), (4, a)]);
+    }
+
+    #[test]
+    fn sync_strategies_order_blocks() {
+        let (mut scheduler, a, b) = scheduler(RoundRobin::default());
+        let blocks = || {
+            (0..6)
+                .map(|index| Availability { index, peers: 1 })
+                .collect::<Vec<_>>()
+        };
+        let indexes = |blocks: Vec<Availability>| -> Vec<u64> {
+            blocks.into_iter().map(|block| block.index).collect()
+        };
+        let mut sequential = blocks();
+        SyncStrategy::Sequential.order(&mut sequential);
+        assert_eq!(indexes(sequential), vec![0, 1, 2, 3, 4, 5]);
+
+        // The same seed gives the same order
+        let mut random = blocks();
+        SyncStrategy::Random { seed: 1 }.order(&mut random);
+        let random = indexes(random);
+        assert_ne!(random, vec![0, 1, 2, 3, 4, 5]);
+        let mut again = blocks();
+        SyncStrategy::Random { seed: 1 }.order(&mut again);
+        assert_eq!(indexes(again), random);
+
+        // Per download, on the peers of the scheduler
+        let mut strategy = SyncStrategy::RarestFirst;
+        assert_eq!(
+            scheduler.schedule_with([3, 8], &mut strategy),
+            vec![(8, a), (3, b)]
+        );
+        assert_eq!(scheduler.schedule(0..6), vec![(0, a), (1, b)]);
+    }
+
+    #[test]
+    fn rarest_first_requests_rare_blocks_first() {
+        let (mut scheduler, a, b) = scheduler(RarestFirst);
+        assert_eq!(scheduler.schedule([3, 8, 20]), vec![(8, a), (3, b)]);
+        assert!(scheduler.remove_peer(b));
+        assert_eq!(scheduler.inflight(b), 0);
+        assert_eq!(scheduler.schedule([3]), vec![(3, a)]);
+    }
+}
@@ -0,0 +1,273 @@
+//! Transports carrying replication [`Message`]s between two peers
+use compact_encoding::{CompactEncoding, decode_usize, encode_usize_var, encoded_size_usize};
+use futures::StreamExt;
+use futures::channel::mpsc::{UnboundedReceiver, UnboundedSender, unbounded};
+use futures::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use std::future::Future;
+use std::io;
+
+use super::ReplicationMethodsError;
+use super::messages::Message;
+#[cfg(any(feature = "tokio", feature = "async-std"))]
+use super::{CoreMethods, ReplicationMethods};
+use crate::common::BufferPool;
+
+/// Largest message [`StreamTransport`] accepts from the remote
+pub const MAX_MESSAGE_LENGTH: usize = 8 * 1024 * 1024;
+
+/// Connection to a remote peer that sends and receives whole replication messages. Used by
+/// [`super::Peer::run`] and [`super::replicate`].
+pub trait ReplicationTransport: Send {
+    /// Send a message to the remote
+    fn send(&mut self, message: Message) -> impl Future<Output = io::Result<()>> + Send;
+    /// Receive the next message from the remote, or `None` when the connection is closed. Must
+    /// be cancel safe: if the future is dropped before completing, no message is lost.
+    fn recv(&mut self) -> impl Future<Output = Option<io::Result<Message>>> + Send;
+}
+
+/// Error of [`super::replicate`]
+#[derive(thiserror::Error, Debug)]
+pub enum ReplicateError {
+    /// Error from the transport
+    #[error("Got a transport error: [{0}]")]
+    Transport(#[from] io::Error),
+    /// Error from replicating the core
+    #[error("Got a replication error: [{0}]")]
+    Replication(#[from] ReplicationMethodsError),
+}
+
+/// Replicate `core` with the remote at the other end of `transport`, until either is closed.
+/// Spawn it as a task for every connection, see [`super::Peer::run`].
+#[cfg(any(feature = "tokio", feature = "async-std"))]
+pub async fn replicate<T: CoreMethods + ReplicationMethods>(
+    core: T,
+    transport: impl ReplicationTransport,
+) -> Result<(), ReplicateError> {
+    super::Peer::new(core).run(transport).await
+}
+
+/// In-memory transport, for replicating two cores in the same process
+#[derive(Debug)]
+pub struct MemoryTransport {
+    sender: UnboundedSender<Message>,
+    receiver: UnboundedReceiver<Message>,
+}
+
+impl MemoryTransport {
+    /// Create two transports connected to each other
+    pub fn pair() -> (Self, Self) {
+        let (a_sender, a_receiver) = unbounded();
+        let (b_sender, b_receiver) = unbounded();
+        (
+            Self {
+                sender: a_sender,
+                receiver: b_receiver,
+            },
+            Self {
+                sender: b_sender,
+                receiver: a_receiver,
+            },
+        )
+    }
+}
+
+impl ReplicationTransport for MemoryTransport {
+    async fn send(&mut self, message: Message) -> io::Result<()> {
+        self.sender
+            .unbounded_send(message)
+            .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))
+    }
+
+    async fn recv(&mut self) -> Option<io::Result<Message>> {
+        self.receiver.next().await.map(Ok)
+    }
+}
+
+/// Transport over a byte stream, like a TCP connection. Each message is prefixed with its length
+/// as a compact encoded integer. This framing is specific to this crate, streams of the
+/// Javascript hypercore are multiplexed with protomux and can't be read with it.
+#[derive(Debug)]
+pub struct StreamTransport<S> {
+    stream: S,
+    /// Bytes read from the stream that don't make a whole message yet
+    buffer: Vec<u8>,
+    /// Buffers to encode sent frames in
+    pool: BufferPool,
+}
+
+impl<S> StreamTransport<S> {
+    /// Create a transport over `stream`
+    pub fn new(stream: S) -> Self {
+        Self {
+            stream,
+            buffer: vec![],
+            pool: BufferPool::default(),
+        }
+    }
+
+    /// Get back the stream
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+
+    /// Take the first message out of the buffer, if it's all there
+    fn take_message(&mut self) -> io::Result<Option<Message>> {
+        let Some(first) = self.buffer.first() else {
+            return Ok(None);
+        };
+        let header_length = match first {
+            0xfd => 3,
+            0xfe => 5,
+            0xff => 9,
+            _ => 1,
+        };
+        if self.buffer.len() < header_length {
+            return Ok(None);
+        }
+        let (length, rest) = decode_usize(&self.buffer)?;
+        if length > MAX_MESSAGE_LENGTH {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Message of {length} bytes is too long"),
+            ));
+        }
+        if rest.len() < length {
+            return Ok(None);
+        }
+        let (message, rest) = Message::decode(&rest[..length])?;
+        if !rest.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Message is shorter than its frame",
+            ));
+        }
+        self.buffer.drain(..header_length + length);
+        Ok(Some(message))
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + Send> ReplicationTransport for StreamTransport<S> {
+    async fn send(&mut self, message: Message) -> io::Result<()> {
+        let length = message.encoded_size()?;
+        let mut frame = self.pool.take_zeroed(encoded_size_usize(length) + length);
+        let rest = encode_usize_var(&length, &mut frame)?;
+        message.encode(rest)?;
+        // Not given back on errors, the transport is unusable anyway
+        self.stream.write_all(&frame).await?;
+        self.pool.put(frame);
+        self.stream.flush().await
+    }
+
+    async fn recv(&mut self) -> Option<io::Result<Message>> {
+        let mut chunk = [0; 4096];
+        loop {
+            match self.take_message() {
+                Ok(Some(message)) => return Some(Ok(message)),
+                Ok(None) => {}
+                Err(error) => return Some(Err(error)),
+            }
+            // Bytes are only taken from the stream when the read completes, so cancelling here
+            // loses nothing
+            match self.stream.read(&mut chunk).await {
+                Ok(0) if self.buffer.is_empty() => return None,
+                Ok(0) => return Some(Err(io::ErrorKind::UnexpectedEof.into())),
+                Ok(read) => self.buffer.extend_from_slice(&chunk[..read]),
+                Err(error) => return Some(Err(error)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::replication::messages::{Extension, NoData, Want};
+    use futures::io::Cursor;
+
+    #[async_std::test]
+    async fn stream_transport_frames_messages() -> io::Result<()> {
+        let messages: Vec<Message> = vec![
+            Want {
+                start: 0,
+                length: 10,
+            }
+            .into(),
+            Extension {
+                name: "big".to_string(),
+                message: vec![7; 5000],
+            }
+            .into(),
+            NoData { request: 1 }.into(),
+        ];
+        let mut writer = StreamTransport::new(Cursor::new(vec![]));
+        for message in &messages {
+            writer.send(message.clone()).await?;
+        }
+        let bytes = writer.into_inner().into_inner();
+
+        let mut reader = StreamTransport::new(Cursor::new(bytes.clone()));
+        for message in messages {
+            assert_eq!(reader.recv().await.unwrap()?, message);
+        }
+        assert!(reader.recv().await.is_none());
+
+        // A stream ending in the middle of a message is an error
+        let mut reader = StreamTransport::new(Cursor::new(bytes[..bytes.len() - 1].to_vec()));
+        reader.recv().await.unwrap()?;
+        reader.recv().await.unwrap()?;
+        assert!(reader.recv().await.unwrap().is_err());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn memory_transport_pair() -> io::Result<()> {
+        let (mut a, mut b) = MemoryTransport::pair();
+        let message = Message::from(NoData { request: 1 });
+        a.send(message.clone()).await?;
+        assert_eq!(b.recv().await.unwrap()?, message);
+        drop(a);
+        assert!(b.recv().await.is_none());
+        assert!(b.send(message).await.is_err());
+        Ok(())
+    }
+
+    #[cfg(all(feature = "shared-core", any(feature = "tokio", feature = "async-std")))]
+    #[tokio::test]
+    async fn replicate_over_memory_transport() -> Result<(), ReplicationMethodsError> {
+        use crate::PartialKeypair;
+        use crate::core::tests::{
+            create_hypercore_with_data, create_hypercore_with_data_and_key_pair,
+        };
+        use crate::replication::{CoreInfo, Priority, SharedCore};
+        use futures::future::{Either, select};
+        use std::pin::pin;
+
+        let main = create_hypercore_with_data(10).await?;
+        let clone = create_hypercore_with_data_and_key_pair(
+            0,
+            PartialKeypair {
+                public: main.key_pair.public,
+                secret: None,
+            },
+        )
+        .await?;
+        let main = SharedCore::from(main);
+        let clone = SharedCore::from(clone);
+
+        let (a, b) = MemoryTransport::pair();
+        let replicating =
+            futures::future::try_join(replicate(main.clone(), a), replicate(clone.clone(), b));
+        match select(
+            pin!(replicating),
+            pin!(clone.download(0..10, Priority::Normal)),
+        )
+        .await
+        {
+            Either::Left((result, _)) => panic!("Replication stopped early: {result:?}"),
+            Either::Right(_) => {}
+        }
+        assert_eq!(clone.info().await.contiguous_length, 10);
+        assert_eq!(clone.get(9).await?, Some(b"#9".to_vec()));
+        Ok(())
+    }
+}
@@ -0,0 +1,217 @@
+//! Negotiation of upgrades with a remote peer
+use hypercore_schema::{Proof, RequestSeek, RequestUpgrade};
+
+use super::messages::{Request, Synchronize};
+use crate::HypercoreError;
+
+/// Decides which upgrade and seek requests to send to a remote, from the fork and length it
+/// announces with [`Synchronize`] messages, and checks that the proofs it returns answer them.
+///
+/// Upgrades are only requested from a remote on the same fork as the local core. This crate
+/// doesn't move a core onto another fork, proofs of other forks are rejected by
+/// [`super::ReplicationMethods::verify_and_apply_proof`]. When the remote switches fork after a
+/// truncate, [`UpgradeNegotiator::remote_synchronized`] reports it so the caller can forget the
+/// blocks the remote dropped, and upgrades resume once the forks match again.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct UpgradeNegotiator {
+    remote_fork: u64,
+    remote_length: u64,
+    can_upgrade: bool,
+    synchronized: bool,
+}
+
+impl UpgradeNegotiator {
+    /// Create a negotiator for a remote that didn't synchronize yet
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Fork announced by the remote
+    pub fn remote_fork(&self) -> u64 {
+        self.remote_fork
+    }
+
+    /// Length announced by the remote
+    pub fn remote_length(&self) -> u64 {
+        self.remote_length
+    }
+
+    /// Whether the remote sent a [`Synchronize`]
+    pub fn is_synchronized(&self) -> bool {
+        self.synchronized
+    }
+
+    /// Record the state announced by the remote. Returns true if the remote switched to another
+    /// fork since its previous [`Synchronize`], i.e. it was truncated.
+    pub fn remote_synchronized(&mut self, synchronize: &Synchronize) -> bool {
+        let fork_switched = self.synchronized && synchronize.fork != self.remote_fork;
+        *self = Self {
+            remote_fork: synchronize.fork,
+            remote_length: synchronize.length,
+            can_upgrade: synchronize.can_upgrade,
+            synchronized: true,
+        };
+        fork_switched
+    }
+
+    /// Upgrade to request to reach the remote's length, if the remote is ahead on the local fork
+    pub fn upgrade(&self, local_fork: u64, local_length: u64) -> Option<RequestUpgrade> {
+        (self.synchronized
+            && self.can_upgrade
+            && self.remote_fork == local_fork
+            && self.remote_length > local_length)
+            .then(|| RequestUpgrade {
+                start: local_length,
+                length: self.remote_length - local_length,
+            })
+    }
+
+    /// Seek of the byte at `bytes`, along with the upgrade it needs when the remote is ahead.
+    /// Bytes past the local byte length can only be found with the upgrade.
+    pub fn seek(
+        &self,
+        bytes: u64,
+        local_fork: u64,
+        local_length: u64,
+    ) -> (RequestSeek, Option<RequestUpgrade>) {
+        (
+            RequestSeek { bytes },
+            self.upgrade(local_fork, local_length),
+        )
+    }
+
+    /// Check that `proof` answers `request`: it's for the requested fork, and has exactly the
+    /// requested block, hash, seek and upgrade. The proof itself is verified when applied.
+    pub fn validate(&self, request: &Request, proof: &Proof) -> Result<(), HypercoreError> {
+        let invalid = |context: String| Err(HypercoreError::InvalidOperation { context });
+        if proof.fork != request.fork {
+            return invalid(format!(
+                "Proof of fork {} answers a request for fork {}",
+                proof.fork, request.fork
+            ));
+        }
+        let block = proof.block.as_ref().map(|block| block.index);
+        if block != request.block.as_ref().map(|block| block.index) {
+            return invalid(format!("Proof has block {block:?} which wasn't requested"));
+        }
+        let hash = proof.hash.as_ref().map(|hash| hash.index);
+        if hash != request.hash.as_ref().map(|hash| hash.index) {
+            return invalid(format!("Proof has hash {hash:?} which wasn't requested"));
+        }
+        if proof.seek.is_some() != request.seek.is_some() {
+            return invalid("Proof seek doesn't match the request".to_string());
+        }
+        let upgrade = proof
+            .upgrade
+            .as_ref()
+            .map(|upgrade| (upgrade.start, upgrade.length));
+        let requested = request
+            .upgrade
+            .as_ref()
+            .map(|upgrade| (upgrade.start, upgrade.length));
+        if upgrade != requested {
+            return invalid(format!(
+                "Proof upgrade {upgrade:?} doesn't match the requested {requested:?}"
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hypercore_schema::{DataBlock, DataSeek, DataUpgrade, RequestBlock};
+
+    fn synchronize(fork: u64, length: u64) -> Synchronize {
+        Synchronize {
+            fork,
+            length,
+            remote_length: 0,
+            downloading: true,
+            uploading: true,
+            can_upgrade: true,
+        }
+    }
+
+    #[test]
+    fn upgrade_negotiator_requests_upgrades() {
+        let mut negotiator = UpgradeNegotiator::new();
+        assert_eq!(negotiator.upgrade(0, 0), None);
+        assert!(!negotiator.remote_synchronized(&synchronize(0, 10)));
+        assert_eq!(
+            negotiator.upgrade(0, 4),
+            Some(RequestUpgrade {
+                start: 4,
+                length: 6
+            })
+        );
+        assert_eq!(negotiator.upgrade(0, 10), None);
+        // No upgrades across forks
+        assert_eq!(negotiator.upgrade(1, 4), None);
+        let (seek, upgrade) = negotiator.seek(100, 0, 4);
+        assert_eq!(seek.bytes, 100);
+        assert!(upgrade.is_some());
+
+        // The remote truncated
+        assert!(negotiator.remote_synchronized(&synchronize(1, 8)));
+        assert_eq!(negotiator.upgrade(0, 4), None);
+        assert_eq!(
+            negotiator.upgrade(1, 4).map(|upgrade| upgrade.length),
+            Some(4)
+        );
+        assert!(!negotiator.remote_synchronized(&synchronize(1, 9)));
+    }
+
+    #[test]
+    fn upgrade_negotiator_validates_proofs() {
+        let negotiator = UpgradeNegotiator::new();
+        let request = Request {
+            id: 1,
+            fork: 0,
+            block: Some(RequestBlock { index: 5, nodes: 0 }),
+            hash: None,
+            seek: None,
+            upgrade: Some(RequestUpgrade {
+                start: 4,
+                length: 6,
+            }),
+        };
+        let proof = Proof {
+            fork: 0,
+            block: Some(DataBlock {
+                index: 5,
+                value: b"#5".to_vec(),
+                nodes: vec![],
+            }),
+            hash: None,
+            seek: None,
+            upgrade: Some(DataUpgrade {
+                start: 4,
+                length: 6,
+                nodes: vec![],
+                additional_nodes: vec![],
+                signature: vec![],
+            }),
+        };
+        assert!(negotiator.validate(&request, &proof).is_ok());
+        let wrong_fork = Proof {
+            fork: 1,
+            ..proof.clone()
+        };
+        assert!(negotiator.validate(&request, &wrong_fork).is_err());
+        let no_upgrade = Proof {
+            upgrade: None,
+            ..proof.clone()
+        };
+        assert!(negotiator.validate(&request, &no_upgrade).is_err());
+        let unrequested_seek = Proof {
+            seek: Some(DataSeek {
+                bytes: 100,
+                nodes: vec![],
+            }),
+            ..proof
+        };
+        assert!(negotiator.validate(&request, &unrequested_seek).is_err());
+    }
+}
@@ -0,0 +1,263 @@
+//! Lock of the directory of a disk storage, held by one process at a time. The lock has a lease
+//! that its holder renews while writing, so that another process can take over the lock of a
+//! holder that crashed once its lease expires.
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::HypercoreError;
+
+/// Name of the lock file in the directory of a disk storage
+const LOCK_FILE: &str = "lock";
+/// Attempts at acquiring a lock that is released or taken over by another process meanwhile
+const ACQUIRE_ATTEMPTS: usize = 3;
+
+/// Holder of the lock file of a directory, see [`crate::Storage::new_disk_locked`]
+#[derive(Debug)]
+pub(crate) struct DiskLock {
+    dir: PathBuf,
+    /// Random id of this holder, written in the lock file
+    owner: u64,
+    lease: Duration,
+    /// When the lease was last renewed
+    renewed: Instant,
+    /// Whether the lock of a holder whose lease expired was taken over
+    taken_over: bool,
+}
+
+/// Content of a lock file
+#[derive(Debug, PartialEq)]
+struct LockState {
+    owner: u64,
+    /// End of the lease, in milliseconds since the Unix epoch
+    expires: u64,
+}
+
+impl LockState {
+    fn encode(&self) -> String {
+        format!("{:016x} {}\n", self.owner, self.expires)
+    }
+
+    /// None if the content isn't that of a lock file
+    fn decode(content: &str) -> Option<Self> {
+        let (owner, expires) = content.strip_suffix('\n')?.split_once(' ')?;
+        Some(Self {
+            owner: u64::from_str_radix(owner, 16).ok()?,
+            expires: expires.parse().ok()?,
+        })
+    }
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| u64::try_from(now.as_millis()).unwrap_or(u64::MAX))
+}
+
+fn locked(context: impl Into<String>) -> HypercoreError {
+    HypercoreError::Locked {
+        context: context.into(),
+    }
+}
+
+impl DiskLock {
+    /// Acquire the lock of `dir`, taking it over if the lease of its holder expired
+    pub(crate) fn acquire(dir: &Path, lease: Duration) -> Result<Self, HypercoreError> {
+        std::fs::create_dir_all(dir)?;
+        let mut lock = Self {
+            dir: dir.to_path_buf(),
+            owner: rand::random(),
+            lease,
+            renewed: Instant::now(),
+            taken_over: false,
+        };
+        for _ in 0..ACQUIRE_ATTEMPTS {
+            // Link a complete lock file, so that others never read a partially written one
+            lock.renewed = Instant::now();
+            lock.write_own_file()?;
+            let linked = std::fs::hard_link(lock.own_path(), lock.path());
+            std::fs::remove_file(lock.own_path())?;
+            match linked {
+                Ok(()) => return Ok(lock),
+                Err(err) if err.kind() == ErrorKind::AlreadyExists => {}
+                Err(err) => return Err(err.into()),
+            }
+            let content = match std::fs::read_to_string(lock.path()) {
+                Ok(content) => content,
+                // Released meanwhile
+                Err(err) if err.kind() == ErrorKind::NotFound => continue,
+                Err(err) => return Err(err.into()),
+            };
+            if let Some(state) = LockState::decode(&content)
+                && state.expires > now_millis()
+            {
+                return Err(locked(format!(
+                    "Held by another process for {} ms more",
+                    state.expires - now_millis().min(state.expires)
+                )));
+            }
+            lock.taken_over |= lock.remove_stale(&content)?;
+        }
+        Err(locked("Taken by another process meanwhile"))
+    }
+
+    /// Whether the lock of a holder whose lease expired was taken over
+    pub(crate) fn taken_over(&self) -> bool {
+        self.taken_over
+    }
+
+    /// Renew the lease, failing if another process took the lock over after it expired
+    pub(crate) fn renew(&mut self) -> Result<(), HypercoreError> {
+        let content = match std::fs::read_to_string(self.path()) {
+            Ok(content) => content,
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                return Err(locked("Lock file was removed"));
+            }
+            Err(err) => return Err(err.into()),
+        };
+        if LockState::decode(&content).map(|state| state.owner) != Some(self.owner) {
+            return Err(locked(
+                "Taken over by another process after the lease expired",
+            ));
+        }
+        let renewed = Instant::now();
+        self.write_own_file()?;
+        std::fs::rename(self.own_path(), self.path())?;
+        self.renewed = renewed;
+        Ok(())
+    }
+
+    /// Renew the lease if a third of it passed since it was last renewed
+    pub(crate) fn renew_if_due(&mut self) -> Result<(), HypercoreError> {
+        if self.renewed.elapsed() >= self.lease / 3 {
+            return self.renew();
+        }
+        Ok(())
+    }
+
+    fn path(&self) -> PathBuf {
+        self.dir.join(LOCK_FILE)
+    }
+
+    /// Path of a file only this holder writes
+    fn own_path(&self) -> PathBuf {
+        self.dir.join(format!("{LOCK_FILE}.{:016x}", self.owner))
+    }
+
+    /// Write the state of the lock with a new lease to the own file
+    fn write_own_file(&self) -> Result<(), HypercoreError> {
+        let lease = u64::try_from(self.lease.as_millis()).unwrap_or(u64::MAX);
+        let state = LockState {
+            owner: self.owner,
+            expires: now_millis().saturating_add(lease),
+        };
+        let mut file = std::fs::File::create(self.own_path())?;
+        file.write_all(state.encode().as_bytes())?;
+        file.sync_all()?;
+        Ok(())
+    }
+
+    /// Remove the lock file with the given stale content. Returns false if it was replaced
+    /// meanwhile, e.g. by another process taking it over first.
+    fn remove_stale(&self, content: &str) -> Result<bool, HypercoreError> {
+        // Move the lock file away first, so that only one process removes it
+        match std::fs::rename(self.path(), self.own_path()) {
+            Ok(()) => {}
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(false),
+            Err(err) => return Err(err.into()),
+        }
+        let removed = std::fs::read_to_string(self.own_path())?;
+        if removed != content {
+            // Put the lock of the other process back, unless a new one was created meanwhile
+            match std::fs::hard_link(self.own_path(), self.path()) {
+                Ok(()) => {}
+                Err(err) if err.kind() == ErrorKind::AlreadyExists => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+        std::fs::remove_file(self.own_path())?;
+        Ok(removed == content)
+    }
+}
+
+impl Drop for DiskLock {
+    /// Release the lock, unless another process took it over
+    fn drop(&mut self) {
+        if let Ok(content) = std::fs::read_to_string(self.path())
+            && LockState::decode(&content).map(|state| state.owner) == Some(self.owner)
+        {
+            let _ = std::fs::remove_file(self.path());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LEASE: Duration = Duration::from_secs(60);
+
+    fn write_lock(dir: &Path, state: &LockState) {
+        std::fs::write(dir.join(LOCK_FILE), state.encode()).unwrap();
+    }
+
+    #[test]
+    fn disk_lock_excludes_until_released() -> Result<(), HypercoreError> {
+        let dir = tempfile::tempdir().unwrap();
+        let mut lock = DiskLock::acquire(dir.path(), LEASE)?;
+        assert!(!lock.taken_over());
+        assert!(matches!(
+            DiskLock::acquire(dir.path(), LEASE),
+            Err(HypercoreError::Locked { .. })
+        ));
+        lock.renew()?;
+        drop(lock);
+        assert!(!dir.path().join(LOCK_FILE).exists());
+        let lock = DiskLock::acquire(dir.path(), LEASE)?;
+        assert!(!lock.taken_over());
+        Ok(())
+    }
+
+    #[test]
+    fn disk_lock_takes_over_expired_lease() -> Result<(), HypercoreError> {
+        let dir = tempfile::tempdir().unwrap();
+        let mut crashed = DiskLock::acquire(dir.path(), LEASE)?;
+        write_lock(
+            dir.path(),
+            &LockState {
+                owner: crashed.owner,
+                expires: now_millis() - 1,
+            },
+        );
+        let lock = DiskLock::acquire(dir.path(), LEASE)?;
+        assert!(lock.taken_over());
+
+        // The previous holder can't renew nor release the lock anymore
+        assert!(matches!(
+            crashed.renew(),
+            Err(HypercoreError::Locked { .. })
+        ));
+        drop(crashed);
+        assert!(matches!(
+            DiskLock::acquire(dir.path(), LEASE),
+            Err(HypercoreError::Locked { .. })
+        ));
+
+        // A lock file that isn't one is stale too
+        drop(lock);
+        std::fs::write(dir.path().join(LOCK_FILE), "garbage").unwrap();
+        assert!(DiskLock::acquire(dir.path(), LEASE)?.taken_over());
+        Ok(())
+    }
+
+    #[test]
+    fn lock_state_encoding() {
+        let state = LockState {
+            owner: 0xabcdef,
+            expires: 1234,
+        };
+        assert_eq!(state.encode(), "0000000000abcdef 1234\n");
+        assert_eq!(LockState::decode(&state.encode()), Some(state));
+        assert_eq!(LockState::decode("0000000000abcdef 12"), None);
+    }
+}
@@ -1,31 +1,183 @@
 //! Save data to a desired storage backend.
 
+#[cfg(all(
+    not(target_arch = "wasm32"),
+    any(feature = "tokio", feature = "async-std")
+))]
+mod lock;
+
 use futures::future::FutureExt;
-#[cfg(not(target_arch = "wasm32"))]
+use futures::lock::{Mutex, MutexGuard};
+#[cfg(all(
+    not(target_arch = "wasm32"),
+    any(feature = "tokio", feature = "async-std")
+))]
 use random_access_disk::RandomAccessDisk;
 use random_access_memory::RandomAccessMemory;
 use random_access_storage::{RandomAccess, RandomAccessError};
 use std::fmt::Debug;
 #[cfg(not(target_arch = "wasm32"))]
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+#[cfg(feature = "tracing")]
 use tracing::instrument;
 
+#[cfg(all(
+    not(target_arch = "wasm32"),
+    any(feature = "tokio", feature = "async-std")
+))]
+use lock::DiskLock;
+
 use crate::{
     HypercoreError,
-    common::{Store, StoreInfo, StoreInfoInstruction, StoreInfoType},
+    common::{BufferPool, Store, StoreInfo, StoreInfoInstruction, StoreInfoType, metrics},
 };
+#[cfg(not(target_arch = "wasm32"))]
+use crate::{common::to_hex, crypto::discovery_key};
+
+/// Number of bytes read at once when copying a store
+#[cfg(all(
+    not(target_arch = "wasm32"),
+    any(feature = "tokio", feature = "async-std")
+))]
+const COPY_CHUNK_SIZE: u64 = 1024 * 1024;
+/// Slices smaller than this are coalesced into one write by [`Storage::write_slices`]
+const COALESCE_SIZE: usize = 64 * 1024;
+/// Size of the pages of the tree, bitfield and oplog stores covered by one checksum
+const CHECKSUM_PAGE_SIZE: u64 = 4096;
+/// Size of a checksum entry: the CRC32 of the page, and a flag telling it is set
+const CHECKSUM_ENTRY_SIZE: u64 = 8;
+/// Stores covered by checksums. Blocks in the data store are covered by the merkle tree.
+const CHECKSUM_STORES: [Store; 3] = [Store::Tree, Store::Bitfield, Store::Oplog];
+
+/// Directory holding the hypercores of a store, like in Javascript corestore
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) const CORES_DIR: &str = "cores";
+
+/// Directory of the hypercore with public key `key` in a store in `dir`:
+/// `cores/<aa>/<bb>/<aabb...>`, after the hex of its [`crate::discovery_key`]. This is the layout
+/// of Javascript corestore, so the directory is stable, doesn't reveal the public key, and is
+/// where Javascript looks for the hypercore. Pass it to [`Storage::new_disk`].
+#[cfg(not(target_arch = "wasm32"))]
+pub fn core_dir(dir: &Path, key: &ed25519_dalek::VerifyingKey) -> PathBuf {
+    let id = to_hex(&discovery_key(key));
+    dir.join(CORES_DIR).join(&id[..2]).join(&id[2..4]).join(id)
+}
 
 /// Supertrait for Storage
 pub trait StorageTraits: RandomAccess + Debug {}
 impl<T: RandomAccess + Debug> StorageTraits for T {}
 
 /// Save data to a desired storage backend.
+///
+/// Each store is behind its own lock so that reads only need a shared reference.
+///
+/// By default every store is a boxed [`StorageTraits`] object, so the stores can be of different
+/// kinds, e.g. with [`Storage::from_parts`]. When they are all of the same kind, `T` can be that
+/// type instead, e.g. `Storage<RandomAccessMemory>`, so that calls to the stores are dispatched
+/// statically. A [`crate::HypercoreBuilder`] given such a storage builds a
+/// `Hypercore<RandomAccessMemory>`.
 #[derive(Debug)]
-pub struct Storage {
-    tree: Box<dyn StorageTraits + Send>,
-    data: Box<dyn StorageTraits + Send>,
-    bitfield: Box<dyn StorageTraits + Send>,
-    oplog: Box<dyn StorageTraits + Send>,
+pub struct Storage<T: ?Sized = dyn StorageTraits + Send> {
+    tree: Mutex<Box<T>>,
+    data: Mutex<Box<T>>,
+    bitfield: Mutex<Box<T>>,
+    oplog: Mutex<Box<T>>,
+    checksums: Option<Checksums>,
+    /// Buffers to coalesce small slices in, see [`Storage::write_slices`]
+    pool: BufferPool,
+    /// Whether a write failed, which may have left the stores partially written
+    poisoned: bool,
+    /// Lock of the directory of the stores, see [`Storage::new_disk_locked`]
+    #[cfg(all(
+        not(target_arch = "wasm32"),
+        any(feature = "tokio", feature = "async-std")
+    ))]
+    lock: Option<DiskLock>,
+}
+
+/// Checksums of the pages of the tree, bitfield and oplog stores, see [`Storage::with_checksums`]
+#[derive(Debug)]
+struct Checksums {
+    store: Mutex<Box<dyn StorageTraits + Send>>,
+    verify_on_read: bool,
+}
+
+/// Offset of the checksum entry of a page of a store in the checksum store
+fn checksum_offset(store: &Store, page: u64) -> Option<u64> {
+    let slot = CHECKSUM_STORES.iter().position(|other| other == store)? as u64;
+    Some((page * CHECKSUM_STORES.len() as u64 + slot) * CHECKSUM_ENTRY_SIZE)
+}
+
+/// Pages covering the given byte range
+fn pages(index: u64, length: u64) -> std::ops::Range<u64> {
+    index / CHECKSUM_PAGE_SIZE..(index + length).div_ceil(CHECKSUM_PAGE_SIZE)
+}
+
+/// Instrument a future with a trace span when the `tracing` feature is on
+macro_rules! traced {
+    ($future:expr, $($span:tt)+) => {{
+        #[cfg(feature = "tracing")]
+        let future = tracing::Instrument::instrument($future, tracing::trace_span!($($span)+));
+        #[cfg(not(feature = "tracing"))]
+        let future = $future;
+        future
+    }};
+}
+
+/// Checksum of a page of a store, None if the store ends before it
+async fn page_checksum<T: StorageTraits + Send + ?Sized>(
+    storage: &mut T,
+    page: u64,
+) -> Result<Option<u32>, HypercoreError> {
+    let length = storage.len().await.map_err(map_random_access_err)?;
+    let start = page * CHECKSUM_PAGE_SIZE;
+    if start >= length {
+        return Ok(None);
+    }
+    let content = storage
+        .read(start, (length - start).min(CHECKSUM_PAGE_SIZE))
+        .await
+        .map_err(map_random_access_err)?;
+    Ok(Some(crc32fast::hash(&content)))
+}
+
+/// Stored checksum of a page, None if it was never set
+async fn stored_checksum(
+    checksums: &mut Box<dyn StorageTraits + Send>,
+    offset: u64,
+) -> Result<Option<u32>, HypercoreError> {
+    match checksums.read(offset, CHECKSUM_ENTRY_SIZE).await {
+        Ok(entry) if entry[4] == 1 => Ok(Some(u32::from_le_bytes(
+            entry[..4].try_into().expect("Entry has a checksum"),
+        ))),
+        Ok(_) | Err(RandomAccessError::OutOfBounds { .. }) => Ok(None),
+        Err(err) => Err(map_random_access_err(err)),
+    }
+}
+
+/// Compare the checksum of a page to the stored one, if any
+async fn verify_page<T: StorageTraits + Send + ?Sized>(
+    storage: &mut T,
+    checksums: &mut Box<dyn StorageTraits + Send>,
+    store: &Store,
+    page: u64,
+) -> Result<(), HypercoreError> {
+    let offset = checksum_offset(store, page).expect("Store has checksums");
+    let Some(expected) = stored_checksum(checksums, offset).await? else {
+        return Ok(());
+    };
+    if page_checksum(storage, page).await? != Some(expected) {
+        return Err(HypercoreError::CorruptStorage {
+            store: store.clone(),
+            offset: Some(page * CHECKSUM_PAGE_SIZE),
+            context: Some(format!(
+                "Checksum mismatch in bytes {}..{}",
+                page * CHECKSUM_PAGE_SIZE,
+                (page + 1) * CHECKSUM_PAGE_SIZE
+            )),
+        });
+    }
+    Ok(())
 }
 
 pub(crate) fn map_random_access_err(err: RandomAccessError) -> HypercoreError {
@@ -34,12 +186,12 @@ pub(crate) fn map_random_access_err(err: RandomAccessError) -> HypercoreError {
             return_code,
             context,
             source,
-        } => HypercoreError::IO {
-            context: Some(format!(
+        } => HypercoreError::from_io(
+            Some(format!(
                 "RandomAccess IO error. Context: {context:?}, return_code: {return_code:?}",
             )),
             source,
-        },
+        ),
         RandomAccessError::OutOfBounds {
             offset,
             end,
@@ -52,55 +204,195 @@ pub(crate) fn map_random_access_err(err: RandomAccessError) -> HypercoreError {
     }
 }
 
-impl Storage {
+impl<T: StorageTraits + Send + ?Sized> Storage<T> {
     /// Create a new instance. Takes a callback to create new storage instances and overwrite flag.
     pub async fn open<Cb>(create: Cb, overwrite: bool) -> Result<Self, HypercoreError>
     where
         Cb: Fn(
             Store,
         ) -> std::pin::Pin<
-            Box<
-                dyn std::future::Future<
-                        Output = Result<Box<dyn StorageTraits + Send>, RandomAccessError>,
-                    > + Send,
-            >,
+            Box<dyn std::future::Future<Output = Result<Box<T>, RandomAccessError>> + Send>,
         >,
     {
-        let mut tree = create(Store::Tree).await.map_err(map_random_access_err)?;
-        let mut data = create(Store::Data).await.map_err(map_random_access_err)?;
-        let mut bitfield = create(Store::Bitfield)
+        let tree = create(Store::Tree).await.map_err(map_random_access_err)?;
+        let data = create(Store::Data).await.map_err(map_random_access_err)?;
+        let bitfield = create(Store::Bitfield)
             .await
             .map_err(map_random_access_err)?;
-        let mut oplog = create(Store::Oplog).await.map_err(map_random_access_err)?;
+        let oplog = create(Store::Oplog).await.map_err(map_random_access_err)?;
+
+        Self::from_stores(tree, data, bitfield, oplog, overwrite).await
+    }
 
+    /// Create a new instance from the given stores
+    async fn from_stores(
+        tree: Box<T>,
+        data: Box<T>,
+        bitfield: Box<T>,
+        oplog: Box<T>,
+        overwrite: bool,
+    ) -> Result<Self, HypercoreError> {
+        let mut instance = Self {
+            tree: Mutex::new(tree),
+            data: Mutex::new(data),
+            bitfield: Mutex::new(bitfield),
+            oplog: Mutex::new(oplog),
+            checksums: None,
+            pool: BufferPool::default(),
+            poisoned: false,
+            #[cfg(all(
+                not(target_arch = "wasm32"),
+                any(feature = "tokio", feature = "async-std")
+            ))]
+            lock: None,
+        };
         if overwrite {
-            if tree.len().await.map_err(map_random_access_err)? > 0 {
-                tree.truncate(0).await.map_err(map_random_access_err)?;
-            }
-            if data.len().await.map_err(map_random_access_err)? > 0 {
-                data.truncate(0).await.map_err(map_random_access_err)?;
+            instance.truncate_all().await?;
+        }
+        Ok(instance)
+    }
+
+    /// Keep checksums of the pages of the tree, bitfield and oplog stores in `checksums`, to
+    /// detect silent corruption of them. With `verify_on_read`, every read of these stores is
+    /// checked and fails with [`HypercoreError::CorruptStorage`] on a mismatch, otherwise use
+    /// [`crate::Hypercore::verify_checksums`]. Pages without a checksum, e.g. when enabling
+    /// checksums on existing stores, get one computed from their current content.
+    pub async fn with_checksums(
+        mut self,
+        checksums: Box<dyn StorageTraits + Send>,
+        verify_on_read: bool,
+    ) -> Result<Self, HypercoreError> {
+        self.checksums = Some(Checksums {
+            store: Mutex::new(checksums),
+            verify_on_read,
+        });
+        for store in CHECKSUM_STORES {
+            let length = self
+                .get_random_access_mut(&store)
+                .len()
+                .await
+                .map_err(map_random_access_err)?;
+            self.update_checksums(&store, pages(0, length), true)
+                .await?;
+        }
+        Ok(self)
+    }
+
+    /// Check the tree, bitfield and oplog stores against their checksums, if kept
+    pub(crate) async fn verify_checksums(&self) -> Result<(), HypercoreError> {
+        let Some(checksums) = &self.checksums else {
+            return Ok(());
+        };
+        for store in CHECKSUM_STORES {
+            let mut storage = self.lock_random_access(&store).await;
+            let mut checksums = checksums.store.lock().await;
+            let length = storage.len().await.map_err(map_random_access_err)?;
+            for page in pages(0, length) {
+                verify_page(&mut **storage, &mut checksums, &store, page).await?;
             }
-            if bitfield.len().await.map_err(map_random_access_err)? > 0 {
-                bitfield.truncate(0).await.map_err(map_random_access_err)?;
+        }
+        Ok(())
+    }
+
+    /// Recompute the checksums of the given pages of a store. With `missing_only`, pages that
+    /// already have a checksum are kept.
+    async fn update_checksums(
+        &mut self,
+        store: &Store,
+        pages: std::ops::Range<u64>,
+        missing_only: bool,
+    ) -> Result<(), HypercoreError> {
+        if self.checksums.is_none() || checksum_offset(store, 0).is_none() {
+            return Ok(());
+        }
+        for page in pages {
+            let offset = checksum_offset(store, page).expect("Store has checksums");
+            let checksums = self
+                .checksums
+                .as_mut()
+                .expect("Checksums kept")
+                .store
+                .get_mut();
+            if missing_only && stored_checksum(checksums, offset).await?.is_some() {
+                continue;
             }
-            if oplog.len().await.map_err(map_random_access_err)? > 0 {
-                oplog.truncate(0).await.map_err(map_random_access_err)?;
+            let mut entry = [0; CHECKSUM_ENTRY_SIZE as usize];
+            if let Some(checksum) =
+                page_checksum(&mut **self.get_random_access_mut(store), page).await?
+            {
+                entry[..4].copy_from_slice(&checksum.to_le_bytes());
+                entry[4] = 1;
             }
+            self.checksums
+                .as_mut()
+                .expect("Checksums kept")
+                .store
+                .get_mut()
+                .write(offset, &entry)
+                .await
+                .map_err(map_random_access_err)?;
         }
+        Ok(())
+    }
 
-        let instance = Self {
-            tree,
-            data,
-            bitfield,
-            oplog,
-        };
+    /// Remove all content from every store.
+    pub(crate) async fn truncate_all(&mut self) -> Result<(), HypercoreError> {
+        for store in [Store::Tree, Store::Data, Store::Bitfield, Store::Oplog] {
+            let storage = self.get_random_access_mut(&store);
+            if storage.len().await.map_err(map_random_access_err)? > 0 {
+                storage.truncate(0).await.map_err(map_random_access_err)?;
+            }
+        }
+        if let Some(checksums) = self.checksums.as_mut() {
+            let store = checksums.store.get_mut();
+            if store.len().await.map_err(map_random_access_err)? > 0 {
+                store.truncate(0).await.map_err(map_random_access_err)?;
+            }
+        }
+        Ok(())
+    }
 
-        Ok(instance)
+    /// Copy the content of every store into `target`, whose stores must be empty
+    #[cfg(all(
+        not(target_arch = "wasm32"),
+        any(feature = "tokio", feature = "async-std")
+    ))]
+    pub(crate) async fn copy_into<U: StorageTraits + Send + ?Sized>(
+        &self,
+        target: &mut Storage<U>,
+    ) -> Result<(), HypercoreError> {
+        for store in [Store::Tree, Store::Data, Store::Bitfield, Store::Oplog] {
+            let mut source = self.lock_random_access(&store).await;
+            let destination = target.get_random_access_mut(&store);
+            if destination.len().await.map_err(map_random_access_err)? > 0 {
+                return Err(HypercoreError::BadArgument {
+                    context: format!("Store {store} of the target storage is not empty"),
+                });
+            }
+            let length = source.len().await.map_err(map_random_access_err)?;
+            let mut offset = 0;
+            while offset < length {
+                let chunk_length = (length - offset).min(COPY_CHUNK_SIZE);
+                let chunk = source
+                    .read(offset, chunk_length)
+                    .await
+                    .map_err(map_random_access_err)?;
+                destination
+                    .write(offset, &chunk)
+                    .await
+                    .map_err(map_random_access_err)?;
+                offset += chunk_length;
+            }
+            target
+                .update_checksums(&store, pages(0, length), false)
+                .await?;
+        }
+        Ok(())
     }
 
     /// Read info from store based on given instruction. Convenience method to `read_infos`.
     pub(crate) async fn read_info(
-        &mut self,
+        &self,
         info_instruction: StoreInfoInstruction,
     ) -> Result<StoreInfo, HypercoreError> {
         let mut infos = self.read_infos_to_vec(&[info_instruction]).await?;
@@ -111,7 +403,7 @@ impl Storage {
 
     /// Read infos from stores based on given instructions
     pub(crate) async fn read_infos(
-        &mut self,
+        &self,
         info_instructions: &[StoreInfoInstruction],
     ) -> Result<Box<[StoreInfo]>, HypercoreError> {
         let infos = self.read_infos_to_vec(info_instructions).await?;
@@ -119,20 +411,26 @@ impl Storage {
     }
 
     /// Reads infos but retains them as a Vec
+    #[cfg_attr(
+        feature = "tracing",
+        instrument(level = "trace", err, skip_all, fields(instructions = info_instructions.len()))
+    )]
     pub(crate) async fn read_infos_to_vec(
-        &mut self,
+        &self,
         info_instructions: &[StoreInfoInstruction],
     ) -> Result<Vec<StoreInfo>, HypercoreError> {
         if info_instructions.is_empty() {
             return Ok(vec![]);
         }
         let mut current_store: Store = info_instructions[0].store.clone();
-        let mut storage = self.get_random_access_mut(&current_store);
+        let mut storage = self.lock_random_access(&current_store).await;
         let mut infos: Vec<StoreInfo> = Vec::with_capacity(info_instructions.len());
         for instruction in info_instructions.iter() {
             if instruction.store != current_store {
                 current_store = instruction.store.clone();
-                storage = self.get_random_access_mut(&current_store);
+                // Release the previous store first, only one store is ever locked at a time
+                drop(storage);
+                storage = self.lock_random_access(&current_store).await;
             }
             match instruction.info_type {
                 StoreInfoType::Content => {
@@ -140,7 +438,26 @@ impl Storage {
                         Some(length) => length,
                         None => storage.len().await.map_err(map_random_access_err)?,
                     };
-                    let read_result = storage.read(instruction.index, read_length).await;
+                    let read_result = traced!(
+                        storage.read(instruction.index, read_length),
+                        "read",
+                        store = %current_store,
+                        index = instruction.index,
+                        length = read_length
+                    )
+                    .await;
+                    metrics::storage_read(&current_store, read_length);
+                    if read_result.is_ok()
+                        && let Some(checksums) = &self.checksums
+                        && checksums.verify_on_read
+                        && checksum_offset(&current_store, 0).is_some()
+                    {
+                        let mut checksums = checksums.store.lock().await;
+                        for page in pages(instruction.index, read_length) {
+                            verify_page(&mut **storage, &mut checksums, &current_store, page)
+                                .await?;
+                        }
+                    }
                     let info: StoreInfo = match read_result {
                         Ok(buf) => Ok(StoreInfo::new_content(
                             instruction.store.clone(),
@@ -185,62 +502,296 @@ impl Storage {
     }
 
     /// Flush infos to storage
+    #[cfg_attr(
+        feature = "tracing",
+        instrument(level = "trace", err, skip_all, fields(infos = infos.len()))
+    )]
     pub(crate) async fn flush_infos(&mut self, infos: &[StoreInfo]) -> Result<(), HypercoreError> {
         if infos.is_empty() {
             return Ok(());
         }
+        self.check_poisoned()?;
+        self.renew_lock_if_due()?;
+        let result = self.write_infos(infos).await;
+        self.poisoned |= result.is_err();
+        result
+    }
+
+    /// Flush the infos of a state in memory that got ahead of the state written while the storage
+    /// was poisoned, see [`Storage::poison`], and allow writing again once they are written
+    pub(crate) async fn flush_poisoned_infos(
+        &mut self,
+        infos: &[StoreInfo],
+    ) -> Result<(), HypercoreError> {
+        self.renew_lock_if_due()?;
+        let result = if infos.is_empty() {
+            Ok(())
+        } else {
+            self.write_infos(infos).await
+        };
+        self.poisoned = result.is_err();
+        result
+    }
+
+    async fn write_infos(&mut self, infos: &[StoreInfo]) -> Result<(), HypercoreError> {
         let mut current_store: Store = infos[0].store.clone();
         let mut storage = self.get_random_access_mut(&current_store);
+        // Pages whose checksums need to be recomputed after writing
+        let mut written_pages: Vec<(Store, std::ops::Range<u64>)> = vec![];
         for info in infos.iter() {
             if info.store != current_store {
                 current_store = info.store.clone();
                 storage = self.get_random_access_mut(&current_store);
             }
+            match info.info_type {
+                StoreInfoType::Content => {
+                    let length = info
+                        .data
+                        .as_ref()
+                        .map_or(info.length.unwrap_or(0), |data| data.len() as u64);
+                    written_pages.push((current_store.clone(), pages(info.index, length)));
+                }
+                StoreInfoType::Size => {
+                    let length = storage.len().await.map_err(map_random_access_err)?;
+                    written_pages.push((
+                        current_store.clone(),
+                        pages(info.index, length.saturating_sub(info.index)),
+                    ));
+                }
+            }
             match info.info_type {
                 StoreInfoType::Content => {
                     if !info.miss {
                         if let Some(data) = &info.data {
-                            storage
-                                .write(info.index, data)
-                                .await
-                                .map_err(map_random_access_err)?;
-                        }
-                    } else {
-                        storage
-                            .del(
-                                info.index,
-                                info.length.expect("When deleting, length must be given"),
+                            traced!(
+                                storage.write(info.index, data),
+                                "write",
+                                store = %current_store,
+                                index = info.index,
+                                length = data.len()
                             )
                             .await
                             .map_err(map_random_access_err)?;
+                            metrics::storage_write(&current_store, data.len() as u64);
+                        }
+                    } else {
+                        let length = info.length.expect("When deleting, length must be given");
+                        traced!(
+                            storage.del(info.index, length),
+                            "delete",
+                            store = %current_store,
+                            index = info.index,
+                            length
+                        )
+                        .await
+                        .map_err(map_random_access_err)?;
                     }
                 }
                 StoreInfoType::Size => {
                     if info.miss {
-                        storage
-                            .truncate(info.index)
-                            .await
-                            .map_err(map_random_access_err)?;
+                        traced!(
+                            storage.truncate(info.index),
+                            "truncate",
+                            store = %current_store,
+                            length = info.index
+                        )
+                        .await
+                        .map_err(map_random_access_err)?;
                     } else {
                         panic!("Flushing a size that isn't miss, is not supported");
                     }
                 }
             }
         }
+        if self.checksums.is_some() {
+            for (store, pages) in written_pages {
+                self.update_checksums(&store, pages, false).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Write `slices` one after the other to `store`, starting at `index`. Unlike flushing a
+    /// [`StoreInfo`] with their concatenation, large slices are written directly, and only small
+    /// ones are coalesced into writes of up to 64 KiB, so a large batch isn't copied.
+    #[cfg_attr(
+        feature = "tracing",
+        instrument(
+            level = "trace",
+            err,
+            skip_all,
+            fields(store = %store, index, slices = slices.len())
+        )
+    )]
+    pub(crate) async fn write_slices<A: AsRef<[u8]>>(
+        &mut self,
+        store: Store,
+        index: u64,
+        slices: &[A],
+    ) -> Result<(), HypercoreError> {
+        self.check_poisoned()?;
+        self.renew_lock_if_due()?;
+        let result = self.write_slices_unchecked(store, index, slices).await;
+        self.poisoned |= result.is_err();
+        result
+    }
+
+    async fn write_slices_unchecked<A: AsRef<[u8]>>(
+        &mut self,
+        store: Store,
+        index: u64,
+        slices: &[A],
+    ) -> Result<(), HypercoreError> {
+        let mut chunk = self.pool.take(COALESCE_SIZE);
+        let mut chunk_index = index;
+        let mut offset = index;
+        for slice in slices {
+            let slice = slice.as_ref();
+            if chunk.len() + slice.len() > COALESCE_SIZE && !chunk.is_empty() {
+                self.write_slice(&store, chunk_index, &chunk).await?;
+                chunk.clear();
+            }
+            if chunk.is_empty() {
+                chunk_index = offset;
+            }
+            if slice.len() >= COALESCE_SIZE {
+                self.write_slice(&store, offset, slice).await?;
+            } else {
+                chunk.extend_from_slice(slice);
+            }
+            offset += slice.len() as u64;
+        }
+        if !chunk.is_empty() {
+            self.write_slice(&store, chunk_index, &chunk).await?;
+        }
+        self.pool.put(chunk);
+        self.update_checksums(&store, pages(index, offset - index), false)
+            .await
+    }
+
+    async fn write_slice(
+        &mut self,
+        store: &Store,
+        index: u64,
+        data: &[u8],
+    ) -> Result<(), HypercoreError> {
+        let storage = self.get_random_access_mut(store);
+        traced!(storage.write(index, data), "write", store = %store, index, length = data.len())
+            .await
+            .map_err(map_random_access_err)?;
+        metrics::storage_write(store, data.len() as u64);
+        Ok(())
+    }
+
+    /// Whether a write failed, see [`crate::Hypercore::recover`]
+    pub(crate) fn is_poisoned(&self) -> bool {
+        self.poisoned
+    }
+
+    /// Fail writes until [`Storage::clear_poisoned`], while the state in memory is ahead of the
+    /// state written
+    pub(crate) fn poison(&mut self) {
+        self.poisoned = true;
+    }
+
+    /// Allow writing again, once the state written before is read again
+    pub(crate) fn clear_poisoned(&mut self) {
+        self.poisoned = false;
+    }
+
+    /// Whether the lock of a process whose lease expired, e.g. because it crashed, was taken
+    /// over when acquiring the lock, see [`Storage::new_disk_locked`]
+    pub fn took_over_lock(&self) -> bool {
+        #[cfg(all(
+            not(target_arch = "wasm32"),
+            any(feature = "tokio", feature = "async-std")
+        ))]
+        if let Some(lock) = &self.lock {
+            return lock.taken_over();
+        }
+        false
+    }
+
+    /// Renew the lease of the lock, if any, see [`crate::Hypercore::heartbeat`]
+    // Only renewing an actual lock needs `&mut self`, there's none without a runtime or on wasm
+    #[cfg_attr(
+        any(
+            target_arch = "wasm32",
+            not(any(feature = "tokio", feature = "async-std"))
+        ),
+        allow(clippy::needless_pass_by_ref_mut)
+    )]
+    pub(crate) fn renew_lock(&mut self) -> Result<(), HypercoreError> {
+        #[cfg(all(
+            not(target_arch = "wasm32"),
+            any(feature = "tokio", feature = "async-std")
+        ))]
+        if let Some(lock) = self.lock.as_mut() {
+            lock.renew()?;
+        }
+        Ok(())
+    }
+
+    // Like `renew_lock`
+    #[cfg_attr(
+        any(
+            target_arch = "wasm32",
+            not(any(feature = "tokio", feature = "async-std"))
+        ),
+        allow(clippy::needless_pass_by_ref_mut)
+    )]
+    fn renew_lock_if_due(&mut self) -> Result<(), HypercoreError> {
+        #[cfg(all(
+            not(target_arch = "wasm32"),
+            any(feature = "tokio", feature = "async-std")
+        ))]
+        if let Some(lock) = self.lock.as_mut() {
+            lock.renew_if_due()?;
+        }
+        Ok(())
+    }
+
+    fn check_poisoned(&self) -> Result<(), HypercoreError> {
+        if self.poisoned {
+            return Err(HypercoreError::Poisoned);
+        }
         Ok(())
     }
 
-    fn get_random_access_mut(&mut self, store: &Store) -> &mut Box<dyn StorageTraits + Send> {
+    fn get_random_access_mut(&mut self, store: &Store) -> &mut Box<T> {
         match store {
-            Store::Tree => &mut self.tree,
-            Store::Data => &mut self.data,
-            Store::Bitfield => &mut self.bitfield,
-            Store::Oplog => &mut self.oplog,
+            Store::Tree => self.tree.get_mut(),
+            Store::Data => self.data.get_mut(),
+            Store::Bitfield => self.bitfield.get_mut(),
+            Store::Oplog => self.oplog.get_mut(),
         }
     }
 
+    async fn lock_random_access(&self, store: &Store) -> MutexGuard<'_, Box<T>> {
+        match store {
+            Store::Tree => self.tree.lock().await,
+            Store::Data => self.data.lock().await,
+            Store::Bitfield => self.bitfield.lock().await,
+            Store::Oplog => self.oplog.lock().await,
+        }
+    }
+}
+
+impl Storage {
+    /// Create a new instance from separate storage instances for each store, e.g. to keep data on
+    /// disk and the other stores in memory. Takes an overwrite flag like [`Storage::open`].
+    pub async fn from_parts(
+        tree: Box<dyn StorageTraits + Send>,
+        data: Box<dyn StorageTraits + Send>,
+        bitfield: Box<dyn StorageTraits + Send>,
+        oplog: Box<dyn StorageTraits + Send>,
+        overwrite: bool,
+    ) -> Result<Self, HypercoreError> {
+        Self::from_stores(tree, data, bitfield, oplog, overwrite).await
+    }
+
     /// New storage backed by a `RandomAccessMemory` instance.
-    #[instrument(err)]
+    #[cfg_attr(feature = "tracing", instrument(err))]
     pub async fn new_memory() -> Result<Self, HypercoreError> {
         let create = |_| {
             async { Ok(Box::new(RandomAccessMemory::default()) as Box<dyn StorageTraits + Send>) }
@@ -251,11 +802,14 @@ impl Storage {
     }
 
     /// New storage backed by a `RandomAccessDisk` instance.
-    #[cfg(not(target_arch = "wasm32"))]
-    #[instrument(err)]
-    pub async fn new_disk(dir: &PathBuf, overwrite: bool) -> Result<Self, HypercoreError> {
+    #[cfg(all(
+        not(target_arch = "wasm32"),
+        any(feature = "tokio", feature = "async-std")
+    ))]
+    #[cfg_attr(feature = "tracing", instrument(err))]
+    pub async fn new_disk(dir: &Path, overwrite: bool) -> Result<Self, HypercoreError> {
         let storage = |store: Store| {
-            let dir = dir.clone();
+            let dir = dir.to_path_buf();
             async move {
                 let name = match store {
                     Store::Tree => "tree",
@@ -263,13 +817,112 @@ impl Storage {
                     Store::Bitfield => "bitfield",
                     Store::Oplog => "oplog",
                 };
-                Ok(
-                    Box::new(RandomAccessDisk::open(dir.as_path().join(name)).await?)
-                        as Box<dyn StorageTraits + Send>,
-                )
+                Ok(Box::new(RandomAccessDisk::open(dir.join(name)).await?)
+                    as Box<dyn StorageTraits + Send>)
             }
             .boxed()
         };
         Self::open(storage, overwrite).await
     }
+
+    /// New storage backed by `RandomAccessDisk` instances, like [`Storage::new_disk`], holding
+    /// the `lock` file of the directory so that no other process opens it meanwhile, which fails
+    /// with [`HypercoreError::Locked`]. The lock has a `lease`, renewed when writing or with
+    /// [`crate::Hypercore::heartbeat`] once a third of it passed. Once the lease of a holder
+    /// expires, e.g. because it crashed, another process takes over the lock, see
+    /// [`Storage::took_over_lock`], and opening the hypercore replays the oplog entries the
+    /// holder didn't flush. Writes of a holder whose lock was taken over fail with
+    /// [`HypercoreError::Locked`], so an idle holder needs to renew the lease in time.
+    #[cfg(all(
+        not(target_arch = "wasm32"),
+        any(feature = "tokio", feature = "async-std")
+    ))]
+    #[cfg_attr(feature = "tracing", instrument(err))]
+    pub async fn new_disk_locked(
+        dir: &Path,
+        overwrite: bool,
+        lease: std::time::Duration,
+    ) -> Result<Self, HypercoreError> {
+        let lock = DiskLock::acquire(dir, lease)?;
+        let mut storage = Self::new_disk(dir, overwrite).await?;
+        storage.lock = Some(lock);
+        Ok(storage)
+    }
+
+    /// New storage backed by `RandomAccessDisk` instances, like [`Storage::new_disk`], keeping
+    /// checksums of the tree, bitfield and oplog in a `checksums` file, see
+    /// [`Storage::with_checksums`].
+    #[cfg(all(
+        not(target_arch = "wasm32"),
+        any(feature = "tokio", feature = "async-std")
+    ))]
+    #[cfg_attr(feature = "tracing", instrument(err))]
+    pub async fn new_disk_with_checksums(
+        dir: &Path,
+        overwrite: bool,
+        verify_on_read: bool,
+    ) -> Result<Self, HypercoreError> {
+        let storage = Self::new_disk(dir, overwrite).await?;
+        let mut checksums = RandomAccessDisk::open(dir.join("checksums"))
+            .await
+            .map_err(map_random_access_err)?;
+        if overwrite {
+            checksums.truncate(0).await.map_err(map_random_access_err)?;
+        }
+        storage
+            .with_checksums(Box::new(checksums), verify_on_read)
+            .await
+    }
+}
+
+impl Storage<RandomAccessMemory> {
+    /// New storage backed by `RandomAccessMemory` instances called without dynamic dispatch,
+    /// like [`Storage::new_memory`] otherwise.
+    #[cfg_attr(feature = "tracing", instrument(err))]
+    pub async fn new_memory_static() -> Result<Self, HypercoreError> {
+        let create = |_| async { Ok(Box::new(RandomAccessMemory::default())) }.boxed();
+        Self::open(create, false).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[async_std::test]
+    async fn write_slices_coalesces_small_slices() -> Result<(), HypercoreError> {
+        let mut storage = Storage::new_memory().await?;
+        let slices: Vec<Vec<u8>> = vec![
+            vec![1; 10],
+            vec![2; COALESCE_SIZE],
+            vec![3; COALESCE_SIZE - 20],
+            vec![4; 30],
+            vec![5; 5],
+        ];
+        storage.write_slices(Store::Data, 7, &slices).await?;
+        let info = storage
+            .read_info(StoreInfoInstruction::new_all_content(Store::Data))
+            .await?;
+        let mut expected = vec![0; 7];
+        expected.extend(slices.concat());
+        assert_eq!(info.data.as_deref(), Some(&expected[..]));
+        Ok(())
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn core_dir_of_discovery_key() {
+        // Key of the discovery key test vector in `crypto::hash`
+        let key = ed25519_dalek::VerifyingKey::from_bytes(&[
+            0x97, 0x60, 0x6c, 0xaa, 0xd2, 0xb0, 0x8c, 0x1d, 0x5f, 0xe1, 0x64, 0x2e, 0xee, 0xa5,
+            0x62, 0xcb, 0x91, 0xd6, 0x55, 0xe2, 0x00, 0xc8, 0xd4, 0x3a, 0x32, 0x09, 0x1d, 0x06,
+            0x4a, 0x33, 0x1e, 0xe3,
+        ])
+        .unwrap();
+        assert_eq!(
+            core_dir(Path::new("store"), &key),
+            Path::new("store/cores/17/e4")
+                .join("17e48ada51127b6fa0c3689a377412842ce54d76d93629a261765f04d58e4f7c")
+        );
+    }
 }
@@ -0,0 +1,156 @@
+//! [`proptest`] strategies generating realistic values of the types exchanged with hypercores,
+//! to property-test protocol crates against them.
+//!
+//! Values are well-formed, e.g. hashes are 32 bytes and signatures 64 bytes, but not consistent
+//! with each other: the nodes of a generated proof don't verify against any tree.
+use proptest::collection::vec;
+use proptest::option;
+use proptest::prelude::*;
+
+use hypercore_schema::{
+    DataBlock, DataHash, DataSeek, DataUpgrade, Node, Proof, RequestBlock, RequestSeek,
+    RequestUpgrade,
+};
+
+#[cfg(test)]
+use crate::common::BitfieldUpdate;
+#[cfg(test)]
+use crate::oplog::{Entry, EntryTreeUpgrade};
+use crate::{Manifest, ManifestSigner};
+
+/// Largest generated block index, so that flat tree indexes of the blocks don't overflow
+const MAX_INDEX: u64 = 1 << 40;
+/// Largest number of nodes in a generated proof part
+const MAX_NODES: usize = 8;
+/// Largest generated block value
+const MAX_VALUE_LENGTH: usize = 256;
+
+fn bytes(length: usize) -> impl Strategy<Value = Vec<u8>> {
+    vec(any::<u8>(), length)
+}
+
+/// Tree node with a 32 byte hash
+pub fn node() -> impl Strategy<Value = Node> {
+    (0..2 * MAX_INDEX, bytes(32), any::<u32>())
+        .prop_map(|(index, hash, length)| Node::new(index, hash, length.into()))
+}
+
+/// Tree nodes, as found in the parts of a proof
+pub fn nodes() -> impl Strategy<Value = Vec<Node>> {
+    vec(node(), 0..=MAX_NODES)
+}
+
+/// Request of a block or of its hash
+pub fn request_block() -> impl Strategy<Value = RequestBlock> {
+    (0..MAX_INDEX, 0..64u64).prop_map(|(index, nodes)| RequestBlock { index, nodes })
+}
+
+/// Request of the block containing a byte
+pub fn request_seek() -> impl Strategy<Value = RequestSeek> {
+    any::<u64>().prop_map(|bytes| RequestSeek { bytes })
+}
+
+/// Request of an upgrade
+pub fn request_upgrade() -> impl Strategy<Value = RequestUpgrade> {
+    (0..MAX_INDEX, 0..MAX_INDEX).prop_map(|(start, length)| RequestUpgrade { start, length })
+}
+
+/// Block part of a proof
+pub fn data_block() -> impl Strategy<Value = DataBlock> {
+    (0..MAX_INDEX, vec(any::<u8>(), 0..MAX_VALUE_LENGTH), nodes()).prop_map(
+        |(index, value, nodes)| DataBlock {
+            index,
+            value,
+            nodes,
+        },
+    )
+}
+
+/// Hash part of a proof
+pub fn data_hash() -> impl Strategy<Value = DataHash> {
+    (0..MAX_INDEX, nodes()).prop_map(|(index, nodes)| DataHash { index, nodes })
+}
+
+/// Seek part of a proof
+pub fn data_seek() -> impl Strategy<Value = DataSeek> {
+    (any::<u64>(), nodes()).prop_map(|(bytes, nodes)| DataSeek { bytes, nodes })
+}
+
+/// Upgrade part of a proof, with a 64 byte signature
+pub fn data_upgrade() -> impl Strategy<Value = DataUpgrade> {
+    (0..MAX_INDEX, 0..MAX_INDEX, nodes(), nodes(), bytes(64)).prop_map(
+        |(start, length, nodes, additional_nodes, signature)| DataUpgrade {
+            start,
+            length,
+            nodes,
+            additional_nodes,
+            signature,
+        },
+    )
+}
+
+/// Proof with any of its parts, except both a block and a hash
+pub fn proof() -> impl Strategy<Value = Proof> {
+    let block_or_hash = prop_oneof![
+        Just((None, None)),
+        data_block().prop_map(|block| (Some(block), None)),
+        data_hash().prop_map(|hash| (None, Some(hash))),
+    ];
+    (
+        any::<u64>(),
+        block_or_hash,
+        option::of(data_seek()),
+        option::of(data_upgrade()),
+    )
+        .prop_map(|(fork, (block, hash), seek, upgrade)| Proof {
+            fork,
+            block,
+            hash,
+            seek,
+            upgrade,
+        })
+}
+
+/// Manifest of a single `ed25519` signer, the only kind supported
+pub fn manifest() -> impl Strategy<Value = Manifest> {
+    (any::<[u8; 32]>(), any::<[u8; 32]>()).prop_map(|(namespace, public_key)| Manifest {
+        hash: "blake2b".to_string(),
+        signer: ManifestSigner {
+            signature: "ed25519".to_string(),
+            namespace,
+            public_key,
+        },
+    })
+}
+
+/// Oplog entry
+#[cfg(test)]
+pub(crate) fn entry() -> impl Strategy<Value = Entry> {
+    let tree_upgrade = (any::<u64>(), any::<u64>(), any::<u64>(), bytes(64)).prop_map(
+        |(fork, ancestors, length, signature)| EntryTreeUpgrade {
+            fork,
+            ancestors,
+            length,
+            signature: signature.into_boxed_slice(),
+        },
+    );
+    let bitfield = (any::<bool>(), any::<u64>(), any::<u64>()).prop_map(|(drop, start, length)| {
+        BitfieldUpdate {
+            drop,
+            start,
+            length,
+        }
+    });
+    (
+        vec("[a-z]{1,8}", 0..3),
+        nodes(),
+        option::of(tree_upgrade),
+        option::of(bitfield),
+    )
+        .prop_map(|(user_data, tree_nodes, tree_upgrade, bitfield)| Entry {
+            user_data,
+            tree_nodes,
+            tree_upgrade,
+            bitfield,
+        })
+}
@@ -0,0 +1,80 @@
+use crate::common::NodeByteRange;
+
+/// Cumulative byte lengths of the first blocks of the tree, kept in memory to find the byte range
+/// of a block, or the block of a byte offset, without reading nodes from storage.
+#[derive(Debug, Default)]
+pub(crate) struct ByteIndex {
+    /// End offset of each indexed block
+    ends: Vec<u64>,
+}
+
+impl ByteIndex {
+    /// Number of indexed blocks, which are the first blocks of the tree
+    pub(crate) fn len(&self) -> u64 {
+        self.ends.len() as u64
+    }
+
+    /// Index the next block
+    pub(crate) fn push(&mut self, length: u64) {
+        let start = self.ends.last().copied().unwrap_or(0);
+        self.ends.push(start + length);
+    }
+
+    /// Drop the blocks from the given index on
+    pub(crate) fn truncate(&mut self, length: u64) {
+        self.ends.truncate(length as usize);
+    }
+
+    /// Byte range of the given block, if indexed
+    pub(crate) fn byte_range(&self, index: u64) -> Option<NodeByteRange> {
+        let end = *self.ends.get(index as usize)?;
+        let start = self.start(index);
+        Some(NodeByteRange {
+            index: start,
+            length: end - start,
+        })
+    }
+
+    /// Index of the block containing the given byte, and the offset of the byte within that
+    /// block, if indexed
+    pub(crate) fn seek(&self, bytes: u64) -> Option<(u64, u64)> {
+        let index = self.ends.partition_point(|end| *end <= bytes) as u64;
+        if index == self.len() {
+            return None;
+        }
+        Some((index, bytes - self.start(index)))
+    }
+
+    fn start(&self, index: u64) -> u64 {
+        match index {
+            0 => 0,
+            _ => self.ends[index as usize - 1],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn byte_index_seek() {
+        let mut index = ByteIndex::default();
+        for length in [3, 0, 2, 5] {
+            index.push(length);
+        }
+        assert_eq!(index.seek(0), Some((0, 0)));
+        assert_eq!(index.seek(2), Some((0, 2)));
+        // Empty blocks are skipped
+        assert_eq!(index.seek(3), Some((2, 0)));
+        assert_eq!(index.seek(9), Some((3, 4)));
+        assert_eq!(index.seek(10), None);
+        let range = index.byte_range(3).unwrap();
+        assert_eq!((range.index, range.length), (5, 5));
+
+        index.truncate(2);
+        assert_eq!(index.len(), 2);
+        assert_eq!(index.seek(3), None);
+        assert!(index.byte_range(2).is_none());
+    }
+}
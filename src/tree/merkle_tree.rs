@@ -4,14 +4,16 @@ use compact_encoding::{
 use ed25519_dalek::Signature;
 use futures::future::Either;
 use intmap::IntMap;
-#[cfg(feature = "cache")]
-use moka::sync::Cache;
 use std::convert::TryFrom;
+use std::ops::Range;
 
 use crate::Store;
-#[cfg(feature = "cache")]
-use crate::common::cache::CacheOptions;
 use crate::common::{HypercoreError, NodeByteRange, ValuelessProof};
+#[cfg(feature = "cache")]
+use crate::common::{
+    cache::{Cache, CacheOptions},
+    metrics,
+};
 use crate::oplog::HeaderTree;
 use crate::{
     VerifyingKey,
@@ -40,7 +42,7 @@ pub(crate) struct MerkleTree {
     node_cache: Option<Cache<u64, Node>>,
 }
 
-const NODE_SIZE: u64 = 40;
+pub(crate) const NODE_SIZE: u64 = 40;
 
 impl MerkleTree {
     /// Opens MerkleTree, based on read infos.
@@ -79,6 +81,7 @@ impl MerkleTree {
                     if index != index_from_info(&infos[i]) {
                         return Err(HypercoreError::CorruptStorage {
                             store: Store::Tree,
+                            offset: None,
                             context: Some(
                                 "Given slices vector not in the correct order".to_string(),
                             ),
@@ -125,6 +128,12 @@ impl MerkleTree {
         }
     }
 
+    /// Does the tree cache nodes in memory
+    #[cfg(feature = "cache")]
+    pub(crate) fn has_node_cache(&self) -> bool {
+        self.node_cache.is_some()
+    }
+
     /// Initialize a changeset for this tree.
     /// This is called batch() in Javascript, see:
     /// https://github.com/hypercore-protocol/hypercore/blob/master/lib/merkle-tree.js
@@ -157,8 +166,9 @@ impl MerkleTree {
         Ok(())
     }
 
-    /// Flush committed made changes to the tree
-    pub(crate) fn flush(&mut self) -> Box<[StoreInfo]> {
+    /// Flush committed made changes to the tree. The changes stay pending until marked
+    /// [`MerkleTree::flushed`].
+    pub(crate) fn flush(&self) -> Box<[StoreInfo]> {
         let mut infos_to_flush: Vec<StoreInfo> = Vec::new();
         if self.truncated {
             infos_to_flush.extend(self.flush_truncation());
@@ -167,6 +177,13 @@ impl MerkleTree {
         infos_to_flush.into_boxed_slice()
     }
 
+    /// Mark the changes returned by [`MerkleTree::flush`] as written
+    pub(crate) fn flushed(&mut self) {
+        self.truncate_to = 0;
+        self.truncated = false;
+        self.unflushed.clear();
+    }
+
     /// Get storage byte range of given hypercore index
     pub(crate) fn byte_range(
         &self,
@@ -258,7 +275,7 @@ impl MerkleTree {
                 .position(|root| root.index == parent.index);
             if let Some(r) = r {
                 for i in 0..r {
-                    tree_offset += self.roots[i].length;
+                    tree_offset += changeset.roots[i].length;
                 }
                 return Ok(Either::Right(tree_offset));
             }
@@ -556,6 +573,85 @@ impl MerkleTree {
         }
     }
 
+    /// Verifies a proof with an upgrade from the start of the tree on its own, without the nodes
+    /// of the tree, and returns a changeset from an empty tree to the tree of the proof.
+    pub(crate) fn verify_remote_tree(
+        &self,
+        proof: &Proof,
+        public_key: &VerifyingKey,
+    ) -> Result<MerkleTreeChangeset, HypercoreError> {
+        let Some(upgrade) = proof.upgrade.as_ref().filter(|upgrade| upgrade.start == 0) else {
+            return Err(HypercoreError::BadArgument {
+                context: "Proof must contain an upgrade from the start".to_string(),
+            });
+        };
+        let mut remote = MerkleTreeChangeset::new(0, 0, self.fork, vec![]);
+        let block_root = verify_tree(
+            proof.block.as_ref(),
+            proof.hash.as_ref(),
+            proof.seek.as_ref(),
+            &mut remote,
+        )?;
+        verify_upgrade(
+            proof.fork,
+            upgrade,
+            block_root.as_ref(),
+            public_key,
+            &mut remote,
+        )?;
+        Ok(remote)
+    }
+
+    /// Verifies a proof of a newer fork, which must contain only an upgrade from the start of the
+    /// tree, and returns a changeset replacing the tree with the one of the proof. The ancestors
+    /// of the changeset are the blocks under the leading roots of the proof that the tree already
+    /// has.
+    pub(crate) fn verify_reorg(
+        &self,
+        proof: &Proof,
+        public_key: &VerifyingKey,
+        infos: Option<&[StoreInfo]>,
+    ) -> Result<Either<Box<[StoreInfoInstruction]>, MerkleTreeChangeset>, HypercoreError> {
+        if proof.block.is_some() || proof.hash.is_some() || proof.seek.is_some() {
+            return Err(HypercoreError::BadArgument {
+                context: "Reorg proof must contain only an upgrade".to_string(),
+            });
+        }
+        let remote = self.verify_remote_tree(proof, public_key)?;
+
+        let nodes: IntMap<Option<Node>> = self.infos_to_nodes(infos)?;
+        let mut instructions: Vec<StoreInfoInstruction> = Vec::new();
+        let mut ancestors = 0;
+        for root in &remote.roots {
+            let end = flat_tree::right_span(root.index) / 2 + 1;
+            if end > self.length {
+                break;
+            }
+            match self.optional_node(root.index, &nodes)? {
+                Either::Left(instruction) => instructions.push(instruction),
+                Either::Right(Some(node)) if node.hash == root.hash && instructions.is_empty() => {
+                    ancestors = end;
+                }
+                Either::Right(_) => break,
+            }
+        }
+        if !instructions.is_empty() {
+            return Ok(Either::Left(instructions.into_boxed_slice()));
+        }
+
+        let mut changeset = self.changeset();
+        changeset.length = remote.length;
+        changeset.byte_length = remote.byte_length;
+        changeset.fork = remote.fork;
+        changeset.roots = remote.roots;
+        changeset.nodes = remote.nodes;
+        changeset.hash = remote.hash;
+        changeset.signature = remote.signature;
+        changeset.ancestors = ancestors;
+        changeset.upgraded = true;
+        Ok(Either::Right(changeset))
+    }
+
     /// Attempts to get missing nodes from given index. NB: must be called in a loop.
     pub(crate) fn missing_nodes(
         &self,
@@ -591,6 +687,88 @@ impl MerkleTree {
         Ok(Either::Right(count))
     }
 
+    /// Attempts to get missing nodes for every hypercore index in the given range. Reads the
+    /// nodes of all indexes in one go, NB: must be called in a loop.
+    pub(crate) fn missing_nodes_range(
+        &self,
+        range: Range<u64>,
+        infos: Option<&[StoreInfo]>,
+    ) -> Result<Either<Box<[StoreInfoInstruction]>, Vec<u64>>, HypercoreError> {
+        let head = 2 * self.length;
+        let nodes: IntMap<Option<Node>> = self.infos_to_nodes(infos)?;
+        let mut instructions: Vec<StoreInfoInstruction> = Vec::new();
+        let mut requested: IntMap<()> = IntMap::new();
+        let mut counts: Vec<u64> =
+            Vec::with_capacity((range.end.saturating_sub(range.start)) as usize);
+        for index in range {
+            let mut iter = flat_tree::Iterator::new(hypercore_index_into_merkle_tree_index(index));
+            // Outside of the current tree, see `missing_nodes`
+            if iter.index() >= head {
+                counts.push(0);
+                continue;
+            }
+            let mut count: u64 = 0;
+            while !iter.contains(head) {
+                match self.optional_node(iter.index(), &nodes)? {
+                    Either::Left(instruction) => {
+                        if requested.insert(iter.index(), ()).is_none() {
+                            instructions.push(instruction);
+                        }
+                        break;
+                    }
+                    Either::Right(Some(_)) => break,
+                    Either::Right(None) => {
+                        count += 1;
+                        iter.parent();
+                    }
+                }
+            }
+            counts.push(count);
+        }
+        if instructions.is_empty() {
+            Ok(Either::Right(counts))
+        } else {
+            Ok(Either::Left(instructions.into_boxed_slice()))
+        }
+    }
+
+    /// Get a node of the tree by merkle tree index, `None` if the node is missing.
+    pub(crate) fn get_node(
+        &self,
+        index: u64,
+        infos: Option<&[StoreInfo]>,
+    ) -> Result<Either<Box<[StoreInfoInstruction]>, Option<Node>>, HypercoreError> {
+        let nodes: IntMap<Option<Node>> = self.infos_to_nodes(infos)?;
+        match self.optional_node(index, &nodes)? {
+            Either::Left(instruction) => Ok(Either::Left(vec![instruction].into_boxed_slice())),
+            Either::Right(node) => Ok(Either::Right(node)),
+        }
+    }
+
+    /// Get the stored nodes of the tree within the given range of merkle tree indexes. Missing
+    /// nodes and nodes outside of the current tree are skipped.
+    pub(crate) fn get_nodes(
+        &self,
+        range: Range<u64>,
+        infos: Option<&[StoreInfo]>,
+    ) -> Result<Either<Box<[StoreInfoInstruction]>, Vec<Node>>, HypercoreError> {
+        let nodes: IntMap<Option<Node>> = self.infos_to_nodes(infos)?;
+        let mut instructions: Vec<StoreInfoInstruction> = Vec::new();
+        let mut result: Vec<Node> = Vec::new();
+        for index in range.start..range.end.min(2 * self.length) {
+            match self.optional_node(index, &nodes)? {
+                Either::Left(instruction) => instructions.push(instruction),
+                Either::Right(Some(node)) => result.push(node),
+                Either::Right(None) => {}
+            }
+        }
+        if instructions.is_empty() {
+            Ok(Either::Right(result))
+        } else {
+            Ok(Either::Left(instructions.into_boxed_slice()))
+        }
+    }
+
     /// Is the changeset commitable to given tree
     pub(crate) fn commitable(&self, changeset: &MerkleTreeChangeset) -> bool {
         let correct_length: bool = if changeset.upgraded {
@@ -638,20 +816,18 @@ impl MerkleTree {
         }
     }
 
-    pub(crate) fn flush_truncation(&mut self) -> Vec<StoreInfo> {
+    fn flush_truncation(&self) -> Vec<StoreInfo> {
         let offset = if self.truncate_to == 0 {
             0
         } else {
             (self.truncate_to - 1) * 80 + 40
         };
-        self.truncate_to = 0;
-        self.truncated = false;
         vec![StoreInfo::new_truncate(Store::Tree, offset)]
     }
 
-    pub(crate) fn flush_nodes(&mut self) -> Vec<StoreInfo> {
+    fn flush_nodes(&self) -> Vec<StoreInfo> {
         let mut infos_to_flush: Vec<StoreInfo> = Vec::with_capacity(self.unflushed.len());
-        for (_, node) in self.unflushed.drain() {
+        for (_, node) in self.unflushed.iter() {
             let buffer = (|| {
                 let hash = as_array::<32>(&node.hash)?;
                 Ok::<Box<[u8]>, EncodingError>(to_encoded_bytes!(
@@ -795,7 +971,7 @@ impl MerkleTree {
         // First check the cache
         #[cfg(feature = "cache")]
         if let Some(node_cache) = &self.node_cache
-            && let Some(node) = node_cache.get(&index)
+            && let Some(node) = metrics::cache_lookup("node", node_cache.get(&index))
         {
             return Ok(Either::Right(Some(node)));
         }
@@ -1391,6 +1567,64 @@ fn verify_tree(
     Ok(root)
 }
 
+/// Finds the block that contains byte `seek.bytes` using the nodes of a verified seek proof and the
+/// roots of the tree the proof was verified against. Returns the index of the block and the
+/// offset of the byte within that block.
+pub(crate) fn seek_position(seek: &DataSeek, roots: &[Node]) -> Result<(u64, u64), HypercoreError> {
+    let Some(seek_node) = seek.nodes.first() else {
+        return Err(HypercoreError::InvalidOperation {
+            context: "Seek proof does not contain any nodes".to_string(),
+        });
+    };
+
+    // Sum up the lengths of the left siblings on the way to the root
+    let mut iter = flat_tree::Iterator::new(seek_node.index);
+    let mut offset: u64 = 0;
+    for node in &seek.nodes[1..] {
+        if node.index < iter.index() {
+            offset += node.length;
+        }
+        iter.parent();
+    }
+
+    // Then the lengths of the roots before that root
+    let root_index = iter.index();
+    let Some(root_position) = roots.iter().position(|root| root.index == root_index) else {
+        return Err(HypercoreError::InvalidOperation {
+            context: format!("Seek proof root {root_index} is not a root of the tree"),
+        });
+    };
+    offset += roots[..root_position]
+        .iter()
+        .map(|root| root.length)
+        .sum::<u64>();
+
+    let end = offset + seek_node.length;
+    if seek.bytes < offset || seek.bytes > end {
+        return Err(HypercoreError::InvalidOperation {
+            context: format!(
+                "Seek proof for byte {} covers bytes {offset}..{end}",
+                seek.bytes
+            ),
+        });
+    }
+    if seek.bytes == end {
+        // The seek landed on the end of the node, the byte is the first of the next block
+        Ok((flat_tree::right_span(seek_node.index) / 2 + 1, 0))
+    } else if seek.bytes == offset {
+        Ok((flat_tree::left_span(seek_node.index) / 2, 0))
+    } else if seek_node.index & 1 == 0 {
+        Ok((seek_node.index / 2, seek.bytes - offset))
+    } else {
+        Err(HypercoreError::InvalidOperation {
+            context: format!(
+                "Seek proof for byte {} does not resolve to a single block",
+                seek.bytes
+            ),
+        })
+    }
+}
+
 fn verify_upgrade(
     fork: u64,
     upgrade: &DataUpgrade,
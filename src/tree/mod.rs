@@ -1,5 +1,7 @@
+mod byte_index;
 mod merkle_tree;
 mod merkle_tree_changeset;
 
-pub(crate) use merkle_tree::MerkleTree;
+pub(crate) use byte_index::ByteIndex;
+pub(crate) use merkle_tree::{MerkleTree, NODE_SIZE, seek_position};
 pub(crate) use merkle_tree_changeset::MerkleTreeChangeset;
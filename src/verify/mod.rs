@@ -0,0 +1,348 @@
+//! Verification of hypercore proofs against a known public key, without storage or an async
+//! runtime. For constrained environments like embedded gateways, which receive proofs from a
+//! peer and only need to check them.
+//!
+//! The verification itself only uses `core` and `alloc` and can be built in `no_std` crates, with
+//! its own plain [`Proof`] type. This module adds conversions from the [`encoding::Proof`]
+//! received from peers.
+//!
+//! ```rust
+//! # #[cfg(feature = "tokio")]
+//! # tokio_test::block_on(async {
+//! # example().await;
+//! # });
+//! # #[cfg(feature = "async-std")]
+//! # async_std::task::block_on(async {
+//! # example().await;
+//! # });
+//! # async fn example() {
+//! use hypercore::encoding::{RequestBlock, RequestUpgrade};
+//! use hypercore::verify::{Proof, verify_proof};
+//! use hypercore::{HypercoreBuilder, Storage};
+//!
+//! let mut core = HypercoreBuilder::new(Storage::new_memory().await.unwrap())
+//!     .build()
+//!     .await
+//!     .unwrap();
+//! core.append_batch([b"a", b"b", b"c"]).await.unwrap();
+//! let proof = core
+//!     .create_proof(
+//!         Some(RequestBlock { index: 1, nodes: 2 }),
+//!         None,
+//!         None,
+//!         Some(RequestUpgrade { start: 0, length: 3 }),
+//!     )
+//!     .await
+//!     .unwrap()
+//!     .unwrap();
+//!
+//! let public_key = core.key_pair().public;
+//! let verified = verify_proof(&public_key, None, &Proof::try_from(&proof).unwrap()).unwrap();
+//! assert_eq!((verified.tree.length, verified.block), (3, Some(1)));
+//! # }
+//! ```
+//!
+//! [`encoding::Proof`]: crate::encoding::Proof
+mod verifier;
+
+pub use verifier::{
+    BlockProof, Node, Proof, UpgradeProof, Verified, VerifiedTree, VerifyError, verify_proof,
+    verify_remote_head,
+};
+
+use ed25519_dalek::VerifyingKey;
+use hypercore_schema::{DataBlock, DataUpgrade};
+
+use crate::Manifest;
+
+/// [`verify_remote_head`] with the public key of the signer of `manifest`
+pub fn verify_remote_head_with_manifest(
+    manifest: &Manifest,
+    fork: u64,
+    length: u64,
+    tree_hash: &[u8; 32],
+    signature: &[u8; 64],
+) -> Result<(), VerifyError> {
+    if manifest.hash != "blake2b" || manifest.signer.signature != "ed25519" {
+        return Err(VerifyError::UnsupportedManifest);
+    }
+    let public_key = VerifyingKey::from_bytes(&manifest.signer.public_key)
+        .map_err(|_| VerifyError::UnsupportedManifest)?;
+    verify_remote_head(&public_key, fork, length, tree_hash, signature)
+}
+
+impl TryFrom<&hypercore_schema::Node> for Node {
+    type Error = VerifyError;
+
+    fn try_from(node: &hypercore_schema::Node) -> Result<Self, Self::Error> {
+        Ok(Self {
+            index: node.index,
+            hash: node
+                .hash
+                .as_slice()
+                .try_into()
+                .map_err(|_| VerifyError::InvalidLength)?,
+            length: node.length,
+        })
+    }
+}
+
+fn nodes(nodes: &[hypercore_schema::Node]) -> Result<Vec<Node>, VerifyError> {
+    nodes.iter().map(Node::try_from).collect()
+}
+
+impl TryFrom<&DataBlock> for BlockProof {
+    type Error = VerifyError;
+
+    fn try_from(block: &DataBlock) -> Result<Self, Self::Error> {
+        Ok(Self {
+            index: block.index,
+            value: block.value.clone(),
+            nodes: nodes(&block.nodes)?,
+        })
+    }
+}
+
+impl TryFrom<&DataUpgrade> for UpgradeProof {
+    type Error = VerifyError;
+
+    fn try_from(upgrade: &DataUpgrade) -> Result<Self, Self::Error> {
+        Ok(Self {
+            start: upgrade.start,
+            length: upgrade.length,
+            nodes: nodes(&upgrade.nodes)?,
+            additional_nodes: nodes(&upgrade.additional_nodes)?,
+            signature: upgrade
+                .signature
+                .as_slice()
+                .try_into()
+                .map_err(|_| VerifyError::InvalidLength)?,
+        })
+    }
+}
+
+/// Conversion of a proof received from a peer. Hash and seek proofs aren't supported and are
+/// ignored.
+impl TryFrom<&hypercore_schema::Proof> for Proof {
+    type Error = VerifyError;
+
+    fn try_from(proof: &hypercore_schema::Proof) -> Result<Self, Self::Error> {
+        Ok(Self {
+            fork: proof.fork,
+            block: proof.block.as_ref().map(BlockProof::try_from).transpose()?,
+            upgrade: proof
+                .upgrade
+                .as_ref()
+                .map(UpgradeProof::try_from)
+                .transpose()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoding::{RequestBlock, RequestUpgrade};
+    use crate::{Hypercore, HypercoreBuilder, Storage};
+
+    async fn create_core(length: u64) -> Result<Hypercore, crate::HypercoreError> {
+        let mut core = HypercoreBuilder::new(Storage::new_memory().await?)
+            .build()
+            .await?;
+        for i in 0..length {
+            core.append(format!("#{i}").as_bytes()).await?;
+        }
+        Ok(core)
+    }
+
+    async fn proof(
+        core: &Hypercore,
+        block: Option<RequestBlock>,
+        upgrade: Option<RequestUpgrade>,
+    ) -> Proof {
+        let proof = core
+            .create_proof(block, None, None, upgrade)
+            .await
+            .unwrap()
+            .unwrap();
+        Proof::try_from(&proof).unwrap()
+    }
+
+    #[async_std::test]
+    async fn verify_upgrades_and_blocks() -> Result<(), crate::HypercoreError> {
+        let mut core = create_core(5).await?;
+        let public_key = core.key_pair().public;
+
+        // Upgrade from nothing, with a block proven by it
+        let upgrade = proof(
+            &core,
+            Some(RequestBlock { index: 3, nodes: 2 }),
+            Some(RequestUpgrade {
+                start: 0,
+                length: 5,
+            }),
+        )
+        .await;
+        let verified = verify_proof(&public_key, None, &upgrade).unwrap();
+        assert_eq!(verified.block, Some(3));
+        assert_eq!(verified.tree.length, 5);
+        assert_eq!(verified.tree.byte_length, 10);
+        let tree = verified.tree;
+
+        // Blocks against the verified tree, with the nodes up to a root
+        for index in 0..5 {
+            let nodes = crate::archive::nodes_below_root(index, 5);
+            let block = proof(&core, Some(RequestBlock { index, nodes }), None).await;
+            let verified = verify_proof(&public_key, Some(&tree), &block).unwrap();
+            assert_eq!(verified.block, Some(index));
+            assert_eq!(verified.tree, tree);
+        }
+
+        // Upgrades of the verified tree
+        core.append_batch([b"#5", b"#6", b"#7", b"#8"]).await?;
+        let upgrade = proof(
+            &core,
+            Some(RequestBlock { index: 6, nodes: 0 }),
+            Some(RequestUpgrade {
+                start: 5,
+                length: 2,
+            }),
+        )
+        .await;
+        let verified = verify_proof(&public_key, Some(&tree), &upgrade).unwrap();
+        assert_eq!(verified.tree.length, 9);
+        assert_eq!(verified.block, Some(6));
+        let mut index_roots = vec![];
+        flat_tree::full_roots(18, &mut index_roots);
+        let roots: Vec<u64> = verified.tree.roots.iter().map(|root| root.index).collect();
+        assert_eq!(roots, index_roots);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn verify_rejects_invalid_proofs() -> Result<(), crate::HypercoreError> {
+        let core = create_core(4).await?;
+        let public_key = core.key_pair().public;
+        let upgrade = Some(RequestUpgrade {
+            start: 0,
+            length: 4,
+        });
+        let valid = proof(
+            &core,
+            Some(RequestBlock { index: 2, nodes: 2 }),
+            upgrade.clone(),
+        )
+        .await;
+        let tree = verify_proof(&public_key, None, &valid).unwrap().tree;
+
+        // Another value
+        let mut tampered = valid.clone();
+        tampered.block.as_mut().unwrap().value = b"#x".to_vec();
+        assert_eq!(
+            verify_proof(&public_key, None, &tampered),
+            Err(VerifyError::InvalidSignature)
+        );
+
+        // Another key
+        let other = create_core(0).await?.key_pair().public;
+        assert_eq!(
+            verify_proof(&other, None, &valid),
+            Err(VerifyError::InvalidSignature)
+        );
+
+        // Another fork than the one signed
+        let mut tampered = valid.clone();
+        tampered.fork = 1;
+        assert_eq!(
+            verify_proof(&public_key, None, &tampered),
+            Err(VerifyError::InvalidSignature)
+        );
+
+        // An upgrade from 0 replaces the verified tree, others must start at its length
+        assert_eq!(
+            verify_proof(&public_key, Some(&tree), &proof(&core, None, upgrade).await)
+                .map(|verified| verified.tree),
+            Ok(tree.clone())
+        );
+        let mut tampered = valid.clone();
+        tampered.upgrade.as_mut().unwrap().start = 2;
+        assert_eq!(
+            verify_proof(&public_key, Some(&tree), &tampered),
+            Err(VerifyError::InvalidStart {
+                start: 2,
+                length: 4
+            })
+        );
+
+        // A block without a tree, or that doesn't reach a root
+        let block = proof(&core, Some(RequestBlock { index: 2, nodes: 1 }), None).await;
+        assert_eq!(
+            verify_proof(&public_key, None, &block),
+            Err(VerifyError::Unverified)
+        );
+        assert_eq!(
+            verify_proof(&public_key, Some(&tree), &block),
+            Err(VerifyError::Unverified)
+        );
+        let mut tampered = proof(&core, Some(RequestBlock { index: 2, nodes: 2 }), None).await;
+        tampered.block.as_mut().unwrap().value = b"#x".to_vec();
+        assert_eq!(
+            verify_proof(&public_key, Some(&tree), &tampered),
+            Err(VerifyError::Unverified)
+        );
+
+        // A signed head known out of band
+        assert_eq!(
+            VerifiedTree::verify(&public_key, 0, tree.roots.clone(), tree.signature),
+            Ok(tree.clone())
+        );
+        assert_eq!(
+            VerifiedTree::verify(&public_key, 0, tree.roots[..0].to_vec(), tree.signature),
+            Err(VerifyError::InvalidSignature)
+        );
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn verify_remote_heads() -> Result<(), crate::HypercoreError> {
+        let core = create_core(3).await?;
+        let public_key = core.key_pair().public;
+        let upgrade = Some(RequestUpgrade {
+            start: 0,
+            length: 3,
+        });
+        let tree = verify_proof(&public_key, None, &proof(&core, None, upgrade).await)
+            .unwrap()
+            .tree;
+        let hash = tree.hash();
+        assert_eq!(
+            verify_remote_head(&public_key, 0, 3, &hash, &tree.signature),
+            Ok(())
+        );
+        assert_eq!(
+            verify_remote_head_with_manifest(core.manifest(), 0, 3, &hash, &tree.signature),
+            Ok(())
+        );
+
+        // Another length, fork, hash or key than the signed ones
+        for (fork, length, hash) in [(0, 2, hash), (1, 3, hash), (0, 3, [0; 32])] {
+            assert_eq!(
+                verify_remote_head(&public_key, fork, length, &hash, &tree.signature),
+                Err(VerifyError::InvalidSignature)
+            );
+        }
+        let other = create_core(0).await?;
+        assert_eq!(
+            verify_remote_head_with_manifest(other.manifest(), 0, 3, &hash, &tree.signature),
+            Err(VerifyError::InvalidSignature)
+        );
+
+        let mut manifest = core.manifest().clone();
+        manifest.signer.signature = "ed448".to_string();
+        assert_eq!(
+            verify_remote_head_with_manifest(&manifest, 0, 3, &hash, &tree.signature),
+            Err(VerifyError::UnsupportedManifest)
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,443 @@
+//! Stateless verification of block and upgrade proofs. Only uses `core`, `alloc`, `blake2` and
+//! `ed25519-dalek`, so that it also builds in `no_std` crates, see `tests/no_std`.
+use alloc::vec::Vec;
+use core::fmt;
+
+use blake2::{Blake2b, Digest, digest::consts::U32};
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+
+type Blake2b256 = Blake2b<U32>;
+
+const LEAF_TYPE: [u8; 1] = [0x00];
+const PARENT_TYPE: [u8; 1] = [0x01];
+const ROOT_TYPE: [u8; 1] = [0x02];
+
+// Namespace of signed trees, `crypto.namespace('hypercore', 1)[0]` in Javascript
+const TREE: [u8; 32] = [
+    0x9F, 0xAC, 0x70, 0xB5, 0xC, 0xA1, 0x4E, 0xFC, 0x4E, 0x91, 0xC8, 0x33, 0xB2, 0x4, 0xE7, 0x5B,
+    0x8B, 0x5A, 0xAD, 0x8B, 0x58, 0x81, 0xBF, 0xC0, 0xAD, 0xB5, 0xEF, 0x38, 0xA3, 0x27, 0x5B, 0x9C,
+];
+
+/// Node of the merkle tree, by its flat tree index
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Node {
+    /// Flat tree index, blocks are at the even indexes
+    pub index: u64,
+    /// Hash of the node
+    pub hash: [u8; 32],
+    /// Byte length of the blocks below the node
+    pub length: u64,
+}
+
+/// Block with the nodes proving it, from its sibling upwards
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockProof {
+    /// Index of the block
+    pub index: u64,
+    /// Value of the block
+    pub value: Vec<u8>,
+    /// Sibling nodes on the path from the block to a root
+    pub nodes: Vec<Node>,
+}
+
+/// Signed upgrade of a tree from length `start` to at least `start + length`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UpgradeProof {
+    /// Length of the tree being upgraded
+    pub start: u64,
+    /// Number of blocks added by the upgrade
+    pub length: u64,
+    /// Nodes to build the roots of the tree of length `start + length`
+    pub nodes: Vec<Node>,
+    /// Nodes to build the roots of the signed tree past `start + length`
+    pub additional_nodes: Vec<Node>,
+    /// Signature of the upgraded tree
+    pub signature: [u8; 64],
+}
+
+/// Proof of a block, an upgrade, or both
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Proof {
+    /// Fork of the tree
+    pub fork: u64,
+    /// Proven block
+    pub block: Option<BlockProof>,
+    /// Proven upgrade
+    pub upgrade: Option<UpgradeProof>,
+}
+
+/// Tree whose roots were verified against the signature of the public key
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerifiedTree {
+    /// Fork of the tree
+    pub fork: u64,
+    /// Number of blocks in the tree
+    pub length: u64,
+    /// Byte length of the blocks in the tree
+    pub byte_length: u64,
+    /// Roots of the tree, from left to right
+    pub roots: Vec<Node>,
+    /// Signature of the tree
+    pub signature: [u8; 64],
+}
+
+/// Outcome of [`verify_proof`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Verified {
+    /// Tree the proof was verified against: the upgraded tree if the proof has an upgrade,
+    /// otherwise the given tree
+    pub tree: VerifiedTree,
+    /// Index of the verified block, if the proof has a block
+    pub block: Option<u64>,
+}
+
+/// Error of a proof that could not be verified
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VerifyError {
+    /// The proof lacks a node, or has another node than expected
+    UnexpectedNode {
+        /// Index of the expected node
+        expected: u64,
+        /// Index of the node found, None if the proof has no more nodes
+        found: Option<u64>,
+    },
+    /// The proof has a node that doesn't belong to the tree
+    InvalidNode {
+        /// Index of the node
+        index: u64,
+    },
+    /// The upgrade doesn't start at the length of the verified tree
+    InvalidStart {
+        /// Start of the upgrade
+        start: u64,
+        /// Length of the verified tree
+        length: u64,
+    },
+    /// The proof doesn't reach a verified root
+    Unverified,
+    /// The signature doesn't match the tree
+    InvalidSignature,
+    /// A hash or signature has the wrong length
+    InvalidLength,
+    /// The manifest doesn't sign with a single `ed25519` key over `blake2b` hashes, or its public
+    /// key is invalid
+    UnsupportedManifest,
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnexpectedNode {
+                expected,
+                found: Some(found),
+            } => write!(f, "Expected node {expected}, got node {found}"),
+            Self::UnexpectedNode {
+                expected,
+                found: None,
+            } => write!(f, "Expected node {expected}, got (nil)"),
+            Self::InvalidNode { index } => write!(f, "Unexpected node {index}"),
+            Self::InvalidStart { start, length } => write!(
+                f,
+                "Upgrade starts at {start}, but the verified tree has length {length}"
+            ),
+            Self::Unverified => write!(f, "Proof does not reach a verified root"),
+            Self::InvalidSignature => write!(f, "Signature could not be verified"),
+            Self::InvalidLength => write!(f, "Invalid hash or signature length"),
+            Self::UnsupportedManifest => write!(f, "Unsupported manifest"),
+        }
+    }
+}
+
+impl core::error::Error for VerifyError {}
+
+impl VerifiedTree {
+    /// Verify a signed tree of `roots`, e.g. the head of a hypercore known out of band
+    pub fn verify(
+        public_key: &VerifyingKey,
+        fork: u64,
+        roots: Vec<Node>,
+        signature: [u8; 64],
+    ) -> Result<Self, VerifyError> {
+        let mut tree = Self::empty(fork);
+        for root in roots {
+            tree.append_root(root);
+        }
+        tree.signature = signature;
+        tree.verify_signature(public_key)?;
+        Ok(tree)
+    }
+
+    fn empty(fork: u64) -> Self {
+        Self {
+            fork,
+            length: 0,
+            byte_length: 0,
+            roots: Vec::new(),
+            signature: [0; 64],
+        }
+    }
+
+    /// Hash of the roots, which is signed with the length and fork
+    pub fn hash(&self) -> [u8; 32] {
+        let mut hasher = Blake2b256::new();
+        hasher.update(ROOT_TYPE);
+        for root in &self.roots {
+            hasher.update(root.hash);
+            hasher.update(root.index.to_le_bytes());
+            hasher.update(root.length.to_le_bytes());
+        }
+        hasher.finalize().into()
+    }
+
+    fn verify_signature(&self, public_key: &VerifyingKey) -> Result<(), VerifyError> {
+        verify_remote_head(
+            public_key,
+            self.fork,
+            self.length,
+            &self.hash(),
+            &self.signature,
+        )
+    }
+
+    /// Add a root on the right, merging it with its sibling roots into their parents
+    fn append_root(&mut self, node: Node) {
+        self.length += 1 << depth(node.index);
+        self.byte_length += node.length;
+        self.roots.push(node);
+        while let [.., left, right] = &self.roots[..] {
+            if sibling(right.index) != left.index {
+                break;
+            }
+            let parent = parent_node(left, right);
+            self.roots.truncate(self.roots.len() - 2);
+            self.roots.push(parent);
+        }
+    }
+
+    fn last_root_index(&self) -> Result<u64, VerifyError> {
+        self.roots
+            .last()
+            .map(|root| root.index)
+            .ok_or(VerifyError::Unverified)
+    }
+}
+
+/// Verify `proof` against the public key and the `tree` verified before, if any. A proof without
+/// `tree` needs an upgrade from length 0. A block must be proven up to a root of the upgraded
+/// tree, or of `tree` if the proof has no upgrade.
+pub fn verify_proof(
+    public_key: &VerifyingKey,
+    tree: Option<&VerifiedTree>,
+    proof: &Proof,
+) -> Result<Verified, VerifyError> {
+    let block_root = proof.block.as_ref().map(block_root).transpose()?;
+    let (upgraded, block_root) = match &proof.upgrade {
+        Some(upgrade) => verify_upgrade(public_key, tree, proof.fork, upgrade, block_root)?,
+        None => (tree.ok_or(VerifyError::Unverified)?.clone(), block_root),
+    };
+    // A block root not consumed by the upgrade must be one of the verified roots
+    if let Some(block_root) = block_root
+        && !upgraded.roots.contains(&block_root)
+        && !tree.is_some_and(|tree| tree.roots.contains(&block_root))
+    {
+        return Err(VerifyError::Unverified);
+    }
+    Ok(Verified {
+        tree: upgraded,
+        block: proof.block.as_ref().map(|block| block.index),
+    })
+}
+
+/// Verify the signed head of a tree, the hash of its roots at `length` and `fork`, without its
+/// roots or any proof. For heads announced by untrusted sources, e.g. in tickets or pinned
+/// checkpoints, before deciding to replicate the hypercore.
+pub fn verify_remote_head(
+    public_key: &VerifyingKey,
+    fork: u64,
+    length: u64,
+    tree_hash: &[u8; 32],
+    signature: &[u8; 64],
+) -> Result<(), VerifyError> {
+    let mut signable = Vec::with_capacity(80);
+    signable.extend_from_slice(&TREE);
+    signable.extend_from_slice(tree_hash);
+    signable.extend_from_slice(&length.to_le_bytes());
+    signable.extend_from_slice(&fork.to_le_bytes());
+    public_key
+        .verify(&signable, &Signature::from_bytes(signature))
+        .map_err(|_| VerifyError::InvalidSignature)
+}
+
+/// Root of the subtree proven by a block proof
+fn block_root(block: &BlockProof) -> Result<Node, VerifyError> {
+    let mut root = Node {
+        index: 2 * block.index,
+        hash: leaf_hash(&block.value),
+        length: block.value.len() as u64,
+    };
+    for node in &block.nodes {
+        let expected = sibling(root.index);
+        if node.index != expected {
+            return Err(VerifyError::UnexpectedNode {
+                expected,
+                found: Some(node.index),
+            });
+        }
+        root = parent_node(&root, node);
+    }
+    Ok(root)
+}
+
+/// Verify an upgrade of `tree`, like `verifyUpgrade` in Javascript. Returns the upgraded tree,
+/// and the block root if it wasn't used by the upgrade.
+fn verify_upgrade(
+    public_key: &VerifyingKey,
+    tree: Option<&VerifiedTree>,
+    fork: u64,
+    upgrade: &UpgradeProof,
+    block_root: Option<Node>,
+) -> Result<(VerifiedTree, Option<Node>), VerifyError> {
+    let mut upgraded = match tree {
+        Some(tree) if upgrade.start > 0 => tree.clone(),
+        _ => VerifiedTree::empty(fork),
+    };
+    if upgrade.start != upgraded.length {
+        return Err(VerifyError::InvalidStart {
+            start: upgrade.start,
+            length: upgraded.length,
+        });
+    }
+    upgraded.fork = fork;
+
+    let mut queue = NodeQueue {
+        nodes: upgrade.nodes.iter(),
+        extra: block_root,
+    };
+    let mut grow = !upgraded.roots.is_empty();
+    let mut kept = 0;
+    for root_index in full_roots(upgrade.start + upgrade.length) {
+        if kept < upgraded.roots.len() && upgraded.roots[kept].index == root_index {
+            kept += 1;
+            continue;
+        }
+        if grow {
+            grow = false;
+            // Grow the last roots of the tree into the new root
+            if kept < upgraded.roots.len() {
+                while upgraded.last_root_index()? != root_index {
+                    let node = queue.shift(sibling(upgraded.last_root_index()?))?;
+                    upgraded.append_root(node);
+                }
+                continue;
+            }
+        }
+        upgraded.append_root(queue.shift(root_index)?);
+    }
+
+    let extra = &upgrade.additional_nodes;
+    let mut i = 0;
+    while i < extra.len() && extra[i].index == sibling(upgraded.last_root_index()?) {
+        upgraded.append_root(extra[i].clone());
+        i += 1;
+    }
+    if i < extra.len() {
+        let mut index = sibling(upgraded.last_root_index()?);
+        for node in &extra[i..] {
+            while node.index != index {
+                if index & 1 == 0 {
+                    return Err(VerifyError::InvalidNode { index: node.index });
+                }
+                index = left_child(index);
+            }
+            upgraded.append_root(node.clone());
+            index = sibling(upgraded.last_root_index()?);
+        }
+    }
+
+    upgraded.signature = upgrade.signature;
+    upgraded.verify_signature(public_key)?;
+    Ok((upgraded, queue.extra))
+}
+
+/// Nodes of an upgrade, and the block root which may stand in for one of them
+struct NodeQueue<'a> {
+    nodes: core::slice::Iter<'a, Node>,
+    extra: Option<Node>,
+}
+
+impl NodeQueue<'_> {
+    fn shift(&mut self, index: u64) -> Result<Node, VerifyError> {
+        if let Some(extra) = self.extra.take_if(|extra| extra.index == index) {
+            return Ok(extra);
+        }
+        match self.nodes.next() {
+            Some(node) if node.index == index => Ok(node.clone()),
+            node => Err(VerifyError::UnexpectedNode {
+                expected: index,
+                found: node.map(|node| node.index),
+            }),
+        }
+    }
+}
+
+fn leaf_hash(value: &[u8]) -> [u8; 32] {
+    let mut hasher = Blake2b256::new();
+    hasher.update(LEAF_TYPE);
+    hasher.update((value.len() as u64).to_le_bytes());
+    hasher.update(value);
+    hasher.finalize().into()
+}
+
+fn parent_node(a: &Node, b: &Node) -> Node {
+    let (left, right) = if a.index <= b.index { (a, b) } else { (b, a) };
+    let mut hasher = Blake2b256::new();
+    hasher.update(PARENT_TYPE);
+    hasher.update((left.length + right.length).to_le_bytes());
+    hasher.update(left.hash);
+    hasher.update(right.hash);
+    Node {
+        index: parent(left.index),
+        hash: hasher.finalize().into(),
+        length: left.length + right.length,
+    }
+}
+
+// Flat tree arithmetic, see the `flat-tree` crate
+
+fn depth(index: u64) -> u32 {
+    index.trailing_ones()
+}
+
+fn offset(index: u64) -> u64 {
+    index >> (depth(index) + 1)
+}
+
+fn flat_index(depth: u32, offset: u64) -> u64 {
+    ((2 * offset + 1) << depth) - 1
+}
+
+fn parent(index: u64) -> u64 {
+    flat_index(depth(index) + 1, offset(index) >> 1)
+}
+
+fn sibling(index: u64) -> u64 {
+    flat_index(depth(index), offset(index) ^ 1)
+}
+
+fn left_child(index: u64) -> u64 {
+    flat_index(depth(index) - 1, offset(index) << 1)
+}
+
+/// Indexes of the roots of a tree of `length` blocks, from left to right
+fn full_roots(length: u64) -> Vec<u64> {
+    let mut roots = Vec::new();
+    let mut remaining = length;
+    let mut offset = 0;
+    while remaining > 0 {
+        let factor = 1 << remaining.ilog2();
+        roots.push(offset + factor - 1);
+        offset += 2 * factor;
+        remaining -= factor;
+    }
+    roots
+}
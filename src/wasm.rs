@@ -0,0 +1,146 @@
+//! JavaScript bindings built with [`wasm_bindgen`], for browser apps that use the Rust hypercore
+//! directly. Build them with e.g.
+//! `wasm-pack build --target web -- --no-default-features --features tokio,wasm`, and use them as:
+//!
+//! ```js
+//! const core = await Hypercore.create()
+//! core.onAppend(({ length, byteLength }) => console.log(length, byteLength))
+//! await core.append(new TextEncoder().encode('hello'))
+//! const block = await core.get(0) // Uint8Array, or null if not stored
+//! const { length, byteLength, contiguousLength, fork, writeable } = await core.info()
+//! ```
+//!
+//! Hypercores are stored in memory, and lengths and indexes are numbers. Operations return promises
+//! and run one at a time, in the order they were called.
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use futures::lock::Mutex;
+use js_sys::{Function, Object, Promise, Reflect, Uint8Array};
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::future_to_promise;
+
+use crate::{AppendOutcome, Hypercore, HypercoreBuilder, HypercoreError, Info, Storage};
+
+/// Hypercore exported to JavaScript as the `Hypercore` class
+#[wasm_bindgen(js_name = Hypercore)]
+#[derive(Debug)]
+pub struct JsHypercore {
+    core: Rc<Mutex<Hypercore>>,
+    listeners: Rc<RefCell<Vec<Function>>>,
+}
+
+#[wasm_bindgen(js_class = Hypercore)]
+impl JsHypercore {
+    /// Create a new writable hypercore in memory, resolves to a `Hypercore`
+    pub fn create() -> Promise {
+        future_to_promise(async {
+            let storage = Storage::new_memory()
+                .await
+                .map_err(|error| to_js_error(&error))?;
+            let core = HypercoreBuilder::new(storage)
+                .build()
+                .await
+                .map_err(|error| to_js_error(&error))?;
+            Ok(JsHypercore {
+                core: Rc::new(Mutex::new(core)),
+                listeners: Rc::default(),
+            }
+            .into())
+        })
+    }
+
+    /// Public key of the hypercore
+    #[wasm_bindgen(getter)]
+    pub fn key(&self) -> Result<Uint8Array, JsError> {
+        let core = self
+            .core
+            .try_lock()
+            .ok_or_else(|| JsError::new("Hypercore is busy"))?;
+        Ok(Uint8Array::from(&core.key_pair().public.to_bytes()[..]))
+    }
+
+    /// Append a block, resolves to `{ length, byteLength }` after the append
+    pub fn append(&self, data: &Uint8Array) -> Promise {
+        let core = self.core.clone();
+        let listeners = self.listeners.clone();
+        let data = data.to_vec();
+        future_to_promise(async move {
+            let outcome = core
+                .lock()
+                .await
+                .append(&data)
+                .await
+                .map_err(|error| to_js_error(&error))?;
+            let outcome = outcome_object(&outcome)?;
+            for listener in listeners.borrow().iter() {
+                listener.call1(&JsValue::NULL, &outcome)?;
+            }
+            Ok(outcome)
+        })
+    }
+
+    /// Read the block at `index`, resolves to a `Uint8Array`, or `null` if it isn't stored
+    pub fn get(&self, index: f64) -> Promise {
+        let core = self.core.clone();
+        future_to_promise(async move {
+            if index < 0.0 || index.fract() != 0.0 || index > MAX_SAFE_INTEGER {
+                return Err(JsError::new("Index must be a non-negative integer").into());
+            }
+            let index = index as u64;
+            let block = core
+                .lock()
+                .await
+                .get(index)
+                .await
+                .map_err(|error| to_js_error(&error))?;
+            Ok(block.map_or(JsValue::NULL, |block| Uint8Array::from(&block[..]).into()))
+        })
+    }
+
+    /// Info of the hypercore, resolves to
+    /// `{ length, byteLength, contiguousLength, fork, writeable }`
+    pub fn info(&self) -> Promise {
+        let core = self.core.clone();
+        future_to_promise(async move { info_object(&core.lock().await.info()) })
+    }
+
+    /// Call `callback` with `{ length, byteLength }` after each append
+    #[wasm_bindgen(js_name = onAppend)]
+    pub fn on_append(&self, callback: Function) {
+        self.listeners.borrow_mut().push(callback);
+    }
+}
+
+/// Largest integer represented exactly by a JavaScript number
+const MAX_SAFE_INTEGER: f64 = 9_007_199_254_740_991.0;
+
+/// JavaScript number of a length or index, exact below 2^53
+fn number(value: u64) -> JsValue {
+    JsValue::from_f64(value as f64)
+}
+
+fn to_js_error(error: &HypercoreError) -> JsValue {
+    JsError::new(&error.to_string()).into()
+}
+
+fn set(object: &Object, key: &str, value: &JsValue) -> Result<(), JsValue> {
+    Reflect::set(object, &key.into(), value).map(|_| ())
+}
+
+fn outcome_object(outcome: &AppendOutcome) -> Result<JsValue, JsValue> {
+    let object = Object::new();
+    set(&object, "length", &number(outcome.length))?;
+    set(&object, "byteLength", &number(outcome.byte_length))?;
+    Ok(object.into())
+}
+
+fn info_object(info: &Info) -> Result<JsValue, JsValue> {
+    let object = Object::new();
+    set(&object, "length", &number(info.length))?;
+    set(&object, "byteLength", &number(info.byte_length))?;
+    set(&object, "contiguousLength", &number(info.contiguous_length))?;
+    set(&object, "fork", &number(info.fork))?;
+    set(&object, "writeable", &info.writeable.into())?;
+    Ok(object.into())
+}
@@ -2,7 +2,13 @@ pub mod common;
 
 use anyhow::Result;
 use common::{create_hypercore, get_test_key_pair, open_hypercore, storage_contains_data};
-use hypercore::{HypercoreBuilder, Storage};
+use hypercore::{
+    BuildOutcome, HypercoreBuilder, HypercoreError, Manifest, PartialKeypair, Storage,
+    generate_signing_key,
+};
+use random_access_disk::RandomAccessDisk;
+use random_access_memory::RandomAccessMemory;
+use std::time::Duration;
 use tempfile::Builder;
 use test_log::test;
 
@@ -44,6 +50,301 @@ async fn hypercore_open_with_key_pair_error() -> Result<()> {
     Ok(())
 }
 
+#[test(async_test)]
+async fn hypercore_new_with_verifying_key() -> Result<()> {
+    let dir = Builder::new()
+        .prefix("hypercore_new_with_verifying_key")
+        .tempdir()
+        .unwrap();
+    let public = get_test_key_pair().public;
+    {
+        let storage = Storage::new_disk(dir.path(), true).await?;
+        let mut hypercore = HypercoreBuilder::new(storage)
+            .verifying_key(public)
+            .build()
+            .await?;
+        assert!(!hypercore.info().writeable);
+        assert_eq!(hypercore.key_pair().public, public);
+        assert!(matches!(
+            hypercore.append(b"Hello").await,
+            Err(HypercoreError::NotWritable)
+        ));
+    }
+
+    let storage = Storage::new_disk(dir.path(), false).await?;
+    let hypercore = HypercoreBuilder::new(storage)
+        .verifying_key(public)
+        .build()
+        .await?;
+    assert_eq!(hypercore.key_pair().public, public);
+
+    let storage = Storage::new_disk(dir.path(), false).await?;
+    let other_public = generate_signing_key().verifying_key();
+    assert!(matches!(
+        HypercoreBuilder::new(storage)
+            .verifying_key(other_public)
+            .build()
+            .await,
+        Err(HypercoreError::KeyMismatch { .. })
+    ));
+    Ok(())
+}
+
+#[test(async_test)]
+async fn hypercore_new_with_manifest() -> Result<()> {
+    let dir = Builder::new()
+        .prefix("hypercore_new_with_manifest")
+        .tempdir()
+        .unwrap();
+    let key_pair = get_test_key_pair();
+    let mut manifest = Manifest::new(&key_pair.public);
+    manifest.signer.namespace = [1; 32];
+    {
+        let storage = Storage::new_disk(dir.path(), true).await?;
+        let mut hypercore = HypercoreBuilder::new(storage)
+            .key_pair(key_pair.clone())
+            .manifest(manifest.clone())
+            .build()
+            .await?;
+        assert_eq!(hypercore.manifest(), &manifest);
+        hypercore.append(b"Hello").await?;
+    }
+
+    let storage = Storage::new_disk(dir.path(), false).await?;
+    let hypercore = HypercoreBuilder::new(storage)
+        .open(true)
+        .manifest(manifest.clone())
+        .build()
+        .await?;
+    assert_eq!(hypercore.manifest(), &manifest);
+
+    let storage = Storage::new_disk(dir.path(), false).await?;
+    assert!(matches!(
+        HypercoreBuilder::new(storage)
+            .open(true)
+            .manifest(Manifest::new(&key_pair.public))
+            .build()
+            .await,
+        Err(HypercoreError::BadArgument { .. })
+    ));
+
+    let mut unsupported = manifest.clone();
+    unsupported.hash = "sha256".to_string();
+    let other_signer = Manifest::new(&generate_signing_key().verifying_key());
+    for manifest in [unsupported, other_signer] {
+        let storage = Storage::new_memory().await?;
+        assert!(matches!(
+            HypercoreBuilder::new(storage)
+                .key_pair(key_pair.clone())
+                .manifest(manifest)
+                .build()
+                .await,
+            Err(HypercoreError::BadArgument { .. })
+        ));
+    }
+    Ok(())
+}
+
+#[test(async_test)]
+async fn hypercore_key_pair_mismatch() -> Result<()> {
+    let dir = Builder::new()
+        .prefix("hypercore_key_pair_mismatch")
+        .tempdir()
+        .unwrap();
+    {
+        let mut hypercore = create_hypercore(&dir.path().to_string_lossy()).await?;
+        hypercore.append(b"Hello").await?;
+    }
+    let signing_key = generate_signing_key();
+    let key_pair = PartialKeypair {
+        public: signing_key.verifying_key(),
+        secret: Some(signing_key),
+    };
+
+    let storage = Storage::new_disk(dir.path(), false).await?;
+    assert!(matches!(
+        HypercoreBuilder::new(storage)
+            .key_pair(key_pair.clone())
+            .build()
+            .await,
+        Err(HypercoreError::KeyMismatch { .. })
+    ));
+
+    let storage = Storage::new_disk(dir.path(), false).await?;
+    let hypercore = HypercoreBuilder::new(storage)
+        .key_pair(key_pair.clone())
+        .overwrite(true)
+        .build()
+        .await?;
+    assert_eq!(hypercore.key_pair().public, key_pair.public);
+    assert_eq!(hypercore.info().length, 0);
+    assert_eq!(hypercore.get(0).await?, None);
+    Ok(())
+}
+
+#[test(async_test)]
+async fn hypercore_build_outcome() -> Result<()> {
+    let dir = Builder::new()
+        .prefix("hypercore_build_outcome")
+        .tempdir()
+        .unwrap();
+    {
+        let storage = Storage::new_disk(dir.path(), false).await?;
+        let (mut hypercore, outcome) = HypercoreBuilder::new(storage)
+            .key_pair(get_test_key_pair())
+            .build_with_outcome()
+            .await?;
+        assert_eq!(
+            outcome,
+            BuildOutcome {
+                created: true,
+                recovered: false
+            }
+        );
+        // The first append flushes, the second stays only in the oplog
+        hypercore.append(b"Hello").await?;
+        hypercore.append(b"World").await?;
+    }
+    let storage = Storage::new_disk(dir.path(), false).await?;
+    let (_, outcome) = HypercoreBuilder::new(storage)
+        .open(true)
+        .build_with_outcome()
+        .await?;
+    assert_eq!(
+        outcome,
+        BuildOutcome {
+            created: false,
+            recovered: true
+        }
+    );
+    Ok(())
+}
+
+#[test(async_test)]
+async fn hypercore_disk_lock_takeover() -> Result<()> {
+    let dir = Builder::new()
+        .prefix("hypercore_disk_lock_takeover")
+        .tempdir()
+        .unwrap();
+    let lease = Duration::from_millis(500);
+    let storage = Storage::new_disk_locked(dir.path(), false, lease).await?;
+    assert!(!storage.took_over_lock());
+    let mut stale = HypercoreBuilder::new(storage)
+        .key_pair(get_test_key_pair())
+        .build()
+        .await?;
+    // The first append flushes, the second stays only in the oplog
+    stale.append(b"Hello").await?;
+    stale.append(b"World").await?;
+    assert!(matches!(
+        Storage::new_disk_locked(dir.path(), false, lease).await,
+        Err(HypercoreError::Locked { .. })
+    ));
+    stale.heartbeat()?;
+
+    // The holder stops renewing its lease, e.g. because it hangs or crashed
+    std::thread::sleep(lease * 2);
+    let storage = Storage::new_disk_locked(dir.path(), false, lease).await?;
+    assert!(storage.took_over_lock());
+    let (hypercore, outcome) = HypercoreBuilder::new(storage)
+        .open(true)
+        .build_with_outcome()
+        .await?;
+    assert!(outcome.recovered);
+    assert_eq!(hypercore.get(1).await?, Some(b"World".to_vec()));
+
+    // The previous holder can't write nor release the lock anymore
+    assert!(matches!(
+        stale.append(b"Late").await,
+        Err(HypercoreError::Locked { .. })
+    ));
+    assert!(matches!(
+        stale.heartbeat(),
+        Err(HypercoreError::Locked { .. })
+    ));
+    drop(stale);
+    assert!(matches!(
+        Storage::new_disk_locked(dir.path(), false, lease).await,
+        Err(HypercoreError::Locked { .. })
+    ));
+    Ok(())
+}
+
+#[test(async_test)]
+async fn hypercore_storage_from_parts() -> Result<()> {
+    let dir = Builder::new()
+        .prefix("hypercore_storage_from_parts")
+        .tempdir()
+        .unwrap();
+    let data_path = dir.path().join("data");
+    let storage = Storage::from_parts(
+        Box::new(RandomAccessMemory::default()),
+        Box::new(RandomAccessDisk::open(data_path.clone()).await?),
+        Box::new(RandomAccessMemory::default()),
+        Box::new(RandomAccessMemory::default()),
+        false,
+    )
+    .await?;
+    let mut hypercore = HypercoreBuilder::new(storage)
+        .key_pair(get_test_key_pair())
+        .build()
+        .await?;
+    hypercore.append(b"Hello").await?;
+    hypercore.append(b"World!").await?;
+    assert_eq!(&hypercore.get(1).await?.unwrap(), b"World!");
+    assert_eq!(std::fs::read(data_path)?, b"HelloWorld!");
+    Ok(())
+}
+
+#[test(async_test)]
+async fn hypercore_storage_checksums() -> Result<()> {
+    let dir = Builder::new()
+        .prefix("hypercore_storage_checksums")
+        .tempdir()
+        .unwrap();
+    let path = dir.path().to_path_buf();
+    {
+        let storage = Storage::new_disk_with_checksums(&path, false, true).await?;
+        let mut hypercore = HypercoreBuilder::new(storage)
+            .key_pair(get_test_key_pair())
+            .build()
+            .await?;
+        hypercore.append_batch([b"Hello", b"World"]).await?;
+        hypercore.verify_checksums().await?;
+    }
+    let open = |verify_on_read| {
+        let path = path.clone();
+        async move {
+            let storage = Storage::new_disk_with_checksums(&path, false, verify_on_read).await?;
+            HypercoreBuilder::new(storage).open(true).build().await
+        }
+    };
+    assert_eq!(open(true).await?.get(1).await?, Some(b"World".to_vec()));
+
+    // Flip a bit of a tree node, as silent disk corruption would
+    let tree_path = dir.path().join("tree");
+    let mut tree = std::fs::read(&tree_path)?;
+    tree[0] ^= 1;
+    std::fs::write(&tree_path, tree)?;
+    assert!(matches!(
+        open(true).await,
+        Err(HypercoreError::CorruptStorage {
+            store: hypercore::Store::Tree,
+            ..
+        })
+    ));
+    let hypercore = open(false).await?;
+    assert!(matches!(
+        hypercore.verify_checksums().await,
+        Err(HypercoreError::CorruptStorage {
+            store: hypercore::Store::Tree,
+            offset: Some(0),
+            context: Some(context),
+        }) if context == "Checksum mismatch in bytes 0..4096"
+    ));
+    Ok(())
+}
+
 #[test(async_test)]
 async fn hypercore_make_read_only() -> Result<()> {
     let dir = Builder::new()
@@ -74,8 +375,132 @@ async fn hypercore_make_read_only() -> Result<()> {
         &write_key_pair.secret.as_ref().unwrap().to_bytes()[16..],
     ));
 
-    let mut hypercore = open_hypercore(&dir.path().to_string_lossy()).await?;
+    let hypercore = open_hypercore(&dir.path().to_string_lossy()).await?;
     assert_eq!(&hypercore.get(0).await?.unwrap(), b"Hello");
     assert_eq!(&hypercore.get(1).await?.unwrap(), b"World!");
     Ok(())
 }
+
+#[test(async_test)]
+async fn hypercore_append_outcome() -> Result<()> {
+    use hypercore::encoding::RequestUpgrade;
+
+    let mut hypercore = HypercoreBuilder::new(Storage::new_memory().await?)
+        .build()
+        .await?;
+    let first = hypercore.append(b"Hello").await?;
+    assert_eq!((first.blocks, first.byte_offsets), (0..1, vec![0]));
+    let outcome = hypercore.append_batch([&b"big"[..], b"World!"]).await?;
+    assert_eq!((outcome.length, outcome.byte_length), (3, 14));
+    assert_eq!(outcome.blocks, 1..3);
+    assert_eq!(outcome.byte_offsets, vec![5, 8]);
+    assert_eq!(outcome.fork, 0);
+    assert_ne!(outcome.signature, first.signature);
+
+    // The signature is the one of the head
+    let proof = hypercore
+        .create_proof(
+            None,
+            None,
+            None,
+            Some(RequestUpgrade {
+                start: 0,
+                length: 3,
+            }),
+        )
+        .await?
+        .unwrap();
+    assert_eq!(outcome.signature, proof.upgrade.unwrap().signature);
+
+    // Appending nothing keeps the head
+    let empty = hypercore.append_batch::<&[u8], _>([]).await?;
+    assert_eq!((empty.blocks, empty.byte_offsets), (3..3, vec![]));
+    assert_eq!(empty.signature, outcome.signature);
+    Ok(())
+}
+
+#[cfg(feature = "tracing")]
+#[test(async_test)]
+async fn hypercore_tracing_spans() -> Result<()> {
+    use hypercore::encoding::{RequestBlock, RequestUpgrade};
+    use std::sync::{Arc, Mutex};
+    use tracing::field::{Field, Visit};
+    use tracing_subscriber::layer::{Context, SubscriberExt};
+
+    /// Names of the spans created, with their discovery key field if any
+    type Records = Vec<(&'static str, Option<String>)>;
+    #[derive(Clone, Default)]
+    struct Spans(Arc<Mutex<Records>>);
+    struct DiscoveryKey(Option<String>);
+    impl Visit for DiscoveryKey {
+        fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+            if field.name() == "discovery_key" {
+                self.0 = Some(format!("{value:?}"));
+            }
+        }
+    }
+    impl<S: tracing::Subscriber> tracing_subscriber::Layer<S> for Spans {
+        fn on_new_span(
+            &self,
+            attrs: &tracing::span::Attributes<'_>,
+            _id: &tracing::span::Id,
+            _ctx: Context<'_, S>,
+        ) {
+            let mut key = DiscoveryKey(None);
+            attrs.record(&mut key);
+            self.0
+                .lock()
+                .unwrap()
+                .push((attrs.metadata().name(), key.0));
+        }
+    }
+
+    let spans = Spans::default();
+    let _guard =
+        tracing::subscriber::set_default(tracing_subscriber::registry().with(spans.clone()));
+    let mut main = HypercoreBuilder::new(Storage::new_memory().await?)
+        .build()
+        .await?;
+    main.append_batch([b"#0", b"#1"]).await?;
+    main.get(1).await?;
+    let mut clone = HypercoreBuilder::new(Storage::new_memory().await?)
+        .verifying_key(main.key_pair().public)
+        .build()
+        .await?;
+    let nodes = clone.missing_nodes(1).await?;
+    let proof = main
+        .create_proof(
+            Some(RequestBlock { index: 1, nodes }),
+            None,
+            None,
+            Some(RequestUpgrade {
+                start: 0,
+                length: 2,
+            }),
+        )
+        .await?
+        .unwrap();
+    clone.verify_and_apply_proof(&proof).await?;
+
+    let key: String = hypercore::discovery_key(&main.key_pair().public)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+    let spans = spans.0.lock().unwrap();
+    for name in [
+        "append_batch",
+        "get",
+        "create_proof",
+        "verify_and_apply_proof",
+        "flush_bitfield_and_tree_and_oplog",
+    ] {
+        assert!(
+            spans
+                .iter()
+                .any(|(span, span_key)| *span == name && span_key.as_ref() == Some(&key)),
+            "no {name} span"
+        );
+    }
+    assert!(spans.iter().any(|(span, _)| *span == "write"));
+    Ok(())
+}
@@ -0,0 +1,72 @@
+//! Opening a store in the layout of Javascript corestore 6, checked in under
+//! `tests/fixtures/corestore6`: the primary key, the bytes 0 to 31, in `primary-key` and the core
+//! named "fixture", holding "Hello", "World" and "!", in `cores/<aa>/<bb>/<discovery key hex>`.
+//!
+//! The fixture was written offline in that layout by this crate's disk storage, without going
+//! through [`Corestore`], as no npm registry was reachable to run Javascript. Step 8 of
+//! `tests/js/interop.js` writes the same store with Javascript corestore, regenerate it with:
+//!
+//! ```sh
+//! cd tests/js && npm install && rm -rf ../fixtures/corestore6 && node interop.js 8 corestore6
+//! ```
+#![cfg(feature = "corestore")]
+
+use anyhow::Result;
+use hypercore::corestore::Corestore;
+use hypercore::replication::{CoreInfo, CoreMethods};
+use std::path::Path;
+use tempfile::Builder;
+use test_log::test;
+
+#[cfg(feature = "async-std")]
+use async_std::test as async_test;
+#[cfg(feature = "tokio")]
+use tokio::test as async_test;
+
+const FIXTURE: &str = "tests/fixtures/corestore6";
+const FIXTURE_CORE: &str =
+    "cores/4a/ba/4aba3622b7e40d35a23335f6449df18c27bb30db3231e74e2bdbb25da9b9f30b";
+
+fn copy_dir(from: &Path, to: &Path) -> Result<()> {
+    std::fs::create_dir_all(to)?;
+    for entry in std::fs::read_dir(from)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &to.join(entry.file_name()))?;
+        } else {
+            std::fs::copy(entry.path(), to.join(entry.file_name()))?;
+        }
+    }
+    Ok(())
+}
+
+#[test(async_test)]
+async fn corestore_opens_corestore6_fixture() -> Result<()> {
+    // Opening the cores writes to them, so work on a copy
+    let dir = Builder::new().prefix("corestore6").tempdir()?;
+    copy_dir(Path::new(FIXTURE), dir.path())?;
+    assert!(dir.path().join(FIXTURE_CORE).join("oplog").exists());
+
+    let store = Corestore::open_disk(dir.path()).await?;
+    assert_eq!(store.primary_key(), &std::array::from_fn(|i| i as u8));
+    let key = store.key_pair("fixture").public;
+    assert_eq!(
+        hypercore::core_dir(dir.path(), &key),
+        dir.path().join(FIXTURE_CORE)
+    );
+    assert_eq!(store.list().await?, vec![key]);
+
+    // The named core is the stored one, still writable
+    let core = store.get_named("fixture").await?;
+    assert_eq!(core.info().await.length, 3);
+    let blocks: [&[u8]; 3] = [b"Hello", b"World", b"!"];
+    for (index, block) in blocks.iter().enumerate() {
+        assert_eq!(core.get(index as u64).await?.as_deref(), Some(*block));
+    }
+    core.append(b"?").await?;
+    assert_eq!(core.get(3).await?.as_deref(), Some(&b"?"[..]));
+
+    // Opened by its key too
+    assert_eq!(store.get(key).await?.info().await.length, 4);
+    Ok(())
+}
@@ -0,0 +1,38 @@
+#![cfg(feature = "test-utils")]
+use hypercore::HypercoreError;
+use hypercore::crash::{CrashHarness, CrashMode};
+
+#[cfg(feature = "async-std")]
+use async_std::test as async_test;
+#[cfg(feature = "tokio")]
+use tokio::test as async_test;
+
+fn batches() -> Vec<Vec<Vec<u8>>> {
+    vec![
+        vec![b"a".to_vec()],
+        vec![b"b".to_vec(), b"c".to_vec()],
+        vec![b"d".to_vec()],
+    ]
+}
+
+#[async_test]
+async fn crash_append_reopens_consistent() -> Result<(), HypercoreError> {
+    let report = CrashHarness::new(CrashMode::Append, batches())
+        .stride(61)
+        .run()
+        .await?;
+    assert!(report.cuts > 0);
+    assert!(report.applied <= report.cuts);
+    Ok(())
+}
+
+#[async_test]
+async fn crash_verify_reopens_consistent() -> Result<(), HypercoreError> {
+    let report = CrashHarness::new(CrashMode::Verify, batches())
+        .stride(61)
+        .run()
+        .await?;
+    assert!(report.cuts > 0);
+    assert!(report.applied <= report.cuts);
+    Ok(())
+}
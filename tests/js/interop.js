@@ -1,4 +1,6 @@
 const Hypercore = require('hypercore');
+const Corestore = require('corestore');
+const fs = require('fs');
 
 // Static test key pair obtained with:
 //
@@ -17,6 +19,9 @@ const testKeyPair = {
         0x91, 0xd6, 0x55, 0xe2, 0x00, 0xc8, 0xd4, 0x3a, 0x32, 0x09, 0x1d, 0x06, 0x4a, 0x33, 0x1e, 0xe3]),
 }
 
+// Primary key of the checked in corestore fixture, the bytes 0 to 31
+const fixturePrimaryKey = Buffer.from([...Array(32).keys()]);
+
 if (process.argv.length !== 4) {
     console.error("Usage: node interop.js [test step] [test set]")
     process.exit(1);
@@ -42,6 +47,18 @@ if (process.argv[2] === '1') {
     step5ClearSome(process.argv[3]).then(result => {
         console.log("step5 ready", result);
     });
+} else if (process.argv[2] === '6'){
+    step6CorestoreAppend(process.argv[3]).then(result => {
+        console.log("step6 ready", result);
+    });
+} else if (process.argv[2] === '7'){
+    step7CorestoreRead(process.argv[3]).then(result => {
+        console.log("step7 ready", result);
+    });
+} else if (process.argv[2] === '8'){
+    step8CorestoreFixture(process.argv[3]).then(result => {
+        console.log("step8 ready", result);
+    });
 } else {
     console.error(`Invalid test step {}`, process.argv[2]);
     process.exit(2);
@@ -121,6 +138,38 @@ async function step5ClearSome(testSet) {
     assert(third, "third");
 }
 
+async function step6CorestoreAppend(testSet) {
+    const store = new Corestore(`work/${testSet}`);
+    const core = store.get({ name: 'interop' });
+    const result = await core.append([Buffer.from('Hello'), Buffer.from('World')]);
+    assert(result.length, 2);
+    await store.close();
+}
+
+async function step7CorestoreRead(testSet) {
+    const store = new Corestore(`work/${testSet}`);
+    const core = store.get({ name: 'interop' });
+    await core.ready();
+    assert(core.writable, true);
+    assert(core.length, 3);
+    assert((await core.get(0)).toString(), "Hello");
+    assert((await core.get(1)).toString(), "World");
+    assert((await core.get(2)).toString(), "!");
+    await store.close();
+}
+
+// Writes the fixture read by tests/corestore.rs, run it on a removed ../fixtures/${testSet}
+async function step8CorestoreFixture(testSet) {
+    const dir = `../fixtures/${testSet}`;
+    fs.mkdirSync(dir, { recursive: true });
+    fs.writeFileSync(`${dir}/primary-key`, fixturePrimaryKey);
+    const store = new Corestore(dir);
+    const core = store.get({ name: 'fixture' });
+    const result = await core.append([Buffer.from('Hello'), Buffer.from('World'), Buffer.from('!')]);
+    assert(result.length, 3);
+    await store.close();
+}
+
 function assert(real, expected) {
     if (real != expected)  {
         throw new Error(`Got ${real} but expected ${expected}`);
@@ -14,6 +14,10 @@ use tokio::test as async_test;
 
 const TEST_SET_JS_FIRST: &str = "jsfirst";
 const TEST_SET_RS_FIRST: &str = "rsfirst";
+#[cfg(feature = "corestore")]
+const TEST_SET_CORESTORE_JS_FIRST: &str = "corestorejsfirst";
+#[cfg(feature = "corestore")]
+const TEST_SET_CORESTORE_RS_FIRST: &str = "corestorersfirst";
 
 static INIT: Once = Once::new();
 fn init() {
@@ -62,6 +66,50 @@ async fn js_interop_rs_first() -> Result<()> {
     Ok(())
 }
 
+#[cfg(feature = "corestore")]
+#[test(async_test)]
+#[cfg_attr(not(feature = "js_interop_tests"), ignore)]
+async fn js_interop_corestore_js_first() -> Result<()> {
+    init();
+    let work_dir = prepare_test_set(TEST_SET_CORESTORE_JS_FIRST);
+    js_run_step(6, TEST_SET_CORESTORE_JS_FIRST);
+    corestore_read_and_append(&work_dir, 2).await?;
+    js_run_step(7, TEST_SET_CORESTORE_JS_FIRST);
+    Ok(())
+}
+
+#[cfg(feature = "corestore")]
+#[test(async_test)]
+#[cfg_attr(not(feature = "js_interop_tests"), ignore)]
+async fn js_interop_corestore_rs_first() -> Result<()> {
+    init();
+    let work_dir = prepare_test_set(TEST_SET_CORESTORE_RS_FIRST);
+    corestore_read_and_append(&work_dir, 0).await?;
+    js_run_step(7, TEST_SET_CORESTORE_RS_FIRST);
+    Ok(())
+}
+
+/// Open the named core of the store in `work_dir`, check it has `length` of "Hello" and "World"
+/// and append up to "Hello", "World", "!"
+#[cfg(feature = "corestore")]
+async fn corestore_read_and_append(work_dir: &str, length: u64) -> Result<()> {
+    use hypercore::corestore::Corestore;
+    use hypercore::replication::{CoreInfo, CoreMethods};
+
+    let store = Corestore::open_disk(work_dir).await?;
+    let core = store.get_named("interop").await?;
+    assert_eq!(core.info().await.length, length);
+    let blocks: [&[u8]; 3] = [b"Hello", b"World", b"!"];
+    for (index, block) in blocks.iter().enumerate().take(length as usize) {
+        assert_eq!(core.get(index as u64).await?.as_deref(), Some(*block));
+    }
+    for block in &blocks[length as usize..] {
+        core.append(block).await?;
+    }
+    assert_eq!(store.list().await?, vec![store.key_pair("interop").public]);
+    Ok(())
+}
+
 async fn step_1_create(work_dir: &str) -> Result<()> {
     create_hypercore(work_dir).await?;
     Ok(())
@@ -0,0 +1,8 @@
+//! The verification of `hypercore::verify`, built as a `no_std` crate.
+#![no_std]
+#![forbid(unsafe_code)]
+
+extern crate alloc;
+
+#[path = "../../src/verify/verifier.rs"]
+pub mod verifier;